@@ -0,0 +1,90 @@
+// The emulator's hot paths. The benchmark IDs are compared across changes
+// with critcmp, so keep them as they are:
+//
+//   cargo bench --bench hot_paths -- --save-baseline before
+//
+// Every machine is built once, with a fixed seed, outside the measured
+// code. `cargo bench -- --test` runs each benchmark once, to check they
+// still build and run.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use chip8::{Chip, Profile};
+
+// Instructions per measured iteration of the cycle benchmarks.
+const CYCLES: u64 = 1000;
+
+fn machine(rom: &[u8]) -> Chip {
+    Chip::builder().profile(Profile::modern()).seed(0).rom(rom).build().unwrap()
+}
+
+// Register arithmetic and skips, no drawing.
+const ARITH_MIX: [u8; 20] = [
+    0x70, 0x03, // ADD V0, 3
+    0x81, 0x04, // ADD V1, V0
+    0x82, 0x15, // SUB V2, V1
+    0x83, 0x23, // XOR V3, V2
+    0x84, 0x06, // SHR V4, V0
+    0x85, 0x1E, // SHL V5, V1
+    0x30, 0x00, // SE V0, 0
+    0xC6, 0x0F, // RND V6, 0x0F
+    0xF0, 0x1E, // ADD I, V0
+    0x12, 0x00, // JP 0x200
+];
+
+// The IBM logo ROM jumping back to its start instead of looping at the
+// end: a CLS and six 15-row DRW every 21 instructions.
+fn ibm_loop() -> Vec<u8> {
+    let mut rom = include_bytes!("../rom/tests/ibm.ch8").to_vec();
+    rom[0x28..0x2A].copy_from_slice(&[0x12, 0x00]); // JP 0x200
+    rom
+}
+
+fn cycle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cycle");
+    group.throughput(Throughput::Elements(CYCLES));
+    for (name, rom) in [("arith_mix", ARITH_MIX.to_vec()), ("ibm_loop", ibm_loop())] {
+        let mut chip = machine(&rom);
+        group.bench_function(name, |b| b.iter(|| {
+            for _ in 0..CYCLES {
+                chip.cycle().unwrap();
+            }
+        }));
+    }
+    group.finish();
+}
+
+// A 15-row sprite drawn in the middle of the screen and across its bottom
+// right corner, where it is clipped.
+fn draw_sprite(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw_sprite");
+    for (name, x, y) in [("interior", 20, 8), ("edge", 60, 28)] {
+        let mut chip = machine(&[0xFF; 15]);
+        chip.set_v(0, x);
+        chip.set_v(1, y);
+        chip.set_i(0x200);
+        group.bench_function(name, |b| b.iter(|| {
+            chip.set_pc(0x200);
+            chip.execute_opcode(black_box(0xD01F)).unwrap(); // DRW V0, V1, 15
+        }));
+    }
+    group.finish();
+}
+
+fn framebuffer(c: &mut Criterion) {
+    let mut chip = machine(&[]);
+    c.bench_function("framebuffer/clear", |b| b.iter(|| {
+        chip.set_pc(0x200);
+        chip.execute_opcode(black_box(0x00E0)).unwrap(); // CLS
+    }));
+
+    // What a frontend takes once a frame, the IBM logo on the screen.
+    let mut chip = machine(&ibm_loop());
+    for _ in 0..20 {
+        chip.cycle().unwrap();
+    }
+    c.bench_function("frame/export", |b| b.iter(|| black_box(chip.get_frame().clone())));
+}
+
+criterion_group!(benches, cycle, draw_sprite, framebuffer);
+criterion_main!(benches);
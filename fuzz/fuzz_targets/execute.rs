@@ -0,0 +1,9 @@
+// Arbitrary machines running arbitrary programs, see chip8::fuzz for the
+// input layout. Seeded with a program per opcode in corpus/execute.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    chip8::fuzz::execute(data);
+});
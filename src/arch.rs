@@ -1,6 +1,56 @@
-pub const RAMSIZE: u32 = 4094;
+use crate::error::ChipError;
+
+pub const RAMSIZE: u32 = 4096;
+// The most RAM 16-bit addresses reach, on any arch.
+pub const MAX_RAMSIZE: u32 = 0x10000;
 pub const STACKSIZE: u32 = 16;
 pub const NVREGS: u32 = 16;
 pub const DISPLAY_WIDTH: u32 = 64;
 pub const DISPLAY_HEIGHT: u32 = 32;
 pub const NSPRITES: u32 = 16;
+// The built-in font's glyphs, loaded from address 0.
+pub const GLYPH_WIDTH: u32 = 4;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+// Machine sizes that vary between Chip-8 variants. The constants above are
+// the original machine's. The stack and the registers are the same on all
+// of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Arch {
+    pub ram_size: u32,
+    pub display_width: u32,
+    pub display_height: u32,
+}
+
+impl Arch {
+    pub fn chip8() -> Arch {
+        Arch {
+            ram_size: RAMSIZE,
+            display_width: DISPLAY_WIDTH,
+            display_height: DISPLAY_HEIGHT,
+        }
+    }
+
+    // Sizes no machine can be built with: RAM too small for the font or
+    // past what 16-bit addresses reach, a screen smaller than a glyph.
+    pub fn check(&self) -> Result<(), ChipError> {
+        let invalid = |message: String| Err(ChipError::InvalidProfile(message));
+        if self.ram_size < NSPRITES * GLYPH_HEIGHT {
+            return invalid(format!("RAM of {} bytes doesn't hold the font", self.ram_size));
+        }
+        if self.ram_size > MAX_RAMSIZE {
+            return invalid(format!("RAM of {:#x} bytes is past 16-bit addresses", self.ram_size));
+        }
+        if self.display_width < GLYPH_WIDTH || self.display_height < GLYPH_HEIGHT {
+            return invalid(format!("a screen of {} by {} pixels is smaller than a glyph", self.display_width, self.display_height));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Arch {
+    fn default() -> Self {
+        Self::chip8()
+    }
+}
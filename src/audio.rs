@@ -0,0 +1,12 @@
+// Short sound effects, played on top of the buzzer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Collision,
+}
+
+pub trait AudioSink {
+    // The sound timer buzzer.
+    fn set_buzzer(&mut self, on: bool);
+    // Starts a one-shot sound effect.
+    fn play(&mut self, sound: Sound);
+}
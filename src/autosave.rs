@@ -0,0 +1,202 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::chip::Chip;
+use crate::statefile::{self, Header, StateFile};
+use crate::util::ByteReader;
+
+// Save states are written alternately into two slot files, "<path>.0" and
+// "<path>.1", so a crash in the middle of a write leaves the other one intact.
+// Every slot starts with a sequence number telling which one is the latest,
+// then a state file.
+pub struct AutoSave {
+    interval_ms: u32,
+    slots: [PathBuf; 2],
+    // The slots' sequence numbers, read once and then kept up to date.
+    seqs: [Option<u64>; 2],
+    seq: u64,
+    last_ms: u32,
+}
+
+pub fn slot_paths(base: &Path) -> [PathBuf; 2] {
+    let slot = |n: u32| {
+        let mut name = base.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    [slot(0), slot(1)]
+}
+
+// An empty (or unreadable) slot is used first, then the older of the two.
+pub fn next_slot(seqs: [Option<u64>; 2]) -> usize {
+    match seqs {
+        [None, _] => 0,
+        [_, None] => 1,
+        [Some(a), Some(b)] => if a <= b { 0 } else { 1 },
+    }
+}
+
+// Slot indexes ordered from the latest save to the oldest one.
+pub fn latest_slots(seqs: [Option<u64>; 2]) -> Vec<usize> {
+    let mut slots: Vec<usize> = (0..2).filter(|&i| seqs[i].is_some()).collect();
+    slots.sort_by_key(|&i| std::cmp::Reverse(seqs[i]));
+    slots
+}
+
+fn read_slot(path: &Path) -> io::Result<(u64, Vec<u8>)> {
+    let data = fs::read(path)?;
+    let mut r = ByteReader::new(&data);
+    let seq = r.u64().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Truncated save slot"))?;
+    Ok((seq, data[8..].to_vec()))
+}
+
+fn slot_seqs(slots: &[PathBuf; 2]) -> [Option<u64>; 2] {
+    [read_slot(&slots[0]).ok().map(|s| s.0), read_slot(&slots[1]).ok().map(|s| s.0)]
+}
+
+// Feeds the latest readable save state to `load`, falling back to the other
+// slot if it is damaged or rejected. Returns the slot path that was loaded.
+pub fn resume<F>(base: &Path, mut load: F) -> io::Result<PathBuf>
+where F: FnMut(&StateFile) -> io::Result<()> {
+    let slots = slot_paths(base);
+
+    for i in latest_slots(slot_seqs(&slots)) {
+        let (_, data) = read_slot(&slots[i])?;
+        match statefile::decode(&data).map_err(io::Error::from).and_then(|file| load(&file)) {
+            Ok(()) => return Ok(slots[i].clone()),
+            Err(e) => warn!("Skipping save slot {}: {}", slots[i].display(), e),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound,
+        format!("No usable save state at {}", base.display())))
+}
+
+impl AutoSave {
+    pub fn new(base: &Path, interval_s: u32, now_ms: u32) -> AutoSave {
+        let slots = slot_paths(base);
+        let seqs = slot_seqs(&slots);
+        let seq = seqs.iter().flatten().max().map_or(0, |s| s + 1);
+
+        AutoSave {
+            interval_ms: interval_s.saturating_mul(1000),
+            slots,
+            seqs,
+            seq,
+            last_ms: now_ms,
+        }
+    }
+
+    // The millisecond clock wraps after 49 days, so the difference wraps
+    // too. A clock that went back makes a save due at once.
+    pub fn is_due(&self, now_ms: u32) -> bool {
+        now_ms.wrapping_sub(self.last_ms) >= self.interval_ms
+    }
+
+    pub fn save(&mut self, now_ms: u32, chip: &Chip) -> io::Result<&Path> {
+        self.last_ms = now_ms;
+
+        let slot = next_slot(self.seqs);
+        let mut data = self.seq.to_le_bytes().to_vec();
+        data.extend_from_slice(&statefile::encode(&Header::new(None, chip.profile()), &chip.save_state()));
+        fs::write(&self.slots[slot], data)?;
+        self.seqs[slot] = Some(self.seq);
+        self.seq += 1;
+
+        Ok(&self.slots[slot])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{next_slot, latest_slots, slot_paths, resume, AutoSave};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    #[test]
+    fn slot_names() {
+        let slots = slot_paths(Path::new("saves/game.state"));
+        assert_eq!(slots[0], PathBuf::from("saves/game.state.0"));
+        assert_eq!(slots[1], PathBuf::from("saves/game.state.1"));
+    }
+
+    #[test]
+    fn next_slot_empty() {
+        assert_eq!(next_slot([None, None]), 0);
+        assert_eq!(next_slot([Some(3), None]), 1);
+        assert_eq!(next_slot([None, Some(3)]), 0);
+    }
+
+    #[test]
+    fn next_slot_older() {
+        assert_eq!(next_slot([Some(4), Some(5)]), 0);
+        assert_eq!(next_slot([Some(6), Some(5)]), 1);
+    }
+
+    #[test]
+    fn latest() {
+        assert_eq!(latest_slots([None, None]), Vec::<usize>::new());
+        assert_eq!(latest_slots([None, Some(1)]), vec![1]);
+        assert_eq!(latest_slots([Some(4), Some(5)]), vec![1, 0]);
+        assert_eq!(latest_slots([Some(6), Some(5)]), vec![0, 1]);
+    }
+
+    #[test]
+    fn rotate_and_resume() {
+        let dir = std::env::temp_dir().join(format!("chip8-autosave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("game.state");
+
+        let mut a = AutoSave::new(&base, 1, 0);
+        assert!(!a.is_due(999));
+        assert!(a.is_due(1000));
+        // Across the clock wrapping, and with the clock gone back.
+        let late = AutoSave::new(&base, 1, u32::MAX - 500);
+        assert!(!late.is_due(400));
+        assert!(late.is_due(500));
+        assert!(late.is_due(u32::MAX - 501));
+
+        let chips: Vec<Chip> = (1..=4).map(|n| {
+            let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x60, n]).build().unwrap();
+            chip.step().unwrap();
+            chip
+        }).collect();
+        assert_eq!(a.save(1000, &chips[0]).unwrap(), dir.join("game.state.0"));
+        assert_eq!(a.save(2000, &chips[1]).unwrap(), dir.join("game.state.1"));
+        assert_eq!(a.save(3000, &chips[2]).unwrap(), dir.join("game.state.0"));
+
+        let mut loaded = Vec::new();
+        resume(&base, |f| { loaded = f.state.clone(); Ok(()) }).unwrap();
+        assert_eq!(loaded, chips[2].save_state());
+
+        // A rejected slot falls back to the previous save.
+        let mut loaded = Vec::new();
+        resume(&base, |f| {
+            if f.state == chips[2].save_state() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad"));
+            }
+            loaded = f.state.clone();
+            Ok(())
+        }).unwrap();
+        assert_eq!(loaded, chips[1].save_state());
+
+        // So does a damaged one.
+        let mut data = std::fs::read(dir.join("game.state.0")).unwrap();
+        data[200] ^= 1;
+        std::fs::write(dir.join("game.state.0"), data).unwrap();
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        resume(&base, |f| Ok(f.load_into(&mut chip, None)?)).unwrap();
+        assert_eq!(chip.v(0), 2);
+
+        // A new session continues the sequence.
+        let mut a = AutoSave::new(&base, 1, 0);
+        assert_eq!(a.save(1000, &chips[3]).unwrap(), dir.join("game.state.1"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
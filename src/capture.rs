@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::chip::Chip;
+use crate::error::ChipError;
+
+// Enough to cover every instruction a typical ROM uses.
+pub const MAX_CASES: usize = 64;
+
+// One recorded instruction: the machine before it, and a hash of the machine
+// after it.
+pub struct Case {
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    // What the instruction changed, for the generated comments.
+    pub changes: Vec<String>,
+    pub before: Vec<u8>,
+    pub after_hash: u64,
+}
+
+// Records the first execution of every distinct opcode of a running program,
+// to be written out as unit tests by to_rust().
+#[derive(Default)]
+pub struct TestCapture {
+    seen: HashSet<u16>,
+    cases: Vec<Case>,
+}
+
+impl TestCapture {
+    pub fn new() -> TestCapture {
+        TestCapture::default()
+    }
+
+    pub fn cases(&self) -> &[Case] {
+        &self.cases
+    }
+
+    // Executes one instruction like Chip::cycle(), recording it when its
+    // opcode is new.
+    pub fn cycle(&mut self, chip: &mut Chip) -> Result<(), ChipError> {
+        let opcode = chip.ram().read_u16(chip.regs().pc as u32)?;
+        if self.cases.len() >= MAX_CASES || self.seen.contains(&opcode) {
+            return chip.cycle();
+        }
+
+        let before = chip.save_state();
+        let r = chip.step()?;
+        self.seen.insert(opcode);
+        self.cases.push(Case {
+            pc: r.pc,
+            opcode,
+            mnemonic: r.mnemonic,
+            changes: r.changes.iter().map(|c| c.to_string()).collect(),
+            before,
+            after_hash: chip.state_hash(),
+        });
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Rust source with one #[test] per case. Each test restores the machine,
+// executes the opcode with Chip::execute_opcode() and compares the state
+// hash. `profile` is the name the machine's profile is looked up by.
+pub fn to_rust(cases: &[Case], profile: &str, source: &str) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by --capture-tests from {}.", source).unwrap();
+    writeln!(out, "use chip8::chip::Chip;").unwrap();
+    writeln!(out, "use chip8::profile::Profile;").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "const PROFILE: &str = {:?};", profile).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "fn state(hex: &str) -> Vec<u8> {{").unwrap();
+    writeln!(out, "    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    for c in cases {
+        writeln!(out).unwrap();
+        writeln!(out, "#[test]").unwrap();
+        writeln!(out, "fn op_{:03x}_{:04x}() {{", c.pc, c.opcode).unwrap();
+        writeln!(out, "    // {}", c.mnemonic).unwrap();
+        for change in &c.changes {
+            writeln!(out, "    // {}", change).unwrap();
+        }
+        writeln!(out, "    let mut chip = Chip::new_seed(0, Profile::from_name(PROFILE).unwrap());").unwrap();
+        writeln!(out, "    chip.load_state(&state(\"{}\")).unwrap();", hex(&c.before)).unwrap();
+        writeln!(out, "    chip.execute_opcode({:#06x}).unwrap();", c.opcode).unwrap();
+        writeln!(out, "    assert_eq!(chip.state_hash(), {:#018x});", c.after_hash).unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_rust, TestCapture};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    fn chip(rom: &[u8]) -> Chip {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(rom, 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    #[test]
+    fn first_of_each_opcode() {
+        let rom = [
+            0x70, 0x01, // ADD V0, 1
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x00, // JP 0x200
+        ];
+        let mut chip = chip(&rom);
+        let mut capture = TestCapture::new();
+        for _ in 0..9 {
+            capture.cycle(&mut chip).unwrap();
+        }
+
+        let opcodes: Vec<u16> = capture.cases().iter().map(|c| c.opcode).collect();
+        assert_eq!(opcodes, vec![0x7001, 0x1200]);
+        assert_eq!(capture.cases()[0].changes, vec!["V0: 0x00 -> 0x01"]);
+        assert_eq!(chip.regs().vx[0_u8], 6);
+    }
+
+    #[test]
+    fn generated_test() {
+        let mut chip = chip(&[0x6A, 0x05]); // LD VA, 0x05
+        let mut capture = TestCapture::new();
+        capture.cycle(&mut chip).unwrap();
+        let src = to_rust(capture.cases(), "modern", "test.ch8");
+
+        assert!(src.starts_with("// Generated by --capture-tests from test.ch8.\n"));
+        assert!(src.contains("const PROFILE: &str = \"modern\";\n"));
+        assert!(src.contains("\n#[test]\nfn op_200_6a05() {\n    // LD VA, 0x5\n    // VA: 0x00 -> 0x05\n"));
+        assert!(src.contains("    chip.execute_opcode(0x6a05).unwrap();\n"));
+        assert!(src.contains(&format!("    assert_eq!(chip.state_hash(), {:#018x});\n", chip.state_hash())));
+        assert_eq!(src.matches('{').count(), src.matches('}').count());
+        assert_eq!(src.matches('(').count(), src.matches(')').count());
+
+        // What the test does, done here: restore, execute, compare.
+        let case = &capture.cases()[0];
+        let hex = src.split("state(\"").nth(1).unwrap().split('"').next().unwrap();
+        let state: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        assert_eq!(state, case.before);
+        let mut replay = Chip::new_seed(0, Profile::from_name("modern").unwrap());
+        replay.load_state(&state).unwrap();
+        replay.execute_opcode(0x6a05).unwrap();
+        assert_eq!(replay.state_hash(), case.after_hash);
+    }
+}
@@ -1,38 +1,67 @@
-use log::{trace, info};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
 
-use crate::arch;
+use log::{trace, info, warn};
+
+use crate::arch::{self, Arch};
+use crate::error::ChipError;
+use crate::heatmap::Heatmap;
 use crate::ram::Ram;
 use crate::regs::RegMap;
-use crate::instr::Instr;
+use crate::instr::{Instr, Operation};
+use crate::keypad::Keypad;
 use crate::framebuffer::Framebuffer;
 use crate::framebuffer::Frame;
+use crate::golden::frame_hash;
 use crate::util;
-use crate::profile::Profile;
+use crate::profile::{PcOverflow, Profile};
+use crate::rom;
+use crate::state::ChipState;
+use crate::step::{self, StepResult, StateChange, StateDiff};
 
 type Stack = util::Array<u16, {arch::STACKSIZE as usize}>;
-type Keys = [bool; 16];
 type Sprite = [u8; 5];
 type SpriteAddrs = util::Array<u16, {arch::NSPRITES as usize}>;
 
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+// Addresses of executed instructions kept for recent_pcs().
+const RECENT_PCS: usize = 32;
+
 pub struct Chip {
     ram: Ram,
     sprite_addr: SpriteAddrs,
     regs: RegMap,
     stack: Stack,
-    keys: Keys,
+    keys: Keypad,
     framebuffer: Framebuffer,
     rnd: oorandom::Rand32,
+    seed: u64,
     profile: Profile,
-}
-
-macro_rules! trace_instr {
-    ($self:ident, $fmt: expr $(, $($arg:tt)* )? ) =>
-    {
-        trace!(concat!("[PC:0x{:04x}] ", $fmt), $self.regs.pc - 2, $( $( $arg )* )? );
-    };
+    // Instructions completed since power-on. FX0A waiting for a key does not count.
+    instructions: u64,
+    // Set by a DRW that collided, until taken by take_collision().
+    collision: bool,
+    // DRW instructions that collided since the last reset_collision_count().
+    collisions: u64,
+    // CLS instructions executed since power-on.
+    clears: u64,
+    // DRW instructions executed since power-on.
+    draws: u64,
+    recent_pcs: VecDeque<u16>,
+    // Executions per address, when enabled.
+    heatmap: Option<Heatmap>,
+    // Where load_rom() put the last ROM.
+    rom: Range<u32>,
+    // DRW addresses already warned about for clipping their sprite, so a
+    // draw in a loop warns once.
+    clipped_draws: HashSet<u16>,
 }
 
 impl Chip {
+    pub fn builder() -> ChipBuilder {
+        ChipBuilder::new()
+    }
+
     pub fn new(profile: Profile) -> Chip {
         // Generate RND seed.
         let mut seed_bytes: [u8; 8] = [0u8; 8];
@@ -142,13 +171,13 @@ impl Chip {
              0b10000000],
         ];
 
-        let mut ram = Ram::new();
+        let mut ram = Ram::with_size(profile.arch.ram_size);
         let mut sprite_addr = SpriteAddrs::new();
         let mut addr: u32 = 0x0;
 
         for (i, s) in sprites.iter().enumerate() {
             sprite_addr[i] = addr as u16;
-            addr = ram.load_block_u8(addr, s);
+            addr = ram.load_block_u8(addr, s).unwrap();
         }
 
         Chip {
@@ -156,145 +185,336 @@ impl Chip {
             sprite_addr,
             regs: RegMap::new(),
             stack: Stack::new(),
-            keys: [false; 16],
-            framebuffer: Framebuffer::new(),
+            keys: Keypad::new(),
+            framebuffer: Framebuffer::with_size(profile.arch.display_width, profile.arch.display_height),
             rnd: oorandom::Rand32::new(seed),
+            seed,
             profile,
+            instructions: 0,
+            collision: false,
+            collisions: 0,
+            clears: 0,
+            draws: 0,
+            recent_pcs: VecDeque::with_capacity(RECENT_PCS),
+            heatmap: None,
+            rom: 0..0,
+            clipped_draws: HashSet::new(),
+        }
+    }
+
+    // Brings the machine back to its power-on state. The profile and the
+    // RNG seed are kept, so a reset machine replays the same random numbers.
+    // An enabled heatmap starts over.
+    pub fn reset(&mut self) {
+        let heatmap = self.heatmap.is_some();
+        *self = Chip::new_seed(self.seed, self.profile);
+        if heatmap {
+            self.enable_heatmap();
         }
     }
 
     pub fn key_press(&mut self, key: u8) {
-        self.keys[key as usize] = true;
+        self.keys.press(key);
     }
 
     pub fn key_unpress(&mut self, key: u8) {
-        self.keys[key as usize] = false;
+        self.keys.release(key);
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keys
     }
 
-    fn is_key_pressed(&self, key: u8) -> bool {
-        self.keys[key as usize]
+    pub(crate) fn set_keypad(&mut self, keypad: Keypad) {
+        self.keys = keypad;
+    }
+
+    // The RNG seed the machine was created with, random unless given.
+    pub fn seed(&self) -> u64 {
+        self.seed
     }
 
     pub fn set_pc(&mut self, pc: u16) {
         self.regs.pc = pc;
     }
 
-    pub fn cycle(&mut self) {
-        let code = self.ram.read_u16(self.regs.pc as u32);
+    pub fn set_v(&mut self, x: u8, value: u8) {
+        self.regs.vx[x] = value;
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.regs.i = i;
+    }
+
+    pub fn set_dt(&mut self, dt: u8) {
+        self.regs.dt = dt;
+    }
+
+    pub fn set_st(&mut self, st: u8) {
+        self.regs.st = st;
+    }
+
+    // True when the next instruction jumps to itself, the usual way for a
+    // Chip-8 program to end.
+    pub fn is_halted(&self) -> bool {
+        let op = self.ram.read_u16(self.regs.pc as u32).ok().and_then(|code| Instr::new(code).operation());
+        matches!(op, Some(Operation::Jp { nnn }) if nnn == self.regs.pc)
+    }
+
+    pub fn clears(&self) -> u64 {
+        self.clears
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    // True once after a DRW collided.
+    pub fn take_collision(&mut self) -> bool {
+        std::mem::replace(&mut self.collision, false)
+    }
+
+    // Collided DRW instructions, for flicker statistics over a frame or a
+    // session.
+    pub fn collision_count(&self) -> u64 {
+        self.collisions
+    }
+
+    pub fn reset_collision_count(&mut self) {
+        self.collisions = 0;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.regs.pc
+    }
+
+    pub fn i(&self) -> u16 {
+        self.regs.i
+    }
+
+    pub fn v(&self, x: u8) -> u8 {
+        self.regs.vx[x]
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.regs.sp
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.regs.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.regs.st
+    }
+
+    // All stack slots, including the ones above SP.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    // The return addresses of the active calls, the latest first. An SP
+    // past the stack gives the whole stack.
+    pub fn call_stack(&self) -> Vec<u16> {
+        let depth = (self.regs.sp as usize).min(self.stack.len());
+        self.stack[..depth].iter().rev().copied().collect()
+    }
+
+    pub fn ram_size(&self) -> u32 {
+        self.ram.size()
+    }
+
+    // Read-only view of RAM, None when `range` doesn't fit.
+    pub fn ram_slice(&self, range: Range<u32>) -> Option<&[u8]> {
+        self.ram.as_slice().get(range.start as usize..range.end as usize)
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub(crate) fn regs(&self) -> &RegMap {
+        &self.regs
+    }
+
+    pub(crate) fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    // Executes one instruction. On error the PC is left on the failing
+    // instruction.
+    pub fn cycle(&mut self) -> Result<(), ChipError> {
+        let code = self.ram.read_u16(self.regs.pc as u32)?;
+        self.execute_opcode(code)
+    }
+
+    // Executes `opcode` as if it had been fetched from PC, whatever RAM
+    // holds there. Errors leave the PC unchanged like cycle().
+    pub fn execute_opcode(&mut self, opcode: u16) -> Result<(), ChipError> {
+        let pc = self.regs.pc;
+        let result = self.execute(opcode);
+        if result.is_err() {
+            self.regs.pc = pc;
+        }
+        self.sync_stack();
+        result
+    }
+
+    // Checks a PC value computed by the instruction at `pc` against the
+    // address space: RAM, at most 64K. Outside it the profile decides
+    // between an error and wrapping around to 0.
+    fn next_pc(&self, pc: u16, next: u32) -> Result<u16, ChipError> {
+        let limit = self.ram.size().min(0x10000);
+        if next < limit {
+            return Ok(next as u16);
+        }
+        match self.profile.pc_overflow {
+            PcOverflow::Error => Err(ChipError::PcOutOfBounds { pc }),
+            PcOverflow::Wrap => {
+                warn!("PC wrapped past {:#x} at {:#05x}", limit, pc);
+                Ok((next % limit) as u16)
+            },
+        }
+    }
+
+    fn skip(&mut self, pc: u16) -> Result<(), ChipError> {
+        self.regs.pc = self.next_pc(pc, self.regs.pc as u32 + 2)?;
+        Ok(())
+    }
+
+    fn execute(&mut self, code: u16) -> Result<(), ChipError> {
+        let pc = self.regs.pc;
+        if self.profile.nop == Some(code) {
+            self.regs.pc = self.next_pc(pc, pc as u32 + 2)?;
+            self.retire(pc);
+            return Ok(());
+        }
         let instr = Instr::new(code);
+        let op = match instr.operation() {
+            Some(op) => op,
+            None => return Err(ChipError::UnknownOpcode { pc, opcode: instr.opcode }),
+        };
+
+        trace!("[PC:0x{:04x}] {}", self.regs.pc, op);
 
-        // PC points to the next instruction to execute.
-        self.regs.pc += 2;
+        // PC points to the next instruction to execute. Checked before
+        // anything else changes, so an error leaves the machine as it was.
+        self.regs.pc = self.next_pc(pc, pc as u32 + 2)?;
 
-        match instr {
-            Instr { opcode: 0x00E0, .. } => {
+        match op {
+            Operation::Cls => {
                 // CLS - Clear framebuffer
-                trace_instr!(self, "CLS");
                 self.framebuffer.clear();
+                self.clears += 1;
             },
 
-            Instr { opcode: 0x00EE, .. } => {
+            Operation::Ret => {
                 // RET - Return from a subroutine.
-                trace_instr!(self, "RET");
-                self.regs.sp -= 1;
-                self.regs.pc = self.stack[self.regs.sp];
+                if self.regs.sp == 0 {
+                    return Err(ChipError::StackUnderflow { pc });
+                }
+                let sp = self.regs.sp - 1;
+                if let Some(base) = self.profile.stack_addr {
+                    let addr = base as u32 + 2 * sp as u32;
+                    self.check_max_address(addr, 2)?;
+                    self.stack[sp] = self.ram.read_u16(addr)?;
+                }
+                self.regs.sp = sp;
+                self.regs.pc = self.stack[sp];
             },
 
-            Instr { c: 0x1, nnn, .. } => {
+            Operation::Jp { nnn } => {
                 // JP addr
-                trace_instr!(self, "JP {:#x}", nnn);
                 self.regs.pc = nnn;
             },
 
-            Instr { c: 0x2, nnn, .. } => {
+            Operation::Call { nnn } => {
                 // CALL addr.
-                trace_instr!(self, "CALL {:#x}", nnn);
+                if self.regs.sp as u32 >= arch::STACKSIZE {
+                    return Err(ChipError::StackOverflow { pc });
+                }
+                if let Some(base) = self.profile.stack_addr {
+                    let addr = base as u32 + 2 * self.regs.sp as u32;
+                    self.check_max_address(addr, 2)?;
+                    self.ram.write_u16(addr, self.regs.pc)?;
+                }
                 self.stack[self.regs.sp] = self.regs.pc;
                 self.regs.sp += 1;
                 self.regs.pc = nnn;
             },
 
-            Instr { c: 0x3, x, nn, .. } => {
+            Operation::SeVxNn { x, nn } => {
                 // SE Vx, nn
-                trace_instr!(self, "SE V{:X}, {:#x}", x, nn);
                 if self.regs.vx[x] == nn {
-                    self.regs.pc += 2;
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0x4, x, nn, .. } => {
+            Operation::SneVxNn { x, nn } => {
                 // SNE Vx, nn
-                trace_instr!(self, "SNE V{:X}, {:#x}", x, nn);
                 if self.regs.vx[x] != nn {
-                    self.regs.pc += 2;
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0x5, x, y, n:0x0, .. } => {
+            Operation::SeVxVy { x, y } => {
                 // SE Vx, Vy
-                trace_instr!(self, "SE V{:X}, V{:X}", x, y);
                 if self.regs.vx[x] == self.regs.vx[y] {
-                    self.regs.pc += 2;
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0x6, x, nn, .. } => {
+            Operation::LdVxNn { x, nn } => {
                 // LD Vx, nn
-                trace_instr!(self, "LD V{:X}, {:#x}", x, nn);
                 self.regs.vx[x] = nn;
             },
 
-            Instr { c: 0x7, x, nn, .. } => {
+            Operation::AddVxNn { x, nn } => {
                 // ADD Vx, nn
-                trace_instr!(self, "ADD V{:X}, {:#x}", x, nn);
                 (self.regs.vx[x], _) = self.regs.vx[x].overflowing_add(nn);
             },
 
-            Instr { c: 0x8, x, y, n: 0x0, .. } => {
+            Operation::LdVxVy { x, y } => {
                 // LD Vx, Vy
-                trace_instr!(self, "LD V{:X}, V{:X}", x, y);
                 self.regs.vx[x] = self.regs.vx[y];
             },
 
-            Instr { c: 0x8, x, y, n: 0x1, .. } => {
+            Operation::OrVxVy { x, y } => {
                 // OR Vx, Vy
-                trace_instr!(self, "OR V{:X}, V{:X}", x, y);
                 self.regs.vx[x] |= self.regs.vx[y];
             },
 
-            Instr { c: 0x8, x, y, n: 0x2, .. } => {
+            Operation::AndVxVy { x, y } => {
                 // AND Vx, Vy
-                trace_instr!(self, "AND V{:X}, V{:X}", x, y);
                 self.regs.vx[x] &= self.regs.vx[y];
             },
 
-            Instr { c: 0x8, x, y, n: 0x3, .. } => {
+            Operation::XorVxVy { x, y } => {
                 // XOR Vx, Vy
-                trace_instr!(self, "XOR V{:X}, V{:X}", x, y);
                 self.regs.vx[x] ^= self.regs.vx[y];
             },
 
-            Instr { c: 0x8, x, y, n: 0x4, .. } => {
+            Operation::AddVxVy { x, y } => {
                 // ADD Vx, Vy
-                trace_instr!(self, "ADD V{:X}, V{:X}", x, y);
                 let overflow: bool;
                 (self.regs.vx[x], overflow) = self.regs.vx[x].overflowing_add(self.regs.vx[y]);
                 // VF := overflow
                 self.regs.vx[0xf_u8] = if overflow { 1 } else { 0 };
             },
 
-            Instr { c: 0x8, x, y, n: 0x5, .. } => {
+            Operation::SubVxVy { x, y } => {
                 // SUB Vx, Vy
-                trace_instr!(self, "SUB V{:X}, V{:X}", x, y);
                 let overflow: bool;
                 (self.regs.vx[x], overflow) = self.regs.vx[x].overflowing_sub(self.regs.vx[y]);
                 // VF := not overflow
                 self.regs.vx[0xf_u8] = if overflow { 0 } else { 1 };
             },
 
-            Instr { c: 0x8, x, y, n: 0x6, .. } => {
-                // SHR Vx, Vy. Ambiguous.
-                trace_instr!(self, "SHR V{:X}, V{:X}", x, y);
+            Operation::ShrVxVy { x, y } => {
+                // SHR Vx, Vy. Ambiguous: the COSMAC VIP shifts VY and
+                // stores the result in VX, later interpreters shift VX in
+                // place and ignore VY. Either way VF gets the bit shifted
+                // out of the shifted operand, and VY is never modified.
                 if self.profile.op_8xy6_use_vy {
                     self.regs.vx[x] = self.regs.vx[y];
                 }
@@ -302,18 +522,16 @@ impl Chip {
                 self.regs.vx[x] >>= 1;
             },
 
-            Instr { c: 0x8, x, y, n: 0x7, .. } => {
+            Operation::SubnVxVy { x, y } => {
                 // SUBN Vx, Vy
-                trace_instr!(self, "SUBN V{:X}, V{:X}", x, y);
                 let overflow: bool;
                 (self.regs.vx[x], overflow) = self.regs.vx[y].overflowing_sub(self.regs.vx[x]);
                 // VF := not overflow
                 self.regs.vx[0xf_u8] = if overflow { 0 } else { 1 };
             },
 
-            Instr { c: 0x8, x, y, n: 0xE, .. } => {
-                // SHL Vx, Vy
-                trace_instr!(self, "SHL V{:X}, V{:X}", x, y);
+            Operation::ShlVxVy { x, y } => {
+                // SHL Vx, Vy. Ambiguous like SHR, VF gets bit 7.
                 if self.profile.op_8xye_use_vy {
                     self.regs.vx[x] = self.regs.vx[y];
                 }
@@ -321,145 +539,274 @@ impl Chip {
                 self.regs.vx[x] <<= 1;
             },
 
-            Instr { c: 0x9, x, y, n: 0x0, .. } => {
+            Operation::SneVxVy { x, y } => {
                 // SNE Vx, Vy
-                trace_instr!(self, "SNE V{:X}, V{:X}", x, y);
                 if self.regs.vx[x] != self.regs.vx[y] {
-                    self.regs.pc += 2;
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0xA, nnn, .. } => {
+            Operation::LdINnn { nnn } => {
                 // LD I, nnn
-                trace_instr!(self, "LD I, {:#x}", nnn);
                 self.regs.i = nnn;
             },
 
-            Instr { c: 0xB, nnn, .. } => {
-                // JP V0, nnn
-                trace_instr!(self, "JP V0, {:#x}", nnn);
-                self.regs.pc = self.regs.vx[0] as u16 + nnn;
+            Operation::JpV0Nnn { nnn } => {
+                // JP V0, nnn, or JP VX, xnn with the quirk.
+                let x = if self.profile.op_bnnn_use_vx { (nnn >> 8) as u8 } else { 0 };
+                self.regs.pc = self.next_pc(pc, self.regs.vx[x] as u32 + nnn as u32)?;
             },
 
-            Instr { c: 0xC, x, nn, .. } => {
+            Operation::RndVxNn { x, nn } => {
                 // RND Vx, nn
-                trace_instr!(self, "RND V{:X}, {:#x}", x, nn);
                 let rnd: u8 = self.rnd.rand_range(0..0x100) as u8;
                 self.regs.vx[x] = rnd & nn;
             },
 
-            Instr { c: 0xD, x, y, n, .. } => {
+            Operation::Drw { x, y, n } => {
                 // DRW Vx, Vy, n
-                trace_instr!(self, "DRW V{:X}, V{:X}, {:#x}", x, y, n);
 
-                let addr_start = self.regs.i as usize;
-                let addr_end = addr_start + (n as usize);
-                let sprites = &self.ram.mem[addr_start..addr_end];
+                let cap = self.profile.max_sprite_bytes;
+                if n > cap && self.clipped_draws.insert(pc) {
+                    warn!("DRW at {:#05x} draws {} rows, clipped to {}", pc, n, cap);
+                }
+                let n = n.min(cap);
+                self.check_max_address(self.regs.i as u32, n as u32)?;
+                let sprites = self.ram.read_block(self.regs.i as u32, n as u32)?;
 
                 let mut colisions: bool = false;
 
-                let start_x = self.regs.vx[x] as u32;
-                let start_y = self.regs.vx[y] as u32;
+                let (start_x, start_y) = self.profile.draw_origin.origin(self.regs.vx[x], self.regs.vx[y],
+                    self.framebuffer.width(), self.framebuffer.height());
 
                 self.framebuffer.draw_sprite(sprites, start_x, start_y, &mut colisions);
 
                 self.regs.vx[0xF] = if colisions { 1u8 } else { 0u8 };
+                self.collision |= colisions;
+                self.collisions += colisions as u64;
+                self.draws += 1;
             },
 
-            Instr { c: 0xE, x, nn: 0x9E, .. } => {
+            Operation::SkpVx { x } => {
                 // SKP Vx
-                trace_instr!(self, "SKP V{:X}", x);
-                if self.is_key_pressed(self.regs.vx[x]) {
-                    self.regs.pc += 2;
+                if self.keys.is_down(self.regs.vx[x]) {
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0xE, x, nn: 0xA1, .. } => {
+            Operation::SkpnVx { x } => {
                 // SKPN Vx
-                trace_instr!(self, "SKPN V{:X}", x);
-                if !self.is_key_pressed(self.regs.vx[x]) {
-                    self.regs.pc += 2;
+                if !self.keys.is_down(self.regs.vx[x]) {
+                    self.skip(pc)?;
                 }
             },
 
-            Instr { c: 0xF, x, nn: 0x07, .. } => {
+            Operation::LdVxDt { x } => {
                 // LD Vx, DT
-                trace_instr!(self, "LD V{:X}, DT", x);
                 self.regs.vx[x] = self.regs.dt;
                 info!("DT={}", self.regs.dt);
             },
 
-            Instr { c: 0xF, x, nn: 0x0A, .. } => {
+            Operation::LdVxK { x } => {
                 // LD Vx, K
-                trace_instr!(self, "LD V{:X}, K", x);
-                match self.keys.iter().position(|&pressed| { pressed }) {
-                    Some(i) => self.regs.vx[x] = i as u8,
-                    None => self.regs.pc -= 2,
+                match self.profile.key_wait.key(&self.keys) {
+                    Some(key) => self.regs.vx[x] = key,
+                    None => {
+                        // Blocked, run this instruction again next cycle.
+                        self.regs.pc = pc;
+                        return Ok(());
+                    },
                 }
             },
 
-            Instr { c: 0xF, x, nn: 0x15, .. } => {
+            Operation::LdDtVx { x } => {
                 // LD DT, Vx
-                trace_instr!(self, "LD DT, V{:X}", x);
                 self.regs.dt = self.regs.vx[x];
                 info!("DT={}", self.regs.dt);
             },
 
-            Instr { c: 0xF, x, nn: 0x18, .. } => {
+            Operation::LdStVx { x } => {
                 // LD ST, Vx
-                trace_instr!(self, "LD ST, V{:X}", x);
                 self.regs.st = self.regs.vx[x];
             },
 
-            Instr { c: 0xF, x, nn: 0x1E, .. } => {
-                // ADD I, Vx
-                trace_instr!(self, "ADD I, V{:X}", x);
-                self.regs.i += self.regs.vx[x] as u16;
+            Operation::AddIVx { x } => {
+                // ADD I, Vx, wrapping at 16 bits.
+                self.regs.i = self.regs.i.wrapping_add(self.regs.vx[x] as u16);
             },
 
-            Instr { c: 0xF, x, nn: 0x29, .. } => {
-                // LD F, Vx
-                trace_instr!(self, "LD F, V{:X}", x);
-                self.regs.i = self.sprite_addr[self.regs.vx[x]];
+            Operation::LdFVx { x } => {
+                // LD F, Vx, the digit in the low nibble.
+                self.regs.i = self.sprite_addr[self.regs.vx[x] & 0xF];
             },
 
-            Instr { c: 0xF, x, nn: 0x33, .. } => {
+            Operation::LdBVx { x } => {
                 // LD B, Vx
-                trace_instr!(self, "LD B, V{:X}", x);
                 let mut bcd = [0u8; 3];
                 bcd[2] = self.regs.vx[x] % 10;
                 bcd[1] = (self.regs.vx[x] / 10) % 10;
                 bcd[0] = self.regs.vx[x] / 100;
 
-                self.ram.load_block_u8(self.regs.i as u32, &bcd);
+                self.check_range(pc, op, 3)?;
+                self.ram.load_block_u8(self.regs.i as u32, &bcd)?;
             },
 
-            Instr { c: 0xF, x, nn: 0x55, .. } => {
+            Operation::LdIVx { x } => {
                 // LD [I], Vx
-                trace_instr!(self, "LD [I], V{:X}", x);
+                self.check_range(pc, op, x as u32 + 1)?;
                 for i in 0..=x {
                     let addr: u32 = self.regs.i as u32 + i as u32;
-                    self.ram.write_u8(addr, self.regs.vx[i]);
+                    self.ram.write_u8(addr, self.regs.vx[i])?;
                 }
                 if self.profile.op_fx55_store_i {
-                    self.regs.i += x as u16 + 1;
+                    self.regs.i = self.regs.i.wrapping_add(x as u16 + 1);
                 }
             },
 
-            Instr { c: 0xF, x, nn: 0x65, .. } => {
+            Operation::LdVxI { x } => {
                 // LD Vx, [I]
-                trace_instr!(self, "LD V{:X}, [I]", x);
+                self.check_range(pc, op, x as u32 + 1)?;
                 for i in 0..=x {
                     let addr: u32 = self.regs.i as u32 + i as u32;
-                    self.regs.vx[i] = self.ram.read_u8(addr);
+                    self.regs.vx[i] = self.ram.read_u8(addr)?;
                 }
                 if self.profile.op_fx65_store_i {
-                    self.regs.i += x as u16 + 1;
+                    self.regs.i = self.regs.i.wrapping_add(x as u16 + 1);
                 }
             },
+        }
+
+        self.retire(pc);
+        Ok(())
+    }
+
+    // The `len` bytes at `addr` must end by the profile's max_address, even
+    // where the RAM goes on.
+    fn check_max_address(&self, addr: u32, len: u32) -> Result<(), ChipError> {
+        match self.profile.max_address {
+            Some(max) if addr + len > max as u32 + 1 => Err(ChipError::RamOutOfBounds { addr: addr.max(max as u32 + 1) }),
+            _ => Ok(()),
+        }
+    }
+
+    // The `len` bytes at I that `op` reads or writes, checked as a whole
+    // before the first one so a failed FX55 doesn't leave half its
+    // registers stored.
+    fn check_range(&self, pc: u16, op: Operation, len: u32) -> Result<(), ChipError> {
+        let start = self.regs.i as u32;
+        let limit = match self.profile.max_address {
+            Some(max) => self.ram.size().min(max as u32 + 1),
+            None => self.ram.size(),
+        };
+        if start + len > limit {
+            return Err(ChipError::RangeOutOfBounds { pc, instr: op.to_string(), start, end: start + len, limit });
+        }
+        Ok(())
+    }
+
+    // Counts the instruction at `pc` as completed.
+    fn retire(&mut self, pc: u16) {
+        self.instructions += 1;
+        if self.recent_pcs.len() == RECENT_PCS {
+            self.recent_pcs.pop_front();
+        }
+        self.recent_pcs.push_back(pc);
+        if let Some(h) = self.heatmap.as_mut() {
+            h.record(pc);
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // Where the last instructions executed were, oldest first. Tells code
+    // from data for disassembling before PC.
+    pub fn recent_pcs(&self) -> Vec<u16> {
+        self.recent_pcs.iter().copied().collect()
+    }
+
+    // Starts counting executions per address, see heatmap().
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap = Some(Heatmap::new(self.ram.size()));
+    }
+
+    // None unless enabled.
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
 
-            _ => panic!("Unknown opcode: {:#x}", instr.opcode),
+    // Executes one instruction like cycle() and reports what it did.
+    pub fn step(&mut self) -> Result<StepResult, ChipError> {
+        let pc = self.regs.pc;
+        let opcode = self.ram.read_u16(pc as u32)?;
+        let instr = Instr::new(opcode);
+        let (description, (ram_range, rows)) = match instr.operation() {
+            _ if self.profile.nop == Some(opcode) => ("no-op".to_string(), (0..0, 0..0)),
+            Some(op) => (step::describe(&op, &self.regs, &self.keys, &self.profile), self.writes(op)),
+            None => ("unknown opcode".to_string(), (0..0, 0..0)),
+        };
+
+        let regs = self.regs.clone();
+        let stack = self.stack;
+        let ram = self.ram.as_slice()[ram_range.start as usize..ram_range.end as usize].to_vec();
+        let pixels = |chip: &Chip| {
+            let width = chip.framebuffer.width() as usize;
+            let frame = chip.framebuffer.get_frame().iter().flatten();
+            frame.skip(rows.start as usize * width).take(rows.len() * width).copied().collect::<Vec<u32>>()
+        };
+        let screen = pixels(self);
+
+        self.cycle()?;
+
+        let mut changes = diff_regs((&regs, &stack), (&self.regs, &self.stack), Some(pc.wrapping_add(2)));
+        for (addr, old) in ram_range.zip(ram) {
+            let new = self.ram.as_slice()[addr as usize];
+            if old != new {
+                changes.push(StateChange::Mem { addr: addr as u16, old, new });
+            }
+        }
+        if pixels(self) != screen {
+            changes.push(StateChange::Screen);
         }
+
+        Ok(StepResult {
+            pc,
+            opcode,
+            mnemonic: instr.to_string(),
+            description,
+            changes,
+        })
+    }
+
+    // The RAM bytes and the screen rows `op` may write, as it would run
+    // now. Only those are compared by step(), not the whole machine.
+    fn writes(&self, op: Operation) -> (Range<u32>, Range<u32>) {
+        let i = self.regs.i as u32;
+        let ram = match op {
+            Operation::Call { .. } => match self.profile.stack_addr {
+                Some(base) => {
+                    let slot = base as u32 + 2 * self.regs.sp as u32;
+                    slot..slot + 2
+                },
+                None => 0..0,
+            },
+            Operation::LdBVx { .. } => i..i + 3,
+            Operation::LdIVx { x } => i..i + x as u32 + 1,
+            _ => 0..0,
+        };
+        let height = self.framebuffer.height();
+        let rows = match op {
+            Operation::Cls => 0..height,
+            Operation::Drw { x, y, n } => {
+                let (_, top) = self.profile.draw_origin.origin(self.regs.vx[x], self.regs.vx[y],
+                    self.framebuffer.width(), height);
+                top..(top + n.min(self.profile.max_sprite_bytes) as u32).min(height)
+            },
+            _ => 0..0,
+        };
+        let size = self.ram.size();
+        (ram.start.min(size)..ram.end.min(size), rows)
     }
 
     pub fn cycle_timers(&mut self) {
@@ -473,35 +820,504 @@ impl Chip {
         info!("cycle_timers, dt={}, st={}", self.regs.dt, self.regs.st);
     }
 
+    // Runs `frames` frames of a timer tick then `ipf` instructions, with no
+    // clock, for tests. Stops early, before the instruction, when the chip
+    // halts, and after it when FX0A waits for a key or an instruction fails.
+    pub fn run_frames(&mut self, frames: u32, ipf: u32) -> RunSummary {
+        let start = self.instructions;
+        let mut summary = RunSummary { frames: 0, instructions: 0, timer_ticks: 0, frame_hash: 0, state_hash: 0, stop: None };
+        'frames: for _ in 0..frames {
+            self.cycle_timers();
+            summary.timer_ticks += 1;
+            for _ in 0..ipf {
+                if let Some(stop) = self.run_one() {
+                    summary.stop = Some(stop);
+                    break 'frames;
+                }
+            }
+            summary.frames += 1;
+        }
+        summary.instructions = self.instructions - start;
+        summary.frame_hash = frame_hash(self.framebuffer.get_frame());
+        summary.state_hash = self.state_hash();
+        summary
+    }
+
+    // Runs up to `budget` instructions, without timers, until `pred` holds
+    // after one. Stops early like run_frames().
+    pub fn run_until(&mut self, budget: u64, mut pred: impl FnMut(&Chip) -> bool) -> UntilResult {
+        let start = self.instructions;
+        let mut result = UntilResult { matched: false, cycles: 0, stop: None };
+        for _ in 0..budget {
+            if let Some(stop) = self.run_one() {
+                result.stop = Some(stop);
+                break;
+            }
+            if pred(self) {
+                result.matched = true;
+                break;
+            }
+        }
+        result.cycles = self.instructions - start;
+        result
+    }
+
+    // An instruction for run_frames() and run_until(), or why there was
+    // none.
+    fn run_one(&mut self) -> Option<RunStop> {
+        let pc = self.regs.pc;
+        if self.is_halted() {
+            return Some(RunStop::Halted { pc });
+        }
+        let executed = self.instructions;
+        if let Err(e) = self.cycle() {
+            return Some(RunStop::Error(e));
+        }
+        if self.instructions == executed {
+            return Some(RunStop::KeyWait { pc });
+        }
+        None
+    }
+
     pub fn is_sound_on(&self) -> bool {
         self.regs.st > 0
     }
 
-    pub fn load_rom(&mut self, rom: &[u8], start: u32) {
+    pub fn poke(&mut self, addr: u32, bytes: &[u8]) -> Result<(), ChipError> {
+        self.ram.load_block_u8(addr, bytes)?;
+        self.sync_stack();
+        Ok(())
+    }
+
+    // With the stack in RAM, the stack slots as RAM has them now.
+    fn sync_stack(&mut self) {
+        if let Some(base) = self.profile.stack_addr {
+            for n in 0..arch::STACKSIZE {
+                if let Ok(addr) = self.ram.read_u16(base as u32 + 2 * n) {
+                    self.stack[n] = addr;
+                }
+            }
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8], start: u32) -> Result<(), ChipError> {
+        let max = self.ram.size().saturating_sub(start) as usize;
+        if rom.len() > max {
+            return Err(ChipError::RomTooLarge { size: rom.len(), max });
+        }
+
         let mut code = Vec::<u16>::new();
         for i in 0..rom.len()/2 {
             let op: u16 = u16::from_be_bytes([rom[2*i], rom[2*i+1]]);
             code.push(op);
         }
-        self.ram.load_block_u16(start, code.as_slice());
+        self.ram.load_block_u16(start, code.as_slice())?;
+        self.rom = start..start + 2 * code.len() as u32;
+        Ok(())
+    }
+
+    // The RAM the last ROM was loaded into, empty before one is.
+    pub fn rom_range(&self) -> Range<u32> {
+        self.rom.clone()
     }
 
     pub fn get_frame(&self) -> &Frame {
         self.framebuffer.get_frame()
     }
+
+    // See Framebuffer::get_pixel().
+    pub fn get_pixel(&self, x: u32, y: u32) -> bool {
+        self.framebuffer.get_pixel(x, y)
+    }
+
+    // See Framebuffer::set_pixel().
+    pub fn set_pixel(&mut self, x: u32, y: u32, on: bool) -> bool {
+        self.framebuffer.set_pixel(x, y, on)
+    }
+
+    // Snapshot of the whole machine: RAM, registers, stack, keys, framebuffer
+    // and RNG state. The profile is not part of the state. Every field has a
+    // fixed width and the multi-byte ones are little-endian, so a snapshot
+    // moves between hosts unchanged.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+
+        out.extend_from_slice(STATE_MAGIC);
+        out.extend(self.ram.as_slice());
+        out.extend(self.regs.vx.iter());
+        out.push(self.regs.dt);
+        out.push(self.regs.st);
+        out.extend_from_slice(&self.regs.i.to_le_bytes());
+        out.extend_from_slice(&self.regs.pc.to_le_bytes());
+        out.push(self.regs.sp);
+        for v in self.stack.iter() {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend(self.keys.to_bytes());
+        for row in self.framebuffer.get_frame().iter() {
+            out.extend(row.iter().map(|&p| p as u8));
+        }
+        let (state, inc) = self.rnd.state();
+        out.extend_from_slice(&state.to_le_bytes());
+        out.extend_from_slice(&inc.to_le_bytes());
+
+        out
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        util::fnv1a64(&self.save_state())
+    }
+
+    // Restores a snapshot made by save_state. The machine is left untouched
+    // if the snapshot is malformed.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), ChipError> {
+        let s = decode_state(state, &self.profile.arch)?;
+
+        self.ram = s.ram;
+        self.regs = s.regs;
+        self.stack = s.stack;
+        self.keys = s.keys;
+        self.framebuffer.set_frame(&s.frame);
+        self.rnd = oorandom::Rand32::from_state(s.rnd);
+        // The instruction count goes on, stepping back and the debugger
+        // count across loads.
+        self.recent_pcs.clear();
+        self.clipped_draws.clear();
+
+        Ok(())
+    }
+
+    pub fn export_state(&self) -> ChipState {
+        let mut stack = [0; arch::STACKSIZE as usize];
+        stack.copy_from_slice(&self.stack[..]);
+        let frame = self.framebuffer.get_frame();
+        ChipState {
+            v: std::array::from_fn(|x| self.regs.vx[x]),
+            i: self.regs.i,
+            pc: self.regs.pc,
+            sp: self.regs.sp,
+            dt: self.regs.dt,
+            st: self.regs.st,
+            stack,
+            keys: self.keys.to_bytes(),
+            ram: self.ram.as_slice().to_vec(),
+            display_width: frame.width(),
+            display_height: frame.height(),
+            pixels: frame.iter().flatten().map(|&p| p as u8).collect(),
+            rng: self.rnd.state(),
+            profile: self.profile,
+        }
+    }
+
+    // Restores an exported state, profile included. Its RAM and screen
+    // sizes must be this machine's and its profile must pass
+    // Profile::check(). The machine is left untouched on errors.
+    pub fn import_state(&mut self, state: ChipState) -> Result<(), ChipError> {
+        let arch = self.profile.arch;
+        let invalid = |message: String| Err(ChipError::InvalidState(message));
+        if state.ram.len() != arch.ram_size as usize {
+            return invalid(format!("RAM of {} bytes, the machine has {}", state.ram.len(), arch.ram_size));
+        }
+        if (state.display_width, state.display_height) != (arch.display_width, arch.display_height)
+            || state.pixels.len() != (arch.display_width * arch.display_height) as usize {
+            return invalid(format!("screen of {}x{}, the machine has {}x{}",
+                state.display_width, state.display_height, arch.display_width, arch.display_height));
+        }
+        if state.profile.arch != arch {
+            return invalid("profile for another machine size".to_string());
+        }
+        state.profile.check()?;
+        if state.sp as u32 > arch::STACKSIZE {
+            return invalid(format!("stack pointer {}", state.sp));
+        }
+
+        self.ram.mem.copy_from_slice(&state.ram);
+        for (x, &v) in state.v.iter().enumerate() {
+            self.regs.vx[x] = v;
+        }
+        self.regs.i = state.i;
+        self.regs.pc = state.pc;
+        self.regs.sp = state.sp;
+        self.regs.dt = state.dt;
+        self.regs.st = state.st;
+        for (n, &addr) in state.stack.iter().enumerate() {
+            self.stack[n] = addr;
+        }
+        self.keys = Keypad::from_bytes(&state.keys);
+        let mut frame = Frame::with_size(arch.display_width, arch.display_height);
+        for (row, pixels) in frame.iter_mut().zip(state.pixels.chunks(arch.display_width as usize)) {
+            for (p, &on) in row.iter_mut().zip(pixels) {
+                *p = on as u32;
+            }
+        }
+        self.framebuffer.set_frame(&frame);
+        self.rnd = oorandom::Rand32::from_state(state.rng);
+        self.profile = state.profile;
+        // Counted by another machine, or under another profile.
+        self.instructions = 0;
+        self.recent_pcs.clear();
+        self.clipped_draws.clear();
+
+        Ok(())
+    }
+
+    // Lists the registers, stack entries, RAM bytes and screen that differ
+    // between two save states, as changes from `a` to `b`. Both must have
+    // the default screen size.
+    pub fn diff_state(a: &[u8], b: &[u8]) -> Result<StateDiff, ChipError> {
+        let mut arch = Arch::chip8();
+        arch.ram_size = state_ram_size(a, &arch).ok_or_else(invalid_state)?;
+        if state_ram_size(b, &arch) != Some(arch.ram_size) {
+            return Err(ChipError::InvalidState("save states have different RAM sizes".to_string()));
+        }
+        let a = decode_state(a, &arch)?;
+        let b = decode_state(b, &arch)?;
+
+        Ok(StateDiff { changes: diff_machine(&a.view(), &b.view(), None) })
+    }
+}
+
+// What Chip::run_frames() ran and left. `frames` counts the frames run to
+// the end, the one stopped in isn't.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    pub frames: u32,
+    pub instructions: u64,
+    pub timer_ticks: u32,
+    pub frame_hash: u64,
+    pub state_hash: u64,
+    pub stop: Option<RunStop>,
+}
+
+// What Chip::run_until() ran. Neither matched nor stopped is the budget
+// spent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UntilResult {
+    pub matched: bool,
+    pub cycles: u64,
+    pub stop: Option<RunStop>,
+}
+
+// Why Chip::run_frames() or Chip::run_until() stopped early.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunStop {
+    // The instruction at `pc` jumps to itself.
+    Halted { pc: u16 },
+    // FX0A at `pc` waits for a key.
+    KeyWait { pc: u16 },
+    Error(ChipError),
+}
+
+// Sets up a Chip, optionally with a ROM loaded at rom::START_ADDR and the PC
+// pointing at it.
+pub struct ChipBuilder {
+    profile: Profile,
+    seed: Option<u64>,
+    rom: Option<Vec<u8>>,
+}
+
+impl ChipBuilder {
+    pub fn new() -> ChipBuilder {
+        ChipBuilder {
+            profile: Profile::modern(),
+            seed: None,
+            rom: None,
+        }
+    }
+
+    pub fn profile(mut self, profile: Profile) -> ChipBuilder {
+        self.profile = profile;
+        self
+    }
+
+    // Random by default.
+    pub fn seed(mut self, seed: u64) -> ChipBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn rom(mut self, rom: &[u8]) -> ChipBuilder {
+        self.rom = Some(rom.to_vec());
+        self
+    }
+
+    // Fails on a profile no machine can have, see Profile::check().
+    pub fn build(self) -> Result<Chip, ChipError> {
+        self.profile.check()?;
+        let mut chip = match self.seed {
+            Some(seed) => Chip::new_seed(seed, self.profile),
+            None => Chip::new(self.profile),
+        };
+        if let Some(rom) = self.rom {
+            chip.load_rom(&rom, rom::START_ADDR)?;
+            chip.set_pc(rom::START_ADDR as u16);
+        }
+        Ok(chip)
+    }
+}
+
+impl Default for ChipBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Everything in a save state after the RAM.
+fn state_tail_size(arch: &Arch) -> usize {
+    arch::NVREGS as usize + 7 + 2 * arch::STACKSIZE as usize + 16
+        + (arch.display_width * arch.display_height) as usize + 16
+}
+
+fn invalid_state() -> ChipError {
+    ChipError::InvalidState("malformed save state".to_string())
+}
+
+// The RAM size of a state saved by a machine with `arch`'s screen.
+fn state_ram_size(state: &[u8], arch: &Arch) -> Option<u32> {
+    state.len().checked_sub(STATE_MAGIC.len() + state_tail_size(arch)).map(|n| n as u32)
+}
+
+struct DecodedState {
+    ram: Ram,
+    regs: RegMap,
+    stack: Stack,
+    keys: Keypad,
+    frame: Frame,
+    rnd: (u64, u64),
+}
+
+impl DecodedState {
+    fn view(&self) -> MachineView<'_> {
+        MachineView {
+            regs: &self.regs,
+            stack: &self.stack,
+            ram: &self.ram,
+            frame: &self.frame,
+        }
+    }
+}
+
+fn decode_state(state: &[u8], arch: &Arch) -> Result<DecodedState, ChipError> {
+    let ram_size = arch.ram_size;
+    let invalid = invalid_state;
+    let mut r = util::ByteReader::new(state);
+
+    if r.bytes(STATE_MAGIC.len()).ok_or_else(invalid)? != STATE_MAGIC {
+        return Err(invalid());
+    }
+
+    let mut ram = Ram::with_size(ram_size);
+    ram.load_block_u8(0, r.bytes(ram_size as usize).ok_or_else(invalid)?)?;
+
+    let mut regs = RegMap::new();
+    for i in 0..arch::NVREGS {
+        regs.vx[i] = r.u8().ok_or_else(invalid)?;
+    }
+    regs.dt = r.u8().ok_or_else(invalid)?;
+    regs.st = r.u8().ok_or_else(invalid)?;
+    regs.i = r.u16().ok_or_else(invalid)?;
+    regs.pc = r.u16().ok_or_else(invalid)?;
+    regs.sp = r.u8().ok_or_else(invalid)?;
+
+    let mut stack = Stack::new();
+    for i in 0..arch::STACKSIZE {
+        stack[i] = r.u16().ok_or_else(invalid)?;
+    }
+
+    let mut key_bytes = [0; 16];
+    for k in key_bytes.iter_mut() {
+        *k = r.u8().ok_or_else(invalid)?;
+    }
+    let keys = Keypad::from_bytes(&key_bytes);
+
+    let mut frame = Frame::with_size(arch.display_width, arch.display_height);
+    for row in frame.iter_mut() {
+        for p in row.iter_mut() {
+            *p = r.u8().ok_or_else(invalid)? as u32;
+        }
+    }
+
+    let rnd_state = r.u64().ok_or_else(invalid)?;
+    let rnd_inc = r.u64().ok_or_else(invalid)?;
+
+    if !r.is_empty() || regs.sp as u32 > arch::STACKSIZE {
+        return Err(invalid());
+    }
+
+    Ok(DecodedState { ram, regs, stack, keys, frame, rnd: (rnd_state, rnd_inc) })
+}
+
+// Register and stack changes from `a` to `b`, see diff_machine().
+pub(crate) fn diff_regs(a: (&RegMap, &[u16]), b: (&RegMap, &[u16]), expected_pc: Option<u16>) -> Vec<StateChange> {
+    let ((a, a_stack), (b, b_stack)) = (a, b);
+    let mut changes = Vec::new();
+    for x in 0..arch::NVREGS as u8 {
+        if a.vx[x] != b.vx[x] {
+            changes.push(StateChange::V { x, old: a.vx[x], new: b.vx[x] });
+        }
+    }
+    if a.i != b.i {
+        changes.push(StateChange::I { old: a.i, new: b.i });
+    }
+    if b.pc != expected_pc.unwrap_or(a.pc) {
+        changes.push(StateChange::Pc { old: a.pc, new: b.pc });
+    }
+    if a.sp != b.sp {
+        changes.push(StateChange::Sp { old: a.sp, new: b.sp });
+    }
+    if a.dt != b.dt {
+        changes.push(StateChange::Dt { old: a.dt, new: b.dt });
+    }
+    if a.st != b.st {
+        changes.push(StateChange::St { old: a.st, new: b.st });
+    }
+    for (level, (&old, &new)) in a_stack.iter().zip(b_stack).enumerate() {
+        if old != new {
+            changes.push(StateChange::Stack { level: level as u8, old, new });
+        }
+    }
+    changes
+}
+
+// The parts of the machine compared by step() and diff_state().
+struct MachineView<'a> {
+    regs: &'a RegMap,
+    stack: &'a Stack,
+    ram: &'a Ram,
+    frame: &'a Frame,
+}
+
+// Changes from `a` to `b`. With `expected_pc` the PC is reported whenever it
+// differs from it instead, so a step that jumps to itself still shows up.
+fn diff_machine(a: &MachineView, b: &MachineView, expected_pc: Option<u16>) -> Vec<StateChange> {
+    let mut changes = diff_regs((a.regs, a.stack), (b.regs, b.stack), expected_pc);
+    for (addr, (&old, &new)) in a.ram.as_slice().iter().zip(b.ram.as_slice()).enumerate() {
+        if old != new {
+            changes.push(StateChange::Mem { addr: addr as u16, old, new });
+        }
+    }
+    if a.frame.iter().zip(b.frame.iter()).any(|(a, b)| a.iter().ne(b.iter())) {
+        changes.push(StateChange::Screen);
+    }
+    changes
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Chip;
+    use super::{Chip, RunStop, UntilResult};
     use super::Sprite;
-    use super::Profile;
+    use super::{PcOverflow, Profile};
+    use crate::profile::KeyWait;
+    use crate::profile::ProfileBuilder;
+    use crate::error::ChipError;
+    use crate::rom;
+    use crate::snapshot::assert_frame_snapshot;
 
     fn run_code(chip: &mut Chip, code: &[u16]) {
-        chip.ram.load_block_u16(0x200, &code);
+        chip.ram.load_block_u16(0x200, code).unwrap();
         chip.set_pc(0x200);
         for _ in code {
-            chip.cycle();
+            chip.cycle().unwrap();
         }
     }
 
@@ -510,6 +1326,710 @@ mod tests {
         let _ = Chip::new(Profile::original());
     }
 
+    #[test]
+    fn collision_count() {
+        let mut chip = Chip::new(Profile::modern());
+        run_code(&mut chip, &[
+            0xA000_u16, // LD I, 0x000
+            0xD015_u16, // DRW V0, V1, 5
+            0xD015_u16, // DRW V0, V1, 5
+            0xD015_u16, // DRW V0, V1, 5
+            0xD015_u16, // DRW V0, V1, 5
+            0xD015_u16, // DRW V0, V1, 5
+        ]);
+        // Every second draw erases the first.
+        assert_eq!(chip.collision_count(), 2);
+        assert!(chip.take_collision());
+        assert_eq!(chip.collision_count(), 2);
+        chip.reset_collision_count();
+        assert_eq!(chip.collision_count(), 0);
+        run_code(&mut chip, &[0xD015_u16]); // DRW V0, V1, 5
+        assert_eq!(chip.collision_count(), 1);
+    }
+
+    #[test]
+    fn drw_snapshot() {
+        let mut chip = Chip::new(Profile::modern());
+        run_code(&mut chip, &[
+            0x6000_u16, // LD V0, 0x0
+            0xF029_u16, // LD F, V0
+            0x6102_u16, // LD V1, 2
+            0x6202_u16, // LD V2, 2
+            0xD125_u16, // DRW V1, V2, 5
+            0x600A_u16, // LD V0, 0xA
+            0xF029_u16, // LD F, V0
+            0x613E_u16, // LD V1, 62
+            0xD125_u16, // DRW V1, V2, 5
+            0x6144_u16, // LD V1, 68
+            0x621E_u16, // LD V2, 30
+            0xD125_u16, // DRW V1, V2, 5
+            0x6008_u16, // LD V0, 0x8
+            0xF029_u16, // LD F, V0
+            0x6104_u16, // LD V1, 4
+            0x6204_u16, // LD V2, 4
+            0xD125_u16, // DRW V1, V2, 5
+        ]);
+        // The "0"; the "A" cut by the right edge; the "A" again with its
+        // origin wrapped to x 4, cut by the bottom edge; an "8" XORed over
+        // the "0".
+        assert_frame_snapshot!(chip, "snapshots/drw.txt");
+    }
+
+    #[test]
+    fn ibm_snapshot() {
+        let mut chip = Chip::new(Profile::modern());
+        rom::start(&mut chip, std::path::Path::new("rom/tests/ibm.ch8"), &[]).unwrap();
+        let summary = chip.run_frames(20, 10);
+        assert_eq!(summary.stop, Some(RunStop::Halted { pc: 0x228 }));
+        assert_frame_snapshot!(chip, "snapshots/ibm_after_200.txt");
+    }
+
+    #[test]
+    fn reset_0() {
+        let mut chip = Chip::new(Profile::original());
+
+        run_code(&mut chip, &[
+            0x6222_u16, // LD V2, 0x22
+            0xA300_u16, // LD I, 0x300
+            0xD225_u16, // DRW V2, V2, 5
+        ]);
+        chip.reset();
+
+        assert_eq!(chip.regs.vx[2], 0_u8);
+        assert_eq!(chip.regs.i, 0_u16);
+        assert_eq!(chip.regs.pc, 0_u16);
+        assert_eq!(chip.ram.read_u16(0x200).unwrap(), 0_u16);
+        assert_eq!(chip.sprite_addr[1], 5_u16);
+        assert!(chip.get_frame().iter().all(|row| row.iter().all(|&p| p == 0)));
+        assert!(chip.profile.op_8xy6_use_vy);
+    }
+
+    #[test]
+    fn save_load_state_0() {
+        let mut chip = Chip::new(Profile::original());
+
+        run_code(&mut chip, &[
+            0x6222_u16, // LD V2, 0x22
+            0xA300_u16, // LD I, 0x300
+            0xD225_u16, // DRW V2, V2, 5
+            0x2300_u16, // CALL 0x300
+        ]);
+        chip.key_press(0x5);
+        let state = chip.save_state();
+
+        let mut other = Chip::new(Profile::original());
+        other.load_state(&state).unwrap();
+
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.regs.vx[2], 0x22_u8);
+        assert_eq!(other.regs.pc, 0x300_u16);
+        assert_eq!(other.regs.sp, 1_u8);
+        assert!(other.keypad().is_down(0x5));
+
+        // Both machines draw the same random numbers from here on.
+        run_code(&mut chip, &[0xC0FF_u16]); // RND V0, 0xFF
+        run_code(&mut other, &[0xC0FF_u16]); // RND V0, 0xFF
+        assert_eq!(chip.regs.vx[0], other.regs.vx[0]);
+    }
+
+    #[test]
+    fn builder() {
+        let chip = Chip::builder().profile(Profile::original()).seed(3).rom(&[0x60, 0x2A]).build().unwrap();
+        assert_eq!(chip.pc(), 0x200);
+        assert_eq!(chip.ram_slice(0x200..0x202), Some(&[0x60, 0x2A][..]));
+        assert_eq!(chip.profile(), &Profile::original());
+        assert_eq!(chip.save_state(), Chip::builder().profile(Profile::original()).seed(3).rom(&[0x60, 0x2A]).build().unwrap().save_state());
+
+        let too_large = vec![0; 0x1000];
+        assert!(matches!(Chip::builder().rom(&too_large).build(), Err(ChipError::RomTooLarge { .. })));
+    }
+
+    #[test]
+    fn accessors() {
+        let mut chip = Chip::builder().seed(0).rom(&[
+            0x6A, 0x05, // LD VA, 0x05
+            0xA3, 0x00, // LD I, 0x300
+            0xFA, 0x15, // LD DT, VA
+            0x23, 0x00, // CALL 0x300
+        ]).build().unwrap();
+        assert_eq!(chip.run_frames(1, 4).stop, None);
+
+        assert_eq!(chip.v(0xA), 0x05);
+        assert_eq!(chip.i(), 0x300);
+        assert_eq!(chip.dt(), 0x05);
+        assert_eq!(chip.st(), 0);
+        assert_eq!((chip.pc(), chip.sp()), (0x300, 1));
+        assert_eq!(chip.stack()[0], 0x208);
+        assert_eq!(chip.stack().len(), 16);
+        assert_eq!(chip.ram_size(), 0x1000);
+        assert_eq!(chip.ram_slice(0xFFE..0x1000), Some(&[0, 0][..]));
+        assert_eq!(chip.ram_slice(0xFFE..0x1001), None);
+    }
+
+    #[test]
+    fn pc_overflow() {
+        use crate::arch::Arch;
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+
+        // Strict: the instruction doesn't run.
+        let mut chip = Chip::new_seed(0, Profile::builder().arch(arch).build());
+        chip.ram.write_u16(0xFFFE, 0x6005).unwrap(); // LD V0, 0x05
+        chip.set_pc(0xFFFE);
+        assert_eq!(chip.cycle(), Err(ChipError::PcOutOfBounds { pc: 0xFFFE }));
+        assert_eq!(chip.regs.pc, 0xFFFE);
+        assert_eq!(chip.regs.vx[0_u8], 0);
+
+        // Lenient: it runs and PC wraps to 0.
+        let mut chip = Chip::new_seed(0, Profile::builder().arch(arch).pc_overflow(PcOverflow::Wrap).build());
+        chip.ram.write_u16(0xFFFE, 0x6005).unwrap(); // LD V0, 0x05
+        chip.set_pc(0xFFFE);
+        chip.cycle().unwrap();
+        assert_eq!(chip.regs.pc, 0);
+        assert_eq!(chip.regs.vx[0_u8], 5);
+
+        // Skips and JP V0 past the end of a 4K RAM.
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.ram.write_u16(0xFFC, 0x3000).unwrap(); // SE V0, 0x00
+        chip.set_pc(0xFFC);
+        assert_eq!(chip.cycle(), Err(ChipError::PcOutOfBounds { pc: 0xFFC }));
+        assert_eq!(chip.regs.pc, 0xFFC);
+
+        let mut chip = Chip::new_seed(0, Profile::builder().pc_overflow(PcOverflow::Wrap).build());
+        chip.regs.vx[0_u8] = 0x10;
+        chip.ram.write_u16(0x200, 0xBFF8).unwrap(); // JP V0, 0xFF8
+        chip.set_pc(0x200);
+        chip.cycle().unwrap();
+        assert_eq!(chip.regs.pc, 0x008);
+    }
+
+    #[test]
+    fn execute_opcode() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.set_pc(0x200);
+
+        chip.execute_opcode(0x6A05).unwrap(); // LD VA, 0x05
+        assert_eq!(chip.regs.vx[0xA_u8], 0x05);
+        assert_eq!(chip.regs.pc, 0x202);
+        // RAM was not involved.
+        assert_eq!(chip.ram.read_u16(0x200), Ok(0));
+
+        assert_eq!(chip.execute_opcode(0x00EE), Err(ChipError::StackUnderflow { pc: 0x202 }));
+        assert_eq!(chip.regs.pc, 0x202);
+    }
+
+    #[test]
+    fn nop() {
+        let profile = ProfileBuilder::new(Profile::modern()).nop(Some(0x0000)).build();
+        let mut chip = Chip::builder().profile(profile).rom(&[0x00, 0x00, 0x00, 0x00]).build().unwrap();
+        let before = chip.save_state();
+
+        let step = chip.step().unwrap();
+        assert_eq!(step.description, "no-op");
+        assert!(step.changes.is_empty());
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.instructions(), 1);
+
+        // Only PC and the instruction count differ.
+        let diff = Chip::diff_state(&before, &chip.save_state()).unwrap();
+        assert_eq!(diff.to_string(), "PC: 0x200 -> 0x202\n");
+
+        // Without it 0000 is still unknown.
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x00, 0x00]).build().unwrap();
+        assert!(chip.cycle().is_err());
+    }
+
+    #[test]
+    fn recent_pcs() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.set_pc(0x200);
+        for _ in 0..40 {
+            chip.execute_opcode(0x7001).unwrap(); // ADD V0, 0x1
+        }
+        // The last 32, failed instructions left out.
+        assert!(chip.execute_opcode(0x00EE).is_err());
+        let recent = chip.recent_pcs();
+        assert_eq!(recent.len(), 32);
+        assert_eq!((recent[0], recent[31]), (0x210, 0x24E));
+
+        chip.reset();
+        assert!(chip.recent_pcs().is_empty());
+    }
+
+    #[test]
+    fn max_address() {
+        use crate::arch::Arch;
+        use crate::profile::ProfileBuilder;
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        let code = [
+            0xAFFF_u16, // LD I, 0xFFF
+            0xF165_u16, // LD V1, [I]
+        ];
+
+        // The original profile stays in the first 4K on a 64K machine.
+        let mut chip = Chip::new_seed(0, ProfileBuilder::new(Profile::original()).arch(arch).build());
+        chip.ram.load_block_u16(0x200, &code).unwrap();
+        chip.set_pc(0x200);
+        chip.cycle().unwrap();
+        assert_eq!(chip.cycle(), Err(ChipError::RangeOutOfBounds {
+            pc: 0x202, instr: "LD V1, [I]".to_string(), start: 0xFFF, end: 0x1001, limit: 0x1000,
+        }));
+
+        // XO-CHIP-like, it reads on past 0xFFF.
+        let mut chip = Chip::new_seed(0, Profile::builder().arch(arch).max_address(None).build());
+        chip.ram.write_u8(0x1000, 0xAB).unwrap();
+        run_code(&mut chip, &code);
+        assert_eq!(chip.regs.vx[1_u8], 0xAB);
+    }
+
+    #[test]
+    fn max_sprite_bytes() {
+        use crate::profile::ProfileBuilder;
+        let code = [
+            0xA300_u16, // LD I, 0x300
+            0xD005_u16, // DRW V0, V0, 5
+        ];
+        let mut chip = Chip::new_seed(0, ProfileBuilder::new(Profile::modern()).max_sprite_bytes(3).build());
+        chip.ram.load_block_u8(0x300, &[0xFF; 5]).unwrap();
+        run_code(&mut chip, &code);
+        let lit: Vec<bool> = (0..5_u32).map(|y| chip.get_frame()[y][0] != 0).collect();
+        assert_eq!(lit, [true, true, true, false, false]);
+        assert_eq!(chip.draws(), 1);
+        // Warned about once.
+        run_code(&mut chip, &code);
+        assert_eq!((chip.draws(), chip.clipped_draws.len()), (2, 1));
+        // And again after a load.
+        let state = chip.save_state();
+        chip.load_state(&state).unwrap();
+        assert!(chip.clipped_draws.is_empty());
+        assert!(chip.recent_pcs().is_empty());
+        assert_eq!(chip.instructions(), 4);
+
+        // Within the cap the whole sprite is drawn.
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.ram.load_block_u8(0x300, &[0xFF; 5]).unwrap();
+        run_code(&mut chip, &code);
+        assert!((0..5_u32).all(|y| chip.get_frame()[y][0] != 0));
+    }
+
+    #[test]
+    fn invalid_arch() {
+        use crate::arch::Arch;
+        let build = |arch: Arch| Chip::builder().profile(Profile::builder().arch(arch).build()).build().err();
+        let invalid = |message: &str| Some(ChipError::InvalidProfile(message.to_string()));
+
+        assert_eq!(build(Arch { ram_size: 0, ..Arch::chip8() }), invalid("RAM of 0 bytes doesn't hold the font"));
+        assert_eq!(build(Arch { ram_size: 79, ..Arch::chip8() }), invalid("RAM of 79 bytes doesn't hold the font"));
+        assert_eq!(build(Arch { ram_size: 0x20000, ..Arch::chip8() }), invalid("RAM of 0x20000 bytes is past 16-bit addresses"));
+        assert_eq!(build(Arch { display_width: 0, ..Arch::chip8() }),
+            invalid("a screen of 0 by 32 pixels is smaller than a glyph"));
+        assert_eq!(build(Arch { display_height: 4, ..Arch::chip8() }),
+            invalid("a screen of 64 by 4 pixels is smaller than a glyph"));
+
+        // The limits themselves are fine.
+        assert_eq!(build(Arch { ram_size: 80, display_width: 4, display_height: 5 }), None);
+        assert_eq!(build(Arch { ram_size: 0x10000, ..Arch::chip8() }), None);
+    }
+
+    #[test]
+    fn arch_sizes() {
+        use crate::arch::Arch;
+        let mut profile = Profile::modern();
+        profile.arch = Arch { ram_size: 0x10000, display_width: 128, display_height: 64 };
+        let mut chip = Chip::new_seed(0, profile);
+
+        assert_eq!(chip.ram().size(), 0x10000);
+        assert_eq!((chip.get_frame().width(), chip.get_frame().height()), (128, 64));
+
+        run_code(&mut chip, &[
+            0x6064_u16, // LD V0, 100
+            0x6128_u16, // LD V1, 40
+            0xA000_u16, // LD I, 0x000
+            0xD015_u16, // DRW V0, V1, 5
+        ]);
+        // No wrap at x = 100 and y = 40 on the larger screen. The top row of
+        // "0" is 0b01100000.
+        assert_eq!(chip.get_frame()[40_u32][101], 1);
+        chip.ram.write_u8(0xFFFF, 0xAB).unwrap();
+
+        let state = chip.save_state();
+        let mut other = Chip::new_seed(0, profile);
+        other.load_state(&state).unwrap();
+        assert_eq!(other.get_frame(), chip.get_frame());
+        assert_eq!(other.ram().read_u8(0xFFFF), Ok(0xAB));
+
+        // A state from a different machine doesn't fit.
+        assert!(Chip::new_seed(0, Profile::modern()).load_state(&state).is_err());
+
+        chip.reset();
+        assert_eq!(chip.get_frame().width(), 128);
+    }
+
+    #[test]
+    fn save_load_state_1() {
+        let mut chip = Chip::new(Profile::original());
+        chip.regs.vx[3] = 0x33_u8;
+        let state = chip.save_state();
+
+        assert!(chip.load_state(&state[..state.len() - 1]).is_err());
+        assert!(chip.load_state(&[state.as_slice(), &[0]].concat()).is_err());
+        assert_eq!(chip.load_state(b"junk"), Err(ChipError::InvalidState("malformed save state".to_string())));
+        assert_eq!(chip.regs.vx[3], 0x33_u8);
+    }
+
+    #[test]
+    fn diff_state_0() {
+        use super::StateChange;
+        let mut chip = Chip::new(Profile::original());
+        let a = chip.save_state();
+
+        chip.regs.vx[5] = 0x55_u8;
+        chip.ram.write_u8(0x345, 0xAB).unwrap();
+        let b = chip.save_state();
+
+        let diff = Chip::diff_state(&a, &b).unwrap();
+        assert_eq!(diff.changes, vec![
+            StateChange::V { x: 0x5, old: 0x00, new: 0x55 },
+            StateChange::Mem { addr: 0x345, old: 0x00, new: 0xAB },
+        ]);
+        assert_eq!(diff.to_string(), "V5: 0x00 -> 0x55\n[0x345]: 0x00 -> 0xab\n");
+
+        assert!(Chip::diff_state(&a, &a).unwrap().is_empty());
+        assert!(Chip::diff_state(&a, &b[..b.len() - 1]).is_err());
+        assert!(Chip::diff_state(b"junk", b"junk").is_err());
+    }
+
+    #[test]
+    fn step_0() {
+        use super::StateChange;
+        let mut chip = Chip::new(Profile::original());
+
+        chip.regs.vx[2] = 0x22_u8;
+        chip.ram.load_block_u16(0x200, &[
+            0x3222_u16, // SE V2, 0x22
+            0x0000_u16,
+            0x8234_u16, // ADD V2, V3
+            0xA300_u16, // LD I, 0x300
+            0xF255_u16, // LD [I], V2
+        ]).unwrap();
+        chip.set_pc(0x200);
+
+        let r = chip.step().unwrap();
+        assert_eq!(r.pc, 0x200);
+        assert_eq!(r.opcode, 0x3222);
+        assert_eq!(r.mnemonic, "SE V2, 0x22");
+        assert_eq!(r.changes, vec![StateChange::Pc { old: 0x200, new: 0x204 }]);
+
+        chip.regs.vx[3] = 0xF0_u8;
+        let r = chip.step().unwrap();
+        assert_eq!(r.changes, vec![
+            StateChange::V { x: 0x2, old: 0x22, new: 0x12 },
+            StateChange::V { x: 0xF, old: 0x00, new: 0x01 },
+        ]);
+
+        chip.step().unwrap();
+        chip.regs.vx[1] = 0xF0_u8;
+        let r = chip.step().unwrap();
+        assert_eq!(r.changes, vec![
+            StateChange::I { old: 0x300, new: 0x303 },
+            StateChange::Mem { addr: 0x301, old: 0x00, new: 0xF0 },
+            StateChange::Mem { addr: 0x302, old: 0x00, new: 0x12 },
+        ]);
+    }
+
+    #[test]
+    fn step_writes() {
+        use super::StateChange;
+        let profile = ProfileBuilder::new(Profile::original()).stack_in_ram(Some(0xEA0)).build();
+        let mut chip = Chip::new_seed(0, profile);
+        chip.regs.vx[0] = 123_u8;
+        chip.regs.vx[1] = 30_u8;
+        chip.ram.load_block_u16(0x200, &[
+            0xA300_u16, // LD I, 0x300
+            0xF033_u16, // LD B, V0
+            0xD115_u16, // DRW V1, V1, 5
+            0x00E0_u16, // CLS
+            0x00E0_u16, // CLS
+            0x2400_u16, // CALL 0x400
+        ]).unwrap();
+        chip.set_pc(0x200);
+
+        chip.step().unwrap();
+        let r = chip.step().unwrap();
+        assert_eq!(r.changes, vec![
+            StateChange::Mem { addr: 0x300, old: 0x00, new: 1 },
+            StateChange::Mem { addr: 0x301, old: 0x00, new: 2 },
+            StateChange::Mem { addr: 0x302, old: 0x00, new: 3 },
+        ]);
+        // Clipped at the bottom, two rows on screen.
+        assert_eq!(chip.step().unwrap().changes, vec![StateChange::Screen]);
+        assert_eq!(chip.step().unwrap().changes, vec![StateChange::Screen]);
+        // Nothing left to clear.
+        assert!(chip.step().unwrap().changes.is_empty());
+        let r = chip.step().unwrap();
+        assert_eq!(r.changes[3..], [
+            StateChange::Mem { addr: 0xEA0, old: 0x00, new: 0x02 },
+            StateChange::Mem { addr: 0xEA1, old: 0x00, new: 0x0C },
+        ]);
+    }
+
+    #[test]
+    fn instructions_0() {
+        let mut chip = Chip::new(Profile::original());
+        chip.ram.load_block_u16(0x200, &[
+            0x6001_u16, // LD V0, 0x01
+            0xF10A_u16, // LD V1, K
+        ]).unwrap();
+        chip.set_pc(0x200);
+
+        chip.cycle().unwrap();
+        chip.cycle().unwrap();
+        chip.cycle().unwrap();
+        assert_eq!(chip.instructions(), 1);
+        assert_eq!(chip.regs.pc, 0x202);
+
+        chip.key_press(0x5);
+        chip.cycle().unwrap();
+        assert_eq!(chip.instructions(), 2);
+        assert_eq!(chip.regs.vx[1], 0x5_u8);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.ram.load_block_u16(0x200, &[0x6001_u16, 0x5121_u16]).unwrap();
+        chip.set_pc(0x200);
+
+        chip.cycle().unwrap();
+        assert_eq!(chip.cycle(), Err(ChipError::UnknownOpcode { pc: 0x202, opcode: 0x5121 }));
+        assert_eq!(chip.regs.pc, 0x202);
+        assert_eq!(chip.instructions(), 1);
+        assert_eq!(chip.step().err(), Some(ChipError::UnknownOpcode { pc: 0x202, opcode: 0x5121 }));
+    }
+
+    #[test]
+    fn run_frames() {
+        let mut chip = Chip::builder().seed(0).rom(&[
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x00, // JP 0x200
+        ]).build().unwrap();
+        chip.set_dt(10);
+
+        let summary = chip.run_frames(3, 7);
+        assert_eq!((summary.frames, summary.instructions, summary.timer_ticks, summary.stop), (3, 21, 3, None));
+        assert_eq!((chip.v(0), chip.dt(), chip.instructions()), (11, 7, 21));
+        assert_eq!(summary.state_hash, chip.state_hash());
+        assert_eq!(summary.frame_hash, crate::golden::frame_hash(chip.get_frame()));
+
+        // Nothing to run is nothing changed.
+        let summary = chip.run_frames(0, 7);
+        assert_eq!((summary.frames, summary.instructions, summary.timer_ticks), (0, 0, 0));
+        assert_eq!(summary.state_hash, chip.state_hash());
+    }
+
+    #[test]
+    fn run_frames_stops() {
+        // FX0A in the middle of the second frame.
+        let mut chip = Chip::builder().seed(0).rom(&[
+            0x70, 0x01, // ADD V0, 1
+            0x70, 0x01, // ADD V0, 1
+            0x70, 0x01, // ADD V0, 1
+            0xF1, 0x0A, // LD V1, K
+            0x12, 0x08, // JP 0x208
+        ]).build().unwrap();
+        let summary = chip.run_frames(5, 2);
+        assert_eq!((summary.frames, summary.instructions, summary.timer_ticks), (1, 3, 2));
+        assert_eq!(summary.stop, Some(RunStop::KeyWait { pc: 0x206 }));
+        assert_eq!(chip.pc(), 0x206);
+
+        // Halted at 0x208 once a key is down, before running the JP.
+        chip.key_press(0x4);
+        let summary = chip.run_frames(5, 2);
+        assert_eq!((summary.frames, summary.instructions, summary.timer_ticks), (0, 1, 1));
+        assert_eq!(summary.stop, Some(RunStop::Halted { pc: 0x208 }));
+        assert_eq!(chip.v(1), 0x4);
+
+        let mut chip = Chip::builder().seed(0).rom(&[0x00, 0xEE]).build().unwrap(); // RET
+        let summary = chip.run_frames(2, 2);
+        assert_eq!(summary.stop, Some(RunStop::Error(ChipError::StackUnderflow { pc: 0x200 })));
+        assert_eq!((summary.frames, summary.instructions), (0, 0));
+    }
+
+    #[test]
+    fn run_until() {
+        let rom = [
+            0x70, 0x01, // ADD V0, 1
+            0x30, 0x05, // SE V0, 5
+            0x12, 0x00, // JP 0x200
+            0x12, 0x06, // JP 0x206
+        ];
+        let mut chip = Chip::builder().seed(0).rom(&rom).build().unwrap();
+        chip.set_dt(3);
+        assert_eq!(chip.run_until(100, |c| c.v(0) == 3), UntilResult { matched: true, cycles: 7, stop: None });
+        // Evaluated after each instruction, not before the first, and the
+        // timers are left alone.
+        assert_eq!(chip.run_until(100, |_| true), UntilResult { matched: true, cycles: 1, stop: None });
+        assert_eq!(chip.dt(), 3);
+
+        // Out of budget.
+        let mut chip = Chip::builder().seed(0).rom(&rom).build().unwrap();
+        assert_eq!(chip.run_until(4, |c| c.v(0) == 3), UntilResult { matched: false, cycles: 4, stop: None });
+
+        // V0 stops at 5 and the ROM halts at 0x206 before it could reach 6.
+        let result = chip.run_until(100, |c| c.v(0) == 6);
+        assert_eq!(result, UntilResult { matched: false, cycles: 10, stop: Some(RunStop::Halted { pc: 0x206 }) });
+    }
+
+    #[test]
+    fn stack_in_ram() {
+        let mut rom = vec![
+            0x23, 0x00, // CALL 0x300
+            0x12, 0x02, // JP 0x202
+        ];
+        rom.resize(0x100, 0);
+        rom.extend_from_slice(&[
+            0x60, 0x04, // LD V0, 0x04
+            0x61, 0x00, // LD V1, 0x00
+            0xAE, 0xA0, // LD I, 0xEA0
+            0xF1, 0x55, // LD [I], V1
+            0x00, 0xEE, // RET
+        ]);
+
+        // Overwriting the return address sends RET elsewhere.
+        let profile = Profile::builder().stack_in_ram(Some(0xEA0)).build();
+        let mut chip = Chip::builder().profile(profile).rom(&rom).build().unwrap();
+        chip.cycle().unwrap();
+        assert_eq!(chip.ram_slice(0xEA0..0xEA2), Some(&[0x02, 0x02][..]));
+        assert_eq!(chip.call_stack(), [0x202]);
+        for _ in 0..4 {
+            chip.cycle().unwrap();
+        }
+        assert_eq!(chip.call_stack(), [0x400]);
+        chip.cycle().unwrap();
+        assert_eq!((chip.pc(), chip.sp()), (0x400, 0));
+
+        // Apart from RAM, the same write doesn't reach the stack.
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&rom).build().unwrap();
+        assert_eq!(chip.run_until(10, |c| c.sp() == 0).cycles, 6);
+        assert_eq!(chip.pc(), 0x202);
+
+        // Nor does it past the end of RAM. The builder won't make such a
+        // machine.
+        let profile = Profile::builder().stack_in_ram(Some(0xFFE)).build();
+        assert!(Chip::builder().profile(profile).build().is_err());
+        let mut chip = Chip::new_seed(0, profile);
+        chip.load_rom(&[0x22, 0x00], 0x200).unwrap(); // CALL 0x200
+        chip.set_pc(0x200);
+        chip.cycle().unwrap();
+        assert_eq!(chip.cycle(), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+        assert_eq!((chip.pc(), chip.sp()), (0x200, 1));
+
+        // Nor past max_address, where the RAM goes on.
+        let arch = crate::arch::Arch { ram_size: 0x10000, ..crate::arch::Arch::chip8() };
+        let profile = Profile::builder().arch(arch).stack_in_ram(Some(0xFFE)).build();
+        let mut chip = Chip::new_seed(0, profile);
+        chip.load_rom(&[0x22, 0x00], 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip.cycle().unwrap();
+        assert_eq!(chip.cycle(), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+        assert_eq!(chip.ram_slice(0x1000..0x1002), Some(&[0, 0][..]));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut chip = Chip::new(Profile::modern());
+        // CALL 0x200, recursing forever.
+        chip.ram.load_block_u16(0x200, &[0x2200_u16]).unwrap();
+        chip.set_pc(0x200);
+
+        let summary = chip.run_frames(1, 17);
+        assert_eq!(summary.stop, Some(RunStop::Error(ChipError::StackOverflow { pc: 0x200 })));
+        assert_eq!(summary.instructions, 16);
+        assert_eq!(chip.regs.sp, 16_u8);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.ram.load_block_u16(0x300, &[0x00EE_u16]).unwrap();
+        chip.set_pc(0x300);
+
+        assert_eq!(chip.cycle(), Err(ChipError::StackUnderflow { pc: 0x300 }));
+        assert_eq!(chip.regs.sp, 0_u8);
+        assert_eq!(chip.regs.pc, 0x300);
+    }
+
+    #[test]
+    fn ram_out_of_bounds() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.ram.load_block_u16(0x200, &[
+            0xAFFE_u16, // LD I, 0xFFE
+            0xF255_u16, // LD [I], V2
+            0xF265_u16, // LD V2, [I]
+            0xF233_u16, // LD B, V2
+            0xD015_u16, // DRW V0, V1, 5
+        ]).unwrap();
+        chip.set_pc(0x200);
+        chip.cycle().unwrap();
+
+        for (pc, instr, end) in [(0x202, "LD [I], V2", 0x1001), (0x204, "LD V2, [I]", 0x1001), (0x206, "LD B, V2", 0x1001)] {
+            chip.set_pc(pc);
+            let e = ChipError::RangeOutOfBounds { pc, instr: instr.to_string(), start: 0xFFE, end, limit: 0x1000 };
+            assert_eq!(chip.cycle(), Err(e));
+            assert_eq!(chip.regs.pc, pc);
+        }
+        chip.set_pc(0x208);
+        assert_eq!(chip.cycle(), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+
+        // Fetching past the end fails too.
+        chip.set_pc(0xFFF);
+        assert_eq!(chip.cycle(), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+        assert!(!chip.is_halted());
+        assert_eq!(chip.poke(0xFFF, &[1, 2]), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+    }
+
+    // A range running past the end fails before any byte of it is read or
+    // written, and leaves I where it was.
+    #[test]
+    fn block_range_near_ram_end() {
+        use crate::arch::Arch;
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        for (profile, end) in [(Profile::modern(), 0x1000_u32), (Profile::builder().arch(arch).max_address(None).build(), 0x10000)] {
+            let mut chip = Chip::new_seed(0, profile);
+            for (n, v) in (0..16).zip(0xA0..) {
+                chip.set_v(n, v);
+            }
+            let ram = |chip: &Chip| chip.ram.read_block(end - 4, 4).unwrap().to_vec();
+            let before = ram(&chip);
+            for (opcode, instr, len) in [(0xF355_u16, "LD [I], V3", 4), (0xF465, "LD V4, [I]", 5), (0xF033, "LD B, V0", 3)] {
+                let start = end + 2 - len;
+                chip.set_i(start as u16);
+                chip.set_pc(0x200);
+                let e = ChipError::RangeOutOfBounds { pc: 0x200, instr: instr.to_string(), start, end: end + 2, limit: end };
+                assert_eq!(chip.execute_opcode(opcode), Err(e));
+                assert_eq!(ram(&chip), before);
+                assert_eq!((chip.v(0), chip.v(4), chip.i()), (0xA0, 0xA4, start as u16));
+            }
+
+            // Up to the last byte is fine.
+            chip.set_i((end - 4) as u16);
+            chip.execute_opcode(0xF355).unwrap();
+            assert_eq!(ram(&chip), [0xA0, 0xA1, 0xA2, 0xA3]);
+            chip.set_i((end - 3) as u16);
+            chip.execute_opcode(0xF033).unwrap(); // 160
+            chip.set_i((end - 3) as u16);
+            chip.execute_opcode(0xF265).unwrap();
+            assert_eq!((chip.v(0), chip.v(1), chip.v(2)), (1, 6, 0));
+        }
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut chip = Chip::new(Profile::modern());
+        let rom = vec![0_u8; 0xE01];
+        assert_eq!(chip.load_rom(&rom, 0x200), Err(ChipError::RomTooLarge { size: 0xE01, max: 0xE00 }));
+        assert_eq!(chip.load_rom(&rom[..0xE00], 0x200), Ok(()));
+    }
+
     #[test]
     fn set_pc() {
         let mut chip = Chip::new(Profile::original());
@@ -521,10 +2041,10 @@ mod tests {
     fn one_instr_pc() {
         let mut chip = Chip::new(Profile::original());
         let code  = [ 0x00E0_u16 ];
-        chip.ram.load_block_u16(0x200, &code);
+        chip.ram.load_block_u16(0x200, &code).unwrap();
         chip.set_pc(0x200);
         assert_eq!(chip.regs.pc, 0x200);
-        chip.cycle();
+        chip.cycle().unwrap();
         assert_eq!(chip.regs.pc, 0x202);
     }
 
@@ -532,18 +2052,18 @@ mod tests {
     fn few_instr_pc() {
         let mut chip = Chip::new(Profile::original());
         let code  = [ 0x00E0_u16, 0x00E0_u16 ];
-        chip.ram.load_block_u16(0x200, &code);
+        chip.ram.load_block_u16(0x200, &code).unwrap();
         chip.set_pc(0x200);
         assert_eq!(chip.regs.pc, 0x200);
-        chip.cycle();
+        chip.cycle().unwrap();
         assert_eq!(chip.regs.pc, 0x202);
-        chip.cycle();
+        chip.cycle().unwrap();
         assert_eq!(chip.regs.pc, 0x204);
 
         chip.set_pc(0x200);
         assert_eq!(chip.regs.pc, 0x200);
         for _ in code {
-            chip.cycle();
+            chip.cycle().unwrap();
         }
         assert_eq!(chip.regs.pc, (0x200 + code.len() * 2) as u16);
 
@@ -837,6 +2357,36 @@ mod tests {
         assert_eq!(chip.regs.vx[0xf], 0_u8);
     }
 
+    #[test]
+    fn shr_vx_vy_differ_orig() {
+        let mut chip = Chip::new(Profile::original());
+
+        chip.regs.vx[2] = 0xF0_u8;
+        chip.regs.vx[3] = 0x0F_u8;
+
+        run_code(&mut chip, &[0x8236_u16]); // SHR V2, V3
+
+        // VY shifted, the carry is its bit 0.
+        assert_eq!(chip.regs.vx[2], 0x07_u8);
+        assert_eq!(chip.regs.vx[3], 0x0F_u8);
+        assert_eq!(chip.regs.vx[0xf], 1_u8);
+    }
+
+    #[test]
+    fn shr_vx_vy_differ_modern() {
+        let mut chip = Chip::new(Profile::modern());
+
+        chip.regs.vx[2] = 0xF0_u8;
+        chip.regs.vx[3] = 0x0F_u8;
+
+        run_code(&mut chip, &[0x8236_u16]); // SHR V2, V3
+
+        // VX shifted in place, the carry is its bit 0.
+        assert_eq!(chip.regs.vx[2], 0x78_u8);
+        assert_eq!(chip.regs.vx[3], 0x0F_u8);
+        assert_eq!(chip.regs.vx[0xf], 0_u8);
+    }
+
     #[test]
     fn subn_vx_vy_0() {
         let mut chip = Chip::new(Profile::original());
@@ -914,6 +2464,36 @@ mod tests {
     }
 
 
+    #[test]
+    fn shl_vx_vy_differ_orig() {
+        let mut chip = Chip::new(Profile::original());
+
+        chip.regs.vx[2] = 0x0F_u8;
+        chip.regs.vx[3] = 0xF0_u8;
+
+        run_code(&mut chip, &[0x823E_u16]); // SHL V2, V3
+
+        // VY shifted, the carry is its bit 7.
+        assert_eq!(chip.regs.vx[2], 0xE0_u8);
+        assert_eq!(chip.regs.vx[3], 0xF0_u8);
+        assert_eq!(chip.regs.vx[0xf], 1_u8);
+    }
+
+    #[test]
+    fn shl_vx_vy_differ_modern() {
+        let mut chip = Chip::new(Profile::modern());
+
+        chip.regs.vx[2] = 0x0F_u8;
+        chip.regs.vx[3] = 0xF0_u8;
+
+        run_code(&mut chip, &[0x823E_u16]); // SHL V2, V3
+
+        // VX shifted in place, the carry is its bit 7.
+        assert_eq!(chip.regs.vx[2], 0x1E_u8);
+        assert_eq!(chip.regs.vx[3], 0xF0_u8);
+        assert_eq!(chip.regs.vx[0xf], 0_u8);
+    }
+
     #[test]
     fn sne_vx_vy_0() {
         let mut chip = Chip::new(Profile::original());
@@ -969,6 +2549,18 @@ mod tests {
         assert_eq!(chip.regs.pc, 0x320);
     }
 
+    #[test]
+    fn jp_vx_nnn_chip48() {
+        let mut chip = Chip::new(Profile::chip48());
+
+        chip.regs.vx[0] = 0x20_u8;
+        chip.regs.vx[3] = 0x04_u8;
+
+        run_code(&mut chip, &[0xB300_u16]); // JP V3, 0x300
+
+        assert_eq!(chip.regs.pc, 0x304);
+    }
+
     #[test]
     fn rnd_vx_nn_0() {
         let mut chip = Chip::new_seed(0x0102030405060708, Profile::original());
@@ -1129,26 +2721,49 @@ mod tests {
         let code = [
             0xF20A_u16, // LD V2, K
         ];
-        chip.ram.load_block_u16(0x200, &code);
+        chip.ram.load_block_u16(0x200, &code).unwrap();
         chip.set_pc(0x200);
-        for _ in 0..5 {
-            chip.cycle();
-        }
-
-        assert_eq!(chip.regs.pc, 0x200);
+        let result = chip.run_until(5, |c| c.pc() != 0x200);
+        assert_eq!(result, UntilResult { matched: false, cycles: 0, stop: Some(RunStop::KeyWait { pc: 0x200 }) });
     }
 
     #[test]
     fn ld_vx_k_1() {
-        let mut chip = Chip::new(Profile::original());
+        let mut chip = Chip::builder().profile(Profile::original()).rom(&[0xF2, 0x0A]).build().unwrap(); // LD V2, K
 
         chip.key_press(0xA);
-        run_code(&mut chip, &[0xF20A_u16]); // LD V2, K
+        assert!(chip.run_until(1, |c| c.pc() == 0x202).matched);
 
-        assert_eq!(chip.regs.pc, 0x202);
         assert_eq!(chip.regs.vx[2], 0xA_u8);
     }
 
+    #[test]
+    fn ld_vx_k_policies() {
+        for (wait, key) in [(KeyWait::LowestIndex, 0x2_u8), (KeyWait::MostRecent, 0xC), (KeyWait::FirstPressed, 0x7)] {
+            let profile = ProfileBuilder::new(Profile::original()).key_wait(wait).build();
+            let mut chip = Chip::new(profile);
+            for k in [0x7, 0x2, 0xC] {
+                chip.key_press(k);
+            }
+            // The press order is saved with the keys.
+            let mut loaded = Chip::new(profile);
+            loaded.load_state(&chip.save_state()).unwrap();
+            for mut chip in [chip, loaded] {
+                run_code(&mut chip, &[0xF20A_u16]); // LD V2, K
+                assert_eq!(chip.regs.vx[2], key, "{:?}", wait);
+            }
+        }
+
+        // And hashed with them.
+        let mut a = Chip::new_seed(0, Profile::original());
+        let mut b = Chip::new_seed(0, Profile::original());
+        a.key_press(0x2);
+        a.key_press(0x7);
+        b.key_press(0x7);
+        b.key_press(0x2);
+        assert_ne!(a.state_hash(), b.state_hash());
+    }
+
     #[test]
     fn ld_dt_vx_0() {
         let mut chip = Chip::new(Profile::original());
@@ -1197,6 +2812,19 @@ mod tests {
         assert_eq!(chip.regs.vx[2], 0x22u8);
     }
 
+    // Found by fuzzing: overflowed in debug builds.
+    #[test]
+    fn add_i_vx_wraps() {
+        let mut chip = Chip::new(Profile::original());
+
+        chip.regs.i = 0xFFF0u16;
+        chip.regs.vx[2] = 0x22u8;
+
+        run_code(&mut chip, &[0xF21E_u16]); // ADD I, V2
+
+        assert_eq!(chip.regs.i, 0x0012u16);
+    }
+
     #[test]
     fn ld_f_vx_0() {
         let mut chip = Chip::new(Profile::original());
@@ -1208,9 +2836,9 @@ mod tests {
         assert_eq!(chip.regs.i, chip.sprite_addr[0x03]);
 
         let mut s:Sprite = [0;5];
-        for i in 0usize..5usize {
+        for (i, b) in s.iter_mut().enumerate() {
             let addr: u32 = (chip.regs.i + i as u16) as u32;
-            s[i] = chip.ram.read_u8(addr);
+            *b = chip.ram.read_u8(addr).unwrap();
         }
 
         let expected: Sprite = [
@@ -1224,6 +2852,18 @@ mod tests {
         assert_eq!(s, expected);
     }
 
+    // Found by fuzzing: a digit above 0xF indexed past the font.
+    #[test]
+    fn ld_f_vx_low_nibble() {
+        let mut chip = Chip::new(Profile::original());
+
+        chip.regs.vx[2] = 0x21u8;
+
+        run_code(&mut chip, &[0xF229_u16]); // LD F, V2
+
+        assert_eq!(chip.regs.i, chip.sprite_addr[0x01]);
+    }
+
     #[test]
     fn ld_b_vx_0() {
         let mut chip = Chip::new(Profile::original());
@@ -1292,14 +2932,14 @@ mod tests {
 
         // Registers up to (including) V[idx] copied into the memory from location I.
         for i in 0..=idx {
-            let addr: u32 = i_start as u32 + i as u32;
-            assert_eq!(c.ram.read_u8(addr), c.regs.vx[i]);
+            let addr: u32 = i_start as u32 + i;
+            assert_eq!(c.ram.read_u8(addr).unwrap(), c.regs.vx[i]);
         }
 
         // Registers from V[idx + 1] are not copied into the memory.
         for i in idx+1..NVREGS {
-            let addr: u32 = i_start as u32 + i as u32;
-            assert_eq!(c.ram.read_u8(addr), 0);
+            let addr: u32 = i_start as u32 + i;
+            assert_eq!(c.ram.read_u8(addr).unwrap(), 0);
         }
     }
 
@@ -1313,7 +2953,7 @@ mod tests {
         chip.regs.i = I;
 
         let mut op: u16 = 0xF055_u16;
-        op = op | (i << 8) as u16;
+        op |= (i << 8) as u16;
 
         run_code(&mut chip, &[op]); // LD [I], V2
 
@@ -1375,8 +3015,8 @@ mod tests {
     fn ld_vx_i_init_mem(c: &mut Chip) {
         use crate::arch::NVREGS;
         for i in 0..NVREGS {
-            let addr: u32 = c.regs.i as u32 + i as u32;
-            c.ram.write_u8(addr, (i+1) as u8);
+            let addr: u32 = c.regs.i as u32 + i;
+            c.ram.write_u8(addr, (i+1) as u8).unwrap();
         }
     }
 
@@ -1385,8 +3025,8 @@ mod tests {
 
         // Registers up to (including) V[idx] read from the memory from location I.
         for i in 0..=idx {
-            let addr: u32 = i_start as u32 + i as u32;
-            assert_eq!(c.ram.read_u8(addr), c.regs.vx[i]);
+            let addr: u32 = i_start as u32 + i;
+            assert_eq!(c.ram.read_u8(addr).unwrap(), c.regs.vx[i]);
         }
 
         // Registers from V[idx + 1] are not read into the memory.
@@ -1406,7 +3046,7 @@ mod tests {
         ld_vx_i_init_mem(&mut chip);
 
         let mut op: u16 = 0xF065_u16;
-        op = op | (i << 8) as u16;
+        op |= (i << 8) as u16;
 
         run_code(&mut chip, &[op]); // LD [I], V2
 
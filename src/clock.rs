@@ -0,0 +1,149 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+// Host time for the run loop, in microseconds from whenever the clock
+// started. Frontends hand one out, so that tests can pace the loop with a
+// clock of their own.
+pub trait Clock {
+    fn now_micros(&self) -> u64;
+    // Returns right away when the deadline has passed.
+    fn sleep_until(&self, deadline_micros: u64);
+}
+
+// The clock of std::time::Instant, sleeping the thread.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn sleep_until(&self, deadline_micros: u64) {
+        let now = self.now_micros();
+        if deadline_micros > now {
+            thread::sleep(Duration::from_micros(deadline_micros - now));
+        }
+    }
+}
+
+// Emulated time driven purely by the number of executed instructions.
+// A frame (timer tick) starts every ips/60 instructions.
+pub struct InstrClock {
+    ipf: u64,
+    instructions: u64,
+}
+
+impl InstrClock {
+    pub fn new(ips: u32) -> InstrClock {
+        InstrClock {
+            ipf: (ips as u64 / FRAMES_PER_SECOND).max(1),
+            instructions: 0,
+        }
+    }
+
+    // True when the next instruction starts a new frame.
+    pub fn frame_sync(&self) -> bool {
+        self.instructions.is_multiple_of(self.ipf)
+    }
+
+    pub fn advance(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub fn now_ms(&self) -> u32 {
+        let frames = self.instructions / self.ipf;
+        (frames * 1000 / FRAMES_PER_SECOND) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, InstrClock, SystemClock};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    // Random sprites at random positions, with the delay timer running.
+    const WORKLOAD: [u8; 14] = [
+        0xC0, 0xFF, // RND V0, 0xFF
+        0xC1, 0xFF, // RND V1, 0xFF
+        0xA0, 0x00, // LD I, 0x000
+        0xD0, 0x15, // DRW V0, V1, 5
+        0x62, 0x05, // LD V2, 5
+        0xF2, 0x15, // LD DT, V2
+        0x12, 0x00, // JP 0x200
+    ];
+
+    // Runs `n` instructions the same way the deterministic main loop does.
+    fn run(clock: &mut InstrClock, chip: &mut Chip, n: u64) {
+        for _ in 0..n {
+            if clock.frame_sync() {
+                chip.cycle_timers();
+            }
+            chip.cycle().unwrap();
+            clock.advance();
+        }
+    }
+
+    fn run_workload(seed: u64) -> u64 {
+        let mut chip = Chip::new_seed(seed, Profile::modern());
+        chip.load_rom(&WORKLOAD, 0x200).unwrap();
+        chip.set_pc(0x200);
+
+        let mut clock = InstrClock::new(600);
+        run(&mut clock, &mut chip, 10_000);
+        chip.state_hash()
+    }
+
+    #[test]
+    fn frame_sync() {
+        let mut clock = InstrClock::new(600);
+        let mut frames = 0;
+        for _ in 0..100 {
+            if clock.frame_sync() {
+                frames += 1;
+            }
+            clock.advance();
+        }
+        assert_eq!(frames, 10);
+        assert_eq!(clock.now_ms(), 166);
+    }
+
+    #[test]
+    fn slow_ips() {
+        let clock = InstrClock::new(30);
+        assert_eq!(clock.ipf, 1);
+    }
+
+    #[test]
+    fn same_hash() {
+        assert_eq!(run_workload(0), run_workload(0));
+        assert_eq!(run_workload(0x1234), run_workload(0x1234));
+        assert_ne!(run_workload(0), run_workload(0x1234));
+    }
+
+    #[test]
+    fn system_clock() {
+        let clock = SystemClock::new();
+        let start = clock.now_micros();
+        // A deadline already passed doesn't sleep.
+        clock.sleep_until(0);
+        clock.sleep_until(start + 1000);
+        assert!(clock.now_micros() >= start + 1000);
+    }
+}
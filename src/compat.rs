@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::instr::{Instr, Operation};
+
+// Behavior Chip-8 interpreters disagree on, that a ROM may rely on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quirk {
+    Shift,
+    MemoryIncrement,
+    Jump,
+    DrawWait,
+}
+
+impl Quirk {
+    const ALL: [Quirk; 4] = [Quirk::Shift, Quirk::MemoryIncrement, Quirk::Jump, Quirk::DrawWait];
+
+    // The quirk an instruction depends on, and its opcode pattern.
+    fn of(opcode: u16) -> Option<(Quirk, &'static str)> {
+        match Instr::new(opcode).operation()? {
+            Operation::ShrVxVy { .. } => Some((Quirk::Shift, "8XY6")),
+            Operation::ShlVxVy { .. } => Some((Quirk::Shift, "8XYE")),
+            Operation::LdIVx { .. } => Some((Quirk::MemoryIncrement, "FX55")),
+            Operation::LdVxI { .. } => Some((Quirk::MemoryIncrement, "FX65")),
+            Operation::JpV0Nnn { .. } => Some((Quirk::Jump, "BNNN")),
+            Operation::Drw { .. } => Some((Quirk::DrawWait, "DXYN")),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Quirk::Shift => "shift",
+            Quirk::MemoryIncrement => "memory increment",
+            Quirk::Jump => "jump",
+            Quirk::DrawWait => "draw wait",
+        }
+    }
+
+    // What it means for picking a profile.
+    fn note(self) -> &'static str {
+        match self {
+            Quirk::Shift => "the original and modern profiles differ",
+            Quirk::MemoryIncrement => "the original and modern profiles differ",
+            Quirk::Jump => "the chip48 profile adds VX instead of V0",
+            Quirk::DrawWait => "the original waits for the next frame, not emulated",
+        }
+    }
+}
+
+// Counts the executed instructions whose behavior depends on a quirk, to
+// tell which profile a ROM likely needs.
+#[derive(Clone, Debug, Default)]
+pub struct CompatReport {
+    counts: BTreeMap<(Quirk, &'static str), u64>,
+}
+
+impl CompatReport {
+    pub fn new() -> CompatReport {
+        CompatReport::default()
+    }
+
+    pub fn record(&mut self, opcode: u16) {
+        if let Some(key) = Quirk::of(opcode) {
+            *self.counts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    // The quirks the program ran into.
+    #[cfg(test)]
+    pub fn quirks(&self) -> Vec<Quirk> {
+        let mut quirks: Vec<Quirk> = self.counts.keys().map(|&(q, _)| q).collect();
+        quirks.dedup();
+        quirks
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Compatibility report.")?;
+        for quirk in Quirk::ALL {
+            let used: Vec<String> = self.counts.iter()
+                .filter(|((q, _), _)| *q == quirk)
+                .map(|((_, pattern), n)| format!("{} x{}", pattern, n))
+                .collect();
+            if used.is_empty() {
+                writeln!(f, "{}: not used", quirk.name())?;
+            } else {
+                writeln!(f, "{}: {}, {}", quirk.name(), used.join(", "), quirk.note())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompatReport, Quirk};
+
+    #[test]
+    fn shift_and_memory() {
+        let mut report = CompatReport::new();
+        for opcode in [0x8126, 0x6001, 0xF255, 0x8346, 0x1200] {
+            report.record(opcode);
+        }
+        assert_eq!(report.quirks(), vec![Quirk::Shift, Quirk::MemoryIncrement]);
+        assert_eq!(report.to_string(), concat!(
+            "Compatibility report.\n",
+            "shift: 8XY6 x2, the original and modern profiles differ\n",
+            "memory increment: FX55 x1, the original and modern profiles differ\n",
+            "jump: not used\n",
+            "draw wait: not used\n",
+        ));
+    }
+}
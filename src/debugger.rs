@@ -0,0 +1,908 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+
+use log::info;
+
+use crate::chip::Chip;
+use crate::disasm;
+use crate::history::History;
+use crate::instr::Instr;
+use crate::poke;
+use crate::rom;
+
+// Instructions listed before and after PC by `dis`.
+pub const LISTING_CONTEXT: usize = 8;
+// Addresses listed by `hot` without a count.
+const HOT_ADDRESSES: usize = 10;
+// Bytes per line of `peek`.
+const PEEK_ROW: usize = 16;
+
+// Parses a breakpoint address. Numbers are decimal or 0x-prefixed hex.
+pub fn parse_addr(s: &str) -> Result<u16, String> {
+    match poke::parse_number(s) {
+        Some(addr) if addr <= 0xFFFF => Ok(addr as u16),
+        _ => Err(format!("invalid address '{}'", s.trim())),
+    }
+}
+
+// Parses a watched range, "addr" for one byte or "start..end" with the end
+// excluded.
+pub fn parse_range(s: &str) -> Result<Range<u32>, String> {
+    let range = match s.split_once("..") {
+        Some((start, end)) => {
+            let start = parse_addr(start)? as u32;
+            let end = poke::parse_number(end).filter(|&e| e <= 0x10000).ok_or_else(|| format!("invalid address '{}'", end.trim()))?;
+            start..end
+        },
+        None => {
+            let addr = parse_addr(s)? as u32;
+            addr..addr + 1
+        },
+    };
+    if range.is_empty() {
+        return Err(format!("empty range '{}'", s.trim()));
+    }
+    Ok(range)
+}
+
+// Numbered address breakpoints and write watchpoints, sharing the numbers.
+// Breakpoints are kept sorted by address, the run loop looks one up before
+// every instruction.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    last_id: usize,
+    by_addr: BTreeMap<u16, usize>,
+    watches: Vec<(usize, Range<u32>)>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints::default()
+    }
+
+    // Returns the breakpoint's number, the existing one if `addr` already
+    // has a breakpoint. Numbers start at 1 and aren't reused.
+    pub fn add(&mut self, addr: u16) -> usize {
+        let next = self.last_id + 1;
+        let id = *self.by_addr.entry(addr).or_insert(next);
+        self.last_id = self.last_id.max(id);
+        id
+    }
+
+    pub fn add_watch(&mut self, range: Range<u32>) -> usize {
+        self.last_id += 1;
+        self.watches.push((self.last_id, range));
+        self.last_id
+    }
+
+    // Deletes a breakpoint or watchpoint. Returns false if there is none
+    // with that number.
+    pub fn delete(&mut self, id: usize) -> bool {
+        if let Some(addr) = self.by_addr.iter().find(|(_, &i)| i == id).map(|(&a, _)| a) {
+            self.by_addr.remove(&addr);
+            return true;
+        }
+        let len = self.watches.len();
+        self.watches.retain(|(i, _)| *i != id);
+        self.watches.len() < len
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        self.by_addr.contains_key(&addr)
+    }
+
+    // (number, address) pairs by number.
+    pub fn list(&self) -> Vec<(usize, u16)> {
+        let mut list: Vec<(usize, u16)> = self.by_addr.iter().map(|(&a, &i)| (i, a)).collect();
+        list.sort();
+        list
+    }
+
+    // (number, watched addresses) pairs by number.
+    pub fn watches(&self) -> &[(usize, Range<u32>)] {
+        &self.watches
+    }
+
+    // Breakpoints and watchpoints together, by number.
+    pub fn items(&self) -> Vec<DebugItem> {
+        let mut items: Vec<DebugItem> = self.list().into_iter()
+            .map(|(id, addr)| DebugItem::Breakpoint { id, addr })
+            .chain(self.watches.iter().map(|(id, r)| DebugItem::Watchpoint { id: *id, range: r.clone() }))
+            .collect();
+        items.sort_by_key(DebugItem::id);
+        items
+    }
+}
+
+impl fmt::Display for Breakpoints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let items = self.items();
+        if items.is_empty() {
+            return writeln!(f, "No breakpoints or watchpoints.");
+        }
+        writeln!(f, "Num  Type        Address")?;
+        for item in items {
+            let (kind, addr) = match &item {
+                DebugItem::Breakpoint { addr, .. } => ("breakpoint", format!("{:#05x}", addr)),
+                DebugItem::Watchpoint { range, .. } => ("watchpoint", format!("{:#05x}..{:#05x}", range.start, range.end)),
+            };
+            writeln!(f, "{:<4} {:<11} {}", item.id(), kind, addr)?;
+        }
+        Ok(())
+    }
+}
+
+// Something the debugger stops on, with the number `delete` takes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DebugItem {
+    Breakpoint { id: usize, addr: u16 },
+    // Stops after an instruction that changed a byte in `range`.
+    Watchpoint { id: usize, range: Range<u32> },
+}
+
+impl DebugItem {
+    pub fn id(&self) -> usize {
+        match self {
+            DebugItem::Breakpoint { id, .. } | DebugItem::Watchpoint { id, .. } => *id,
+        }
+    }
+}
+
+// What the debugger is set to stop on, for a frontend to list.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugState {
+    // By number.
+    pub items: Vec<DebugItem>,
+    // Stops before the next instruction.
+    pub stepping: bool,
+}
+
+// A byte a watched range changed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Watchpoint {}: [{:#05x}] {:#04x} -> {:#04x}", self.id, self.addr, self.old, self.new)
+    }
+}
+
+// A register `set` can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+}
+
+impl Register {
+    fn parse(s: &str) -> Result<Register, String> {
+        let name = s.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "dt" => Ok(Register::Dt),
+            "st" => Ok(Register::St),
+            _ => match name.strip_prefix('v').map(|x| u8::from_str_radix(x, 16)) {
+                Some(Ok(x)) if name.len() == 2 => Ok(Register::V(x)),
+                _ => Err(format!("unknown register '{}', expected v0-vf, i, pc, dt or st", s.trim())),
+            },
+        }
+    }
+
+    fn max(self) -> u32 {
+        match self {
+            Register::V(_) | Register::Dt | Register::St => 0xFF,
+            Register::I | Register::Pc => 0xFFFF,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    match poke::parse_number(s) {
+        Some(b) if b <= 0xFF => Ok(b as u8),
+        _ => Err(format!("invalid byte '{}'", s)),
+    }
+}
+
+// Parses "REG = VALUE", with or without spaces around "=".
+fn parse_set(args: &str) -> Result<Command, String> {
+    let (reg, value) = args.split_once('=').ok_or_else(|| "expected set <register> = <value>".to_string())?;
+    let reg = Register::parse(reg)?;
+    match poke::parse_number(value) {
+        Some(v) if v <= reg.max() => Ok(Command::Set(reg, v as u16)),
+        _ => Err(format!("invalid value '{}' for {}", value.trim(), reg)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Break(u16),
+    Watch(Range<u32>),
+    Delete(usize),
+    InfoBreakpoints,
+    Disassemble,
+    Backtrace,
+    // The N most executed addresses.
+    Hot(usize),
+    Step,
+    ReverseStep,
+    History,
+    Continue,
+    Quit,
+    // After an unknown opcode: go past it, or stop with the error.
+    Skip,
+    Abort,
+    Set(Register, u16),
+    Poke(u16, Vec<u8>),
+    // Writes the --poke pokes in again, like F8.
+    ApplyPokes,
+    // Address and byte count.
+    Peek(u16, usize),
+}
+
+// Parses a debugger command line, gdb style: "b 0x240", "watch 0x300..0x310",
+// "delete 1", "info breakpoints", "dis", "bt", "hot 5", "s", "rs",
+// "history", "c", "q", "set v3 = 0x1f", "poke 0x340 0xde 0xad", "poke",
+// "peek 0x340 4", and "skip" or "abort" after an unknown opcode.
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["b" | "break", addr] => parse_addr(addr).map(Command::Break),
+        ["watch", range] => parse_range(range).map(Command::Watch),
+        ["d" | "delete", id] => id.parse().map(Command::Delete).map_err(|_| format!("invalid breakpoint number '{}'", id)),
+        ["i" | "info", "b" | "breakpoints"] => Ok(Command::InfoBreakpoints),
+        ["dis" | "disassemble"] => Ok(Command::Disassemble),
+        ["bt" | "backtrace"] => Ok(Command::Backtrace),
+        ["hot"] => Ok(Command::Hot(HOT_ADDRESSES)),
+        ["hot", n] => n.parse().map(Command::Hot).map_err(|_| format!("invalid count '{}'", n)),
+        ["s" | "step"] => Ok(Command::Step),
+        ["rs" | "reverse-step"] => Ok(Command::ReverseStep),
+        ["history"] => Ok(Command::History),
+        ["c" | "continue"] => Ok(Command::Continue),
+        ["q" | "quit"] => Ok(Command::Quit),
+        ["skip"] => Ok(Command::Skip),
+        ["abort"] => Ok(Command::Abort),
+        ["set", ..] => parse_set(line.trim().trim_start_matches("set")),
+        ["poke"] => Ok(Command::ApplyPokes),
+        ["poke", addr, bytes @ ..] if !bytes.is_empty() => {
+            let bytes = bytes.iter().map(|b| parse_byte(b)).collect::<Result<Vec<u8>, String>>()?;
+            parse_addr(addr).map(|addr| Command::Poke(addr, bytes))
+        },
+        ["peek", addr] => parse_addr(addr).map(|addr| Command::Peek(addr, 1)),
+        ["peek", addr, n] => {
+            let n = n.parse().map_err(|_| format!("invalid count '{}'", n))?;
+            parse_addr(addr).map(|addr| Command::Peek(addr, n))
+        },
+        _ => Err(format!("unknown command '{}'", line.trim())),
+    }
+}
+
+// Where the chip stopped: the next instruction and the registers.
+pub fn location(chip: &Chip) -> String {
+    let pc = chip.pc();
+    let instr = match chip.ram_slice(pc as u32..pc as u32 + 2) {
+        Some(b) => {
+            let opcode = u16::from_be_bytes([b[0], b[1]]);
+            format!("{:04X}  {}", opcode, Instr::new(opcode))
+        },
+        None => "past the end of RAM".to_string(),
+    };
+    let vx: Vec<String> = (0..16).map(|x| format!("V{:X}={:02x}", x, chip.v(x))).collect();
+    format!("Stopped at {:#05x}  {}\n{}\nI={:#05x} SP={} DT={} ST={}\n",
+        pc, instr, vx.join(" "), chip.i(), chip.sp(), chip.dt(), chip.st())
+}
+
+// The disassembly around PC, formatted like the standalone disassembler
+// with "=>" on the PC line and "*" on breakpoints.
+pub fn listing(chip: &Chip, breakpoints: &Breakpoints) -> Vec<String> {
+    let ram = chip.ram_slice(0..chip.ram_size()).unwrap();
+    disasm::around(ram, chip.pc(), &chip.recent_pcs(), LISTING_CONTEXT).iter()
+        .map(|l| {
+            let bp = if l.is_code && breakpoints.contains(l.addr) { '*' } else { ' ' };
+            let at = if l.addr == chip.pc() { "=>" } else { "  " };
+            format!("{}{} {}", bp, at, l)
+        })
+        .collect()
+}
+
+// The active calls from `stack` and `sp`, the latest first, each with the
+// subroutine it is in as `callee` names it for the return address. An SP
+// past the stack is reported and the whole stack listed.
+pub fn backtrace(stack: &[u16], sp: u8, callee: impl Fn(u16) -> Option<String>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if sp == 0 {
+        lines.push("No active calls.".to_string());
+    } else if sp as usize > stack.len() {
+        lines.push(format!("SP is {}, past the {} stack entries.", sp, stack.len()));
+    }
+    let depth = (sp as usize).min(stack.len());
+    for (i, &ret) in stack[..depth].iter().rev().enumerate() {
+        let callee = callee(ret).unwrap_or_else(|| "??".to_string());
+        lines.push(format!("#{:<2} {:<10} returns to {:#05x}", i, callee, ret));
+    }
+    lines
+}
+
+// The last instructions executed before PC, the latest last, with the
+// instructions there now.
+pub fn recent(chip: &Chip, n: usize) -> Vec<String> {
+    let pcs = chip.recent_pcs();
+    pcs[pcs.len().saturating_sub(n)..].iter()
+        .map(|&pc| {
+            let opcode = chip.ram().read_u16(pc as u32).unwrap_or(0);
+            format!("{:#05x}  {:04X}  {}", pc, opcode, Instr::new(opcode))
+        })
+        .collect()
+}
+
+// Sets a register from the debugger. PC has to stay in RAM.
+pub fn set_register(chip: &mut Chip, reg: Register, value: u16) -> Result<(), String> {
+    match reg {
+        Register::V(x) => chip.set_v(x, value as u8),
+        Register::I => chip.set_i(value),
+        Register::Pc if value as u32 >= chip.ram_size() => {
+            return Err(format!("{:#05x} is outside RAM", value));
+        },
+        Register::Pc => chip.set_pc(value),
+        Register::Dt => chip.set_dt(value as u8),
+        Register::St => chip.set_st(value as u8),
+    }
+    Ok(())
+}
+
+// `count` bytes from `addr` in hex, 16 to a line.
+pub fn peek(chip: &Chip, addr: u16, count: usize) -> Result<Vec<String>, String> {
+    let end = addr as u32 + count as u32;
+    let bytes = chip.ram_slice(addr as u32..end).ok_or_else(|| format!("{:#05x}..{:#05x} is outside RAM", addr, end))?;
+    Ok(bytes.chunks(PEEK_ROW).enumerate()
+        .map(|(i, row)| {
+            let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{:#05x}  {}", addr as usize + i * PEEK_ROW, hex.join(" "))
+        })
+        .collect())
+}
+
+// The `n` most executed addresses with their share of all executed
+// instructions and the instruction there now.
+pub fn hot_list(chip: &Chip, n: usize) -> Vec<String> {
+    let heatmap = match chip.heatmap() {
+        Some(h) => h,
+        None => return vec!["No heatmap, run with --profile-out to count executions.".to_string()],
+    };
+    heatmap.hottest(n).into_iter()
+        .map(|(addr, count)| {
+            let opcode = chip.ram().read_u16(addr as u32).unwrap_or(0);
+            let share = 100.0 * count as f64 / heatmap.total() as f64;
+            format!("{:#05x}  {:>10}  {:>5.1}%  {}", addr, count, share, Instr::new(opcode))
+        })
+        .collect()
+}
+
+// Names the subroutine a return address goes back from, with the labels
+// of the disassembly from the ROM start when it has one.
+fn callee_name(chip: &Chip) -> impl Fn(u16) -> Option<String> + '_ {
+    let ram = chip.ram_slice(0..chip.ram_size()).unwrap();
+    let start = rom::START_ADDR as usize;
+    let labels = disasm::labels(&disasm::disassemble(&ram[start.min(ram.len())..], start as u16));
+    move |ret| {
+        let target = disasm::call_target(ram, ret)?;
+        Some(labels.get(&target).cloned().unwrap_or_else(|| format!("{:#05x}", target)))
+    }
+}
+
+// Stops the run loop at breakpoints and after steps, and takes commands
+// until told to go on.
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    breakpoints: Breakpoints,
+    // Stop before the next instruction.
+    stepping: bool,
+    // PC and executed instruction count where it last stopped. Resuming
+    // runs that instruction instead of stopping on it again, and a blocked
+    // LD Vx, K doesn't stop on every retry.
+    stopped_at: Option<(u16, u64)>,
+    // The --poke pokes, for the poke command.
+    pokes: Vec<poke::Poke>,
+}
+
+impl Debugger {
+    pub fn new(breakpoints: &[u16], watchpoints: &[Range<u32>]) -> Debugger {
+        let mut d = Debugger::default();
+        for &addr in breakpoints {
+            d.breakpoints.add(addr);
+        }
+        for r in watchpoints {
+            d.breakpoints.add_watch(r.clone());
+        }
+        d
+    }
+
+    pub fn set_pokes(&mut self, pokes: &[poke::Poke]) {
+        self.pokes = pokes.to_vec();
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn debug_state(&self) -> DebugState {
+        DebugState { items: self.breakpoints.items(), stepping: self.stepping }
+    }
+
+    // The part of a watched range that is in RAM.
+    fn in_ram(chip: &Chip, r: &Range<u32>) -> Range<u32> {
+        let end = r.end.min(chip.ram_size());
+        r.start.min(end)..end
+    }
+
+    // The watched bytes, to be taken before an instruction and handed to
+    // watch_hits() after it. None without watchpoints, so that the run loop
+    // copies nothing then.
+    pub fn watched_bytes(&self, chip: &Chip) -> Option<Vec<u8>> {
+        let watches = self.breakpoints.watches();
+        if watches.is_empty() {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        for (_, r) in watches {
+            bytes.extend_from_slice(chip.ram_slice(Debugger::in_ram(chip, r)).unwrap());
+        }
+        Some(bytes)
+    }
+
+    // The watched bytes that differ from `before`.
+    pub fn watch_hits(&self, chip: &Chip, before: &[u8]) -> Vec<WatchHit> {
+        let mut hits = Vec::new();
+        let mut old = before.iter();
+        for (id, r) in self.breakpoints.watches() {
+            let r = Debugger::in_ram(chip, r);
+            for (addr, &new) in r.clone().zip(chip.ram_slice(r).unwrap()) {
+                match old.next() {
+                    Some(&old) if old != new => hits.push(WatchHit { id: *id, addr: addr as u16, old, new }),
+                    _ => {},
+                }
+            }
+        }
+        hits
+    }
+
+    pub fn should_stop(&self, chip: &Chip) -> bool {
+        if self.stopped_at == Some((chip.pc(), chip.instructions())) {
+            return false;
+        }
+        self.stepping || self.breakpoints.contains(chip.pc())
+    }
+
+    // Prints where the chip stopped and runs commands from `read` until one
+    // resumes. Returns false to end the run, on quit or end of input.
+    // Stepping back restores states from `history` and stops there.
+    pub fn stop(&mut self, chip: &mut Chip, history: &mut History, read: impl FnMut() -> Option<String>) -> bool {
+        self.stopped_at = Some((chip.pc(), chip.instructions()));
+        print!("{}", location(chip));
+        match self.commands(chip, history, read, false) {
+            Some(Command::Step) => {
+                self.stepping = true;
+                true
+            },
+            Some(Command::Continue) => {
+                self.stepping = false;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Stops on an unknown opcode at PC, with the instructions that led
+    // there. Returns true when told to skip it, PC then points past it.
+    pub fn unknown_opcode(&mut self, chip: &mut Chip, history: &mut History, opcode: u16, read: impl FnMut() -> Option<String>) -> bool {
+        self.stopped_at = Some((chip.pc(), chip.instructions()));
+        println!("Unknown opcode {:04X} at {:#05x}, after:", opcode, chip.pc());
+        for line in recent(chip, LISTING_CONTEXT) {
+            println!("  {}", line);
+        }
+        print!("{}", location(chip));
+        println!("Type skip to go past it or abort to stop.");
+        if self.commands(chip, history, read, true) == Some(Command::Skip) {
+            chip.set_pc(chip.pc().wrapping_add(2));
+            self.stopped_at = Some((chip.pc(), chip.instructions()));
+            return true;
+        }
+        false
+    }
+
+    // Runs commands from `read` until one resumes or ends the run, and
+    // returns it. None at the end of input. On an unknown opcode only skip,
+    // abort and quit go on, elsewhere skip and abort are refused.
+    fn commands(&mut self, chip: &mut Chip, history: &mut History, mut read: impl FnMut() -> Option<String>, unknown: bool) -> Option<Command> {
+        loop {
+            print!("(chip8) ");
+            std::io::stdout().flush().unwrap();
+            let line = read()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_command(&line) {
+                Ok(Command::Break(addr)) => println!("Breakpoint {} at {:#05x}", self.breakpoints.add(addr), addr),
+                Ok(Command::Watch(r)) => {
+                    println!("Watchpoint {} on {:#05x}..{:#05x}", self.breakpoints.add_watch(r.clone()), r.start, r.end);
+                },
+                Ok(Command::Delete(id)) => {
+                    if !self.breakpoints.delete(id) {
+                        println!("No breakpoint number {}.", id);
+                    }
+                },
+                Ok(Command::InfoBreakpoints) => print!("{}", self.breakpoints),
+                Ok(Command::Set(reg, value)) => match set_register(chip, reg, value) {
+                    Ok(()) => {
+                        println!("{} = {:#x}", reg, value);
+                        info!("Debugger set {} to {:#x} at {:#05x}", reg, value, chip.pc());
+                    },
+                    Err(e) => println!("{}", e),
+                },
+                Ok(Command::Poke(addr, bytes)) => {
+                    let poke = poke::Poke { addr, bytes };
+                    match chip.poke(addr as u32, &poke.bytes) {
+                        Ok(()) => {
+                            println!("Poked {}", poke);
+                            info!("Debugger poked {} at {:#05x}", poke, chip.pc());
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                Ok(Command::ApplyPokes) if self.pokes.is_empty() => println!("No pokes, give them with --poke."),
+                Ok(Command::ApplyPokes) => match poke::apply(chip, &self.pokes) {
+                    Ok(()) => {
+                        println!("Applied {} poke(s)", self.pokes.len());
+                        info!("Debugger applied {} poke(s) at {:#05x}", self.pokes.len(), chip.pc());
+                    },
+                    Err(e) => println!("{}", e),
+                },
+                Ok(Command::Peek(addr, n)) => match peek(chip, addr, n) {
+                    Ok(lines) => {
+                        for line in lines {
+                            println!("{}", line);
+                        }
+                    },
+                    Err(e) => println!("{}", e),
+                },
+                Ok(Command::Disassemble) => {
+                    for line in listing(chip, &self.breakpoints) {
+                        println!("{}", line);
+                    }
+                },
+                Ok(Command::Backtrace) => {
+                    for line in backtrace(chip.stack(), chip.sp(), callee_name(chip)) {
+                        println!("{}", line);
+                    }
+                },
+                Ok(Command::Hot(n)) => {
+                    for line in hot_list(chip, n) {
+                        println!("{}", line);
+                    }
+                },
+                Ok(Command::Step | Command::ReverseStep | Command::Continue) if unknown => {
+                    println!("The instruction at PC is unknown, skip or abort.");
+                },
+                Ok(Command::Skip | Command::Abort) if !unknown => println!("No unknown opcode to skip."),
+                Ok(Command::ReverseStep) => {
+                    if history.step_back(chip) {
+                        self.stopped_at = Some((chip.pc(), chip.instructions()));
+                        print!("{}", location(chip));
+                    } else if history.depth() == 0 {
+                        println!("No history, run with --history to step back.");
+                    } else {
+                        println!("No more history.");
+                    }
+                },
+                Ok(Command::History) => println!("{} of {} instructions back", history.len(), history.depth()),
+                Ok(c @ (Command::Step | Command::Continue | Command::Quit | Command::Skip | Command::Abort)) => return Some(c),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backtrace, callee_name, hot_list, listing, location, parse_addr, parse_command, parse_range, peek, set_register, Breakpoints, Command, DebugItem, DebugState, Debugger, Register, WatchHit};
+    use crate::chip::Chip;
+    use crate::history::History;
+    use crate::poke;
+    use crate::profile::Profile;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command("b 0x0240"), Ok(Command::Break(0x240)));
+        assert_eq!(parse_command("break 576\n"), Ok(Command::Break(0x240)));
+        assert_eq!(parse_command("delete 2"), Ok(Command::Delete(2)));
+        assert_eq!(parse_command("info breakpoints"), Ok(Command::InfoBreakpoints));
+        assert_eq!(parse_command("i b"), Ok(Command::InfoBreakpoints));
+        assert_eq!(parse_command(" s "), Ok(Command::Step));
+        assert_eq!(parse_command("continue"), Ok(Command::Continue));
+        assert_eq!(parse_command("q"), Ok(Command::Quit));
+        assert_eq!(parse_command("dis"), Ok(Command::Disassemble));
+        assert_eq!(parse_command("bt"), Ok(Command::Backtrace));
+        assert_eq!(parse_command("hot"), Ok(Command::Hot(10)));
+        assert_eq!(parse_command("hot 3"), Ok(Command::Hot(3)));
+        assert_eq!(parse_command("hot x"), Err("invalid count 'x'".to_string()));
+        assert_eq!(parse_command("rs"), Ok(Command::ReverseStep));
+        assert_eq!(parse_command("history"), Ok(Command::History));
+        assert_eq!(parse_command("skip"), Ok(Command::Skip));
+        assert_eq!(parse_command("abort"), Ok(Command::Abort));
+
+        assert_eq!(parse_command("b 0x10000"), Err("invalid address '0x10000'".to_string()));
+        assert_eq!(parse_command("delete x"), Err("invalid breakpoint number 'x'".to_string()));
+        assert_eq!(parse_command("run"), Err("unknown command 'run'".to_string()));
+        assert_eq!(parse_addr("0x240"), Ok(0x240));
+
+        assert_eq!(parse_command("watch 0x300"), Ok(Command::Watch(0x300..0x301)));
+        assert_eq!(parse_command("watch 0x300..0x310"), Ok(Command::Watch(0x300..0x310)));
+        assert_eq!(parse_range("0xFFF0..0x10000"), Ok(0xFFF0..0x10000));
+        assert_eq!(parse_range("0x310..0x300"), Err("empty range '0x310..0x300'".to_string()));
+        assert_eq!(parse_range("0x300..x"), Err("invalid address 'x'".to_string()));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut b = Breakpoints::new();
+        assert_eq!(b.to_string(), "No breakpoints or watchpoints.\n");
+        assert_eq!(b.add(0x240), 1);
+        assert_eq!(b.add(0x200), 2);
+        assert_eq!(b.add(0x240), 1);
+        assert_eq!(b.add_watch(0x300..0x310), 3);
+        assert_eq!(b.list(), vec![(1, 0x240), (2, 0x200)]);
+        assert_eq!(b.to_string(), concat!(
+            "Num  Type        Address\n",
+            "1    breakpoint  0x240\n",
+            "2    breakpoint  0x200\n",
+            "3    watchpoint  0x300..0x310\n",
+        ));
+
+        assert!(b.delete(1));
+        assert!(!b.delete(1));
+        assert!(!b.contains(0x240));
+        assert!(b.contains(0x200));
+        assert!(b.delete(3));
+        assert!(b.watches().is_empty());
+        // Numbers aren't reused.
+        assert_eq!(b.add(0x240), 4);
+    }
+
+    #[test]
+    fn watch_hits() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        let debugger = Debugger::new(&[], &[0x300..0x302, 0x400..0x401]);
+        let before = debugger.watched_bytes(&chip).unwrap();
+        assert_eq!(before, vec![0, 0, 0]);
+
+        chip.poke(0x2FF, &[1, 2]).unwrap();
+        chip.poke(0x400, &[3]).unwrap();
+        assert_eq!(debugger.watch_hits(&chip, &before), vec![
+            WatchHit { id: 1, addr: 0x300, old: 0, new: 2 },
+            WatchHit { id: 2, addr: 0x400, old: 0, new: 3 },
+        ]);
+        assert_eq!(WatchHit { id: 1, addr: 0x300, old: 0, new: 2 }.to_string(), "Watchpoint 1: [0x300] 0x00 -> 0x02");
+
+        // Nothing to copy without watchpoints.
+        assert_eq!(Debugger::new(&[0x200], &[]).watched_bytes(&chip), None);
+    }
+
+    #[test]
+    fn debug_state() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let mut debugger = Debugger::new(&[0x200, 0x210], &[0x400..0x401, 0x410..0x412]);
+        assert!(!debugger.debug_state().stepping);
+        let mut commands = ["b 0x250", "watch 0x300..0x304", "delete 1", "s"].into_iter().map(String::from);
+        assert!(debugger.stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!(debugger.debug_state(), DebugState {
+            items: vec![
+                DebugItem::Breakpoint { id: 2, addr: 0x210 },
+                DebugItem::Watchpoint { id: 3, range: 0x400..0x401 },
+                DebugItem::Watchpoint { id: 4, range: 0x410..0x412 },
+                DebugItem::Breakpoint { id: 5, addr: 0x250 },
+                DebugItem::Watchpoint { id: 6, range: 0x300..0x304 },
+            ],
+            stepping: true,
+        });
+        assert_eq!(Debugger::default().debug_state(), DebugState::default());
+    }
+
+    #[test]
+    fn disassembly() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x60, 0x02, // LD V0, 0x2
+            0x22, 0x08, // CALL 0x208
+            0x12, 0x04, // JP 0x204
+            0x00, 0x2A, // data
+            0x70, 0xFF, // ADD V0, 0xff
+            0x30, 0x00, // SE V0, 0x0
+            0x12, 0x08, // JP 0x208
+            0x00, 0xEE, // RET
+        ]).build().unwrap();
+        let mut b = Breakpoints::new();
+        b.add(0x20A);
+        b.add(0x206);
+        for _ in 0..4 {
+            chip.cycle().unwrap();
+        }
+
+        // The data between code before PC is listed, breakpoints on data
+        // aren't flagged.
+        assert_eq!(listing(&chip, &b).join("\n"), [
+            "    0x200  6002  LD V0, 0x2",
+            "    0x202  2208  CALL 0x208",
+            "    0x204  1204  JP 0x204",
+            "    0x206  00    DB 0x00",
+            "    0x207  2A    DB 0x2a",
+            "    0x208  70FF  ADD V0, 0xff",
+            "*   0x20a  3000  SE V0, 0x0",
+            " => 0x20c  1208  JP 0x208",
+            "    0x20e  00EE  RET",
+            "    0x210  00    DB 0x00",
+            "    0x211  00    DB 0x00",
+            "    0x212  00    DB 0x00",
+            "    0x213  00    DB 0x00",
+            "    0x214  00    DB 0x00",
+            "    0x215  00    DB 0x00",
+            "    0x216  00    DB 0x00",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn backtraces() {
+        let name = |ret: u16| (ret == 0x204).then(|| "sub_208".to_string());
+        assert_eq!(backtrace(&[0x204, 0x20C, 0], 2, name), vec![
+            "#0  ??         returns to 0x20c",
+            "#1  sub_208    returns to 0x204",
+        ]);
+        assert_eq!(backtrace(&[0x204], 0, name), vec!["No active calls."]);
+        assert_eq!(backtrace(&[0x204], 5, name), vec![
+            "SP is 5, past the 1 stack entries.",
+            "#0  sub_208    returns to 0x204",
+        ]);
+
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x22, 0x04, // CALL 0x204
+            0x12, 0x02, // JP 0x202
+            0x22, 0x08, // CALL 0x208
+            0x00, 0xEE, // RET
+            0x12, 0x08, // JP 0x208
+        ]).build().unwrap();
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        assert_eq!(chip.call_stack(), vec![0x206, 0x202]);
+        assert_eq!(backtrace(chip.stack(), chip.sp(), callee_name(&chip)), vec![
+            "#0  sub_208    returns to 0x206",
+            "#1  sub_204    returns to 0x202",
+        ]);
+    }
+
+    #[test]
+    fn hot_addresses() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x60, 0x00, // LD V0, 0x0
+            0x70, 0x01, // ADD V0, 0x1
+            0x30, 0x10, // SE V0, 0x10
+            0x12, 0x02, // JP 0x202
+            0x12, 0x08, // JP 0x208
+        ]).build().unwrap();
+        assert_eq!(hot_list(&chip, 3), vec!["No heatmap, run with --profile-out to count executions."]);
+
+        chip.enable_heatmap();
+        for _ in 0..100 {
+            chip.step().unwrap();
+        }
+        // The loop body runs 16 times, the first instruction once.
+        assert_eq!(hot_list(&chip, 4), vec![
+            "0x208          52   52.0%  JP 0x208",
+            "0x202          16   16.0%  ADD V0, 0x1",
+            "0x204          16   16.0%  SE V0, 0x10",
+            "0x206          15   15.0%  JP 0x202",
+        ]);
+        assert_eq!(chip.heatmap().unwrap().count(0x200), 1);
+    }
+
+    #[test]
+    fn set_poke_peek_commands() {
+        assert_eq!(parse_command("set v3 = 0x1f"), Ok(Command::Set(Register::V(3), 0x1F)));
+        assert_eq!(parse_command("set VA=255"), Ok(Command::Set(Register::V(0xA), 0xFF)));
+        assert_eq!(parse_command("set i = 0x300"), Ok(Command::Set(Register::I, 0x300)));
+        assert_eq!(parse_command("set pc = 0x200"), Ok(Command::Set(Register::Pc, 0x200)));
+        assert_eq!(parse_command("set dt = 60"), Ok(Command::Set(Register::Dt, 60)));
+        assert_eq!(parse_command("poke 0x340 0xde 0xad"), Ok(Command::Poke(0x340, vec![0xDE, 0xAD])));
+        assert_eq!(parse_command("poke"), Ok(Command::ApplyPokes));
+        assert_eq!(parse_command("peek 0x340 4"), Ok(Command::Peek(0x340, 4)));
+        assert_eq!(parse_command("peek 0x340"), Ok(Command::Peek(0x340, 1)));
+
+        assert!(parse_command("set v3 = 0x100").is_err());
+        assert!(parse_command("set vg = 1").is_err());
+        assert!(parse_command("set v3 0x1f").is_err());
+        assert!(parse_command("set i = 0x10000").is_err());
+        assert!(parse_command("poke 0x340").is_err());
+        assert!(parse_command("poke 0x340 0x1ff").is_err());
+        assert!(parse_command("peek 0x340 x").is_err());
+    }
+
+    #[test]
+    fn set_and_peek() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        set_register(&mut chip, Register::V(3), 0x1F).unwrap();
+        set_register(&mut chip, Register::I, 0x300).unwrap();
+        set_register(&mut chip, Register::St, 5).unwrap();
+        assert_eq!((chip.v(3), chip.i(), chip.st()), (0x1F, 0x300, 5));
+        assert_eq!(set_register(&mut chip, Register::Pc, 0x1000), Err("0x1000 is outside RAM".to_string()));
+        assert_eq!(chip.pc(), 0x200);
+
+        assert_eq!(peek(&chip, 0x1FF, 3), Ok(vec!["0x1ff  00 12 00".to_string()]));
+        assert_eq!(peek(&chip, 0x200, 17).unwrap().len(), 2);
+        assert!(peek(&chip, 0xFFE, 4).is_err());
+    }
+
+    #[test]
+    fn mutate_from_prompt() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let before = chip.state_hash();
+        let mut commands = ["set pc = 0x1000", "poke 0xFFF 1 2", "set v3 = 0x100", "peek 0x340 2"].into_iter().map(String::from);
+        // Failures leave the machine as it was.
+        assert!(!Debugger::default().stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!(chip.state_hash(), before);
+
+        let mut commands = ["set v3 = 0x1f", "poke 0x340 0xde 0xad", "set pc = 0x202", "c"].into_iter().map(String::from);
+        assert!(Debugger::default().stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!((chip.v(3), chip.pc()), (0x1F, 0x202));
+        assert_eq!(chip.ram_slice(0x340..0x342), Some(&[0xDE, 0xAD][..]));
+    }
+
+    #[test]
+    fn apply_pokes() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let mut debugger = Debugger::default();
+        let before = chip.state_hash();
+        let mut commands = ["poke", "c"].into_iter().map(String::from);
+        assert!(debugger.stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!(chip.state_hash(), before);
+
+        debugger.set_pokes(&[poke::parse("0x300=1,2").unwrap(), poke::parse("0x201=0x04").unwrap()]);
+        let mut commands = ["poke", "c"].into_iter().map(String::from);
+        assert!(debugger.stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!(chip.ram_slice(0x300..0x302), Some(&[1, 2][..]));
+        assert_eq!(chip.ram_slice(0x200..0x202), Some(&[0x12, 0x04][..]));
+
+        // One past the end of RAM fails, the ones before it are written.
+        debugger.set_pokes(&[poke::parse("0x310=3").unwrap(), poke::parse("0x1000=1").unwrap()]);
+        let mut commands = ["poke", "c"].into_iter().map(String::from);
+        assert!(debugger.stop(&mut chip, &mut History::new(0), || commands.next()));
+        assert_eq!(chip.ram_slice(0x310..0x311), Some(&[3][..]));
+    }
+
+    #[test]
+    fn stop_location() {
+        let chip = Chip::builder().profile(Profile::modern()).rom(&[0x60, 0x2A]).build().unwrap();
+        let text = location(&chip);
+        assert!(text.starts_with("Stopped at 0x200  602A  LD V0, 0x2a\nV0=00 V1=00 "));
+        assert!(text.ends_with(" VF=00\nI=0x000 SP=0 DT=0 ST=0\n"));
+    }
+}
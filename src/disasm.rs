@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::instr::{Instr, Operation};
+
+// One line of a disassembly: an instruction reachable from the entry point,
+// or a single byte of data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    // Where a JP or CALL goes.
+    pub target: Option<u16>,
+    pub is_code: bool,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:#05x}  {:<4}  {}", self.addr, bytes.concat(), self.text)
+    }
+}
+
+fn fetch(rom: &[u8], base: u16, addr: u16) -> Option<Instr> {
+    let i = addr.checked_sub(base)? as usize;
+    let bytes = rom.get(i..i + 2)?;
+    Some(Instr::new(u16::from_be_bytes([bytes[0], bytes[1]])))
+}
+
+// Addresses of the instructions reachable from `start` by following jumps,
+// calls and skips. Computed jumps (JP V0) and unknown opcodes end a path.
+pub fn reachable(rom: &[u8], base: u16, start: u16) -> BTreeSet<u16> {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![start];
+
+    while let Some(addr) = todo.pop() {
+        let op = match fetch(rom, base, addr).and_then(|i| i.operation()) {
+            Some(op) => op,
+            None => continue,
+        };
+        if !seen.insert(addr) {
+            continue;
+        }
+        let next = addr.wrapping_add(2);
+        match op {
+            Operation::Ret | Operation::JpV0Nnn { .. } => {},
+            Operation::Jp { nnn } => todo.push(nnn),
+            Operation::Call { nnn } => todo.extend([nnn, next]),
+            Operation::SeVxNn { .. } | Operation::SneVxNn { .. } |
+                Operation::SeVxVy { .. } | Operation::SneVxVy { .. } |
+                Operation::SkpVx { .. } | Operation::SkpnVx { .. } => todo.extend([next, next.wrapping_add(2)]),
+            _ => todo.push(next),
+        }
+    }
+    seen
+}
+
+// The line at `addr`: the instruction there when `code` has it, the byte
+// there otherwise. None past the end of `rom`.
+fn line_at(rom: &[u8], base: u16, addr: u16, code: &BTreeSet<u16>) -> Option<Line> {
+    let i = addr.checked_sub(base)? as usize;
+    match fetch(rom, base, addr).filter(|_| code.contains(&addr)) {
+        Some(instr) => {
+            let target = match instr.operation() {
+                Some(Operation::Jp { nnn }) | Some(Operation::Call { nnn }) => Some(nnn),
+                _ => None,
+            };
+            Some(Line { addr, bytes: rom[i..i + 2].to_vec(), text: instr.to_string(), target, is_code: true })
+        },
+        None => {
+            let b = *rom.get(i)?;
+            Some(Line { addr, bytes: vec![b], text: format!("DB {:#04x}", b), target: None, is_code: false })
+        },
+    }
+}
+
+// Disassembles a ROM loaded at `base`, starting execution there. Bytes no
+// path reaches are listed as data.
+pub fn disassemble(rom: &[u8], base: u16) -> Vec<Line> {
+    disassemble_from(rom, base, &[base])
+}
+
+// Like disassemble(), with the code reachable from any of `entries`, e.g.
+// the targets of computed jumps seen while running.
+pub fn disassemble_from(rom: &[u8], base: u16, entries: &[u16]) -> Vec<Line> {
+    let mut code = BTreeSet::new();
+    for &entry in entries {
+        if !code.contains(&entry) {
+            code.extend(reachable(rom, base, entry));
+        }
+    }
+    let mut lines = Vec::new();
+    let mut addr = base;
+
+    while let Some(line) = line_at(rom, base, addr, &code) {
+        addr += line.bytes.len() as u16;
+        lines.push(line);
+    }
+    lines
+}
+
+// `n` lines before `pc` in `ram`, then the line at PC and `n` after it. The
+// code is what control flow reaches from PC and from `recent`, the
+// instructions executed lately, since that is all known to be code before
+// PC. Without any code below PC the words before it are decoded as
+// instructions.
+pub fn around(ram: &[u8], pc: u16, recent: &[u16], n: usize) -> Vec<Line> {
+    let mut code = reachable(ram, 0, pc);
+    for &addr in recent {
+        if !code.contains(&addr) {
+            code.extend(reachable(ram, 0, addr));
+        }
+    }
+
+    // Lines from the n-th instruction before PC, data in between included.
+    let start = match code.range(..pc).rev().take(n).last() {
+        Some(&addr) => addr,
+        None => {
+            let start = pc.saturating_sub(2 * n as u16);
+            code.extend((start..pc).step_by(2));
+            start
+        },
+    };
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while let Some(line) = line_at(ram, 0, addr, &code).filter(|_| addr < pc) {
+        addr += line.bytes.len() as u16;
+        lines.push(line);
+    }
+    lines.drain(..lines.len().saturating_sub(n));
+
+    let mut addr = pc;
+    for _ in 0..=n {
+        match line_at(ram, 0, addr, &code) {
+            Some(line) => {
+                addr = addr.wrapping_add(line.bytes.len() as u16);
+                lines.push(line);
+            },
+            None => break,
+        }
+    }
+    lines
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Names for the CALL targets in a disassembly, "sub_206".
+pub fn labels(lines: &[Line]) -> BTreeMap<u16, String> {
+    lines.iter()
+        .filter(|l| l.text.starts_with("CALL"))
+        .filter_map(|l| l.target)
+        .map(|t| (t, format!("sub_{:03x}", t)))
+        .collect()
+}
+
+// Where the CALL that pushed return address `ret` went, if the instruction
+// before `ret` in `ram` is a CALL.
+pub fn call_target(ram: &[u8], ret: u16) -> Option<u16> {
+    match fetch(ram, 0, ret.checked_sub(2)?)?.operation()? {
+        Operation::Call { nnn } => Some(nnn),
+        _ => None,
+    }
+}
+
+fn anchor(addr: u16) -> String {
+    format!("L{:03X}", addr)
+}
+
+// A standalone HTML page with one table row per line. Every row has an
+// anchor, and JP/CALL targets link to theirs.
+pub fn to_html(lines: &[Line], title: &str) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "<!DOCTYPE html>").unwrap();
+    writeln!(out, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape(title)).unwrap();
+    writeln!(out, "<style>body {{ font-family: monospace; }} td {{ padding: 0 1em; }} tr.data {{ color: #888; }} :target {{ background: #ffa; }}</style>").unwrap();
+    writeln!(out, "</head><body>").unwrap();
+    writeln!(out, "<h1>{}</h1>", escape(title)).unwrap();
+    writeln!(out, "<table>").unwrap();
+    writeln!(out, "<tr><th>Address</th><th>Bytes</th><th>Instruction</th></tr>").unwrap();
+
+    for l in lines {
+        let bytes: Vec<String> = l.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let text = match l.target {
+            Some(t) if lines.iter().any(|o| o.addr == t) => {
+                format!("<a href=\"#{}\">{}</a>", anchor(t), escape(&l.text))
+            },
+            _ => escape(&l.text),
+        };
+        writeln!(out, "<tr id=\"{}\" class=\"{}\"><td>{:#05x}</td><td>{}</td><td>{}</td></tr>",
+            anchor(l.addr), if l.is_code { "code" } else { "data" }, l.addr, bytes.join(" "), text).unwrap();
+    }
+
+    writeln!(out, "</table>").unwrap();
+    writeln!(out, "</body></html>").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{around, call_target, disassemble, labels, reachable, to_html};
+
+    const ROM: [u8; 11] = [
+        0x22, 0x06, // 0x200: CALL 0x206
+        0x12, 0x04, // 0x202: JP 0x204
+        0x12, 0x04, // 0x204: JP 0x204
+        0x30, 0x01, // 0x206: SE V0, 0x1
+        0x00, 0xEE, // 0x208: RET
+        0xAB,       // 0x20A: data
+    ];
+
+    #[test]
+    fn reachability() {
+        let code = reachable(&ROM, 0x200, 0x200);
+        assert_eq!(code.into_iter().collect::<Vec<u16>>(), vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+    }
+
+    #[test]
+    fn lines() {
+        let lines = disassemble(&ROM, 0x200);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].to_string(), "0x200  2206  CALL 0x206");
+        assert_eq!(lines[0].target, Some(0x206));
+        assert_eq!(lines[3].to_string(), "0x206  3001  SE V0, 0x1");
+        assert!(!lines[5].is_code);
+        assert_eq!(lines[5].to_string(), "0x20a  AB    DB 0xab");
+    }
+
+    // Bytes after an unconditional jump are data unless something else
+    // reaches them.
+    #[test]
+    fn unreached_code_is_data() {
+        let rom = [0x12, 0x00, 0x60, 0x01];
+        let lines = disassemble(&rom, 0x200);
+        assert_eq!(lines.iter().filter(|l| l.is_code).count(), 1);
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn around_pc() {
+        let mut ram = vec![0; 0x200];
+        ram.extend_from_slice(&ROM);
+        let text = |lines: Vec<super::Line>| lines.iter().map(|l| l.to_string()).collect::<Vec<String>>();
+
+        // Nothing ran yet: linear before PC, control flow after it.
+        assert_eq!(text(around(&ram, 0x206, &[], 2)), vec![
+            "0x202  1204  JP 0x204",
+            "0x204  1204  JP 0x204",
+            "0x206  3001  SE V0, 0x1",
+            "0x208  00EE  RET",
+            "0x20a  AB    DB 0xab",
+        ]);
+
+        // Executed instructions are code on both sides.
+        assert_eq!(text(around(&ram, 0x204, &[0x200, 0x206, 0x208, 0x202], 3)), vec![
+            "0x200  2206  CALL 0x206",
+            "0x202  1204  JP 0x204",
+            "0x204  1204  JP 0x204",
+            "0x206  3001  SE V0, 0x1",
+            "0x208  00EE  RET",
+            "0x20a  AB    DB 0xab",
+        ]);
+        // Past a loop nothing ran yet is data.
+        assert_eq!(text(around(&ram, 0x204, &[0x202], 1))[2], "0x206  30    DB 0x30");
+
+        // Clipped at the ends of RAM.
+        assert_eq!(around(&ram, 0x20A, &[], 4).len(), 4 + 1);
+        assert_eq!(around(&ram, 0x2, &[], 4)[0].addr, 0x0);
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(labels(&disassemble(&ROM, 0x200)).into_iter().collect::<Vec<_>>(), vec![(0x206, "sub_206".to_string())]);
+
+        let mut ram = vec![0; 0x200];
+        ram.extend_from_slice(&ROM);
+        assert_eq!(call_target(&ram, 0x202), Some(0x206));
+        // After a JP, and before RAM.
+        assert_eq!(call_target(&ram, 0x204), None);
+        assert_eq!(call_target(&ram, 0x1), None);
+    }
+
+    #[test]
+    fn html_links() {
+        let html = to_html(&disassemble(&ROM, 0x200), "test <rom>");
+        assert!(html.contains("<title>test &lt;rom&gt;</title>"));
+        assert!(html.contains("<tr id=\"L200\" class=\"code\"><td>0x200</td><td>22 06</td><td><a href=\"#L206\">CALL 0x206</a></td></tr>"));
+        assert!(html.contains("<tr id=\"L206\" class=\"code\">"));
+        assert!(html.contains("<tr id=\"L20A\" class=\"data\">"));
+    }
+}
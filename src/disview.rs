@@ -0,0 +1,71 @@
+use crate::chip::Chip;
+use crate::debugger::{self, Breakpoints, LISTING_CONTEXT};
+use crate::framebuffer::Frame;
+use crate::memview;
+use crate::text::{self, ADVANCE, GLYPH_HEIGHT, LINE_SPACING};
+
+// Text columns of a listing line, "*=> 0x200  D01F  DRW V0, V1, 0xf".
+const COLUMNS: u32 = 32;
+
+// The debugger's disassembly around PC shown over the screen, with the PC
+// line highlighted.
+#[derive(Default)]
+pub struct DisView {
+    visible: bool,
+}
+
+impl DisView {
+    pub fn new() -> DisView {
+        DisView::default()
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // The listing as memview::TEXT and memview::HIGHLIGHT pixels on 0.
+    pub fn render(&self, chip: &Chip, breakpoints: &Breakpoints) -> Frame {
+        let line_height = GLYPH_HEIGHT + LINE_SPACING;
+        let rows = 2 * LISTING_CONTEXT as u32 + 1;
+        let mut frame = Frame::with_size(COLUMNS * ADVANCE - 1 + 2, rows * line_height - LINE_SPACING + 2);
+        for (row, line) in debugger::listing(chip, breakpoints).iter().enumerate() {
+            let y = 1 + row as u32 * line_height;
+            text::draw_text(&mut frame, 1, y, line);
+            if line[1..].starts_with("=>") {
+                let width = text::text_width(line).min(frame.width() - 1);
+                memview::highlight(&mut frame, 1, y, width);
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisView;
+    use crate::chip::Chip;
+    use crate::debugger::Breakpoints;
+    use crate::memview::{HIGHLIGHT, TEXT};
+    use crate::profile::Profile;
+
+    #[test]
+    fn render() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x60, 0x2A, // LD V0, 0x2a
+            0x12, 0x02, // JP 0x202
+        ]).build().unwrap();
+        chip.cycle().unwrap();
+        let frame = DisView::new().render(&chip, &Breakpoints::new());
+
+        assert_eq!((frame.width(), frame.height()), (32 * 4 - 1 + 2, 17 * 7 - 2 + 2));
+        // LD V0 on the first line, the PC line below it highlighted.
+        assert!(frame[1_u32].contains(&TEXT));
+        assert!(!frame[1_u32].contains(&HIGHLIGHT));
+        assert!(frame[8_u32].contains(&HIGHLIGHT));
+        assert!(!frame[8_u32].contains(&TEXT));
+    }
+}
@@ -0,0 +1,291 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::trace;
+
+use crate::audio::Sound;
+use crate::chip::Chip;
+use crate::clock::{Clock, SystemClock};
+use crate::framebuffer::Frame;
+use crate::playlist::Playlist;
+use crate::runner::{self, Event, Frontend, RunOptions};
+use crate::stats::RunStats;
+
+// Frames waiting for the UI. Newer frames are dropped while it is full.
+pub const FRAME_QUEUE: usize = 2;
+// How long the UI thread waits for output before polling input again.
+const UI_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// What the emulation thread sends to the UI thread. Frames go through a
+// queue of FRAME_QUEUE, the rest through one that never fills, so neither
+// holds up the emulation.
+pub enum Output {
+    // The screen, and an overlay for Frontend::present_overlay().
+    Frame(Box<Frame>, Option<Box<Frame>>),
+    // The sound timer buzzer, sent when it changes.
+    Sound(bool),
+    Play(Sound),
+    Title(String),
+}
+
+// The run loop's frontend on the emulation thread: input comes from the UI
+// thread, output goes back to it.
+struct ChannelFrontend {
+    events: Receiver<Event>,
+    frames: SyncSender<Output>,
+    output: Sender<Output>,
+    sound: bool,
+    clock: SystemClock,
+}
+
+impl Frontend for ChannelFrontend {
+    fn poll_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.events.try_recv() {
+                Ok(e) => events.push(e),
+                Err(TryRecvError::Empty) => return events,
+                Err(TryRecvError::Disconnected) => {
+                    // Nobody is left to send Quit.
+                    events.push(Event::Quit);
+                    return events;
+                },
+            }
+        }
+    }
+
+    fn present(&mut self, frame: &Frame) {
+        if let Err(TrySendError::Full(_)) = self.frames.try_send(Output::Frame(Box::new(frame.clone()), None)) {
+            trace!("UI busy, frame dropped");
+        }
+    }
+
+    fn present_overlay(&mut self, frame: &Frame, overlay: &Frame) {
+        let out = Output::Frame(Box::new(frame.clone()), Some(Box::new(overlay.clone())));
+        if let Err(TrySendError::Full(_)) = self.frames.try_send(out) {
+            trace!("UI busy, frame dropped");
+        }
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        if self.sound != on {
+            self.sound = on;
+            let _ = self.output.send(Output::Sound(on));
+        }
+    }
+
+    fn play_sound(&mut self, sound: Sound) {
+        let _ = self.output.send(Output::Play(sound));
+    }
+
+    fn set_title(&mut self, title: &str) {
+        let _ = self.output.send(Output::Title(title.to_string()));
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
+    }
+}
+
+// Runs the emulation on its own thread so that rendering and input handling
+// don't hold it up. The thread ends when the run loop does, on Quit or when
+// the program stops by itself.
+pub struct EmuThread {
+    events: Sender<Event>,
+    frames: Receiver<Output>,
+    output: Receiver<Output>,
+    handle: Option<JoinHandle<(Chip, RunStats)>>,
+}
+
+impl EmuThread {
+    pub fn spawn(mut chip: Chip, mut playlist: Playlist, opts: RunOptions) -> EmuThread {
+        let (events_tx, events_rx) = mpsc::channel();
+        let (frames_tx, frames_rx) = mpsc::sync_channel(FRAME_QUEUE);
+        let (output_tx, output_rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut frontend = ChannelFrontend {
+                events: events_rx,
+                frames: frames_tx,
+                output: output_tx,
+                sound: false,
+                clock: SystemClock::new(),
+            };
+            let stats = runner::run(&mut chip, &mut playlist, &mut frontend, &opts);
+            (chip, stats)
+        });
+
+        EmuThread {
+            events: events_tx,
+            frames: frames_rx,
+            output: output_rx,
+            handle: Some(handle),
+        }
+    }
+
+    // Events sent after the thread ended are dropped.
+    pub fn send(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    // Output other than frames first, it doesn't wait.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Output, RecvTimeoutError> {
+        match self.output.try_recv() {
+            Ok(out) => Ok(out),
+            Err(_) => self.frames.recv_timeout(timeout),
+        }
+    }
+
+    // Forwards input from `frontend` and plays back the output until the
+    // emulation ends.
+    pub fn run_ui(mut self, frontend: &mut dyn Frontend) -> (Chip, RunStats) {
+        loop {
+            for e in frontend.poll_events() {
+                self.send(e);
+            }
+            for out in self.output.try_iter() {
+                show(frontend, out);
+            }
+            match self.frames.recv_timeout(UI_POLL_INTERVAL) {
+                Ok(out) => {
+                    show(frontend, out);
+                    for out in self.frames.try_iter() {
+                        show(frontend, out);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => {
+                    // What was sent before the end.
+                    for out in self.output.try_iter() {
+                        show(frontend, out);
+                    }
+                    break;
+                },
+            }
+        }
+        self.join()
+    }
+
+    // Asks the emulation to quit and waits for it.
+    pub fn shutdown(mut self) -> (Chip, RunStats) {
+        self.send(Event::Quit);
+        self.join()
+    }
+
+    fn join(&mut self) -> (Chip, RunStats) {
+        // Keep draining until the thread lets go of the queue.
+        while self.frames.recv().is_ok() {}
+        self.handle.take().unwrap().join().expect("emulation thread panicked")
+    }
+}
+
+impl Drop for EmuThread {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.send(Event::Quit);
+            while self.frames.recv().is_ok() {}
+            let _ = self.handle.take().unwrap().join();
+        }
+    }
+}
+
+fn show(frontend: &mut dyn Frontend, out: Output) {
+    match out {
+        Output::Frame(frame, None) => frontend.present(&frame),
+        Output::Frame(frame, Some(overlay)) => frontend.present_overlay(&frame, &overlay),
+        Output::Sound(on) => frontend.set_sound(on),
+        Output::Play(sound) => frontend.play_sound(sound),
+        Output::Title(title) => frontend.set_title(&title),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{EmuThread, Output, FRAME_QUEUE};
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::playlist::Playlist;
+    use crate::profile::Profile;
+    use crate::testing::{deterministic, fixtures, TestFrontend};
+    use crate::runner::Event;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Chip>();
+        assert_send::<Playlist>();
+    }
+
+    #[test]
+    fn frames_then_quit() {
+        let (chip, playlist) = fixtures();
+        let emu = EmuThread::spawn(chip, playlist, deterministic());
+
+        assert!(matches!(emu.recv_timeout(TIMEOUT), Ok(Output::Frame(..))));
+        let (chip, stats) = emu.shutdown();
+        assert!(stats.frames() >= 1);
+        assert!(chip.instructions() >= 10);
+    }
+
+    // A UI that doesn't keep up loses frames but doesn't stall the emulation.
+    #[test]
+    fn drops_frames() {
+        let (chip, playlist) = fixtures();
+        let emu = EmuThread::spawn(chip, playlist, deterministic());
+
+        std::thread::sleep(Duration::from_millis(100));
+        let (_, stats) = emu.shutdown();
+        assert!(stats.frames() > 10 * FRAME_QUEUE as u64);
+    }
+
+    // Nor does it stall on titles it isn't reading.
+    #[test]
+    fn titles_dont_block() {
+        let (chip, playlist) = fixtures();
+        let emu = EmuThread::spawn(chip, playlist, deterministic());
+        for _ in 0..10 {
+            emu.send(Event::NextRom);
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+        let (_, stats) = emu.shutdown();
+        assert!(stats.frames() > 10 * FRAME_QUEUE as u64);
+    }
+
+    #[test]
+    fn run_ui() {
+        let (chip, playlist) = fixtures();
+        let emu = EmuThread::spawn(chip, playlist, deterministic());
+        let mut frontend = TestFrontend::new(vec![vec![Event::NextRom], vec![]]);
+
+        emu.run_ui(&mut frontend);
+        assert_eq!(frontend.titles, vec!["Chip-8 emulator - draw_1.ch8"]);
+    }
+
+    #[test]
+    fn ends_on_chip_error() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[0x00, 0xEE], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let emu = EmuThread::spawn(chip, Playlist::new(&[]).unwrap(), deterministic());
+        let mut frontend = TestFrontend::new(vec![vec![]; 100_000]);
+
+        let (_, stats) = emu.run_ui(&mut frontend);
+        assert_eq!(stats.error(), Some(&ChipError::StackUnderflow { pc: 0x200 }));
+        // Ended without the frontend quitting.
+        assert!(!frontend.events.is_empty());
+    }
+
+    #[test]
+    fn drop_stops_thread() {
+        let (chip, playlist) = fixtures();
+        let emu = EmuThread::spawn(chip, playlist, deterministic());
+        assert!(emu.recv_timeout(TIMEOUT).is_ok());
+        drop(emu);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io;
+
+// Everything that can go wrong inside the emulator core.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChipError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // `addr` is the first byte outside the RAM.
+    RamOutOfBounds { addr: u32 },
+    // LD B, LD [I] or LD Vx, [I] at `pc` would access `start..end`, past
+    // `limit`, the end of RAM or the profile's max_address.
+    RangeOutOfBounds { pc: u16, instr: String, start: u32, end: u32, limit: u32 },
+    // The instruction at `pc` would move PC past the end of RAM.
+    PcOutOfBounds { pc: u16 },
+    // CALL with all stack levels in use.
+    StackOverflow { pc: u16 },
+    // RET with an empty stack.
+    StackUnderflow { pc: u16 },
+    RomTooLarge { size: usize, max: usize },
+    InvalidProfile(String),
+    // A ChipState that doesn't fit the machine.
+    InvalidState(String),
+    // Reading a save state or replay file, see statefile::unpack().
+    NotAStateFile,
+    UnsupportedStateVersion { major: u8, minor: u8 },
+    StateChecksum,
+    StateForOtherRom,
+}
+
+impl fmt::Display for ChipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChipError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:#06x} at {:#05x}", opcode, pc),
+            ChipError::RamOutOfBounds { addr } => write!(f, "RAM access out of bounds at {:#x}", addr),
+            ChipError::RangeOutOfBounds { pc, instr, start, end, limit } =>
+                write!(f, "{} at {:#05x} accesses {:#x}-{:#x}, past the end of memory at {:#x}", instr, pc, start, end - 1, limit),
+            ChipError::PcOutOfBounds { pc } => write!(f, "PC past the end of RAM after {:#05x}", pc),
+            ChipError::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            ChipError::StackUnderflow { pc } => write!(f, "stack underflow at {:#05x}", pc),
+            ChipError::RomTooLarge { size, max } => write!(f, "ROM is too large: {} bytes, max {} bytes", size, max),
+            ChipError::InvalidProfile(name) => write!(f, "invalid profile '{}'", name),
+            ChipError::InvalidState(message) => write!(f, "invalid state: {}", message),
+            ChipError::NotAStateFile => write!(f, "not a save state or replay file"),
+            ChipError::UnsupportedStateVersion { major, minor } => write!(f, "file format {}.{} is not supported", major, minor),
+            ChipError::StateChecksum => write!(f, "file is damaged, the checksum doesn't match"),
+            ChipError::StateForOtherRom => write!(f, "save state is for a different ROM"),
+        }
+    }
+}
+
+impl ChipError {
+    // Process exit status for a run that ended with this error. 1 is left
+    // for I/O and argument errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ChipError::InvalidProfile(_) => 2,
+            ChipError::UnknownOpcode { .. } => 3,
+            ChipError::RamOutOfBounds { .. } | ChipError::RangeOutOfBounds { .. } | ChipError::PcOutOfBounds { .. } => 4,
+            ChipError::StackOverflow { .. } | ChipError::StackUnderflow { .. } => 5,
+            ChipError::RomTooLarge { .. } => 6,
+            ChipError::InvalidState(_) | ChipError::NotAStateFile | ChipError::UnsupportedStateVersion { .. } |
+                ChipError::StateChecksum | ChipError::StateForOtherRom => 1,
+        }
+    }
+}
+
+impl std::error::Error for ChipError {}
+
+impl From<ChipError> for io::Error {
+    fn from(e: ChipError) -> io::Error {
+        let kind = match e {
+            ChipError::InvalidProfile(_) => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChipError;
+
+    #[test]
+    fn display() {
+        assert_eq!(ChipError::UnknownOpcode { pc: 0x204, opcode: 0x5121 }.to_string(), "unknown opcode 0x5121 at 0x204");
+        assert_eq!(ChipError::RamOutOfBounds { addr: 0x1000 }.to_string(), "RAM access out of bounds at 0x1000");
+        let e = ChipError::RangeOutOfBounds { pc: 0x204, instr: "LD [I], V2".to_string(), start: 0xFFE, end: 0x1001, limit: 0x1000 };
+        assert_eq!(e.to_string(), "LD [I], V2 at 0x204 accesses 0xffe-0x1000, past the end of memory at 0x1000");
+        assert_eq!(ChipError::PcOutOfBounds { pc: 0xFFE }.to_string(), "PC past the end of RAM after 0xffe");
+        assert_eq!(ChipError::RomTooLarge { size: 5000, max: 3584 }.to_string(), "ROM is too large: 5000 bytes, max 3584 bytes");
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(ChipError::InvalidProfile("x".to_string()).exit_code(), 2);
+        assert_eq!(ChipError::StackOverflow { pc: 0 }.exit_code(), ChipError::StackUnderflow { pc: 0 }.exit_code());
+        assert_ne!(ChipError::UnknownOpcode { pc: 0, opcode: 0 }.exit_code(), ChipError::RamOutOfBounds { addr: 0 }.exit_code());
+    }
+
+    #[test]
+    fn into_io_error() {
+        let e: std::io::Error = ChipError::StackOverflow { pc: 0x200 }.into();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(e.get_ref().unwrap().downcast_ref::<ChipError>(), Some(&ChipError::StackOverflow { pc: 0x200 }));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::chip::Chip;
+use crate::clock::InstrClock;
+use crate::error::ChipError;
+use crate::framebuffer::Frame;
+
+// A frame's worth of emulation per item, scheduled like --deterministic:
+// a timer tick, then ips/60 instructions. Yields a copy of the screen at the
+// end of each frame and stops early if the chip fails.
+pub struct FrameStream {
+    chip: Chip,
+    clock: InstrClock,
+    frame: u64,
+    // (frame index, key, pressed), applied at the start of that frame.
+    keys: Vec<(u64, u8, bool)>,
+    error: Option<ChipError>,
+}
+
+impl FrameStream {
+    pub fn new(chip: Chip, ips: u32) -> FrameStream {
+        FrameStream {
+            chip,
+            clock: InstrClock::new(ips),
+            frame: 0,
+            keys: Vec::new(),
+            error: None,
+        }
+    }
+
+    // Presses `key` at the start of frame `frame`, counting from 0.
+    pub fn key_press(mut self, frame: u64, key: u8) -> FrameStream {
+        self.keys.push((frame, key, true));
+        self
+    }
+
+    pub fn key_release(mut self, frame: u64, key: u8) -> FrameStream {
+        self.keys.push((frame, key, false));
+        self
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    // Why the stream ended, if the chip failed.
+    pub fn error(&self) -> Option<&ChipError> {
+        self.error.as_ref()
+    }
+}
+
+impl Iterator for FrameStream {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        for &(_, key, pressed) in self.keys.iter().filter(|(f, _, _)| *f == self.frame) {
+            if pressed {
+                self.chip.key_press(key);
+            } else {
+                self.chip.key_unpress(key);
+            }
+        }
+
+        self.chip.cycle_timers();
+        loop {
+            if let Err(e) = self.chip.cycle() {
+                self.error = Some(e);
+                return None;
+            }
+            self.clock.advance();
+            if self.clock.frame_sync() {
+                break;
+            }
+        }
+
+        self.frame += 1;
+        Some(self.chip.get_frame().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameStream;
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::framebuffer::Frame;
+    use crate::profile::Profile;
+
+    fn same(a: &Frame, b: &Frame) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| a.iter().eq(b.iter()))
+    }
+
+    fn blank(f: &Frame) -> bool {
+        f.iter().all(|row| row.iter().all(|&p| p == 0))
+    }
+
+    fn chip(rom: &[u8], seed: u64) -> Chip {
+        let mut chip = Chip::new_seed(seed, Profile::modern());
+        chip.load_rom(rom, 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    #[test]
+    fn drawing() {
+        let rom = [
+            0xA0, 0x00, // LD I, 0x000
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x70, 0x08, // ADD V0, 8
+            0x12, 0x02, // JP 0x202
+        ];
+        // One instruction per frame.
+        let frames: Vec<Frame> = FrameStream::new(chip(&rom, 0), 60).take(5).collect();
+
+        assert_eq!(frames.len(), 5);
+        assert!(blank(&frames[0]));
+        assert!(!same(&frames[1], &frames[0]));
+        assert!(same(&frames[2], &frames[1]));
+        assert!(same(&frames[3], &frames[2]));
+        assert!(!same(&frames[4], &frames[3]));
+        // The second "0" is 8 pixels to the right of the first.
+        assert_eq!(frames[4][0_u32][9], 1);
+    }
+
+    #[test]
+    fn deterministic() {
+        let rom = [
+            0xC0, 0xFF, // RND V0, 0xFF
+            0xC1, 0xFF, // RND V1, 0xFF
+            0xA0, 0x00, // LD I, 0x000
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x12, 0x00, // JP 0x200
+        ];
+        let a: Vec<Frame> = FrameStream::new(chip(&rom, 7), 600).take(5).collect();
+        let b: Vec<Frame> = FrameStream::new(chip(&rom, 7), 600).take(5).collect();
+        let c: Vec<Frame> = FrameStream::new(chip(&rom, 8), 600).take(5).collect();
+
+        assert!(a.iter().zip(&b).all(|(a, b)| same(a, b)));
+        assert!(!a.iter().zip(&c).all(|(a, c)| same(a, c)));
+    }
+
+    #[test]
+    fn scripted_keys() {
+        let rom = [
+            0xF0, 0x0A, // LD V0, K
+            0xF0, 0x29, // LD F, V0
+            0xD1, 0x15, // DRW V1, V1, 5
+            0x12, 0x06, // JP 0x206
+        ];
+        let mut stream = FrameStream::new(chip(&rom, 0), 60).key_press(2, 0x7).key_release(3, 0x7);
+        let frames: Vec<Frame> = stream.by_ref().take(5).collect();
+
+        assert!(frames[..4].iter().all(blank));
+        assert!(!blank(&frames[4]));
+        assert_eq!(stream.chip().regs().vx[0_u8], 0x7);
+    }
+
+    #[test]
+    fn stops_on_error() {
+        let mut stream = FrameStream::new(chip(&[0x00, 0xEE], 0), 600);
+        assert!(stream.next().is_none());
+        assert_eq!(stream.error(), Some(&ChipError::StackUnderflow { pc: 0x200 }));
+        assert!(stream.next().is_none());
+    }
+}
@@ -1,11 +1,87 @@
+use std::ops::Index;
+use std::ops::IndexMut;
+use std::slice::{ChunksExact, ChunksExactMut};
+
 use crate::arch;
-use crate::util;
 
-// Each pixel is stored as 1 or 0 value.
+// Each pixel is stored as 1 or 0 value, row after row.
 // Waste of memory, but OK to start with.
-pub type Frame = util::Array<util::Array<u32, {arch::DISPLAY_WIDTH as usize}>, {arch::DISPLAY_HEIGHT as usize}>;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame::with_size(arch::DISPLAY_WIDTH, arch::DISPLAY_HEIGHT)
+    }
+
+    pub fn with_size(width: u32, height: u32) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // The rows, top to bottom.
+    pub fn iter(&self) -> ChunksExact<'_, u32> {
+        self.pixels.chunks_exact(self.width as usize)
+    }
+
+    pub fn iter_mut(&mut self) -> ChunksExactMut<'_, u32> {
+        self.pixels.chunks_exact_mut(self.width as usize)
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-pub struct Framebuffer {
+// Indexing by row number gives the row.
+macro_rules! impl_index_row {
+    ($t:ty) => {
+        impl Index<$t> for Frame {
+            type Output = [u32];
+
+            fn index(&self, y: $t) -> &[u32] {
+                let start = y as usize * self.width as usize;
+                &self.pixels[start..start + self.width as usize]
+            }
+        }
+
+        impl IndexMut<$t> for Frame {
+            fn index_mut(&mut self, y: $t) -> &mut [u32] {
+                let start = y as usize * self.width as usize;
+                &mut self.pixels[start..start + self.width as usize]
+            }
+        }
+    }
+}
+
+impl_index_row!(u32);
+impl_index_row!(u16);
+impl_index_row!(u8);
+impl_index_row!(usize);
+impl_index_row!(i32);
+
+pub(crate) struct Framebuffer {
     frame: Frame,
 }
 
@@ -16,6 +92,20 @@ impl Framebuffer {
         }
     }
 
+    pub fn with_size(width: u32, height: u32) -> Self {
+        Framebuffer {
+            frame: Frame::with_size(width, height),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame.height()
+    }
+
     pub fn clear(&mut self) {
         self.frame.clear();
     }
@@ -24,31 +114,40 @@ impl Framebuffer {
         &self.frame
     }
 
-    #[cfg(test)]
-    fn fill_frame_u8(&mut self, v: u8) {
-        for i in 0..arch::DISPLAY_HEIGHT {
-            for j in 0..arch::DISPLAY_WIDTH {
-                let shift = 7 - (j % 8);
-                let mask = 1 << shift;
-                let set: bool = (v & mask) != 0;
+    pub fn set_frame(&mut self, frame: &Frame) {
+        self.frame.clone_from(frame);
+    }
 
-                self.frame[i][j] = if set { 1 } else { 0 };
-            }
+    // Off outside the screen.
+    pub fn get_pixel(&self, x: u32, y: u32) -> bool {
+        x < self.width() && y < self.height() && self.frame[y][x as usize] == 1
+    }
+
+    // Sets a pixel without drawing, for tools and test setup. Returns false
+    // and changes nothing outside the screen.
+    pub fn set_pixel(&mut self, x: u32, y: u32, on: bool) -> bool {
+        if x >= self.width() || y >= self.height() {
+            return false;
         }
+        self.frame[y][x as usize] = on as u32;
+        true
     }
 
+    // The start is taken as it is, see DrawOrigin for bringing it onto the
+    // screen. A sprite starting off the screen draws nothing.
     pub fn draw_sprite(&mut self, sprite: &[u8], start_x: u32, start_y: u32, colisions: &mut bool) {
         *colisions = false;
 
-        // Start position wraps.
-        let start_x = start_x % arch::DISPLAY_WIDTH;
-        let start_y = start_y % arch::DISPLAY_HEIGHT;
+        let (width, height) = (self.width(), self.height());
+        if start_x >= width || start_y >= height {
+            return;
+        }
 
         for (n, s) in sprite.iter().enumerate() {
             let frame_y = start_y + n as u32;
 
             // Drawing should be clipped.
-            if frame_y >= arch::DISPLAY_HEIGHT {
+            if frame_y >= height {
                 break;
             }
 
@@ -56,7 +155,7 @@ impl Framebuffer {
                 let frame_x = start_x + x;
 
                 // Drawing should be clipped.
-                if frame_x >= arch::DISPLAY_WIDTH {
+                if frame_x >= width {
                     break;
                 }
 
@@ -64,37 +163,31 @@ impl Framebuffer {
                 let flip_bit: bool = s & bit_mask != 0;
 
                 if flip_bit {
-                    let frame_bit = self.frame[frame_y][frame_x];
-                    *colisions = frame_bit == 1;
+                    let frame_bit = self.frame[frame_y][frame_x as usize];
+                    // Any lit pixel turned off collides, not just the last.
+                    *colisions |= frame_bit == 1;
 
-                    self.frame[frame_y][frame_x] = 1 - frame_bit;
+                    self.frame[frame_y][frame_x as usize] = 1 - frame_bit;
                 }
             }
         }
     }
+}
 
-    #[cfg(test)]
-    pub fn print_screen(&self) {
-        let out: &Frame = self.get_frame();
-        print!("[");
-        for row in out.iter() {
-            for col in row.iter() {
-                if *col == 1 {
-                    print!("*");
-                } else {
-                    print!(" ");
-                }
-            }
-            println!("]");
-            print!("[");
-        }
-        println!("]");
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Framebuffer;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::{Frame, Framebuffer};
+    use crate::profile::DrawOrigin;
+    use crate::snapshot::assert_frame_snapshot;
 
     fn match_screen(d: &Framebuffer, pixel: u32) -> bool {
         for row in d.frame.iter() {
@@ -113,6 +206,32 @@ mod tests {
         assert!(match_screen(&d, 0x00));
     }
 
+    #[test]
+    fn pixels() {
+        let mut d = Framebuffer::with_size(128, 64);
+        assert!(d.set_pixel(127, 63, true));
+        assert!(d.set_pixel(5, 0, true));
+        assert!(d.get_pixel(127, 63));
+        assert!(d.get_pixel(5, 0));
+        assert!(!d.get_pixel(4, 0));
+        assert_eq!(d.get_frame()[63_u32][127], 1);
+
+        assert!(d.set_pixel(5, 0, false));
+        assert!(!d.get_pixel(5, 0));
+
+        // Outside the screen: ignored and off.
+        assert!(!d.set_pixel(128, 0, true));
+        assert!(!d.set_pixel(0, 64, true));
+        assert!(!d.get_pixel(128, 63));
+        assert!(!d.get_pixel(u32::MAX, 0));
+        assert_eq!(d.get_frame().iter().flatten().filter(|&&p| p == 1).count(), 1);
+
+        // Bounds follow the resolution.
+        let mut low = Framebuffer::with_size(64, 32);
+        assert!(!low.set_pixel(64, 31, true));
+        assert!(low.set_pixel(63, 31, true));
+    }
+
     // Sprite 3x8
     //    01234567
     // 0  ***  ***  E7
@@ -127,9 +246,8 @@ mod tests {
         let mut c = false;
 
         d.draw_sprite(&SPRITE_3X8, 0, 0, &mut c);
-        println!("draw_sprite");
-        d.print_screen();
-        assert_eq!(c, false);
+        assert_frame_snapshot!(d, "snapshots/draw_sprite_1.txt");
+        assert!(!c);
     }
 
     #[test]
@@ -139,9 +257,8 @@ mod tests {
         let mut c = false;
 
         d.draw_sprite(&SPRITE_3X8, arch::DISPLAY_WIDTH-1, 0, &mut c);
-        println!("draw_sprite");
-        d.print_screen();
-        assert_eq!(c, false);
+        assert_frame_snapshot!(d, "snapshots/draw_sprite_2.txt");
+        assert!(!c);
     }
 
     #[test]
@@ -151,20 +268,123 @@ mod tests {
         let mut c = false;
 
         d.draw_sprite(&SPRITE_3X8, arch::DISPLAY_WIDTH-1, arch::DISPLAY_HEIGHT-2, &mut c);
-        println!("draw_sprite");
-        d.print_screen();
-        assert_eq!(c, false);
+        assert_frame_snapshot!(d, "snapshots/draw_sprite_3.txt");
+        assert!(!c);
+    }
+
+    #[test]
+    fn sizes() {
+        for (width, height) in [(64, 32), (128, 64), (64, 64)] {
+            let mut d = Framebuffer::with_size(width, height);
+            assert_eq!((d.width(), d.height()), (width, height));
+            assert_eq!(d.get_frame().iter().count(), height as usize);
+            assert!(d.get_frame().iter().all(|row| row.len() == width as usize));
+            assert!(match_screen(&d, 0x00));
+
+            // Wraps at the edges of this size, not the default one.
+            let mut c = false;
+            let (x, y) = DrawOrigin::Wrap.origin(width as u8, (height + 1) as u8, width, height);
+            d.draw_sprite(&[0x80], x, y, &mut c);
+            assert_eq!(d.get_frame()[1_u32][0], 1);
+            d.draw_sprite(&[0x80], width, 0, &mut c);
+            assert!(d.get_frame()[0_u32].iter().all(|&p| p == 0));
+            d.draw_sprite(&[0xC0], width - 1, height - 1, &mut c);
+            assert_eq!(d.get_frame()[height - 1][width as usize - 1], 1);
+            assert!(!c);
+        }
+    }
+
+    // A frame of either size, from empty to full, most of them in between.
+    fn prefilled() -> impl Strategy<Value = Frame> {
+        (prop_oneof![Just((64, 32)), Just((128, 64))], 0.0..=1.0).prop_flat_map(|((width, height), density)| {
+            vec(prop::bool::weighted(density), (width * height) as usize).prop_map(move |pixels| {
+                let mut frame = Frame::with_size(width, height);
+                for (p, on) in frame.iter_mut().flatten().zip(pixels) {
+                    *p = on as u32;
+                }
+                frame
+            })
+        })
+    }
+
+    fn sprite() -> impl Strategy<Value = Vec<u8>> {
+        vec(any::<u8>(), 1..16)
+    }
+
+    // Clipped, the start anywhere up to a sprite past the edges.
+    fn clipped() -> impl Strategy<Value = (Frame, u32, u32)> {
+        prefilled().prop_flat_map(|frame| {
+            let (width, height) = (frame.width(), frame.height());
+            (Just(frame), 0..width + 16, 0..height + 16)
+        })
+    }
+
+    // Checks one draw of `sprite` at (x, y) against the frame before it.
+    fn check_draw(d: &mut Framebuffer, sprite: &[u8], x: u32, y: u32) {
+        let before = d.get_frame().clone();
+        let mut c = false;
+        d.draw_sprite(sprite, x, y, &mut c);
+        let after = d.get_frame().clone();
+
+        let mut collided = false;
+        for py in 0..d.height() {
+            for px in 0..d.width() {
+                let (was, is) = (before[py][px as usize], after[py][px as usize]);
+                let bit = px >= x && px - x < 8 && py >= y && py - y < sprite.len() as u32
+                    && sprite[(py - y) as usize] & (0x80 >> (px - x)) != 0;
+                // Sprite pixels flip, everything else stays as it was.
+                assert_eq!(is, was ^ bit as u32, "pixel ({}, {}) after drawing at ({}, {})", px, py, x, y);
+                collided |= bit && was == 1;
+            }
+        }
+        assert_eq!(c, collided, "collision drawing {:02x?} at ({}, {})", sprite, x, y);
+
+        // Drawing it again restores the frame.
+        d.draw_sprite(sprite, x, y, &mut c);
+        assert_eq!(*d.get_frame(), before);
+    }
+
+    #[test]
+    fn collision_before_last_pixel() {
+        let mut d = Framebuffer::new();
+        d.set_pixel(0, 0, true);
+        let mut c = false;
+        // Only the first of the two pixels was lit.
+        d.draw_sprite(&[0xC0], 0, 0, &mut c);
+        assert!(c);
+    }
+
+    proptest! {
+        #[test]
+        fn draw_properties((frame, x, y) in clipped(), sprite in sprite()) {
+            check_draw(&mut Framebuffer { frame }, &sprite, x, y);
+        }
+
+        // Wrapped from register values, as DRW does.
+        #[test]
+        fn draw_wrapped_properties(
+            frame in prefilled(),
+            sprite in sprite(),
+            (vx, vy) in any::<(u8, u8)>(),
+            origin in prop_oneof![Just(DrawOrigin::Wrap), Just(DrawOrigin::VipMask)],
+        ) {
+            let (x, y) = origin.origin(vx, vy, frame.width(), frame.height());
+            check_draw(&mut Framebuffer { frame }, &sprite, x, y);
+        }
     }
 
     #[test]
     fn draw_sprite_4() {
         let mut d = Framebuffer::new();
-        d.fill_frame_u8(0xff);
+        for y in 0..d.height() {
+            for x in 0..d.width() {
+                d.set_pixel(x, y, true);
+            }
+        }
         let mut c = false;
 
         d.draw_sprite(&SPRITE_3X8, 0, 0, &mut c);
-        println!("draw_sprite");
-        d.print_screen();
-        assert_eq!(c, true);
+        assert_frame_snapshot!(d, "snapshots/draw_sprite_4.txt");
+        assert!(c);
     }
 }
@@ -0,0 +1,193 @@
+// The instruction executor under arbitrary input, for fuzz/ and the
+// randomized tests below. Built for tests and with the fuzzing feature. An input is a machine setup followed by the
+// program:
+//
+//   0      quirks: bits 0-1 base profile (original, chip48, modern, modern),
+//          bit 2 VIP-masked draw origin, bit 3 PC wraps, bits 4-5 LD Vx, K
+//          key choice, bit 6 no max address, bit 7 NOP opcode from bytes 2-3
+//   1      machine size, modulo 3: 64x32 with 4K, 128x64 with 4K, 64x32
+//          with 64K
+//   2-3    NOP opcode
+//   4-19   V0 to VF
+//   20-21  I
+//   22     SP, modulo 17
+//   23-24  DT, ST
+//   25-56  the stack
+//   57-58  the keys held, a bit each
+//   59-    the program, loaded at 0x200 and cut to fit RAM
+//
+// Short inputs read as zeros. Whatever the input, running it must end in
+// one of the errors an instruction can raise, or run out of cycles, and
+// never panic.
+
+use crate::arch::Arch;
+use crate::chip::Chip;
+use crate::error::ChipError;
+use crate::profile::{DrawOrigin, KeyWait, PcOverflow, Profile, ProfileBuilder};
+use crate::rom;
+
+pub const HEADER_SIZE: usize = 59;
+// Cycles an input runs for at most, the timers ticking every TIMER_CYCLES.
+pub const MAX_CYCLES: u32 = 500;
+const TIMER_CYCLES: u32 = 10;
+
+fn profile(header: &[u8]) -> Profile {
+    let flags = header[0];
+    let base = match flags & 3 {
+        0 => Profile::original(),
+        1 => Profile::chip48(),
+        _ => Profile::modern(),
+    };
+    let arch = match header[1] % 3 {
+        0 => Arch::chip8(),
+        1 => Arch { display_width: 128, display_height: 64, ..Arch::chip8() },
+        _ => Arch { ram_size: 0x10000, ..Arch::chip8() },
+    };
+    let mut builder = ProfileBuilder::new(base).arch(arch);
+    if flags & 0x04 != 0 {
+        builder = builder.draw_origin(DrawOrigin::VipMask);
+    }
+    if flags & 0x08 != 0 {
+        builder = builder.pc_overflow(PcOverflow::Wrap);
+    }
+    builder = builder.key_wait(match (flags >> 4) & 3 {
+        0 => KeyWait::LowestIndex,
+        1 => KeyWait::MostRecent,
+        _ => KeyWait::FirstPressed,
+    });
+    if flags & 0x40 != 0 {
+        builder = builder.max_address(None);
+    }
+    if flags & 0x80 != 0 {
+        builder = builder.nop(Some(u16::from_be_bytes([header[2], header[3]])));
+    }
+    builder.build()
+}
+
+// The machine an input sets up, ready to run its program.
+pub fn machine(data: &[u8]) -> Chip {
+    let mut header = [0_u8; HEADER_SIZE];
+    let n = data.len().min(HEADER_SIZE);
+    header[..n].copy_from_slice(&data[..n]);
+    let program = &data[n..];
+
+    let mut chip = Chip::builder().profile(profile(&header)).seed(0).build().unwrap();
+    let mut state = chip.export_state();
+    state.v.copy_from_slice(&header[4..20]);
+    state.i = u16::from_be_bytes([header[20], header[21]]);
+    state.sp = header[22] % 17;
+    state.dt = header[23];
+    state.st = header[24];
+    for (n, addr) in state.stack.iter_mut().enumerate() {
+        *addr = u16::from_be_bytes([header[25 + 2 * n], header[26 + 2 * n]]);
+    }
+    let keys = u16::from_be_bytes([header[57], header[58]]);
+    for (k, down) in state.keys.iter_mut().enumerate() {
+        *down = (keys & (1 << k) != 0) as u8;
+    }
+    let start = rom::START_ADDR as usize;
+    let len = program.len().min(state.ram.len() - start);
+    state.ram[start..start + len].copy_from_slice(&program[..len]);
+    state.pc = start as u16;
+    chip.import_state(state).unwrap();
+    chip
+}
+
+// The errors running a program may end in.
+pub fn is_execution_error(e: &ChipError) -> bool {
+    matches!(e, ChipError::UnknownOpcode { .. } | ChipError::RamOutOfBounds { .. } | ChipError::RangeOutOfBounds { .. }
+        | ChipError::PcOutOfBounds { .. } | ChipError::StackOverflow { .. } | ChipError::StackUnderflow { .. })
+}
+
+// Runs an input for up to MAX_CYCLES. Returns the cycles run and the error
+// that stopped it, panicking on an error no instruction should raise.
+pub fn execute(data: &[u8]) -> (u32, Option<ChipError>) {
+    let mut chip = machine(data);
+    for cycle in 0..MAX_CYCLES {
+        if cycle % TIMER_CYCLES == 0 {
+            chip.cycle_timers();
+        }
+        if let Err(e) = chip.cycle() {
+            assert!(is_execution_error(&e), "{:?} from a program", e);
+            return (cycle, Some(e));
+        }
+    }
+    (MAX_CYCLES, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::{select, Index};
+
+    use super::{execute, machine, HEADER_SIZE};
+    use crate::error::ChipError;
+    use crate::instr::opcodes;
+
+    fn corpus() -> Vec<(String, Vec<u8>)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/execute");
+        let mut seeds: Vec<(String, Vec<u8>)> = std::fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| (p.file_name().unwrap().to_string_lossy().into_owned(), std::fs::read(&p).unwrap()))
+            .collect();
+        seeds.sort();
+        seeds
+    }
+
+    // The corpus has a seed per opcode, named by its pattern.
+    #[test]
+    fn corpus_covers_opcodes() {
+        let seeds = corpus();
+        let mut patterns: Vec<&str> = opcodes().iter().map(|o| o.pattern).collect();
+        patterns.sort();
+        assert_eq!(seeds.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), patterns);
+        for (name, data) in &seeds {
+            let opcode = u16::from_be_bytes([data[HEADER_SIZE], data[HEADER_SIZE + 1]]);
+            assert!(opcodes().iter().find(|o| o.pattern == name).unwrap().matches(opcode), "{}", name);
+            execute(data);
+        }
+    }
+
+    // A seed with up to 7 bytes changed.
+    fn mutated_seed() -> impl Strategy<Value = Vec<u8>> {
+        (select(corpus()), vec(any::<(Index, u8)>(), 0..8)).prop_map(|((_, mut data), changes)| {
+            for (at, b) in changes {
+                let at = at.index(data.len());
+                data[at] = b;
+            }
+            data
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        // Stands in for cargo fuzz where it isn't installed: the seeds with
+        // bytes changed, and random programs.
+        #[test]
+        fn random_inputs(data in prop_oneof![mutated_seed(), vec(any::<u8>(), 0..200)]) {
+            execute(&data);
+        }
+    }
+
+    #[test]
+    fn setup() {
+        let mut data = vec![0_u8; HEADER_SIZE];
+        data[1] = 1; // 128x64
+        data[4] = 0x12; // V0
+        data[21] = 0x34; // I
+        data[22] = 20; // SP, wraps to 3
+        data[57] = 0x80; // key F
+        data.extend_from_slice(&[0x12, 0x00]); // JP 0x200
+        let chip = machine(&data);
+        assert_eq!((chip.v(0), chip.i(), chip.sp(), chip.pc()), (0x12, 0x34, 3, 0x200));
+        assert_eq!(chip.profile().arch().display_width, 128);
+        assert!(chip.keypad().is_down(0xF));
+        assert_eq!(execute(&data).1, None);
+        // Nothing but the header is a machine running zeros.
+        assert_eq!(execute(&[]).1, Some(ChipError::UnknownOpcode { pc: 0x200, opcode: 0 }));
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+use crate::chip::Chip;
+use crate::clock::InstrClock;
+use crate::error::ChipError;
+use crate::framebuffer::Frame;
+use crate::util;
+
+// FNV-1a 64 of the pixels, one byte each, row after row.
+pub fn frame_hash(frame: &Frame) -> u64 {
+    let pixels: Vec<u8> = frame.iter().flatten().map(|&p| p as u8).collect();
+    util::fnv1a64(&pixels)
+}
+
+// A row of text per pixel row, '#' for lit pixels and '.' for dark ones.
+pub fn render(frame: &Frame) -> String {
+    let mut out = String::new();
+    for row in frame.iter() {
+        out.extend(row.iter().map(|&p| if p != 0 { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+// Runs `cycles` instructions, scheduled like --deterministic at `ips`.
+pub fn run(chip: &mut Chip, cycles: u64, ips: u32) -> Result<(), ChipError> {
+    let mut clock = InstrClock::new(ips);
+    for _ in 0..cycles {
+        if clock.frame_sync() {
+            chip.cycle_timers();
+        }
+        chip.cycle()?;
+        clock.advance();
+    }
+    Ok(())
+}
+
+// What `chip hash` prints: the state hash and the frame hash, 16 hex digits
+// each. Scripts compare it across versions, so it must not change.
+pub fn hash_line(chip: &Chip) -> String {
+    format!("{:016x} {:016x}", chip.state_hash(), frame_hash(chip.get_frame()))
+}
+
+// Runs like run() and returns the golden file for the screen left:
+//
+//   # <name> after <cycles> cycles
+//   hash <frame_hash, 16 hex digits>
+//   <render>
+pub fn capture(chip: &mut Chip, name: &str, cycles: u64, ips: u32) -> Result<String, ChipError> {
+    run(chip, cycles, ips)?;
+    let frame = chip.get_frame();
+    let mut out = String::new();
+    writeln!(out, "# {} after {} cycles", name, cycles).unwrap();
+    writeln!(out, "hash {:016x}", frame_hash(frame)).unwrap();
+    out.push_str(&render(frame));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{capture, frame_hash, hash_line, run};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+    use crate::rom;
+
+    #[test]
+    fn golden_file() {
+        let rom = [
+            0x60, 0x0A, // LD V0, 0xA
+            0xF0, 0x29, // LD F, V0
+            0xD1, 0x15, // DRW V1, V1, 5
+            0x12, 0x06, // JP 0x206
+        ];
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&rom).build().unwrap();
+        let golden = capture(&mut chip, "a.ch8", 50, 600).unwrap();
+        let lines: Vec<&str> = golden.lines().collect();
+        assert_eq!(lines[0], "# a.ch8 after 50 cycles");
+        let hash = lines[1].strip_prefix("hash ").unwrap();
+        assert_eq!(hash.len(), 16);
+        assert_eq!(u64::from_str_radix(hash, 16).unwrap(), frame_hash(chip.get_frame()));
+
+        let screen = &lines[2..];
+        assert_eq!(screen.len(), 32);
+        assert!(screen.iter().all(|row| row.len() == 64 && row.chars().all(|c| c == '#' || c == '.')));
+        // The "A" glyph, in the top left corner.
+        assert!(screen[0].starts_with(".##....."));
+        assert!(screen[2].starts_with("####...."));
+        assert!(screen[5].chars().all(|c| c == '.'));
+    }
+
+    // Pinned on purpose: this fails whenever the core's behavior changes,
+    // and the new line has to be checked and copied in by hand.
+    #[test]
+    fn pinned_hash() {
+        let mut chip = Chip::builder().profile(Profile::modern()).seed(1).build().unwrap();
+        rom::start(&mut chip, Path::new("rom/tests/ibm.ch8"), &[]).unwrap();
+        run(&mut chip, 1000, 600).unwrap();
+        assert_eq!(hash_line(&chip), "e6de3b4e6a910510 1f1d341cab07e169");
+    }
+}
@@ -0,0 +1,84 @@
+use crate::chip::Chip;
+
+pub const MESSAGE: [&str; 2] = ["PROGRAM ENDED", "PRESS A KEY"];
+
+// "Press any key to continue" once the program halts.
+pub struct WaitOnHalt {
+    waiting: bool,
+}
+
+impl WaitOnHalt {
+    pub fn new() -> WaitOnHalt {
+        WaitOnHalt {
+            waiting: false,
+        }
+    }
+
+    // Called after each instruction. Returns true when the program has just
+    // halted. Waiting stops by itself if the chip leaves the halt, e.g. when
+    // another ROM is started.
+    pub fn check(&mut self, chip: &Chip) -> bool {
+        let was_waiting = self.waiting;
+        self.waiting = chip.is_halted();
+        self.waiting && !was_waiting
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    // Returns true when the key press should end the run.
+    pub fn key_press(&self) -> bool {
+        self.waiting
+    }
+}
+
+impl Default for WaitOnHalt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaitOnHalt;
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    #[test]
+    fn halt_then_key_quits() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x01, // LD V0, 0x01
+            0x12, 0x02, // JP 0x202
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+
+        let mut halt = WaitOnHalt::new();
+        assert!(!halt.check(&chip));
+        assert!(!halt.key_press());
+
+        chip.cycle().unwrap();
+        assert!(halt.check(&chip));
+        assert!(halt.is_waiting());
+
+        // Still halted, but only reported once.
+        chip.cycle().unwrap();
+        assert!(!halt.check(&chip));
+        assert!(halt.key_press());
+    }
+
+    #[test]
+    fn leaves_halt() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.load_rom(&[0x12, 0x00], 0x200).unwrap();
+        chip.set_pc(0x200);
+
+        let mut halt = WaitOnHalt::new();
+        assert!(halt.check(&chip));
+
+        chip.reset();
+        assert!(!halt.check(&chip));
+        assert!(!halt.key_press());
+    }
+}
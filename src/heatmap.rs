@@ -0,0 +1,106 @@
+use std::fmt::Write;
+
+use crate::disasm::Line;
+
+// Executions per instruction address. One counter per byte of RAM, so that
+// counting an instruction is an index and an add.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heatmap {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Heatmap {
+    pub fn new(ram_size: u32) -> Heatmap {
+        Heatmap {
+            counts: vec![0; ram_size as usize],
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, pc: u16) {
+        if let Some(c) = self.counts.get_mut(pc as usize) {
+            *c += 1;
+            self.total += 1;
+        }
+    }
+
+    pub fn count(&self, addr: u16) -> u64 {
+        self.counts.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // The `n` most executed addresses with their counts, the most first and
+    // ties by address.
+    pub fn hottest(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut hot: Vec<(u16, u64)> = self.executed().collect();
+        hot.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        hot.truncate(n);
+        hot
+    }
+
+    // The addresses executed at least once and their counts.
+    pub fn executed(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        self.counts.iter().enumerate()
+            .filter(|(_, &c)| c > 0)
+            .map(|(addr, &c)| (addr as u16, c))
+    }
+
+    // The executed addresses and their counts as a JSON object, keyed by the
+    // address in hex: {"0x200": 1, "0x202": 60}.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (addr, count)) in self.executed().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write!(out, "\"{:#05x}\": {}", addr, count).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// A disassembly with the instructions that never executed marked by "!",
+// then how many did.
+pub fn coverage_report(lines: &[Line], heatmap: &Heatmap) -> String {
+    let mut out = String::new();
+    let (mut code, mut executed) = (0, 0);
+    for line in lines {
+        let missed = line.is_code && heatmap.count(line.addr) == 0;
+        if line.is_code {
+            code += 1;
+            executed += !missed as u32;
+        }
+        writeln!(out, "{} {}", if missed { '!' } else { ' ' }, line).unwrap();
+    }
+    let share = 100.0 * executed as f64 / code.max(1) as f64;
+    writeln!(out, "Executed {} of {} instructions, {:.1}%", executed, code, share).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Heatmap;
+
+    #[test]
+    fn counts() {
+        let mut h = Heatmap::new(0x1000);
+        for pc in [0x200, 0x204, 0x202, 0x204, 0x202, 0x204] {
+            h.record(pc);
+        }
+        // Past RAM, not counted.
+        h.record(0x1000);
+
+        assert_eq!(h.total(), 6);
+        assert_eq!(h.count(0x204), 3);
+        assert_eq!(h.count(0xFFFF), 0);
+        assert_eq!(h.hottest(2), vec![(0x204, 3), (0x202, 2)]);
+        assert_eq!(h.hottest(10).len(), 3);
+        assert_eq!(h.to_json(), "{\"0x200\": 1, \"0x202\": 2, \"0x204\": 3}\n");
+        assert_eq!(Heatmap::new(16).to_json(), "{}\n");
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use crate::chip::Chip;
+
+// The machine state before each of the last `depth` instructions, for
+// stepping back. The states are save states, so the RNG is restored along
+// with the rest and running forward again takes the same path.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    depth: usize,
+    states: VecDeque<Vec<u8>>,
+}
+
+impl History {
+    // A depth of 0 records nothing.
+    pub fn new(depth: usize) -> History {
+        History {
+            depth,
+            states: VecDeque::with_capacity(depth),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // How many instructions can be stepped back.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    // The state to be taken before an instruction and handed to record()
+    // once it ran. None when not recording, so that nothing is copied then.
+    pub fn snapshot(&self, chip: &Chip) -> Option<Vec<u8>> {
+        if self.depth == 0 {
+            return None;
+        }
+        Some(chip.save_state())
+    }
+
+    pub fn record(&mut self, state: Option<Vec<u8>>) {
+        if let Some(state) = state {
+            if self.states.len() == self.depth {
+                self.states.pop_front();
+            }
+            self.states.push_back(state);
+        }
+    }
+
+    // Restores the state before the last recorded instruction. Returns false
+    // when there is none left.
+    pub fn step_back(&mut self, chip: &mut Chip) -> bool {
+        match self.states.pop_back() {
+            Some(state) => {
+                chip.load_state(&state).expect("recorded state");
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    fn step(chip: &mut Chip, history: &mut History) {
+        let before = history.snapshot(chip);
+        chip.step().unwrap();
+        history.record(before);
+    }
+
+    #[test]
+    fn step_back_and_replay() {
+        let mut chip = Chip::builder().profile(Profile::modern()).seed(7).rom(&[
+            0xF2, 0x29, // LD F, V2
+            0xC0, 0x3F, // RND V0, 0x3f
+            0xC1, 0x1F, // RND V1, 0x1f
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x72, 0x01, // ADD V2, 0x1
+            0x12, 0x00, // JP 0x200
+        ]).build().unwrap();
+        let mut history = History::new(8);
+        let mut hashes = vec![chip.state_hash()];
+        for _ in 0..20 {
+            step(&mut chip, &mut history);
+            hashes.push(chip.state_hash());
+        }
+        assert_eq!(chip.draws(), 3);
+        assert_eq!(history.len(), 8);
+
+        for back in 1..=5 {
+            assert!(history.step_back(&mut chip));
+            assert_eq!(chip.state_hash(), hashes[20 - back]);
+        }
+        // Same random numbers and sprites the second time around.
+        for &hash in &hashes[16..=20] {
+            step(&mut chip, &mut history);
+            assert_eq!(chip.state_hash(), hash);
+        }
+
+        // As far back as the depth.
+        while history.step_back(&mut chip) {}
+        assert!(history.is_empty());
+        assert_eq!(chip.state_hash(), hashes[12]);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let mut history = History::new(0);
+        assert_eq!(history.snapshot(&chip), None);
+        step(&mut chip, &mut history);
+        assert!(!history.step_back(&mut chip));
+    }
+}
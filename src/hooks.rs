@@ -0,0 +1,183 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+
+use crate::chip::Chip;
+
+// A failed hook, with the script line when it comes from one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookError {
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// What a hook can do with the machine: read the registers and memory, and
+// press keys. Nothing else, in particular no drawing.
+pub struct HookApi<'a> {
+    chip: &'a mut Chip,
+}
+
+impl<'a> HookApi<'a> {
+    pub(crate) fn new(chip: &'a mut Chip) -> HookApi<'a> {
+        HookApi { chip }
+    }
+
+    pub fn v(&self, x: u8) -> u8 {
+        self.chip.v(x)
+    }
+
+    pub fn i(&self) -> u16 {
+        self.chip.i()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.chip.pc()
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.chip.dt()
+    }
+
+    pub fn st(&self) -> u8 {
+        self.chip.st()
+    }
+
+    // None past the end of RAM.
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        self.chip.ram_slice(addr as u32..addr as u32 + 1).map(|b| b[0])
+    }
+
+    // All of RAM.
+    pub fn memory(&self) -> &[u8] {
+        self.chip.ram().as_slice()
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.chip.key_press(key);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.chip.key_unpress(key);
+    }
+}
+
+// Callbacks from the run loop, for ROM specific trainers, input bots and
+// assertions. A script engine implements this for its scripts.
+pub trait Hooks: Send {
+    // Once a frame, after the input events.
+    fn on_frame(&mut self, _api: &mut HookApi) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    // After each executed instruction.
+    fn on_instruction(&mut self, _pc: u16, _opcode: u16) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    // On a key event from the frontend, before the chip sees it.
+    fn on_key(&mut self, _key: u8, _pressed: bool) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+// Hooks as RunOptions holds them, shared with whoever set them up.
+pub type SharedHooks = Arc<Mutex<dyn Hooks>>;
+
+// The hooks of a run. The first error is logged and turns them off, the
+// emulation goes on without them.
+pub(crate) struct ActiveHooks {
+    hooks: Option<SharedHooks>,
+}
+
+impl ActiveHooks {
+    pub(crate) fn new(hooks: Option<SharedHooks>) -> ActiveHooks {
+        ActiveHooks { hooks }
+    }
+
+    pub(crate) fn is_on(&self) -> bool {
+        self.hooks.is_some()
+    }
+
+    fn call(&mut self, f: impl FnOnce(&mut dyn Hooks) -> Result<(), HookError>) {
+        let result = match &self.hooks {
+            Some(h) => f(&mut *h.lock().unwrap()),
+            None => return,
+        };
+        if let Err(e) = result {
+            error!("Hook failed, {}. Hooks are off for the rest of the run.", e);
+            self.hooks = None;
+        }
+    }
+
+    pub(crate) fn frame(&mut self, chip: &mut Chip) {
+        self.call(|h| h.on_frame(&mut HookApi::new(chip)));
+    }
+
+    pub(crate) fn instruction(&mut self, pc: u16, opcode: u16) {
+        self.call(|h| h.on_instruction(pc, opcode));
+    }
+
+    pub(crate) fn key(&mut self, key: u8, pressed: bool) {
+        self.call(|h| h.on_key(key, pressed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{ActiveHooks, HookApi, HookError, Hooks};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    // Fails on the second frame.
+    #[derive(Default)]
+    struct Failing {
+        frames: u32,
+    }
+
+    impl Hooks for Failing {
+        fn on_frame(&mut self, api: &mut HookApi) -> Result<(), HookError> {
+            self.frames += 1;
+            if self.frames == 2 {
+                return Err(HookError { line: Some(3), message: format!("V0 is {}", api.v(0)) });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn error_turns_hooks_off() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let failing = Arc::new(Mutex::new(Failing::default()));
+        let mut hooks = ActiveHooks::new(Some(failing.clone()));
+        for _ in 0..4 {
+            hooks.frame(&mut chip);
+        }
+        assert!(!hooks.is_on());
+        assert_eq!(failing.lock().unwrap().frames, 2);
+        assert_eq!(HookError { line: Some(3), message: "V0 is 0".to_string() }.to_string(), "line 3: V0 is 0");
+    }
+
+    #[test]
+    fn api() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x6A, 0x07]).build().unwrap();
+        chip.step().unwrap();
+        let mut api = HookApi { chip: &mut chip };
+        assert_eq!((api.v(0xA), api.pc()), (7, 0x202));
+        assert_eq!(api.read(0x200), Some(0x6A));
+        assert_eq!(api.read(0xFFFF), None);
+        api.press(0xC);
+        assert!(chip.keypad().is_down(0xC));
+    }
+}
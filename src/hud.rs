@@ -0,0 +1,139 @@
+use crate::framebuffer::Frame;
+use crate::stats::RunStats;
+use crate::text;
+
+// The numbers are averaged over this long so they stay readable.
+const UPDATE_MS: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rates {
+    pub ips: f64,
+    pub fps: f64,
+    // Instructions per frame.
+    pub ipf: f64,
+}
+
+impl Rates {
+    pub fn lines(&self) -> [String; 3] {
+        [
+            format!("IPS {:.0}", self.ips),
+            format!("FPS {:.0}", self.fps),
+            format!("IPF {:.1}", self.ipf),
+        ]
+    }
+}
+
+// Instruction and frame rates drawn in the top left corner of the screen.
+pub struct Hud {
+    visible: bool,
+    window_start_ms: u32,
+    window_instructions: u64,
+    window_frames: u64,
+    rates: Option<Rates>,
+}
+
+impl Hud {
+    pub fn new(visible: bool, now_ms: u32) -> Hud {
+        Hud {
+            visible,
+            window_start_ms: now_ms,
+            window_instructions: 0,
+            window_frames: 0,
+            rates: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    #[cfg(test)]
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    #[cfg(test)]
+    pub fn rates(&self) -> Option<Rates> {
+        self.rates
+    }
+
+    // Recomputes the rates once a second from the run counters.
+    pub fn update(&mut self, now_ms: u32, stats: &RunStats) {
+        let elapsed = now_ms - self.window_start_ms;
+        if elapsed < UPDATE_MS {
+            return;
+        }
+        let instructions = stats.instructions() - self.window_instructions;
+        let frames = stats.frames() - self.window_frames;
+        self.rates = Some(Rates {
+            ips: 1000.0 * instructions as f64 / elapsed as f64,
+            fps: 1000.0 * frames as f64 / elapsed as f64,
+            ipf: instructions as f64 / frames.max(1) as f64,
+        });
+        self.window_start_ms = now_ms;
+        self.window_instructions = stats.instructions();
+        self.window_frames = stats.frames();
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        if !self.visible {
+            return;
+        }
+        let lines = match self.rates {
+            Some(r) => r.lines(),
+            None => ["IPS -".to_string(), "FPS -".to_string(), "IPF -".to_string()],
+        };
+        let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        text::draw_panel(frame, 0, 0, &lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hud, Rates};
+    use crate::framebuffer::Frame;
+    use crate::stats::RunStats;
+
+    #[test]
+    fn lines() {
+        let r = Rates { ips: 612.4, fps: 59.96, ipf: 10.2133 };
+        assert_eq!(r.lines(), ["IPS 612", "FPS 60", "IPF 10.2"]);
+
+        let r = Rates { ips: 0.0, fps: 0.0, ipf: 0.0 };
+        assert_eq!(r.lines(), ["IPS 0", "FPS 0", "IPF 0.0"]);
+    }
+
+    #[test]
+    fn update_once_a_second() {
+        let mut stats = RunStats::new(0);
+        let mut hud = Hud::new(true, 0);
+
+        // 600 instructions and 60 frames a second.
+        for ms in 1..=2000_u32 {
+            if ms % 5 < 3 {
+                stats.cycle(true);
+            }
+            if ms % 50 < 3 {
+                stats.frame_presented(ms);
+            }
+            hud.update(ms, &stats);
+            if ms == 999 {
+                assert_eq!(hud.rates(), None);
+            }
+        }
+        assert_eq!(hud.rates(), Some(Rates { ips: 600.0, fps: 60.0, ipf: 10.0 }));
+    }
+
+    #[test]
+    fn toggle() {
+        let mut hud = Hud::new(false, 0);
+        let mut frame = Frame::new();
+        hud.draw(&mut frame);
+        assert!(frame.iter().all(|row| row.iter().all(|&p| p == 0)));
+
+        hud.toggle();
+        assert!(hud.is_visible());
+        hud.draw(&mut frame);
+        assert!(frame.iter().any(|row| row.iter().any(|&p| p != 0)));
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::Path;
+
+use crate::chip::{Chip, RunStop};
+use crate::hooks::{HookApi, HookError, Hooks};
+
+// A key action of a --script file, e.g. "at frame 60 press 5".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Action {
+    pub frame: u64,
+    pub key: u8,
+    pub press: bool,
+}
+
+// None for blank lines and # comments.
+pub fn parse_line(line: &str) -> Result<Option<Action>, String> {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || format!("expected 'at frame N press|release KEY', got '{}'", line);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["at", "frame", frame, action @ ("press" | "release"), key] => {
+            let frame = frame.parse().map_err(|_| invalid())?;
+            let key = u8::from_str_radix(key, 16).ok().filter(|&k| k < 16).ok_or_else(|| format!("invalid key '{}', expected 0-F", key))?;
+            Ok(Some(Action { frame, key, press: *action == "press" }))
+        },
+        _ => Err(invalid()),
+    }
+}
+
+// The actions ordered by frame, those of the same frame as written.
+pub fn parse_script(text: &str) -> Result<Vec<Action>, String> {
+    let mut actions = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if let Some(a) = parse_line(line).map_err(|e| format!("line {}: {}", n + 1, e))? {
+            actions.push(a);
+        }
+    }
+    actions.sort_by_key(|a| a.frame);
+    Ok(actions)
+}
+
+// "expect pixel X Y on|off within N", for test drivers running a ROM
+// headless: the pixel at (X, Y) is lit, or dark, within N instructions. See
+// expect_pixel().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpectPixel {
+    pub x: u32,
+    pub y: u32,
+    pub on: bool,
+    pub within: u64,
+}
+
+pub fn parse_expect(line: &str) -> Result<ExpectPixel, String> {
+    let line = line.split('#').next().unwrap().trim();
+    let invalid = || format!("expected 'expect pixel X Y on|off within N', got '{}'", line);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["expect", "pixel", x, y, state @ ("on" | "off"), "within", n] => Ok(ExpectPixel {
+            x: x.parse().map_err(|_| invalid())?,
+            y: y.parse().map_err(|_| invalid())?,
+            on: *state == "on",
+            within: n.parse().map_err(|_| invalid())?,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+// Runs `chip` until `expect` holds, without timers. Returns the instructions
+// it took, 0 if it held already, or why it never did.
+pub fn expect_pixel(chip: &mut Chip, expect: &ExpectPixel) -> Result<u64, String> {
+    let (width, height) = (chip.get_frame().width(), chip.get_frame().height());
+    if expect.x >= width || expect.y >= height {
+        return Err(format!("pixel ({}, {}) is off the {}x{} screen", expect.x, expect.y, width, height));
+    }
+    let holds = |c: &Chip| c.get_pixel(expect.x, expect.y) == expect.on;
+    if holds(chip) {
+        return Ok(0);
+    }
+    let result = chip.run_until(expect.within, holds);
+    let state = if expect.on { "on" } else { "off" };
+    match result.stop {
+        _ if result.matched => Ok(result.cycles),
+        None => Err(format!("pixel ({}, {}) not {} after {} instructions", expect.x, expect.y, state, result.cycles)),
+        Some(RunStop::Halted { pc }) => Err(format!("halted at {:#05x} before pixel ({}, {}) was {}", pc, expect.x, expect.y, state)),
+        Some(RunStop::KeyWait { pc }) => Err(format!("waiting for a key at {:#05x} before pixel ({}, {}) was {}", pc, expect.x, expect.y, state)),
+        Some(RunStop::Error(e)) => Err(e.to_string()),
+    }
+}
+
+// Presses and releases keys at given frames, for playing a game the same way
+// every time. Frames count from the first one of the run, and with
+// --deterministic they fall on the same instructions on every run.
+pub struct InputScript {
+    actions: Vec<Action>,
+    next: usize,
+    frame: u64,
+}
+
+impl InputScript {
+    pub fn new(actions: Vec<Action>) -> InputScript {
+        InputScript { actions, next: 0, frame: 0 }
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<InputScript> {
+        let text = std::fs::read_to_string(path)?;
+        let actions = parse_script(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        Ok(InputScript::new(actions))
+    }
+
+    // True once every action fired.
+    pub fn is_done(&self) -> bool {
+        self.next == self.actions.len()
+    }
+}
+
+impl Hooks for InputScript {
+    fn on_frame(&mut self, api: &mut HookApi) -> Result<(), HookError> {
+        while let Some(a) = self.actions.get(self.next).filter(|a| a.frame <= self.frame) {
+            if a.press {
+                api.press(a.key);
+            } else {
+                api.release(a.key);
+            }
+            self.next += 1;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expect_pixel, parse_expect, parse_line, parse_script, Action, ExpectPixel, InputScript};
+    use crate::chip::Chip;
+    use crate::hooks::{HookApi, Hooks};
+    use crate::profile::Profile;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_line("at frame 60 press 5"), Ok(Some(Action { frame: 60, key: 5, press: true })));
+        assert_eq!(parse_line("  at frame 90 release F # fire"), Ok(Some(Action { frame: 90, key: 0xF, press: false })));
+        assert_eq!(parse_line("# start"), Ok(None));
+        assert_eq!(parse_line("at frame 1 press 10"), Err("invalid key '10', expected 0-F".to_string()));
+        assert!(parse_line("at frame x press 1").is_err());
+        assert!(parse_line("at frame 1 hold 1").is_err());
+        assert_eq!(parse_script("at frame 1 press 1\nat frame\n"), Err("line 2: expected 'at frame N press|release KEY', got 'at frame'".to_string()));
+
+        let actions = parse_script("at frame 9 release 2\n\nat frame 3 press 2\n").unwrap();
+        assert_eq!(actions.iter().map(|a| a.frame).collect::<Vec<_>>(), vec![3, 9]);
+    }
+
+    #[test]
+    fn expect() {
+        assert_eq!(parse_expect("expect pixel 10 5 on within 600"), Ok(ExpectPixel { x: 10, y: 5, on: true, within: 600 }));
+        assert_eq!(parse_expect("expect pixel 0 0 off within 1 # cleared"), Ok(ExpectPixel { x: 0, y: 0, on: false, within: 1 }));
+        assert!(parse_expect("expect pixel 0 0 lit within 1").is_err());
+        assert!(parse_expect("expect pixel 0 x on within 1").is_err());
+
+        // The top of a "0" drawn at (8, 4), then a halt.
+        let rom = [
+            0x60, 0x08, // LD V0, 8
+            0x61, 0x04, // LD V1, 4
+            0xF2, 0x29, // LD F, V2
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x12, 0x08, // JP 0x208
+        ];
+        let chip = || Chip::builder().profile(Profile::modern()).rom(&rom).build().unwrap();
+        let lit = parse_expect("expect pixel 9 4 on within 10").unwrap();
+        assert_eq!(expect_pixel(&mut chip(), &lit), Ok(4));
+        assert_eq!(expect_pixel(&mut chip(), &ExpectPixel { within: 3, ..lit }),
+            Err("pixel (9, 4) not on after 3 instructions".to_string()));
+        assert_eq!(expect_pixel(&mut chip(), &ExpectPixel { x: 8, ..lit }),
+            Err("halted at 0x208 before pixel (8, 4) was on".to_string()));
+        assert_eq!(expect_pixel(&mut chip(), &ExpectPixel { on: false, ..lit }), Ok(0));
+        assert_eq!(expect_pixel(&mut chip(), &ExpectPixel { x: 64, ..lit }),
+            Err("pixel (64, 4) is off the 64x32 screen".to_string()));
+    }
+
+    #[test]
+    fn fires_on_frames() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        let mut script = InputScript::new(parse_script(concat!(
+            "at frame 1 press 5\n",
+            "at frame 1 press A\n",
+            "at frame 3 release 5\n",
+        )).unwrap());
+
+        let mut down = Vec::new();
+        for _ in 0..5 {
+            script.on_frame(&mut HookApi::new(&mut chip)).unwrap();
+            down.push((chip.keypad().is_down(0x5), chip.keypad().is_down(0xA)));
+        }
+        assert_eq!(down, vec![(false, false), (true, true), (true, true), (false, true), (false, true)]);
+        assert!(script.is_done());
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub struct Instr {
     pub opcode: u16,
     pub c: u8,
@@ -8,6 +10,46 @@ pub struct Instr {
     pub nnn: u16,
 }
 
+// Decoded instruction. Operands are named after the opcode nibbles they come from;
+// register operands (x, y) are always 0..=15, see Instr::vx_index().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Cls,                            // 00E0
+    Ret,                            // 00EE
+    Jp { nnn: u16 },                // 1NNN
+    Call { nnn: u16 },              // 2NNN
+    SeVxNn { x: u8, nn: u8 },       // 3XNN
+    SneVxNn { x: u8, nn: u8 },      // 4XNN
+    SeVxVy { x: u8, y: u8 },        // 5XY0
+    LdVxNn { x: u8, nn: u8 },       // 6XNN
+    AddVxNn { x: u8, nn: u8 },      // 7XNN
+    LdVxVy { x: u8, y: u8 },        // 8XY0
+    OrVxVy { x: u8, y: u8 },        // 8XY1
+    AndVxVy { x: u8, y: u8 },       // 8XY2
+    XorVxVy { x: u8, y: u8 },       // 8XY3
+    AddVxVy { x: u8, y: u8 },       // 8XY4
+    SubVxVy { x: u8, y: u8 },       // 8XY5
+    ShrVxVy { x: u8, y: u8 },       // 8XY6
+    SubnVxVy { x: u8, y: u8 },      // 8XY7
+    ShlVxVy { x: u8, y: u8 },       // 8XYE
+    SneVxVy { x: u8, y: u8 },       // 9XY0
+    LdINnn { nnn: u16 },            // ANNN
+    JpV0Nnn { nnn: u16 },           // BNNN
+    RndVxNn { x: u8, nn: u8 },      // CXNN
+    Drw { x: u8, y: u8, n: u8 },    // DXYN
+    SkpVx { x: u8 },                // EX9E
+    SkpnVx { x: u8 },               // EXA1
+    LdVxDt { x: u8 },               // FX07
+    LdVxK { x: u8 },                // FX0A
+    LdDtVx { x: u8 },               // FX15
+    LdStVx { x: u8 },               // FX18
+    AddIVx { x: u8 },               // FX1E
+    LdFVx { x: u8 },                // FX29
+    LdBVx { x: u8 },                // FX33
+    LdIVx { x: u8 },                // FX55
+    LdVxI { x: u8 },                // FX65
+}
+
 impl Instr {
     pub fn new(opcode: u16) -> Self {
         Instr {
@@ -17,7 +59,246 @@ impl Instr {
             y: ((opcode & 0x00f0) >> 4) as u8,
             n: (opcode & 0x000f) as u8,
             nn: (opcode & 0x00ff) as u8,
-            nnn: (opcode & 0x0fff) as u16,
+            nnn: opcode & 0x0fff,
+        }
+    }
+
+    // Register operand indices. Always 0..=15: only the low nibble of the
+    // field is used, so they can index V0..VF directly.
+    pub fn vx_index(&self) -> u8 {
+        self.x & 0x0f
+    }
+
+    pub fn vy_index(&self) -> u8 {
+        self.y & 0x0f
+    }
+
+    pub fn operation(&self) -> Option<Operation> {
+        let (x, y) = (self.vx_index(), self.vy_index());
+        let op = match *self {
+            Instr { opcode: 0x00E0, .. } => Operation::Cls,
+            Instr { opcode: 0x00EE, .. } => Operation::Ret,
+            Instr { c: 0x1, nnn, .. } => Operation::Jp { nnn },
+            Instr { c: 0x2, nnn, .. } => Operation::Call { nnn },
+            Instr { c: 0x3, nn, .. } => Operation::SeVxNn { x, nn },
+            Instr { c: 0x4, nn, .. } => Operation::SneVxNn { x, nn },
+            Instr { c: 0x5, n: 0x0, .. } => Operation::SeVxVy { x, y },
+            Instr { c: 0x6, nn, .. } => Operation::LdVxNn { x, nn },
+            Instr { c: 0x7, nn, .. } => Operation::AddVxNn { x, nn },
+            Instr { c: 0x8, n: 0x0, .. } => Operation::LdVxVy { x, y },
+            Instr { c: 0x8, n: 0x1, .. } => Operation::OrVxVy { x, y },
+            Instr { c: 0x8, n: 0x2, .. } => Operation::AndVxVy { x, y },
+            Instr { c: 0x8, n: 0x3, .. } => Operation::XorVxVy { x, y },
+            Instr { c: 0x8, n: 0x4, .. } => Operation::AddVxVy { x, y },
+            Instr { c: 0x8, n: 0x5, .. } => Operation::SubVxVy { x, y },
+            Instr { c: 0x8, n: 0x6, .. } => Operation::ShrVxVy { x, y },
+            Instr { c: 0x8, n: 0x7, .. } => Operation::SubnVxVy { x, y },
+            Instr { c: 0x8, n: 0xE, .. } => Operation::ShlVxVy { x, y },
+            Instr { c: 0x9, n: 0x0, .. } => Operation::SneVxVy { x, y },
+            Instr { c: 0xA, nnn, .. } => Operation::LdINnn { nnn },
+            Instr { c: 0xB, nnn, .. } => Operation::JpV0Nnn { nnn },
+            Instr { c: 0xC, nn, .. } => Operation::RndVxNn { x, nn },
+            Instr { c: 0xD, n, .. } => Operation::Drw { x, y, n },
+            Instr { c: 0xE, nn: 0x9E, .. } => Operation::SkpVx { x },
+            Instr { c: 0xE, nn: 0xA1, .. } => Operation::SkpnVx { x },
+            Instr { c: 0xF, nn: 0x07, .. } => Operation::LdVxDt { x },
+            Instr { c: 0xF, nn: 0x0A, .. } => Operation::LdVxK { x },
+            Instr { c: 0xF, nn: 0x15, .. } => Operation::LdDtVx { x },
+            Instr { c: 0xF, nn: 0x18, .. } => Operation::LdStVx { x },
+            Instr { c: 0xF, nn: 0x1E, .. } => Operation::AddIVx { x },
+            Instr { c: 0xF, nn: 0x29, .. } => Operation::LdFVx { x },
+            Instr { c: 0xF, nn: 0x33, .. } => Operation::LdBVx { x },
+            Instr { c: 0xF, nn: 0x55, .. } => Operation::LdIVx { x },
+            Instr { c: 0xF, nn: 0x65, .. } => Operation::LdVxI { x },
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+// One row of the opcode reference: the pattern as written in the Operation
+// comments, with X/Y/N standing for operand nibbles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub pattern: &'static str,
+    pub mnemonic: &'static str,
+    pub description: &'static str,
+}
+
+// Kept in the same order as Operation. The decoder test below checks the two
+// agree.
+const OPCODES: [OpcodeInfo; 34] = [
+    OpcodeInfo { pattern: "00E0", mnemonic: "CLS", description: "Clear the screen." },
+    OpcodeInfo { pattern: "00EE", mnemonic: "RET", description: "Return from a subroutine." },
+    OpcodeInfo { pattern: "1NNN", mnemonic: "JP nnn", description: "Jump to nnn." },
+    OpcodeInfo { pattern: "2NNN", mnemonic: "CALL nnn", description: "Call the subroutine at nnn." },
+    OpcodeInfo { pattern: "3XNN", mnemonic: "SE Vx, nn", description: "Skip the next instruction if Vx equals nn." },
+    OpcodeInfo { pattern: "4XNN", mnemonic: "SNE Vx, nn", description: "Skip the next instruction if Vx does not equal nn." },
+    OpcodeInfo { pattern: "5XY0", mnemonic: "SE Vx, Vy", description: "Skip the next instruction if Vx equals Vy." },
+    OpcodeInfo { pattern: "6XNN", mnemonic: "LD Vx, nn", description: "Set Vx to nn." },
+    OpcodeInfo { pattern: "7XNN", mnemonic: "ADD Vx, nn", description: "Add nn to Vx, without carry." },
+    OpcodeInfo { pattern: "8XY0", mnemonic: "LD Vx, Vy", description: "Set Vx to Vy." },
+    OpcodeInfo { pattern: "8XY1", mnemonic: "OR Vx, Vy", description: "Set Vx to Vx OR Vy." },
+    OpcodeInfo { pattern: "8XY2", mnemonic: "AND Vx, Vy", description: "Set Vx to Vx AND Vy." },
+    OpcodeInfo { pattern: "8XY3", mnemonic: "XOR Vx, Vy", description: "Set Vx to Vx XOR Vy." },
+    OpcodeInfo { pattern: "8XY4", mnemonic: "ADD Vx, Vy", description: "Add Vy to Vx, VF = carry." },
+    OpcodeInfo { pattern: "8XY5", mnemonic: "SUB Vx, Vy", description: "Subtract Vy from Vx, VF = not borrow." },
+    OpcodeInfo { pattern: "8XY6", mnemonic: "SHR Vx, Vy", description: "Shift right by one into Vx, VF = bit shifted out." },
+    OpcodeInfo { pattern: "8XY7", mnemonic: "SUBN Vx, Vy", description: "Set Vx to Vy minus Vx, VF = not borrow." },
+    OpcodeInfo { pattern: "8XYE", mnemonic: "SHL Vx, Vy", description: "Shift left by one into Vx, VF = bit shifted out." },
+    OpcodeInfo { pattern: "9XY0", mnemonic: "SNE Vx, Vy", description: "Skip the next instruction if Vx does not equal Vy." },
+    OpcodeInfo { pattern: "ANNN", mnemonic: "LD I, nnn", description: "Set I to nnn." },
+    OpcodeInfo { pattern: "BNNN", mnemonic: "JP V0, nnn", description: "Jump to nnn plus V0." },
+    OpcodeInfo { pattern: "CXNN", mnemonic: "RND Vx, nn", description: "Set Vx to a random number AND nn." },
+    OpcodeInfo { pattern: "DXYN", mnemonic: "DRW Vx, Vy, n", description: "Draw the n-byte sprite at I at (Vx, Vy), VF = collision." },
+    OpcodeInfo { pattern: "EX9E", mnemonic: "SKP Vx", description: "Skip the next instruction if key Vx is pressed." },
+    OpcodeInfo { pattern: "EXA1", mnemonic: "SKPN Vx", description: "Skip the next instruction if key Vx is not pressed." },
+    OpcodeInfo { pattern: "FX07", mnemonic: "LD Vx, DT", description: "Set Vx to the delay timer." },
+    OpcodeInfo { pattern: "FX0A", mnemonic: "LD Vx, K", description: "Wait for a key press and store it in Vx." },
+    OpcodeInfo { pattern: "FX15", mnemonic: "LD DT, Vx", description: "Set the delay timer to Vx." },
+    OpcodeInfo { pattern: "FX18", mnemonic: "LD ST, Vx", description: "Set the sound timer to Vx." },
+    OpcodeInfo { pattern: "FX1E", mnemonic: "ADD I, Vx", description: "Add Vx to I." },
+    OpcodeInfo { pattern: "FX29", mnemonic: "LD F, Vx", description: "Point I at the font sprite for digit Vx." },
+    OpcodeInfo { pattern: "FX33", mnemonic: "LD B, Vx", description: "Store the decimal digits of Vx at I, I+1 and I+2." },
+    OpcodeInfo { pattern: "FX55", mnemonic: "LD [I], Vx", description: "Store V0..Vx in memory starting at I." },
+    OpcodeInfo { pattern: "FX65", mnemonic: "LD Vx, [I]", description: "Load V0..Vx from memory starting at I." },
+];
+
+pub fn opcodes() -> Vec<OpcodeInfo> {
+    OPCODES.to_vec()
+}
+
+// True when `opcode` fits a pattern like "8XY6", anything but a hex digit
+// matching any nibble.
+pub fn pattern_matches(pattern: &str, opcode: u16) -> bool {
+    pattern.chars().enumerate().all(|(i, c)| {
+        let nibble = (opcode >> (12 - 4 * i)) & 0xf;
+        match c.to_digit(16) {
+            Some(d) => d as u16 == nibble,
+            None => true,
+        }
+    })
+}
+
+impl OpcodeInfo {
+    // True when `opcode` fits the pattern, X/Y/N matching any nibble.
+    pub fn matches(&self, opcode: u16) -> bool {
+        pattern_matches(self.pattern, opcode)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::Cls => write!(f, "CLS"),
+            Operation::Ret => write!(f, "RET"),
+            Operation::Jp { nnn } => write!(f, "JP {:#x}", nnn),
+            Operation::Call { nnn } => write!(f, "CALL {:#x}", nnn),
+            Operation::SeVxNn { x, nn } => write!(f, "SE V{:X}, {:#x}", x, nn),
+            Operation::SneVxNn { x, nn } => write!(f, "SNE V{:X}, {:#x}", x, nn),
+            Operation::SeVxVy { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Operation::LdVxNn { x, nn } => write!(f, "LD V{:X}, {:#x}", x, nn),
+            Operation::AddVxNn { x, nn } => write!(f, "ADD V{:X}, {:#x}", x, nn),
+            Operation::LdVxVy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Operation::OrVxVy { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Operation::AndVxVy { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Operation::XorVxVy { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Operation::AddVxVy { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Operation::SubVxVy { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Operation::ShrVxVy { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Operation::SubnVxVy { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Operation::ShlVxVy { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Operation::SneVxVy { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Operation::LdINnn { nnn } => write!(f, "LD I, {:#x}", nnn),
+            Operation::JpV0Nnn { nnn } => write!(f, "JP V0, {:#x}", nnn),
+            Operation::RndVxNn { x, nn } => write!(f, "RND V{:X}, {:#x}", x, nn),
+            Operation::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {:#x}", x, y, n),
+            Operation::SkpVx { x } => write!(f, "SKP V{:X}", x),
+            Operation::SkpnVx { x } => write!(f, "SKPN V{:X}", x),
+            Operation::LdVxDt { x } => write!(f, "LD V{:X}, DT", x),
+            Operation::LdVxK { x } => write!(f, "LD V{:X}, K", x),
+            Operation::LdDtVx { x } => write!(f, "LD DT, V{:X}", x),
+            Operation::LdStVx { x } => write!(f, "LD ST, V{:X}", x),
+            Operation::AddIVx { x } => write!(f, "ADD I, V{:X}", x),
+            Operation::LdFVx { x } => write!(f, "LD F, V{:X}", x),
+            Operation::LdBVx { x } => write!(f, "LD B, V{:X}", x),
+            Operation::LdIVx { x } => write!(f, "LD [I], V{:X}", x),
+            Operation::LdVxI { x } => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operation() {
+            Some(op) => op.fmt(f),
+            None => write!(f, "??? {:#06x}", self.opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{opcodes, Instr, Operation};
+
+    #[test]
+    fn fields() {
+        let i = Instr::new(0xD123);
+        assert_eq!(i.c, 0xD);
+        assert_eq!(i.x, 0x1);
+        assert_eq!(i.y, 0x2);
+        assert_eq!(i.n, 0x3);
+        assert_eq!(i.nn, 0x23);
+        assert_eq!(i.nnn, 0x123);
+    }
+
+    #[test]
+    fn register_index() {
+        let i = Instr::new(0x8AB4);
+        assert_eq!(i.vx_index(), 0xA);
+        assert_eq!(i.vy_index(), 0xB);
+
+        // Out-of-range fields can only come from a decode bug; the indices stay in range.
+        let i = Instr { x: 0xFA, y: 0x1B, ..Instr::new(0x8004) };
+        assert_eq!(i.vx_index(), 0xA);
+        assert_eq!(i.vy_index(), 0xB);
+        assert_eq!(i.operation(), Some(Operation::AddVxVy { x: 0xA, y: 0xB }));
+    }
+
+    #[test]
+    fn operation() {
+        assert_eq!(Instr::new(0x00E0).operation(), Some(Operation::Cls));
+        assert_eq!(Instr::new(0x8236).operation(), Some(Operation::ShrVxVy { x: 2, y: 3 }));
+        assert_eq!(Instr::new(0xF265).operation(), Some(Operation::LdVxI { x: 2 }));
+        assert_eq!(Instr::new(0x5231).operation(), None);
+        assert_eq!(Instr::new(0xE2FF).operation(), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Instr::new(0x3222).to_string(), "SE V2, 0x22");
+        assert_eq!(Instr::new(0xD125).to_string(), "DRW V1, V2, 0x5");
+        assert_eq!(Instr::new(0xF155).to_string(), "LD [I], V1");
+        assert_eq!(Instr::new(0x0123).to_string(), "??? 0x0123");
+    }
+
+    #[test]
+    fn opcode_table() {
+        let table = opcodes();
+
+        // Every opcode the decoder accepts is described by exactly one row,
+        // and every row describes decodable opcodes only.
+        for opcode in 0..=0xFFFF_u16 {
+            let rows = table.iter().filter(|info| info.matches(opcode)).count();
+            match Instr::new(opcode).operation() {
+                Some(_) => assert_eq!(rows, 1, "{:#06x}", opcode),
+                None => assert_eq!(rows, 0, "{:#06x}", opcode),
+            }
         }
+
+        let families: std::collections::HashSet<char> = table.iter()
+            .map(|info| info.pattern.chars().next().unwrap())
+            .collect();
+        assert_eq!(families.len(), 16);
     }
 }
@@ -0,0 +1,247 @@
+// The 16-key hex keypad. Keys are looked up by their low nibble, the way the
+// COSMAC VIP decoded them, so any register value names a key; presses and
+// releases of keys past 0xF are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keypad {
+    // One bit per key.
+    down: u16,
+    // Keys released since the last clear_released().
+    released: u16,
+    // When each key went down, by the count of presses, for telling which
+    // of the keys held was pressed first or last.
+    pressed_at: [u32; 16],
+    presses: u32,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    fn bit(key: u8) -> u16 {
+        1 << (key & 0xF)
+    }
+
+    pub fn press(&mut self, key: u8) {
+        if key <= 0xF {
+            if !self.is_down(key) {
+                self.presses += 1;
+                self.pressed_at[key as usize] = self.presses;
+            }
+            self.down |= Keypad::bit(key);
+            self.released &= !Keypad::bit(key);
+        }
+    }
+
+    pub fn release(&mut self, key: u8) {
+        if key <= 0xF && self.is_down(key) {
+            self.down &= !Keypad::bit(key);
+            self.released |= Keypad::bit(key);
+        }
+    }
+
+    pub fn is_down(&self, key: u8) -> bool {
+        self.down & Keypad::bit(key) != 0
+    }
+
+    // The lowest key held down, the one LD Vx, K stores by default.
+    pub fn first_down(&self) -> Option<u8> {
+        (0..16).find(|&k| self.is_down(k))
+    }
+
+    // The key held down the longest.
+    pub fn first_pressed(&self) -> Option<u8> {
+        (0..16).filter(|&k| self.is_down(k)).min_by_key(|&k| self.pressed_at[k as usize])
+    }
+
+    // The key held down that was pressed last.
+    pub fn last_pressed(&self) -> Option<u8> {
+        (0..16).filter(|&k| self.is_down(k)).max_by_key(|&k| self.pressed_at[k as usize])
+    }
+
+    pub fn just_released(&self, key: u8) -> bool {
+        self.released & Keypad::bit(key) != 0
+    }
+
+    pub fn clear_released(&mut self) {
+        self.released = 0;
+    }
+
+    // The down and released bits, key 0 lowest, as replays store them.
+    pub(crate) fn bits(&self) -> (u16, u16) {
+        (self.down, self.released)
+    }
+
+    // The down bits that take `before` to this keypad, a press at a time in
+    // the order the keys went down, for replays to feed set_bits(). A key
+    // pressed again since is released first. Never empty when the keypads
+    // differ.
+    pub(crate) fn steps_from(&self, before: &Keypad) -> Vec<u16> {
+        let fresh: Vec<u8> = self.held_in_order().into_iter()
+            .filter(|&k| !before.is_down(k) || before.pressed_at[k as usize] != self.pressed_at[k as usize])
+            .collect();
+        let mut down = fresh.iter().fold(before.down & self.down, |down, &k| down & !Keypad::bit(k));
+        let mut steps = Vec::new();
+        if down != before.down || fresh.is_empty() {
+            steps.push(down);
+        }
+        for k in fresh {
+            down |= Keypad::bit(k);
+            steps.push(down);
+        }
+        steps
+    }
+
+    // Presses and releases keys to match bits(). Keys going down together
+    // are pressed lowest first, see steps_from().
+    pub(crate) fn set_bits(&mut self, down: u16, released: u16) {
+        for k in 0..16 {
+            match (self.is_down(k), down & Keypad::bit(k) != 0) {
+                (false, true) => self.press(k),
+                (true, false) => self.release(k),
+                _ => {},
+            }
+        }
+        self.released = released;
+    }
+
+    // The keys held down, first pressed first.
+    fn held_in_order(&self) -> Vec<u8> {
+        let mut held: Vec<u8> = (0..16).filter(|&k| self.is_down(k)).collect();
+        held.sort_by_key(|&k| self.pressed_at[k as usize]);
+        held
+    }
+
+    // One byte per key, as save states store them: 0 when up, else the
+    // key's place in the press order of the keys down, 1 for the first.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        for (n, k) in self.held_in_order().into_iter().enumerate() {
+            bytes[k as usize] = n as u8 + 1;
+        }
+        bytes
+    }
+
+    // Presses the keys in the order to_bytes() gives, keys with the same
+    // place lowest first. States from before the order was kept have a 1
+    // for every key down.
+    pub fn from_bytes(bytes: &[u8; 16]) -> Keypad {
+        let mut keys: Vec<u8> = (0..16).filter(|&k| bytes[k as usize] != 0).collect();
+        keys.sort_by_key(|&k| bytes[k as usize]);
+        let mut keypad = Keypad::new();
+        for k in keys {
+            keypad.press(k);
+        }
+        keypad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keypad;
+
+    #[test]
+    fn press_release() {
+        let mut keypad = Keypad::new();
+        assert_eq!(keypad.first_down(), None);
+
+        keypad.press(0xA);
+        keypad.press(0x3);
+        assert!(keypad.is_down(0x3) && keypad.is_down(0xA));
+        assert_eq!(keypad.first_down(), Some(0x3));
+
+        keypad.release(0x3);
+        assert!(!keypad.is_down(0x3));
+        assert!(keypad.just_released(0x3));
+        assert_eq!(keypad.first_down(), Some(0xA));
+
+        // Pressing again or clearing forgets the release.
+        keypad.press(0x3);
+        assert!(!keypad.just_released(0x3));
+        keypad.release(0x3);
+        keypad.clear_released();
+        assert!(!keypad.just_released(0x3));
+
+        // Releasing a key that isn't down is no release.
+        keypad.release(0x5);
+        assert!(!keypad.just_released(0x5));
+    }
+
+    #[test]
+    fn press_order() {
+        let mut keypad = Keypad::new();
+        assert_eq!(keypad.first_pressed(), None);
+        for key in [0x9, 0x2, 0xC] {
+            keypad.press(key);
+        }
+        assert_eq!((keypad.first_down(), keypad.first_pressed(), keypad.last_pressed()), (Some(0x2), Some(0x9), Some(0xC)));
+        // Pressing a held key again doesn't make it newer.
+        keypad.press(0x9);
+        assert_eq!(keypad.last_pressed(), Some(0xC));
+        // Released and pressed again does.
+        keypad.release(0x9);
+        keypad.press(0x9);
+        assert_eq!((keypad.first_pressed(), keypad.last_pressed()), (Some(0x2), Some(0x9)));
+
+        let mut other = Keypad::new();
+        other.press(0x2);
+        other.set_bits(0x1204, 0x0001);
+        assert_eq!(other.bits(), (0x1204, 0x0001));
+        assert_eq!((other.first_pressed(), other.last_pressed()), (Some(0x2), Some(0xC)));
+    }
+
+    #[test]
+    fn steps_from() {
+        let mut before = Keypad::new();
+        before.press(0x2);
+        before.press(0x7);
+        let mut after = before;
+        after.release(0x2);
+        after.press(0xC);
+        after.press(0x2);
+        after.press(0x1);
+        assert_eq!(after.steps_from(&before), [0x0080, 0x1080, 0x1084, 0x1086]);
+
+        let mut replayed = before;
+        for down in after.steps_from(&before) {
+            replayed.set_bits(down, 0);
+        }
+        assert_eq!(replayed.to_bytes(), after.to_bytes());
+        assert_eq!((replayed.first_pressed(), replayed.last_pressed()), (Some(0x7), Some(0x1)));
+
+        // Only the released bits changed.
+        let mut cleared = before;
+        cleared.release(0x2);
+        let mut after = cleared;
+        after.clear_released();
+        assert_eq!(after.steps_from(&cleared), [0x0080]);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x15);
+        keypad.release(0xFF);
+        assert_eq!(keypad, Keypad::new());
+
+        // Lookups use the low nibble.
+        keypad.press(0x5);
+        assert!(keypad.is_down(0x15));
+        assert!(keypad.is_down(0xF5));
+    }
+
+    #[test]
+    fn bytes() {
+        let mut keypad = Keypad::new();
+        keypad.press(0xF);
+        keypad.press(0x0);
+        let bytes = keypad.to_bytes();
+        assert_eq!(bytes, [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(Keypad::from_bytes(&bytes), keypad);
+        assert_eq!(Keypad::from_bytes(&bytes).first_pressed(), Some(0xF));
+
+        // Older states, lowest first.
+        let old = Keypad::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!((old.first_pressed(), old.last_pressed()), (Some(0x0), Some(0xF)));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::framebuffer::Frame;
+use crate::keypad::Keypad;
+use crate::memview;
+use crate::text::{self, ADVANCE, GLYPH_HEIGHT, LINE_SPACING};
+
+// The hex keypad as laid out on the COSMAC VIP, with the keyboard key the
+// SDL frontend maps to each.
+const LAYOUT: [[(u8, char); 4]; 4] = [
+    [(0x1, '1'), (0x2, '2'), (0x3, '3'), (0xC, '4')],
+    [(0x4, 'Q'), (0x5, 'W'), (0x6, 'E'), (0xD, 'R')],
+    [(0x7, 'A'), (0x8, 'S'), (0x9, 'D'), (0xE, 'F')],
+    [(0xA, 'Z'), (0x0, 'X'), (0xB, 'C'), (0xF, 'V')],
+];
+// Text columns per key, "1=1 ".
+const CELL: u32 = 4;
+// Text columns of a row, "1=1 2=2 3=3 C=4".
+const COLUMNS: u32 = 3 * CELL + 3;
+
+// A key of the pad: where it goes, its label and whether it is down.
+#[derive(Debug, PartialEq, Eq)]
+struct Cell {
+    row: u32,
+    col: u32,
+    text: String,
+    down: bool,
+}
+
+fn cells(keypad: &Keypad) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for (row, keys) in LAYOUT.iter().enumerate() {
+        for (i, &(key, host)) in keys.iter().enumerate() {
+            cells.push(Cell {
+                row: row as u32,
+                col: i as u32 * CELL,
+                text: format!("{:X}={}", key, host),
+                down: keypad.is_down(key),
+            });
+        }
+    }
+    cells
+}
+
+// The keypad shown over the screen with the keys held down highlighted, to
+// learn which keyboard key is which Chip-8 key.
+#[derive(Default)]
+pub struct KeyView {
+    visible: bool,
+}
+
+impl KeyView {
+    pub fn new(visible: bool) -> KeyView {
+        KeyView { visible }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // The pad as memview::TEXT and memview::HIGHLIGHT pixels on 0.
+    pub fn render(&self, keypad: &Keypad) -> Frame {
+        let line_height = GLYPH_HEIGHT + LINE_SPACING;
+        let mut frame = Frame::with_size(COLUMNS * ADVANCE - 1 + 2, 4 * line_height - LINE_SPACING + 2);
+        for c in cells(keypad) {
+            let (x, y) = (1 + c.col * ADVANCE, 1 + c.row * line_height);
+            text::draw_text(&mut frame, x, y, &c.text);
+            if c.down {
+                memview::highlight(&mut frame, x, y, text::text_width(&c.text));
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cells, KeyView};
+    use crate::keypad::Keypad;
+    use crate::memview::HIGHLIGHT;
+
+    #[test]
+    fn pressed_keys() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x5);
+        keypad.press(0xF);
+        let all = cells(&keypad);
+        assert_eq!(all.len(), 16);
+        let down: Vec<(u32, u32, &str)> = all.iter().filter(|c| c.down).map(|c| (c.row, c.col, c.text.as_str())).collect();
+        assert_eq!(down, vec![(1, 4, "5=W"), (3, 12, "F=V")]);
+        let first_row: Vec<&str> = all[..4].iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(first_row, vec!["1=1", "2=2", "3=3", "C=4"]);
+
+        keypad.release(0x5);
+        assert_eq!(cells(&keypad).iter().filter(|c| c.down).count(), 1);
+    }
+
+    #[test]
+    fn render() {
+        let mut keypad = Keypad::new();
+        let frame = KeyView::new(true).render(&keypad);
+        assert_eq!((frame.width(), frame.height()), (15 * 4 - 1 + 2, 4 * 7 - 2 + 2));
+        assert!(!frame.iter().flatten().any(|&p| p == HIGHLIGHT));
+
+        // 0 is the second key of the bottom row.
+        keypad.press(0x0);
+        let frame = KeyView::new(true).render(&keypad);
+        let y = 1 + 3 * 7_u32;
+        assert!(frame[y][1 + 4 * 4..1 + 4 * 4 + 11].contains(&HIGHLIGHT));
+        assert!(!frame[y][1..12].contains(&HIGHLIGHT));
+    }
+}
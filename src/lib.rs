@@ -0,0 +1,98 @@
+//! Chip-8 emulator core: the machine itself plus the host-independent pieces
+//! (ROM loading, save states, scheduling) shared by frontends. The SDL
+//! frontend lives in the `chip` binary behind the `sdl` feature.
+//!
+//! The supported API is what the crate root and [`prelude`] export, plus the
+//! public modules below. Everything else is internal.
+//!
+//! ```
+//! use chip8::prelude::*;
+//!
+//! let mut chip = Chip::builder()
+//!     .profile(Profile::builder().arch(Arch::chip8()).build())
+//!     .seed(0)
+//!     .rom(&[0x60, 0x2A, 0x12, 0x02]) // LD V0, 0x2A; JP 0x202
+//!     .build()
+//!     .unwrap();
+//!
+//! let step = chip.step().unwrap();
+//! assert_eq!(step.mnemonic, "LD V0, 0x2a");
+//! assert_eq!(chip.v(0), 0x2A);
+//! assert!(chip.is_halted());
+//! ```
+
+mod arch;
+mod capture;
+mod chip;
+mod clock;
+mod compat;
+mod disview;
+mod error;
+mod frame_stream;
+mod framebuffer;
+mod halt;
+mod hud;
+mod instr;
+mod keypad;
+mod keyview;
+mod pause;
+mod profile;
+mod ram;
+mod regs;
+mod regview;
+#[cfg(test)]
+mod snapshot;
+mod stats;
+mod step;
+#[cfg(test)]
+mod testing;
+mod text;
+mod watchdog;
+
+pub mod audio;
+pub mod autosave;
+pub mod debugger;
+pub mod disasm;
+pub mod emu_thread;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod golden;
+pub mod heatmap;
+pub mod history;
+pub mod hooks;
+pub mod input_script;
+pub mod memview;
+pub mod playlist;
+pub mod poke;
+pub mod quicksave;
+pub mod quirks_report;
+pub mod replay;
+pub mod rewind;
+pub mod rom;
+pub mod runner;
+#[cfg(feature = "scripting")]
+pub mod script_hooks;
+pub mod state;
+pub mod statefile;
+pub mod trace;
+pub mod tracediff;
+pub mod util;
+pub mod watch_csv;
+
+pub use arch::Arch;
+pub use chip::{Chip, ChipBuilder, RunStop, RunSummary, UntilResult};
+pub use clock::{Clock, SystemClock};
+pub use error::ChipError;
+pub use frame_stream::FrameStream;
+pub use framebuffer::Frame;
+pub use instr::{opcodes, Instr, OpcodeInfo, Operation};
+pub use keypad::Keypad;
+pub use profile::{DrawOrigin, KeyWait, PcOverflow, Profile, ProfileBuilder};
+pub use runner::{DrawLimit, Event, Frontend, RunOptions};
+pub use stats::RunStats;
+pub use step::{StateChange, StateDiff, StepResult};
+
+// The types most programs using the emulator need.
+pub mod prelude {
+    pub use crate::{Arch, Chip, ChipBuilder, ChipError, Event, Frame, Frontend, Profile, ProfileBuilder, RunOptions, StepResult};
+}
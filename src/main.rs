@@ -1,24 +1,127 @@
-mod arch;
-mod ram;
-mod regs;
-mod chip;
-mod instr;
-mod framebuffer;
 mod ui;
-mod util;
-mod profile;
 
 use std::io::Write;
-use std::io::Read;
-use std::fs::File;
-use std::env;
-use std::thread::sleep;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use log::{info, trace};
+use log::{info, warn};
 
-use crate::ui::Event;
-use crate::profile::Profile;
+use chip8::{autosave, debugger, disasm, golden, input_script, quicksave, quirks_report, replay, rom, runner, trace, tracediff, watch_csv};
+use chip8::{Chip, DrawLimit, Frontend, PcOverflow, Profile, ProfileBuilder, RunOptions};
+use chip8::emu_thread::EmuThread;
+use chip8::hooks::SharedHooks;
+use chip8::playlist::Playlist;
+use chip8::poke::{self, Poke};
+
+// --autosave-exit ask: true unless the answer starts with n.
+fn ask_resume(rom: &Path) -> bool {
+    print!("Resume {} where it was left? [Y/n] ", rom.display());
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && !answer.trim_start().to_ascii_lowercase().starts_with('n')
+}
+
+// Programs written for ~10 instructions a frame break when run far faster
+// or slower, which is easy to do by accident with --ips.
+fn ips_warning(profile: &Profile, name: &str, ips: u32) -> Option<String> {
+    let ipf = ips / 60;
+    let range = profile.ipf_range();
+    if range.contains(&ipf) {
+        return None;
+    }
+    let suggested = ipf.clamp(*range.start(), *range.end()) * 60;
+    Some(format!("--ips {} runs {} instructions a frame, programs for the {} profile expect {} to {}. Try --ips {} if they glitch.",
+        ips, ipf, name, range.start(), range.end(), suggested))
+}
+
+// How the run is paced, for --print-config.
+fn timing(deterministic: bool, fast: bool, ips: u32) -> String {
+    match (deterministic, fast) {
+        (true, _) => format!("{} instructions a second, {} a frame, deterministic", ips, (ips / 60).max(1)),
+        (false, true) => "host clock, as fast as possible".to_string(),
+        (false, false) => "host clock".to_string(),
+    }
+}
+
+// --print-config: everything a run depends on, a setting a line, to paste
+// into a bug report.
+fn config_dump(profile: &Profile, seed: u64, timing: &str, palette: &str) -> String {
+    let mut out = format!("chip {}\nprofile: {}\nseed: {}\ntiming: {}\npalette: {}\n",
+        env!("CARGO_PKG_VERSION"), profile.name(), seed, timing, palette);
+    for (name, value) in profile.settings() {
+        out.push_str(&format!("{}: {}\n", name, value));
+    }
+    out
+}
+
+// --script-hooks and --script-budget, only in builds with the scripting
+// feature.
+#[cfg(feature = "scripting")]
+fn script_hook_args() -> Vec<clap::Arg<'static>> {
+    vec![
+        clap::Arg::new("script_hooks")
+            .help("Run the on_frame, on_instruction and on_key functions of this rhai script from the run loop. They can read the registers and memory and press keys.")
+            .long("script-hooks")
+            .value_name("path")
+            .conflicts_with("script"),
+        clap::Arg::new("script_budget")
+            .help("Operations a --script-hooks script may run per frame before it is stopped.")
+            .long("script-budget")
+            .value_name("ops")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("100000")
+            .requires("script_hooks"),
+    ]
+}
+
+#[cfg(not(feature = "scripting"))]
+fn script_hook_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(feature = "scripting")]
+fn script_hooks(args: &clap::ArgMatches) -> std::io::Result<Option<SharedHooks>> {
+    let budget = *args.get_one::<u64>("script_budget").unwrap();
+    Ok(match args.get_one::<String>("script_hooks") {
+        Some(path) => Some(Arc::new(Mutex::new(chip8::script_hooks::ScriptHooks::read(Path::new(path), budget)?))),
+        None => None,
+    })
+}
+
+#[cfg(not(feature = "scripting"))]
+fn script_hooks(_args: &clap::ArgMatches) -> std::io::Result<Option<SharedHooks>> {
+    Ok(None)
+}
+
+// `chip hash <rom>`: runs headless and prints golden::hash_line().
+fn hash_command(args: &clap::ArgMatches) -> std::io::Result<()> {
+    let profile = Profile::from_name(args.get_one::<String>("profile").unwrap())?;
+    let mut chip = Chip::builder().profile(profile).seed(*args.get_one::<u64>("seed").unwrap()).build()?;
+    rom::start(&mut chip, Path::new(args.get_one::<String>("rom").unwrap()), &[])?;
+    if let Err(e) = golden::run(&mut chip, *args.get_one::<u64>("cycles").unwrap(), *args.get_one::<u32>("ips").unwrap()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+    println!("{}", golden::hash_line(&chip));
+    Ok(())
+}
+
+// Exits with 1 when a result isn't what the profile's settings predict.
+fn quirks_report_command(args: &clap::ArgMatches) -> std::io::Result<()> {
+    let rom = Path::new(args.get_one::<String>("rom").unwrap());
+    let expected_dir = Path::new(args.get_one::<String>("expected_dir").unwrap());
+    let profiles: Vec<&str> = if *args.get_one::<bool>("all_profiles").unwrap() {
+        quirks_report::PROFILES.to_vec()
+    } else {
+        vec![args.get_one::<String>("profile").unwrap().as_str()]
+    };
+    let columns = profiles.iter().map(|p| quirks_report::run(rom, p, expected_dir)).collect::<std::io::Result<Vec<_>>>()?;
+    print!("{}", quirks_report::table(&columns));
+    if columns.iter().any(quirks_report::Column::has_mismatch) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
 fn main() -> std::io::Result<()>{
 
@@ -28,110 +131,711 @@ fn main() -> std::io::Result<()>{
         .version(clap::crate_version!())
         .author(clap::crate_authors!())
         .arg(clap::Arg::new("rom_path")
-             .help("ROM file name.")
+             .help("ROM file names or directories. PageUp/PageDown switch between them.")
              .long("rom_path")
              .short('r')
              .value_name("path")
              .takes_value(true)
-             .default_value("rom/tests/ibm.ch8"))
+             .multiple_values(true)
+             .default_value("ibm.ch8")
+             .default_value_if("load_state", None, None))
+        .arg(clap::Arg::new("rom_dir")
+             .help("Look up ROM names without a directory here. Repeatable, searched in order before $CHIP8_ROM_PATH.")
+             .long("rom-dir")
+             .value_name("dir")
+             .action(clap::ArgAction::Append))
         .arg(clap::Arg::new("profile")
              .help("Chip-8 profile.")
              .long("profile")
              .short('p')
-             .value_parser(["original", "modern"])
+             .value_parser(["original", "modern", "chip48"])
              .default_value("modern"))
+        .arg(clap::Arg::new("pc_overflow")
+             .help("When PC runs past the end of RAM: stop with an error, or wrap around to 0.")
+             .long("pc-overflow")
+             .value_parser(["error", "wrap"])
+             .default_value("error"))
+        .arg(clap::Arg::new("nop")
+             .help("Execute this opcode as a no-op, e.g. 0x0000 to patch out instructions.")
+             .long("nop")
+             .value_name("opcode")
+             .value_parser(poke::parse_opcode))
+        .arg(clap::Arg::new("stack_addr")
+             .help("Keep the stack's return addresses in RAM from this address, e.g. 0xEA0 like the COSMAC VIP, so writes there change where RET goes.")
+             .long("stack-addr")
+             .value_name("addr")
+             .value_parser(debugger::parse_addr))
         .arg(clap::Arg::new("fast")
              .help("Run emulation as fast as possible.")
              .long("fast")
              .short('f')
              .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("autosave")
+             .help("Periodically save the machine state into a rotating pair of files.")
+             .long("autosave")
+             .value_names(&["seconds", "path"])
+             .takes_value(true)
+             .number_of_values(2))
+        .arg(clap::Arg::new("explain")
+             .help("Print a plain-English explanation of every executed instruction.")
+             .long("explain")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("trace_sample")
+             .help("With --explain, print only every N-th executed instruction, or every N-th matching an opcode pattern like DXYN.")
+             .long("trace-sample")
+             .value_name("N[:pattern]")
+             .value_parser(trace::parse_trace_sample)
+             .requires("explain"))
+        .arg(clap::Arg::new("trace_file")
+             .help("Write the cycle, PC and opcode of every executed instruction to this file, to compare with --tracediff.")
+             .long("trace-file")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("tracediff")
+             .help("Compare two trace logs line by line, print where they differ, and exit.")
+             .long("tracediff")
+             .value_names(&["mine", "theirs"])
+             .takes_value(true)
+             .number_of_values(2))
+        .arg(clap::Arg::new("trace_against")
+             .help("Compare every executed instruction with this reference trace log while running, and stop after the last mismatch reported.")
+             .long("trace-against")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("trace_ignore")
+             .help("Trace log columns the comparison ignores: cycle, pc or opcode, comma separated.")
+             .long("trace-ignore")
+             .value_name("columns")
+             .value_parser(tracediff::parse_fields))
+        .arg(clap::Arg::new("trace_mismatches")
+             .help("Mismatches the trace comparison reports before it stops.")
+             .long("trace-mismatches")
+             .value_name("count")
+             .value_parser(clap::value_parser!(usize))
+             .default_value("1"))
+        .arg(clap::Arg::new("deterministic")
+             .help("Schedule by instruction count only: fixed seed, no host clock, no sleeping, no audio.")
+             .long("deterministic")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("script")
+             .help("Press and release keys at given frames, from lines like 'at frame 60 press 5'. Use with --deterministic for the same playthrough every run.")
+             .long("script")
+             .value_name("path"))
+        .args(script_hook_args())
+        .arg(clap::Arg::new("seed")
+             .help("RNG seed. Defaults to 0 in deterministic mode and with --golden, random otherwise.")
+             .long("seed")
+             .value_name("seed")
+             .value_parser(clap::value_parser!(u64)))
+        .arg(clap::Arg::new("ips")
+             .help("Instructions per second in deterministic mode.")
+             .long("ips")
+             .value_name("ips")
+             .value_parser(clap::value_parser!(u32).range(1..))
+             .default_value("600"))
+        .arg(clap::Arg::new("aspect")
+             .help("Pixel aspect ratio as W:H, e.g. 1:2 to approximate a CRT. Square by default.")
+             .long("aspect")
+             .value_name("W:H")
+             .value_parser(ui::parse_aspect)
+             .default_value("1:1"))
+        .arg(clap::Arg::new("pan")
+             .help("Play sound in stereo at this position, from -1.0 for left to 1.0 for right. Mono by default.")
+             .long("pan")
+             .value_name("pan")
+             .allow_hyphen_values(true)
+             .value_parser(ui::parse_pan))
+        .arg(clap::Arg::new("flash_draws")
+             .help("Highlight the pixels that just flipped, fading over a few frames, to see what each draw changed.")
+             .long("flash-draws")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("rotate")
+             .help("Turn the screen clockwise by this many degrees, for rotated displays.")
+             .long("rotate")
+             .value_name("degrees")
+             .value_parser(["0", "90", "180", "270"])
+             .default_value("0"))
+        .arg(clap::Arg::new("flip")
+             .help("Mirror the screen, after --rotate, e.g. for projection.")
+             .long("flip")
+             .value_name("axis")
+             .value_parser(["horizontal", "vertical", "both"]))
+        .arg(clap::Arg::new("transparent_bg")
+             .help("Clear the screen to a fully transparent background instead of the solid color, for compositing where the window supports it.")
+             .long("transparent-bg")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("renderer")
+             .help("Window rendering backend. Accelerated falls back to software when it isn't available.")
+             .long("renderer")
+             .value_parser(["software", "accelerated"])
+             .default_value("accelerated"))
+        .arg(clap::Arg::new("break")
+             .help("Stop before executing the instruction at this address and take debugger commands on stdin. Repeatable.")
+             .long("break")
+             .value_name("addr")
+             .value_parser(debugger::parse_addr)
+             .action(clap::ArgAction::Append))
+        .arg(clap::Arg::new("watch")
+             .help("Stop after an instruction writes into this address or start..end range and take debugger commands on stdin. Repeatable.")
+             .long("watch")
+             .value_name("addr[..end]")
+             .value_parser(debugger::parse_range)
+             .action(clap::ArgAction::Append))
+        .arg(clap::Arg::new("watch_csv")
+             .help("Append a CSV row with the frame number and the --watch-value values to this file every frame.")
+             .long("watch-csv")
+             .value_name("path")
+             .takes_value(true)
+             .requires("watch_value"))
+        .arg(clap::Arg::new("watch_value")
+             .help("A value for --watch-csv: v0-vf, i, pc, sp, dt, st or mem:ADDR for a byte of memory. Repeatable.")
+             .long("watch-value")
+             .value_name("item")
+             .value_parser(watch_csv::parse_watch_item)
+             .action(clap::ArgAction::Append)
+             .requires("watch_csv"))
+        .arg(clap::Arg::new("csv_registers")
+             .help("Write a CSV row with the cycle, PC and registers to this file after every executed instruction.")
+             .long("csv-registers")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("debug")
+             .help("Stop in the debugger on an unknown opcode, to skip it or abort.")
+             .long("debug")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("history")
+             .help("Keep the state before each of the last <count> instructions, to step back with rs in the debugger or Shift+step key while paused.")
+             .long("history")
+             .value_name("count")
+             .value_parser(clap::value_parser!(usize))
+             .default_value("0"))
+        .arg(clap::Arg::new("rewind")
+             .help("Keep this many seconds of play to run backwards while Backspace is held.")
+             .long("rewind")
+             .value_name("seconds")
+             .value_parser(clap::value_parser!(u32).range(1..)))
+        .arg(clap::Arg::new("rewind_interval")
+             .help("Frames between the snapshots kept for --rewind.")
+             .long("rewind-interval")
+             .value_name("frames")
+             .value_parser(clap::value_parser!(u32).range(1..))
+             .default_value("2")
+             .requires("rewind"))
+        .arg(clap::Arg::new("poke")
+             .help("Write bytes into RAM after the ROM loads, e.g. 0x2E3=0xFF. Repeatable, F8 re-applies.")
+             .long("poke")
+             .value_name("addr=byte[,byte...]")
+             .value_parser(poke::parse)
+             .action(clap::ArgAction::Append))
+        .arg(clap::Arg::new("wait_on_halt")
+             .help("When the program halts, show a message and quit on the next key press.")
+             .long("wait-on-halt")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("sticky_keys")
+             .help("A key press latches the key down until the next press, for playing without holding keys.")
+             .long("sticky-keys")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("list_opcodes")
+             .help("Print the supported opcodes and exit.")
+             .long("list-opcodes")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("print_config")
+             .help("Print the profile, quirks, seed, timing and palette the run uses, for bug reports.")
+             .long("print-config")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("print_config_only")
+             .help("Print the configuration like --print-config and exit.")
+             .long("print-config-only")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("collision_beep")
+             .help("Play a short beep when a sprite draw collides.")
+             .long("collision-beep")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("cls_watchdog")
+             .help("Stop when a single frame executes more CLS instructions than this.")
+             .long("cls-watchdog")
+             .value_name("count")
+             .value_parser(clap::value_parser!(u32).range(1..)))
+        .arg(clap::Arg::new("max_draws")
+             .help("Limit the DRW instructions a single frame may execute.")
+             .long("max-draws")
+             .value_name("count")
+             .value_parser(clap::value_parser!(u32).range(1..)))
+        .arg(clap::Arg::new("draw_debug")
+             .help("Present the screen after every DRW and pause this long, to watch the drawing order.")
+             .long("draw-debug")
+             .value_name("ms")
+             .value_parser(clap::value_parser!(u32)))
+        .arg(clap::Arg::new("max_draws_action")
+             .help("What happens past --max-draws: log a warning, or present the screen early to keep the window responsive.")
+             .long("max-draws-action")
+             .value_parser(["warn", "present"])
+             .default_value("warn"))
+        .arg(clap::Arg::new("warn_draw_before_i")
+             .help("Warn when the first DRW executes before any LD I, drawing the font at address 0.")
+             .long("warn-draw-before-i")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("gamepad")
+             .help("Read keys from game controllers too. Controllers can be plugged in while running.")
+             .long("gamepad")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("gamepad_map")
+             .help("Change the gamepad button mapping, e.g. a=f,start=0. Keys are hex digits.")
+             .long("gamepad-map")
+             .value_name("button=key[,button=key...]")
+             .value_parser(ui::GamepadMap::parse)
+             .requires("gamepad"))
+        .arg(clap::Arg::new("step_key")
+             .help("Key that executes a single instruction while paused (P pauses). Repeats while held down.")
+             .long("step-key")
+             .value_name("key")
+             .takes_value(true)
+             .default_value("N"))
+        .arg(clap::Arg::new("hud")
+             .help("Show instructions and frames per second in the corner. F1 toggles it.")
+             .long("hud")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("show_keys")
+             .help("Show the hex keypad with the keyboard key for each and highlight the keys held down.")
+             .long("show-keys")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("input_poll_cycles")
+             .help("Also poll input every this many cycles instead of only once a frame, so key checks see presses sooner at low --ips.")
+             .long("input-poll-cycles")
+             .value_name("cycles")
+             .value_parser(clap::value_parser!(u32).range(1..)))
+        .arg(clap::Arg::new("start_delay")
+             .help("Show a blank screen for this long before the program starts, e.g. to sync a recorder.")
+             .long("start-delay")
+             .value_name("ms")
+             .value_parser(clap::value_parser!(u32))
+             .default_value("0"))
+        .arg(clap::Arg::new("disasm_html")
+             .help("Write an HTML disassembly of the first ROM, with links for jumps and calls, and exit.")
+             .long("disasm-html")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("capture_tests")
+             .help("Write a Rust unit test for the first execution of every opcode the program runs.")
+             .long("capture-tests")
+             .value_name("path")
+             .takes_value(true)
+             .conflicts_with("explain"))
+        .arg(clap::Arg::new("profile_out")
+             .help("Count how often each address executes and write the counts to this JSON file at exit. The debugger's hot command lists the top ones.")
+             .long("profile-out")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("coverage")
+             .help("Write a disassembly of the ROM to this file at exit, with the instructions that never executed marked by !.")
+             .long("coverage")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("compat_report")
+             .help("After the run, report the executed instructions that behave differently between Chip-8 variants, to help pick a profile.")
+             .long("compat-report")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("autosave_exit")
+             .help("Save the machine next to the ROM when the emulator quits without an error, and resume from it the next time the same ROM starts: right away with auto, after a y/n question with ask.")
+             .long("autosave-exit")
+             .value_name("mode")
+             .value_parser(["auto", "ask"]))
+        .arg(clap::Arg::new("load_state")
+             .help("Start from a state saved by --save-state-on-quit or F5 instead of the ROM's start. The ROM is then optional.")
+             .long("load-state")
+             .value_name("path")
+             .takes_value(true)
+             .conflicts_with("resume"))
+        .arg(clap::Arg::new("force_state_load")
+             .help("Let --load-state and F4 load a state saved for another ROM file, e.g. after patching the ROM.")
+             .long("force-state-load")
+             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("save_state_on_quit")
+             .help("Save the machine to this file when the emulator quits, for --load-state.")
+             .long("save-state-on-quit")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("resume")
+             .help("Resume from the latest autosave at the given path.")
+             .long("resume")
+             .value_name("path")
+             .takes_value(true))
+        .arg(clap::Arg::new("golden")
+             .help("Run the ROM headless for --golden-cycles instructions and write the screen's hash and an ASCII picture of it to this file, as a test fixture.")
+             .long("golden")
+             .value_name("path")
+             .conflicts_with_all(&["load_state", "resume", "record_replay", "replay"]))
+        .arg(clap::Arg::new("golden_cycles")
+             .help("Instructions run for --golden, with timers ticking at --ips.")
+             .long("golden-cycles")
+             .value_name("cycles")
+             .value_parser(clap::value_parser!(u64))
+             .default_value("600"))
+        .arg(clap::Arg::new("record_replay")
+             .help("Record the ROM's start, seed and inputs into this replay file, for --replay.")
+             .long("record-replay")
+             .value_name("path")
+             .conflicts_with_all(&["load_state", "resume"]))
+        .arg(clap::Arg::new("replay")
+             .help("Run a replay file headless against the ROM it was recorded with, and print the state hash it ends in.")
+             .long("replay")
+             .value_name("path")
+             .conflicts_with_all(&["load_state", "resume", "record_replay", "poke"]))
+        .arg(clap::Arg::new("verify")
+             .help("With --replay, exit with an error unless the replay ends in the recorded state.")
+             .long("verify")
+             .requires("replay")
+             .action(clap::ArgAction::SetTrue))
+        .subcommand(clap::Command::new("hash")
+             .about("Run a ROM headless and print the state hash and the frame hash it ends with, for regression checks.")
+             .arg(clap::Arg::new("rom")
+                  .help("ROM file.")
+                  .value_name("path")
+                  .required(true))
+             .arg(clap::Arg::new("cycles")
+                  .help("Instructions to run.")
+                  .long("cycles")
+                  .value_name("count")
+                  .value_parser(clap::value_parser!(u64))
+                  .required(true))
+             .arg(clap::Arg::new("seed")
+                  .help("RNG seed.")
+                  .long("seed")
+                  .value_name("seed")
+                  .value_parser(clap::value_parser!(u64))
+                  .default_value("0"))
+             .arg(clap::Arg::new("profile")
+                  .help("Chip-8 profile.")
+                  .long("profile")
+                  .value_name("profile")
+                  .value_parser(["original", "modern", "chip48"])
+                  .default_value("modern"))
+             .arg(clap::Arg::new("ips")
+                  .help("Instructions per second, for the timers.")
+                  .long("ips")
+                  .value_name("ips")
+                  .value_parser(clap::value_parser!(u32).range(1..))
+                  .default_value("600")))
+        .subcommand(clap::Command::new("quirks-report")
+             .about("Run Timendus' quirks test ROM headless and print which quirks pass on each profile. Exits with 1 when a result isn't what the profile's settings predict.")
+             .arg(clap::Arg::new("rom")
+                  .help("The quirks test ROM, 5-quirks.ch8.")
+                  .long("rom")
+                  .value_name("path")
+                  .required(true))
+             .arg(clap::Arg::new("all_profiles")
+                  .help("Run it on every built-in profile.")
+                  .long("all-profiles")
+                  .action(clap::ArgAction::SetTrue))
+             .arg(clap::Arg::new("profile")
+                  .help("Chip-8 profile.")
+                  .long("profile")
+                  .value_name("profile")
+                  .value_parser(["original", "modern", "chip48"])
+                  .conflicts_with("all_profiles")
+                  .default_value("modern"))
+             .arg(clap::Arg::new("expected_dir")
+                  .help("Where the rom_suites test keeps its expectations, whose frame hashes stand in for marks that can't be read.")
+                  .long("expected-dir")
+                  .value_name("dir")
+                  .default_value("tests/expected")))
         .get_matches();
 
-    let rom_name = args.get_one::<String>("rom_path").unwrap();
-    let mut f = File::open(rom_name)?;
+    match args.subcommand() {
+        Some(("hash", sub)) => return hash_command(sub),
+        Some(("quirks-report", sub)) => return quirks_report_command(sub),
+        _ => {},
+    }
 
-    let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer)?;
+    if *args.get_one::<bool>("list_opcodes").unwrap() {
+        for info in chip8::opcodes() {
+            println!("{}  {:<14} {}", info.pattern, info.mnemonic, info.description);
+        }
+        return Ok(());
+    }
 
-    let profile = match args.get_one::<String>("profile").unwrap().as_str() {
-        "original" => Profile::original(),
-        "modern" => Profile::modern(),
-        _ => unreachable!(),
-    };
+    let trace_ignore: Vec<tracediff::Field> = args.get_one::<Vec<tracediff::Field>>("trace_ignore").cloned().unwrap_or_default();
+    let trace_mismatches = *args.get_one::<usize>("trace_mismatches").unwrap();
+    if let Some(mut paths) = args.get_many::<String>("tracediff") {
+        let mine = tracediff::read_log(Path::new(paths.next().unwrap()))?;
+        let theirs = tracediff::read_log(Path::new(paths.next().unwrap()))?;
+        let found = tracediff::diff(&mine, theirs, &trace_ignore, trace_mismatches);
+        if found.is_empty() {
+            println!("The traces match, {} lines.", mine.len());
+            return Ok(());
+        }
+        for m in &found {
+            print!("{}", m);
+        }
+        std::process::exit(1);
+    }
 
-    let fast = args.get_one::<bool>("fast").unwrap();
+    let script = match args.get_one::<String>("script") {
+        Some(path) => Some(Arc::new(Mutex::new(input_script::InputScript::read(Path::new(path))?)) as SharedHooks),
+        None => script_hooks(&args)?,
+    };
 
-    let mut chip = chip::Chip::new(profile);
+    let rom_dirs: Vec<PathBuf> = args.get_many::<String>("rom_dir").unwrap_or_default().map(PathBuf::from).collect();
+    let search = rom::search_path(&rom_dirs, std::env::var_os(rom::ROM_PATH_ENV).as_deref());
+    let rom_paths: Vec<PathBuf> = args.get_many::<String>("rom_path").unwrap_or_default()
+        .map(|p| rom::resolve(Path::new(p), &search))
+        .collect();
+    let mut playlist = Playlist::new(&rom_paths)?;
+    info!("Playlist: {} ROM(s)", playlist.len());
 
-    chip.load_rom(&buffer, 0x200);
-    chip.set_pc(0x200);
+    if let Some(out) = args.get_one::<String>("disasm_html") {
+        if playlist.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No ROM to disassemble"));
+        }
+        let rom = rom::read(playlist.current())?;
+        let lines = disasm::disassemble(&rom, rom::START_ADDR as u16);
+        std::fs::write(out, disasm::to_html(&lines, &playlist.current().display().to_string()))?;
+        return Ok(());
+    }
 
-    let mut ui = ui::Ui::new();
+    let pc_overflow = match args.get_one::<String>("pc_overflow").unwrap().as_str() {
+        "wrap" => PcOverflow::Wrap,
+        _ => PcOverflow::Error,
+    };
+    let profile = ProfileBuilder::new(Profile::from_name(args.get_one::<String>("profile").unwrap())?)
+        .pc_overflow(pc_overflow)
+        .nop(args.get_one::<u16>("nop").copied())
+        .stack_in_ram(args.get_one::<u16>("stack_addr").copied())
+        .build();
 
-    let mut running = true;
+    let deterministic = *args.get_one::<bool>("deterministic").unwrap();
+    // --ips only paces deterministic runs.
+    if deterministic {
+        if let Some(w) = ips_warning(&profile, args.get_one::<String>("profile").unwrap(), *args.get_one::<u32>("ips").unwrap()) {
+            warn!("{}", w);
+        }
+    }
 
-    let start_ms = ui.timers.get_ms();
-    let mut cycles: u64 = 0;
-    let mut last_frame_ms = start_ms;
-    let frame_interval: [u32; 3] = [17, 17, 16];
-    let mut frame_idx = 0;
+    let seed = match args.get_one::<u64>("seed") {
+        Some(seed) => Some(*seed),
+        None if deterministic || args.contains_id("golden") => Some(0),
+        None => None,
+    };
 
-    let mut no_frame_cycles: u64 = 0;
+    let mut builder = Chip::builder().profile(profile);
+    if let Some(seed) = seed {
+        builder = builder.seed(seed);
+    }
+    let mut chip = builder.build()?;
 
-    while running {
-        let now_ms = ui.timers.get_ms();
-        let frame_sync = now_ms - last_frame_ms > frame_interval[frame_idx];
+    let print_config_only = *args.get_one::<bool>("print_config_only").unwrap();
+    if print_config_only || *args.get_one::<bool>("print_config").unwrap() {
+        let timing = timing(deterministic, *args.get_one::<bool>("fast").unwrap(), *args.get_one::<u32>("ips").unwrap());
+        print!("{}", config_dump(&profile, chip.seed(), &timing, &ui::palette(*args.get_one::<bool>("transparent_bg").unwrap())));
+        if print_config_only {
+            return Ok(());
+        }
+    }
 
-        if frame_sync {
-            last_frame_ms = now_ms;
-            frame_idx += 1;
-            if frame_idx == frame_interval.len() {
-                frame_idx = 0;
-            }
+    let pokes: Vec<Poke> = args.get_many::<Poke>("poke").unwrap_or_default().cloned().collect();
+    for p in &pokes {
+        info!("Poke {}", p);
+    }
 
-            for e in ui.events.poll_iter() {
-                match e {
-                    Event::Quit =>  { info!("Quit!"); std::io::stdout().flush().unwrap(); running = false },
-                    Event::KeyPress(key) => { trace!("Key pressed: {}", key); chip.key_press(key) },
-                    Event::KeyUnpress(key) => { trace!("Key unpressed {}", key); chip.key_unpress(key) },
-                }
-            }
+    if let Some(path) = args.get_one::<String>("replay") {
+        let replay = replay::Replay::read(Path::new(path))?;
+        // A custom profile isn't in the file, it has to be given again.
+        let profile = match replay.header.profile.as_str() {
+            "custom" => profile,
+            name => Profile::from_name(name)?,
+        };
+        let mut chip = Chip::builder().profile(profile).seed(replay.header.seed).build()?;
+        // The recorded pokes go in with replay::play().
+        if playlist.open(|p| rom::start(&mut chip, p, &[])).is_none() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No loadable ROM"));
         }
-
-        if running {
-            cycles += 1;
-
-            if frame_sync {
-                info!("frame_sync");
-                chip.cycle_timers();
-                if chip.is_sound_on() {
-                    ui.audio.on();
-                } else {
-                    ui.audio.off();
-                }
-            } else {
-                no_frame_cycles += 1;
-            }
-            chip.cycle();
-
-            if frame_sync {
-                let f: &framebuffer::Frame = chip.get_frame();
-                ui.display.present_frame(f);
-            }
+        if !replay.is_for_rom(&rom::read(playlist.current())?) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Replay is for a different ROM"));
+        }
+        chip.set_pc(replay.header.start);
+        let played = replay::play(&mut chip, &replay);
+        if let Some(e) = &played.error {
+            println!("The replay stopped with: {}", e);
+        }
+        println!("{} cycles, state hash {:016x}", replay.cycles, played.hash);
+        if *args.get_one::<bool>("verify").unwrap() && played.hash != replay.final_hash {
+            println!("Verification failed, the recording ended in {:016x}", replay.final_hash);
+            std::process::exit(1);
         }
-        if !fast {
-            sleep(Duration::from_millis(1));
+        return Ok(());
+    }
+
+    let load_state = args.get_one::<String>("load_state").map(Path::new);
+    if (load_state.is_none() || !playlist.is_empty()) && playlist.open(|p| rom::start(&mut chip, p, &pokes)).is_none() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No loadable ROM"));
+    }
+    if let Some(out) = args.get_one::<String>("golden") {
+        let name = playlist.current().file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let cycles = *args.get_one::<u64>("golden_cycles").unwrap();
+        std::fs::write(out, golden::capture(&mut chip, &name, cycles, *args.get_one::<u32>("ips").unwrap())?)?;
+        println!("Wrote {}", out);
+        return Ok(());
+    }
+    if let Some(path) = load_state {
+        let rom = (!playlist.is_empty()).then(|| playlist.current());
+        quicksave::load_from(&mut chip, path, rom, *args.get_one::<bool>("force_state_load").unwrap())?;
+        info!("Loaded state from {}", path.display());
+    }
+
+    let autosave_exit = args.get_one::<String>("autosave_exit");
+    // A recording starts at the ROM's start.
+    if let Some(mode) = autosave_exit.filter(|_| load_state.is_none() && !args.contains_id("resume") && !args.contains_id("record_replay")) {
+        match quicksave::exit_save(playlist.current()) {
+            quicksave::ExitSave::Resumable(path) if mode == "auto" || ask_resume(playlist.current()) => {
+                quicksave::load_from(&mut chip, &path, Some(playlist.current()), false)?;
+                info!("Resumed from {}", path.display());
+            },
+            quicksave::ExitSave::Stale => warn!("Ignoring {}, it is not a save state of this ROM", quicksave::exit_path(playlist.current()).display()),
+            _ => {},
         }
     }
-    let end_ms = ui.timers.get_ms();
-    let duration_ms = end_ms - start_ms;
-    let cps: f64 = 1000.0 * cycles as f64 / duration_ms as f64;
 
-    println!("Stats.");
-    println!("Execution time: {} ms", duration_ms);
-    println!("Cycles: {}", cycles);
-    println!("Cycles per second: {}", cps);
-    println!("No frame cycles: {}", no_frame_cycles);
+    if let Some(path) = args.get_one::<String>("resume") {
+        let slot = autosave::resume(Path::new(path), |file| Ok(file.load_into(&mut chip, None)?))?;
+        info!("Resumed from {}", slot.display());
+    }
+
+    let autosave = match args.get_many::<String>("autosave") {
+        Some(mut vals) => {
+            let interval: u32 = vals.next().unwrap().parse()
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid autosave interval"))?;
+            Some((interval, PathBuf::from(vals.next().unwrap())))
+        },
+        None => None,
+    };
+
+    let opts = RunOptions {
+        fast: *args.get_one::<bool>("fast").unwrap(),
+        deterministic,
+        ips: *args.get_one::<u32>("ips").unwrap(),
+        explain: *args.get_one::<bool>("explain").unwrap(),
+        trace_sample: args.get_one::<trace::TraceSample>("trace_sample").cloned(),
+        trace_file: args.get_one::<String>("trace_file").map(PathBuf::from),
+        trace_against: match args.get_one::<String>("trace_against") {
+            Some(path) => Some(tracediff::TraceDiff::new(tracediff::read_log(Path::new(path))?, &trace_ignore, trace_mismatches)),
+            None => None,
+        },
+        collision_beep: *args.get_one::<bool>("collision_beep").unwrap(),
+        wait_on_halt: *args.get_one::<bool>("wait_on_halt").unwrap(),
+        sticky_keys: *args.get_one::<bool>("sticky_keys").unwrap(),
+        hud: *args.get_one::<bool>("hud").unwrap(),
+        show_keys: *args.get_one::<bool>("show_keys").unwrap(),
+        input_poll_cycles: args.get_one::<u32>("input_poll_cycles").copied(),
+        start_delay_ms: *args.get_one::<u32>("start_delay").unwrap(),
+        cls_watchdog: args.get_one::<u32>("cls_watchdog").copied(),
+        max_draws: args.get_one::<u32>("max_draws").map(|&limit| {
+            let action = match args.get_one::<String>("max_draws_action").unwrap().as_str() {
+                "present" => DrawLimit::Present,
+                _ => DrawLimit::Warn,
+            };
+            (limit, action)
+        }),
+        warn_draw_before_i: *args.get_one::<bool>("warn_draw_before_i").unwrap(),
+        draw_debug: args.get_one::<u32>("draw_debug").copied(),
+        autosave,
+        save_state_on_quit: args.get_one::<String>("save_state_on_quit").map(PathBuf::from),
+        autosave_exit: autosave_exit.is_some(),
+        force_state_load: *args.get_one::<bool>("force_state_load").unwrap(),
+        csv_registers: args.get_one::<String>("csv_registers").map(PathBuf::from),
+        watch_csv: args.get_one::<String>("watch_csv").map(|path| {
+            (PathBuf::from(path), args.get_many::<watch_csv::WatchItem>("watch_value").unwrap_or_default().copied().collect())
+        }),
+        capture_tests: args.get_one::<String>("capture_tests")
+            .map(|p| (PathBuf::from(p), args.get_one::<String>("profile").unwrap().clone())),
+        compat_report: *args.get_one::<bool>("compat_report").unwrap(),
+        profile_out: args.get_one::<String>("profile_out").map(PathBuf::from),
+        coverage: args.get_one::<String>("coverage").map(PathBuf::from),
+        breakpoints: args.get_many::<u16>("break").unwrap_or_default().copied().collect(),
+        watchpoints: args.get_many::<std::ops::Range<u32>>("watch").unwrap_or_default().cloned().collect(),
+        debug: *args.get_one::<bool>("debug").unwrap(),
+        history: *args.get_one::<usize>("history").unwrap(),
+        rewind: args.get_one::<u32>("rewind").map(|&seconds| (seconds, *args.get_one::<u32>("rewind_interval").unwrap())),
+        record_replay: args.get_one::<String>("record_replay").map(PathBuf::from),
+        hooks: script,
+        pokes,
+    };
+
+    let gamepad = if *args.get_one::<bool>("gamepad").unwrap() {
+        Some(args.get_one::<ui::GamepadMap>("gamepad_map").cloned().unwrap_or_default())
+    } else {
+        None
+    };
+
+    let renderer = match args.get_one::<String>("renderer").unwrap().as_str() {
+        "software" => ui::Renderer::Software,
+        _ => ui::Renderer::Accelerated,
+    };
+    let step_key = args.get_one::<String>("step_key").unwrap();
+    let look = ui::Look {
+        transparent: *args.get_one::<bool>("transparent_bg").unwrap(),
+        flash_draws: *args.get_one::<bool>("flash_draws").unwrap(),
+        orientation: ui::Orientation {
+            quarter_turns: (args.get_one::<String>("rotate").unwrap().parse::<u32>().unwrap() / 90) as u8,
+            flip_x: matches!(args.get_one::<String>("flip").map(String::as_str), Some("horizontal" | "both")),
+            flip_y: matches!(args.get_one::<String>("flip").map(String::as_str), Some("vertical" | "both")),
+        },
+    };
+    let mut ui = ui::Ui::new(*args.get_one::<f32>("aspect").unwrap(), &profile.arch(), renderer, gamepad, step_key, args.get_one::<f32>("pan").copied(), look)
+        .map_err(std::io::Error::other)?;
+    let title_path = match load_state {
+        Some(path) if playlist.is_empty() => path,
+        _ => playlist.current(),
+    };
+    ui.set_title(&runner::window_title(title_path));
+
+    let emu = EmuThread::spawn(chip, playlist, opts);
+    let (chip, stats) = emu.run_ui(&mut ui);
+
+    print!("{}", stats.report());
+    if deterministic {
+        println!("State hash: {:016x}", chip.state_hash());
+    }
+    if let Some(e) = stats.error() {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chip8::{Profile, ProfileBuilder};
+
+    use super::{config_dump, ips_warning, timing};
+
+    #[test]
+    fn ips_warnings() {
+        assert_eq!(ips_warning(&Profile::modern(), "modern", 600), None);
+        assert_eq!(ips_warning(&Profile::original(), "original", 60_000).unwrap(),
+            "--ips 60000 runs 1000 instructions a frame, programs for the original profile expect 5 to 20. Try --ips 1200 if they glitch.");
+        assert!(ips_warning(&Profile::original(), "original", 120).unwrap().ends_with("Try --ips 300 if they glitch."));
+        let fast = ProfileBuilder::new(Profile::modern()).ipf_range(100..=1000).build();
+        assert_eq!(ips_warning(&fast, "modern", 60_000), None);
+    }
+
+    #[test]
+    fn print_config() {
+        let profile = ProfileBuilder::new(Profile::chip48()).nop(Some(0x0000)).build();
+        let dump = config_dump(&profile, 42, &timing(true, false, 600), "pixels #C8C8C8, background #0000FF");
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[1..5], [
+            "profile: custom",
+            "seed: 42",
+            "timing: 600 instructions a second, 10 a frame, deterministic",
+            "palette: pixels #C8C8C8, background #0000FF",
+        ]);
+        for (name, value) in profile.settings() {
+            assert!(lines.contains(&format!("{}: {}", name, value).as_str()), "{}", name);
+        }
+        assert!(lines.contains(&"NOP opcode: 0x0000"));
+        assert!(lines.contains(&"8XY6 shifts VY: false"));
+        assert!(lines.contains(&"FX55 moves I: true"));
+        assert_eq!(timing(false, true, 600), "host clock, as fast as possible");
+    }
+}
@@ -0,0 +1,165 @@
+use crate::chip::Chip;
+use crate::framebuffer::Frame;
+use crate::text::{self, ADVANCE, GLYPH_HEIGHT, LINE_SPACING};
+
+pub const BYTES_PER_ROW: u32 = 16;
+pub const ROWS: u32 = 16;
+const PAGE: u32 = ROWS * BYTES_PER_ROW;
+// Text columns before the first byte of a line, "200 ".
+const ADDR_CHARS: u32 = 4;
+
+// Pixel values of the rendered view.
+pub const TEXT: u32 = 1;
+pub const HIGHLIGHT: u32 = 2;
+
+// Turns the TEXT pixels of a line of text `width` pixels wide at (x, y)
+// into HIGHLIGHT ones.
+pub fn highlight(frame: &mut Frame, x: u32, y: u32, width: u32) {
+    for py in y..y + GLYPH_HEIGHT {
+        for p in &mut frame[py][x as usize..(x + width) as usize] {
+            if *p == TEXT {
+                *p = HIGHLIGHT;
+            }
+        }
+    }
+}
+
+// First address of a view starting around `addr`: a row boundary, with the
+// whole view inside a RAM of `ram_size` bytes when it fits.
+pub fn clamp_top(addr: i64, ram_size: u32) -> u32 {
+    let last = ram_size.saturating_sub(PAGE) as i64;
+    let top = addr.clamp(0, last) as u32;
+    top - top % BYTES_PER_ROW
+}
+
+// A hexdump of RAM shown over the screen, with the byte at I and the
+// instruction at PC highlighted. It follows I until scrolled.
+#[derive(Default)]
+pub struct MemView {
+    visible: bool,
+    // Set once scrolled.
+    top: Option<u32>,
+}
+
+impl MemView {
+    pub fn new() -> MemView {
+        MemView::default()
+    }
+
+    // Showing the view centers it on I again.
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.top = None;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn top(&self, chip: &Chip) -> u32 {
+        match self.top {
+            Some(top) => top,
+            None => clamp_top(chip.i() as i64 - (PAGE / 2) as i64, chip.ram_size()),
+        }
+    }
+
+    // Moves the view by `pages` screens, negative towards 0.
+    pub fn scroll(&mut self, pages: i32, chip: &Chip) {
+        let top = self.top(chip) as i64 + pages as i64 * PAGE as i64;
+        self.top = Some(clamp_top(top, chip.ram_size()));
+    }
+
+    // (row, column) of the highlighted bytes in view.
+    pub fn highlights(&self, chip: &Chip) -> Vec<(u32, u32)> {
+        let top = self.top(chip);
+        [chip.i() as u32, chip.pc() as u32, chip.pc() as u32 + 1].iter()
+            .filter(|&&addr| addr >= top && addr < top + PAGE)
+            .map(|&addr| ((addr - top) / BYTES_PER_ROW, (addr - top) % BYTES_PER_ROW))
+            .collect()
+    }
+
+    // The view as TEXT and HIGHLIGHT pixels on 0, with a one pixel margin.
+    pub fn render(&self, chip: &Chip) -> Frame {
+        let line_height = GLYPH_HEIGHT + LINE_SPACING;
+        let width = (ADDR_CHARS + 3 * BYTES_PER_ROW) * ADVANCE - ADVANCE + 2;
+        let height = ROWS * line_height - LINE_SPACING + 2;
+        let mut frame = Frame::with_size(width, height);
+
+        let top = self.top(chip);
+        for (row, line) in chip.ram().hexdump(top..top + PAGE).iter().enumerate() {
+            text::draw_text(&mut frame, 1, 1 + row as u32 * line_height, line);
+        }
+        for (row, col) in self.highlights(chip) {
+            let x = 1 + (ADDR_CHARS + 3 * col) * ADVANCE;
+            highlight(&mut frame, x, 1 + row * line_height, 2 * ADVANCE - 1);
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clamp_top, MemView, HIGHLIGHT, TEXT};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    fn chip() -> Chip {
+        Chip::builder().profile(Profile::modern()).rom(&[
+            0xA3, 0x05, // LD I, 0x305
+            0x12, 0x02, // JP 0x202
+        ]).build().unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(clamp_top(0x285, 0x1000), 0x280);
+        assert_eq!(clamp_top(-0x80, 0x1000), 0);
+        assert_eq!(clamp_top(0xFFF, 0x1000), 0xF00);
+        // Smaller than a page.
+        assert_eq!(clamp_top(0x40, 0x80), 0);
+
+        let mut chip = chip();
+        let mut view = MemView::new();
+        assert_eq!(view.top(&chip), 0);
+        chip.cycle().unwrap();
+        // Centered on I: row 8 of 16.
+        assert_eq!(view.top(&chip), 0x280);
+        assert_eq!(view.highlights(&chip), vec![(8, 5)]);
+
+        view.scroll(-1, &chip);
+        assert_eq!(view.top(&chip), 0x180);
+        // I is out of view, PC is in it.
+        assert_eq!(view.highlights(&chip), vec![(8, 2), (8, 3)]);
+        view.scroll(-5, &chip);
+        assert_eq!(view.top(&chip), 0);
+        assert_eq!(view.highlights(&chip), vec![]);
+        view.scroll(100, &chip);
+        assert_eq!(view.top(&chip), 0xF00);
+
+        // Showing it again follows I.
+        view.toggle();
+        assert!(view.is_visible());
+        assert_eq!(view.top(&chip), 0x280);
+    }
+
+    #[test]
+    fn render() {
+        let mut chip = chip();
+        chip.cycle().unwrap();
+        let mut view = MemView::new();
+        view.scroll(-1, &chip);
+        view.scroll(1, &chip);
+        let frame = view.render(&chip);
+
+        // 16 lines of 52 characters.
+        assert_eq!(frame.width(), 52 * 4 - 4 + 2);
+        assert_eq!(frame.height(), 16 * 7 - 2 + 2);
+        // "280" starts the first line.
+        assert_eq!(frame[1_u32][1..4], [TEXT, TEXT, 0]);
+        // The byte at I, on line 8, column 5.
+        let (x, y) = (1 + (4 + 3 * 5) * 4, 1 + 8 * 7);
+        assert!(frame[y as u32][x..x + 7].contains(&HIGHLIGHT));
+        assert!(!frame[y as u32][x..x + 7].contains(&TEXT));
+        assert!(!frame[y as u32 - 7][x..x + 7].contains(&HIGHLIGHT));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::chip::Chip;
+use crate::clock::InstrClock;
+use crate::error::ChipError;
+use crate::history::History;
+use crate::step::StepResult;
+
+// Pausing from the UI, and executing one instruction at a time while paused.
+// Stepped instructions have their own instruction clock at --ips: the timers
+// tick after every ips/60-th step, when the step crosses into a new frame,
+// and never just because time passes during the pause.
+pub struct Pause {
+    paused: bool,
+    // Requested and not run yet.
+    steps: u32,
+    clock: InstrClock,
+}
+
+impl Pause {
+    pub fn new(ips: u32) -> Pause {
+        Pause {
+            paused: false,
+            steps: 0,
+            clock: InstrClock::new(ips),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle(&mut self) {
+        self.paused = !self.paused;
+        self.steps = 0;
+    }
+
+    // Ignored while running.
+    pub fn request_step(&mut self) {
+        if self.paused {
+            self.steps += 1;
+        }
+    }
+
+    // Runs the steps requested since the last call, recording them in
+    // `history`.
+    pub fn take_steps(&mut self, chip: &mut Chip, history: &mut History) -> Result<Vec<StepResult>, ChipError> {
+        let mut results = Vec::new();
+        while self.steps > 0 {
+            self.steps -= 1;
+            let before = history.snapshot(chip);
+            results.push(chip.step()?);
+            history.record(before);
+            self.clock.advance();
+            if self.clock.frame_sync() {
+                chip.cycle_timers();
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pause;
+    use crate::chip::Chip;
+    use crate::history::History;
+    use crate::profile::Profile;
+
+    #[test]
+    fn steps_and_timers() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x60, 0x09, // LD V0, 9
+            0xF0, 0x15, // LD DT, V0
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x04, // JP 0x204
+        ]).build().unwrap();
+        // Three instructions per frame.
+        let mut pause = Pause::new(180);
+        let mut history = History::new(0);
+
+        // Not paused, nothing to step.
+        pause.request_step();
+        assert!(pause.take_steps(&mut chip, &mut history).unwrap().is_empty());
+
+        pause.toggle();
+        for _ in 0..2 {
+            pause.request_step();
+        }
+        let steps = pause.take_steps(&mut chip, &mut history).unwrap();
+        assert_eq!(steps.iter().map(|s| s.mnemonic.as_str()).collect::<Vec<&str>>(), vec!["LD V0, 0x9", "LD DT, V0"]);
+        assert_eq!(chip.dt(), 9);
+
+        // The third step ends the frame, the next tick comes three steps later.
+        pause.request_step();
+        pause.take_steps(&mut chip, &mut history).unwrap();
+        assert_eq!(chip.dt(), 8);
+        for _ in 0..5 {
+            pause.request_step();
+        }
+        pause.take_steps(&mut chip, &mut history).unwrap();
+        assert_eq!(chip.dt(), 7);
+        assert_eq!(chip.instructions(), 8);
+
+        // Steps requested just before resuming are dropped.
+        pause.request_step();
+        pause.toggle();
+        assert!(pause.take_steps(&mut chip, &mut history).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+pub struct Playlist {
+    entries: Vec<PathBuf>,
+    current: usize,
+}
+
+impl Playlist {
+    // Directories are expanded into the files they contain, sorted by name.
+    // Plain files keep the order they were given in.
+    pub fn new(paths: &[PathBuf]) -> io::Result<Playlist> {
+        let mut entries = Vec::new();
+
+        for path in paths {
+            if path.is_dir() {
+                let mut files: Vec<PathBuf> = fs::read_dir(path)?
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file())
+                    .collect();
+                files.sort();
+                entries.append(&mut files);
+            } else {
+                entries.push(path.clone());
+            }
+        }
+
+        Ok(Playlist {
+            entries,
+            current: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.entries[self.current]
+    }
+
+    // Loads the current entry, moving forward past unloadable ones.
+    pub fn open<T, F>(&mut self, load: F) -> Option<T>
+    where F: FnMut(&Path) -> io::Result<T> {
+        self.seek(1, true, load)
+    }
+
+    pub fn next<T, F>(&mut self, load: F) -> Option<T>
+    where F: FnMut(&Path) -> io::Result<T> {
+        self.seek(1, false, load)
+    }
+
+    pub fn prev<T, F>(&mut self, load: F) -> Option<T>
+    where F: FnMut(&Path) -> io::Result<T> {
+        self.seek(-1, false, load)
+    }
+
+    // Tries every entry at most once, wrapping around at the ends.
+    // When nothing can be loaded the current position is left unchanged.
+    fn seek<T, F>(&mut self, step: isize, include_current: bool, mut load: F) -> Option<T>
+    where F: FnMut(&Path) -> io::Result<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let len = self.len() as isize;
+        let first = if include_current { 0 } else { 1 };
+        for n in first..first + len {
+            let idx = (self.current as isize + n * step).rem_euclid(len) as usize;
+            match load(&self.entries[idx]) {
+                Ok(v) => {
+                    self.current = idx;
+                    return Some(v);
+                },
+                Err(e) => warn!("Skipping {}: {}", self.entries[idx].display(), e),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::Playlist;
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+    use crate::rom;
+
+    fn playlist(names: &[&str]) -> Playlist {
+        let paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+        Playlist::new(&paths).unwrap()
+    }
+
+    fn load_ok(p: &Path) -> io::Result<PathBuf> {
+        Ok(p.to_path_buf())
+    }
+
+    fn load_skip_b(p: &Path) -> io::Result<PathBuf> {
+        if p == Path::new("b") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        }
+        Ok(p.to_path_buf())
+    }
+
+    fn load_none(_p: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "bad"))
+    }
+
+    #[test]
+    fn order() {
+        let mut pl = playlist(&["a", "b", "c"]);
+        assert_eq!(pl.len(), 3);
+        assert_eq!(pl.open(load_ok).unwrap(), PathBuf::from("a"));
+        assert_eq!(pl.next(load_ok).unwrap(), PathBuf::from("b"));
+        assert_eq!(pl.next(load_ok).unwrap(), PathBuf::from("c"));
+        assert_eq!(pl.prev(load_ok).unwrap(), PathBuf::from("b"));
+    }
+
+    #[test]
+    fn wrap() {
+        let mut pl = playlist(&["a", "b", "c"]);
+        assert_eq!(pl.prev(load_ok).unwrap(), PathBuf::from("c"));
+        assert_eq!(pl.next(load_ok).unwrap(), PathBuf::from("a"));
+    }
+
+    #[test]
+    fn skip() {
+        let mut pl = playlist(&["a", "b", "c"]);
+        assert_eq!(pl.next(load_skip_b).unwrap(), PathBuf::from("c"));
+        assert_eq!(pl.prev(load_skip_b).unwrap(), PathBuf::from("a"));
+
+        let mut pl = playlist(&["b", "c"]);
+        assert_eq!(pl.open(load_skip_b).unwrap(), PathBuf::from("c"));
+    }
+
+    #[test]
+    fn nothing_loadable() {
+        let mut pl = playlist(&["a", "b", "c"]);
+        pl.next(load_ok);
+        assert!(pl.next(load_none).is_none());
+        assert_eq!(pl.current(), Path::new("b"));
+
+        let mut pl = playlist(&[]);
+        assert!(pl.is_empty());
+        assert!(pl.open(load_ok).is_none());
+    }
+
+    #[test]
+    fn directory() {
+        let pl = playlist(&["rom/fixtures"]);
+        assert_eq!(pl.len(), 2);
+        assert_eq!(pl.current(), Path::new("rom/fixtures/draw_0.ch8"));
+    }
+
+    // Steps through the fixture ROMs the same way the emulator does,
+    // skipping a missing file on the way.
+    #[test]
+    fn fixture_roms() {
+        let mut pl = playlist(&["rom/fixtures/draw_0.ch8", "rom/fixtures/missing.ch8", "rom/fixtures/draw_1.ch8"]);
+        let mut chip = Chip::new(Profile::modern());
+
+        let mut load = |p: &Path| -> io::Result<()> {
+            let buffer = rom::read(p)?;
+            chip.reset();
+            chip.load_rom(&buffer, rom::START_ADDR)?;
+            chip.set_pc(rom::START_ADDR as u16);
+            for _ in 0..10 {
+                chip.cycle().unwrap();
+            }
+            Ok(())
+        };
+
+        pl.open(&mut load).unwrap();
+        pl.next(&mut load).unwrap();
+        assert_eq!(pl.current(), Path::new("rom/fixtures/draw_1.ch8"));
+
+        // The second ROM draws "1" at (8, 8) on a cleared screen.
+        let frame = chip.get_frame();
+        assert_eq!(frame[0_usize][1], 0);
+        assert_eq!(frame[8_usize][10], 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use crate::arch;
+use crate::chip::Chip;
+use crate::error::ChipError;
+
+// Bytes written into RAM after a ROM is loaded, e.g. to cheat extra lives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Poke {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+}
+
+pub(crate) fn parse_number(s: &str) -> Option<u32> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// Parses "addr=byte[,byte...]". Numbers are decimal or 0x-prefixed hex.
+pub fn parse(s: &str) -> Result<Poke, String> {
+    let (addr, values) = s.split_once('=').ok_or_else(|| format!("expected addr=byte[,byte...], got '{}'", s))?;
+    let addr = parse_number(addr).ok_or_else(|| format!("invalid address '{}'", addr))?;
+
+    let mut bytes = Vec::new();
+    for v in values.split(',') {
+        match parse_number(v) {
+            Some(b) if b <= 0xFF => bytes.push(b as u8),
+            _ => return Err(format!("invalid byte '{}'", v)),
+        }
+    }
+
+    if addr > u16::MAX as u32 {
+        return Err(format!("address {:#x} is past 0xffff", addr));
+    }
+    match addr.checked_add(bytes.len() as u32) {
+        Some(end) if end <= arch::MAX_RAMSIZE => {},
+        _ => return Err(format!("{:#05x}..{:#05x} is outside RAM", addr, addr as u64 + bytes.len() as u64)),
+    }
+
+    Ok(Poke { addr: addr as u16, bytes })
+}
+
+// Parses an opcode for --nop, decimal or 0x-prefixed hex.
+pub fn parse_opcode(s: &str) -> Result<u16, String> {
+    match parse_number(s) {
+        Some(op) if op <= 0xFFFF => Ok(op as u16),
+        _ => Err(format!("invalid opcode '{}'", s.trim())),
+    }
+}
+
+// Pokes past the end of the machine's RAM fail here, parse() only knows the
+// largest RAM there is.
+pub fn apply(chip: &mut Chip, pokes: &[Poke]) -> Result<(), ChipError> {
+    for p in pokes {
+        chip.poke(p.addr as u32, &p.bytes)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Poke {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+        write!(f, "{:#05x}={}", self.addr, bytes.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, parse, parse_opcode, Poke};
+    use crate::arch::{self, Arch};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    #[test]
+    fn parse_single() {
+        assert_eq!(parse("0x2E3=0xFF"), Ok(Poke { addr: 0x2E3, bytes: vec![0xFF] }));
+        assert_eq!(parse("512=7"), Ok(Poke { addr: 0x200, bytes: vec![7] }));
+        assert_eq!(parse("0x2e3=0xff").unwrap().to_string(), "0x2e3=0xff");
+    }
+
+    #[test]
+    fn parse_multi() {
+        let p = parse("0x300=0x01, 0x02,3").unwrap();
+        assert_eq!(p, Poke { addr: 0x300, bytes: vec![0x01, 0x02, 0x03] });
+        assert_eq!(p.to_string(), "0x300=0x01,0x02,0x03");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("0x300").is_err());
+        assert!(parse("0x300=").is_err());
+        assert!(parse("0x300=0x100").is_err());
+        assert!(parse("zz=1").is_err());
+        let last = arch::MAX_RAMSIZE - 2;
+        assert!(parse(&format!("{}=1,2", last)).is_ok());
+        assert!(parse(&format!("{}=1,2,3", last)).is_err());
+        assert_eq!(parse("0xFFFFFFFF=1"), Err("address 0xffffffff is past 0xffff".to_string()));
+        assert_eq!(parse("0x10000=1"), Err("address 0x10000 is past 0xffff".to_string()));
+    }
+
+    #[test]
+    fn opcodes() {
+        assert_eq!(parse_opcode("0x8000"), Ok(0x8000));
+        assert_eq!(parse_opcode("0"), Ok(0));
+        assert_eq!(parse_opcode("0x10000"), Err("invalid opcode '0x10000'".to_string()));
+    }
+
+    #[test]
+    fn apply_pokes() {
+        let mut chip = Chip::new(Profile::modern());
+        let pokes = [parse("0x300=0xAA,0xBB").unwrap(), parse("0x301=0xCC").unwrap()];
+        apply(&mut chip, &pokes).unwrap();
+
+        assert_eq!(chip.ram().read_u8(0x300).unwrap(), 0xAA);
+        assert_eq!(chip.ram().read_u8(0x301).unwrap(), 0xCC);
+        assert_eq!(chip.ram().read_u8(0x302).unwrap(), 0x00);
+
+        // Past 4K only with the RAM for it.
+        let pokes = [parse("0x1000=1").unwrap()];
+        assert!(apply(&mut chip, &pokes).is_err());
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        let mut chip = Chip::new(Profile::builder().arch(arch).build());
+        apply(&mut chip, &pokes).unwrap();
+        assert_eq!(chip.ram().read_u8(0x1000).unwrap(), 1);
+    }
+}
@@ -1,26 +1,462 @@
+use std::ops::RangeInclusive;
+
+use crate::arch::{self, Arch};
+use crate::error::ChipError;
+use crate::keypad::Keypad;
+
+// What happens when PC moves past the end of the address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PcOverflow {
+    // Stop with ChipError::PcOutOfBounds.
+    Error,
+    // Continue from address 0, with a warning.
+    Wrap,
+}
+
+// How DRW turns VX and VY into the sprite's top left corner on a screen of
+// `width` by `height`. The sprite is clipped from there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DrawOrigin {
+    // Modulo the screen size.
+    Wrap,
+    // The COSMAC VIP masks the coordinates to its 64x32 screen first, X to
+    // the low 6 bits and Y to the low 5, then wraps. Only differs from Wrap
+    // on a larger screen.
+    VipMask,
+}
+
+impl DrawOrigin {
+    pub fn origin(self, x: u8, y: u8, width: u32, height: u32) -> (u32, u32) {
+        let (x, y) = match self {
+            DrawOrigin::Wrap => (x as u32, y as u32),
+            DrawOrigin::VipMask => ((x & 0x3F) as u32, (y & 0x1F) as u32),
+        };
+        (x % width, y % height)
+    }
+}
+
+// Which key LD Vx, K stores when several are held down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyWait {
+    LowestIndex,
+    MostRecent,
+    FirstPressed,
+}
+
+impl KeyWait {
+    pub fn key(self, keypad: &Keypad) -> Option<u8> {
+        match self {
+            KeyWait::LowestIndex => keypad.first_down(),
+            KeyWait::MostRecent => keypad.last_pressed(),
+            KeyWait::FirstPressed => keypad.first_pressed(),
+        }
+    }
+}
+
+// The last address of the original 4K machine.
+pub const CLASSIC_MAX_ADDRESS: u16 = 0xFFF;
+// Room for a 16x16 SCHIP sprite, more than CHIP-8's DXYN can ask for.
+pub const MAX_SPRITE_BYTES: u8 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Profile {
-    pub op_8xy6_use_vy: bool,
-    pub op_8xye_use_vy: bool,
-    pub op_fx55_store_i: bool,
-    pub op_fx65_store_i: bool,
+    pub(crate) arch: Arch,
+    pub(crate) op_8xy6_use_vy: bool,
+    pub(crate) op_8xye_use_vy: bool,
+    pub(crate) op_fx55_store_i: bool,
+    pub(crate) op_fx65_store_i: bool,
+    // BNNN jumps to NNN plus VX, X being the top nibble of NNN, instead of
+    // plus V0.
+    pub(crate) op_bnnn_use_vx: bool,
+    pub(crate) pc_overflow: PcOverflow,
+    pub(crate) draw_origin: DrawOrigin,
+    pub(crate) key_wait: KeyWait,
+    // An opcode that does nothing but move PC on, for patching ROMs.
+    pub(crate) nop: Option<u16>,
+    // The highest address DRW, FX33, FX55 and FX65 may reach through I,
+    // None for all of RAM. Keeps a classic ROM inside the 4K it was written
+    // for when the arch has more memory.
+    pub(crate) max_address: Option<u16>,
+    // Where the stack's return addresses are kept in RAM, two bytes each
+    // from this address up, like the COSMAC VIP's at 0xEA0. RAM writes
+    // there change where RET goes. None keeps the stack apart from RAM.
+    pub(crate) stack_addr: Option<u16>,
+    // The most sprite bytes a draw reads, the rest of a longer sprite is
+    // left out. A guard against corrupt draws, not a quirk of any machine.
+    pub(crate) max_sprite_bytes: u8,
+    // Instructions per frame the programs written for it expect, lowest and
+    // highest. Only advice, for warning about unrealistic --ips.
+    pub(crate) ipf: (u32, u32),
 }
 
 impl Profile {
     pub fn original() -> Profile {
         Profile {
+            arch: Arch::chip8(),
             op_8xy6_use_vy: true,
             op_8xye_use_vy: true,
             op_fx55_store_i: true,
             op_fx65_store_i: true,
+            op_bnnn_use_vx: false,
+            pc_overflow: PcOverflow::Error,
+            draw_origin: DrawOrigin::Wrap,
+            key_wait: KeyWait::LowestIndex,
+            nop: None,
+            max_address: Some(CLASSIC_MAX_ADDRESS),
+            stack_addr: None,
+            max_sprite_bytes: MAX_SPRITE_BYTES,
+            ipf: (5, 20),
+        }
+    }
+
+    // The HP48 CHIP-48 interpreter: shifts work on VX, FX55 and FX65 move I
+    // past the registers and BNNN adds VX. The real CHIP-48 moved I by X, one
+    // short, which isn't emulated.
+    pub fn chip48() -> Profile {
+        Profile {
+            arch: Arch::chip8(),
+            op_8xy6_use_vy: false,
+            op_8xye_use_vy: false,
+            op_fx55_store_i: true,
+            op_fx65_store_i: true,
+            op_bnnn_use_vx: true,
+            pc_overflow: PcOverflow::Error,
+            draw_origin: DrawOrigin::Wrap,
+            key_wait: KeyWait::LowestIndex,
+            nop: None,
+            max_address: Some(CLASSIC_MAX_ADDRESS),
+            stack_addr: None,
+            max_sprite_bytes: MAX_SPRITE_BYTES,
+            ipf: (10, 40),
+        }
+    }
+
+    // Looks up a profile by its command line name.
+    pub fn from_name(name: &str) -> Result<Profile, ChipError> {
+        match name {
+            "original" => Ok(Profile::original()),
+            "modern" => Ok(Profile::modern()),
+            "chip48" => Ok(Profile::chip48()),
+            _ => Err(ChipError::InvalidProfile(name.to_string())),
         }
     }
 
     pub fn modern() -> Profile {
         Profile {
+            arch: Arch::chip8(),
             op_8xy6_use_vy: false,
             op_8xye_use_vy: false,
             op_fx55_store_i: false,
             op_fx65_store_i: false,
+            op_bnnn_use_vx: false,
+            pc_overflow: PcOverflow::Error,
+            draw_origin: DrawOrigin::Wrap,
+            key_wait: KeyWait::LowestIndex,
+            nop: None,
+            max_address: Some(CLASSIC_MAX_ADDRESS),
+            stack_addr: None,
+            max_sprite_bytes: MAX_SPRITE_BYTES,
+            ipf: (8, 60),
+        }
+    }
+
+    // Starts from the modern profile.
+    pub fn builder() -> ProfileBuilder {
+        ProfileBuilder::new(Profile::modern())
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    // Settings no machine can run with, on top of Arch::check(): a stack
+    // that doesn't fit under the end of RAM and max_address, or sprites
+    // clipped to nothing. A max_address past the RAM is fine, the end of
+    // RAM comes first.
+    pub fn check(&self) -> Result<(), ChipError> {
+        self.arch.check()?;
+        let invalid = |message: String| Err(ChipError::InvalidProfile(message));
+        if let Some(base) = self.stack_addr {
+            let limit = match self.max_address {
+                Some(max) => self.arch.ram_size.min(max as u32 + 1),
+                None => self.arch.ram_size,
+            };
+            if base as u32 + 2 * arch::STACKSIZE > limit {
+                return invalid(format!("stack at {:#06x} runs past {:#x}", base, limit));
+            }
+        }
+        if self.max_sprite_bytes == 0 {
+            return invalid("sprites clipped to no rows".to_string());
+        }
+        Ok(())
+    }
+
+    // The command line name, "custom" for one built with other settings.
+    pub fn name(&self) -> &'static str {
+        ["original", "modern", "chip48"].into_iter()
+            .find(|name| Profile::from_name(name).as_ref() == Ok(self))
+            .unwrap_or("custom")
+    }
+
+    pub fn ipf_range(&self) -> RangeInclusive<u32> {
+        self.ipf.0..=self.ipf.1
+    }
+
+    pub fn draw_origin(&self) -> DrawOrigin {
+        self.draw_origin
+    }
+
+    pub fn key_wait(&self) -> KeyWait {
+        self.key_wait
+    }
+
+    // Every setting with its value, by the names quirk_differences() uses,
+    // for bug reports.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let hex = |v: Option<u16>| v.map_or("none".to_string(), |v| format!("{:#06x}", v));
+        vec![
+            ("RAM size", format!("{:#x}", self.arch.ram_size)),
+            ("display", format!("{}x{}", self.arch.display_width, self.arch.display_height)),
+            ("8XY6 shifts VY", self.op_8xy6_use_vy.to_string()),
+            ("8XYE shifts VY", self.op_8xye_use_vy.to_string()),
+            ("FX55 moves I", self.op_fx55_store_i.to_string()),
+            ("FX65 moves I", self.op_fx65_store_i.to_string()),
+            ("BNNN adds VX", self.op_bnnn_use_vx.to_string()),
+            ("PC overflow", format!("{:?}", self.pc_overflow)),
+            ("NOP opcode", hex(self.nop)),
+            ("max address", hex(self.max_address)),
+            ("stack in RAM", hex(self.stack_addr)),
+            ("max sprite bytes", self.max_sprite_bytes.to_string()),
+            ("draw origin", format!("{:?}", self.draw_origin)),
+            ("FX0A key choice", format!("{:?}", self.key_wait)),
+            ("expected IPF", format!("{}-{}", self.ipf.0, self.ipf.1)),
+        ]
+    }
+
+    // The settings that change how instructions execute where `self` and
+    // `other` differ, by name. The arch and the ipf advice aren't compared.
+    pub fn quirk_differences(&self, other: &Profile) -> Vec<&'static str> {
+        [
+            ("8XY6 shifts VY", self.op_8xy6_use_vy != other.op_8xy6_use_vy),
+            ("8XYE shifts VY", self.op_8xye_use_vy != other.op_8xye_use_vy),
+            ("FX55 moves I", self.op_fx55_store_i != other.op_fx55_store_i),
+            ("FX65 moves I", self.op_fx65_store_i != other.op_fx65_store_i),
+            ("BNNN adds VX", self.op_bnnn_use_vx != other.op_bnnn_use_vx),
+            ("PC overflow", self.pc_overflow != other.pc_overflow),
+            ("NOP opcode", self.nop != other.nop),
+            ("max address", self.max_address != other.max_address),
+            ("stack in RAM", self.stack_addr != other.stack_addr),
+            ("max sprite bytes", self.max_sprite_bytes != other.max_sprite_bytes),
+            ("draw origin", self.draw_origin != other.draw_origin),
+            ("FX0A key choice", self.key_wait != other.key_wait),
+        ].into_iter().filter(|(_, differs)| *differs).map(|(name, _)| name).collect()
+    }
+}
+
+// Builds a custom profile from one of the named ones.
+pub struct ProfileBuilder {
+    profile: Profile,
+}
+
+impl ProfileBuilder {
+    pub fn new(base: Profile) -> ProfileBuilder {
+        ProfileBuilder { profile: base }
+    }
+
+    pub fn arch(mut self, arch: Arch) -> ProfileBuilder {
+        self.profile.arch = arch;
+        self
+    }
+
+    // 8XY6 and 8XYE shift VY into VX instead of shifting VX.
+    pub fn shift_uses_vy(mut self, on: bool) -> ProfileBuilder {
+        self.profile.op_8xy6_use_vy = on;
+        self.profile.op_8xye_use_vy = on;
+        self
+    }
+
+    // FX55 and FX65 leave I pointing past the last register.
+    pub fn load_store_moves_i(mut self, on: bool) -> ProfileBuilder {
+        self.profile.op_fx55_store_i = on;
+        self.profile.op_fx65_store_i = on;
+        self
+    }
+
+    // BNNN jumps to NNN plus VX instead of V0.
+    pub fn jump_uses_vx(mut self, on: bool) -> ProfileBuilder {
+        self.profile.op_bnnn_use_vx = on;
+        self
+    }
+
+    pub fn pc_overflow(mut self, pc_overflow: PcOverflow) -> ProfileBuilder {
+        self.profile.pc_overflow = pc_overflow;
+        self
+    }
+
+    pub fn draw_origin(mut self, draw_origin: DrawOrigin) -> ProfileBuilder {
+        self.profile.draw_origin = draw_origin;
+        self
+    }
+
+    pub fn key_wait(mut self, key_wait: KeyWait) -> ProfileBuilder {
+        self.profile.key_wait = key_wait;
+        self
+    }
+
+    // Executes `opcode` as a no-op, whatever it would do otherwise.
+    pub fn nop(mut self, opcode: Option<u16>) -> ProfileBuilder {
+        self.profile.nop = opcode;
+        self
+    }
+
+    // Bounds the program's memory accesses below the end of RAM, or lifts
+    // the bound with None, e.g. for XO-CHIP's 64K.
+    pub fn max_address(mut self, addr: Option<u16>) -> ProfileBuilder {
+        self.profile.max_address = addr;
+        self
+    }
+
+    // Keeps the stack in RAM from `addr`, or apart from it with None.
+    pub fn stack_in_ram(mut self, addr: Option<u16>) -> ProfileBuilder {
+        self.profile.stack_addr = addr;
+        self
+    }
+
+    // Clips sprites to `bytes` rows, see MAX_SPRITE_BYTES.
+    pub fn max_sprite_bytes(mut self, bytes: u8) -> ProfileBuilder {
+        self.profile.max_sprite_bytes = bytes;
+        self
+    }
+
+    // The instructions per frame to expect, see Profile::ipf_range().
+    pub fn ipf_range(mut self, range: RangeInclusive<u32>) -> ProfileBuilder {
+        self.profile.ipf = (*range.start(), *range.end());
+        self
+    }
+
+    pub fn build(self) -> Profile {
+        self.profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DrawOrigin, KeyWait, PcOverflow, Profile, ProfileBuilder};
+    use crate::arch::Arch;
+    use crate::error::ChipError;
+
+    #[test]
+    fn from_name() {
+        assert!(Profile::from_name("original").unwrap().op_fx55_store_i);
+        assert!(!Profile::from_name("modern").unwrap().op_fx55_store_i);
+        assert_eq!(Profile::from_name("schip").err(), Some(ChipError::InvalidProfile("schip".to_string())));
+    }
+
+    #[test]
+    fn settings() {
+        let profile = ProfileBuilder::new(Profile::original()).nop(Some(0x0000)).max_address(None).build();
+        let settings: Vec<String> = profile.settings().into_iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        assert_eq!(settings, [
+            "RAM size: 0x1000",
+            "display: 64x32",
+            "8XY6 shifts VY: true",
+            "8XYE shifts VY: true",
+            "FX55 moves I: true",
+            "FX65 moves I: true",
+            "BNNN adds VX: false",
+            "PC overflow: Error",
+            "NOP opcode: 0x0000",
+            "max address: none",
+            "stack in RAM: none",
+            "max sprite bytes: 32",
+            "draw origin: Wrap",
+            "FX0A key choice: LowestIndex",
+            "expected IPF: 5-20",
+        ]);
+        // Each quirk quirk_differences() compares is listed.
+        let names: Vec<&str> = profile.settings().into_iter().map(|(name, _)| name).collect();
+        let other = ProfileBuilder::new(Profile::chip48()).load_store_moves_i(false).pc_overflow(PcOverflow::Wrap)
+            .nop(Some(1)).max_address(Some(0)).stack_in_ram(Some(0xEA0)).draw_origin(DrawOrigin::VipMask)
+            .key_wait(KeyWait::MostRecent).max_sprite_bytes(8).build();
+        let quirks = profile.quirk_differences(&other);
+        assert_eq!(quirks.len(), 12);
+        for quirk in quirks {
+            assert!(names.contains(&quirk), "{}", quirk);
+        }
+    }
+
+    #[test]
+    fn name() {
+        for name in ["original", "modern", "chip48"] {
+            assert_eq!(Profile::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(Profile::builder().nop(Some(0)).build().name(), "custom");
+    }
+
+    #[test]
+    fn builder() {
+        assert_eq!(Profile::builder().build(), Profile::modern());
+        let original = Profile::builder().shift_uses_vy(true).load_store_moves_i(true).ipf_range(5..=20).build();
+        assert_eq!(original, Profile::original());
+        assert_eq!(original.ipf_range(), 5..=20);
+        assert_eq!(ProfileBuilder::new(Profile::original()).shift_uses_vy(false).load_store_moves_i(false).ipf_range(8..=60).build(), Profile::modern());
+
+        let arch = Arch { display_width: 128, display_height: 64, ..Arch::chip8() };
+        let hires = Profile::builder().arch(arch).build();
+        assert_eq!(hires.arch(), arch);
+        assert!(!hires.op_8xy6_use_vy);
+    }
+
+    #[test]
+    fn check() {
+        assert_eq!(Profile::modern().check(), Ok(()));
+        assert_eq!(Profile::builder().stack_in_ram(Some(0xFE0)).build().check(), Ok(()));
+        let invalid = |message: &str| Err(ChipError::InvalidProfile(message.to_string()));
+        assert_eq!(Profile::builder().stack_in_ram(Some(0xFE2)).build().check(), invalid("stack at 0x0fe2 runs past 0x1000"));
+        assert_eq!(Profile::builder().stack_in_ram(Some(0xEA0)).max_address(Some(0xEAF)).build().check(),
+            invalid("stack at 0x0ea0 runs past 0xeb0"));
+        assert_eq!(Profile::builder().max_sprite_bytes(0).build().check(), invalid("sprites clipped to no rows"));
+        assert_eq!(Profile::builder().arch(Arch { ram_size: 0, ..Arch::chip8() }).build().check(),
+            invalid("RAM of 0 bytes doesn't hold the font"));
+        // Bounded by the RAM, not max_address.
+        let arch = Arch { ram_size: 0x800, ..Arch::chip8() };
+        assert_eq!(Profile::builder().arch(arch).stack_in_ram(Some(0x7E0)).build().check(), Ok(()));
+    }
+
+    #[test]
+    fn chip48() {
+        let p = Profile::from_name("chip48").unwrap();
+        assert_eq!(p, Profile::chip48());
+        // Shifts use VX, like modern.
+        assert!(!p.op_8xy6_use_vy);
+        assert!(!p.op_8xye_use_vy);
+        // FX55 and FX65 move I, like original.
+        assert!(p.op_fx55_store_i);
+        assert!(p.op_fx65_store_i);
+        // BNNN adds VX, unlike both.
+        assert!(p.op_bnnn_use_vx);
+        assert!(!Profile::original().op_bnnn_use_vx);
+        assert!(!Profile::modern().op_bnnn_use_vx);
+        assert_eq!(p.arch(), Arch::chip8());
+        let built = Profile::builder().load_store_moves_i(true).jump_uses_vx(true).ipf_range(10..=40).build();
+        assert_eq!(built, p);
+    }
+
+    #[test]
+    fn draw_origin() {
+        // Off the screen to the right and below.
+        for (width, height) in [(64, 32), (128, 64)] {
+            assert_eq!(DrawOrigin::Wrap.origin(200, 70, width, height), (200 % width, 70 % height));
         }
+        // The same on the VIP's own screen size, masked first on a larger one.
+        assert_eq!(DrawOrigin::VipMask.origin(200, 70, 64, 32), (8, 6));
+        assert_eq!(DrawOrigin::VipMask.origin(200, 70, 128, 64), (8, 6));
+        assert_eq!(DrawOrigin::Wrap.origin(200, 70, 128, 64), (72, 6));
+        assert_eq!(Profile::original().draw_origin(), DrawOrigin::Wrap);
+        assert_eq!(Profile::builder().draw_origin(DrawOrigin::VipMask).build().name(), "custom");
     }
 }
@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::chip::Chip;
+use crate::rom;
+use crate::statefile;
+
+// F5 saves the machine to "<rom>.state" next to the ROM, F4 loads it back.
+pub fn path_for(rom: &Path) -> PathBuf {
+    let mut name = rom.as_os_str().to_owned();
+    name.push(".state");
+    PathBuf::from(name)
+}
+
+// Writes the machine to `path` in a state file, tied to the ROM file if there
+// is one.
+pub fn save_to(chip: &Chip, path: &Path, rom: Option<&Path>) -> io::Result<()> {
+    let rom = rom.map(rom::read).transpose()?;
+    statefile::write(path, chip, rom.as_deref())
+}
+
+// Loads the machine from `path`, see StateFile::load_into(). `force` loads a
+// state saved for another ROM file too, with a warning, for ROM hacks and
+// fixed up dumps. The machine is left untouched on errors.
+pub fn load_from(chip: &mut Chip, path: &Path, rom: Option<&Path>, force: bool) -> io::Result<()> {
+    let file = statefile::read(path)?;
+    let mut rom = rom.map(rom::read).transpose()?;
+    if force && rom.as_deref().is_some_and(|rom| !file.is_for_rom(rom)) {
+        warn!("{} was saved for another ROM, loading it anyway", path.display());
+        rom = None;
+    }
+    Ok(file.load_into(chip, rom.as_deref())?)
+}
+
+// --autosave-exit saves here when the emulator quits, "<rom>.exit.state".
+pub fn exit_path(rom: &Path) -> PathBuf {
+    let mut name = rom.as_os_str().to_owned();
+    name.push(".exit.state");
+    PathBuf::from(name)
+}
+
+// What the last exit left for a ROM.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExitSave {
+    None,
+    // A file that is malformed or was saved for another ROM. It is
+    // overwritten on the next exit.
+    Stale,
+    Resumable(PathBuf),
+}
+
+pub fn exit_save(rom: &Path) -> ExitSave {
+    let path = exit_path(rom);
+    let Ok(data) = fs::read(&path) else { return ExitSave::None };
+    match (statefile::decode(&data), rom::read(rom)) {
+        (Ok(file), Ok(rom)) if file.is_for_rom(&rom) => ExitSave::Resumable(path),
+        _ => ExitSave::Stale,
+    }
+}
+
+// Returns the file written.
+pub fn save(chip: &Chip, rom: &Path) -> io::Result<PathBuf> {
+    let path = path_for(rom);
+    save_to(chip, &path, Some(rom))?;
+    Ok(path)
+}
+
+// Returns the file read.
+pub fn load(chip: &mut Chip, rom: &Path, force: bool) -> io::Result<PathBuf> {
+    let path = path_for(rom);
+    load_from(chip, &path, Some(rom), force)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{exit_path, exit_save, load_from, path_for, save_to, ExitSave};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    #[test]
+    fn checks() {
+        assert_eq!(path_for(Path::new("rom/pong.ch8")), PathBuf::from("rom/pong.ch8.state"));
+
+        let path = std::env::temp_dir().join(format!("chip8-quicksave-{}.state", std::process::id()));
+        let chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        save_to(&chip, &path, Some(Path::new("rom/fixtures/draw_0.ch8"))).unwrap();
+        let mut other = Chip::new_seed(0, Profile::chip48());
+        let err = load_from(&mut other, &path, Some(Path::new("rom/fixtures/draw_1.ch8")), false).unwrap_err();
+        assert_eq!(err.to_string(), "save state is for a different ROM");
+        // Another profile only warns.
+        load_from(&mut other, &path, Some(Path::new("rom/fixtures/draw_0.ch8")), false).unwrap();
+        assert_eq!(other.state_hash(), chip.state_hash());
+        // Forced onto another ROM.
+        let mut forced = Chip::new_seed(0, Profile::modern());
+        load_from(&mut forced, &path, Some(Path::new("rom/fixtures/draw_1.ch8")), true).unwrap();
+        assert_eq!(forced.state_hash(), chip.state_hash());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_after_exit() {
+        let dir = std::env::temp_dir().join(format!("chip8-exit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("loop.ch8");
+        std::fs::write(&rom, [0x12, 0x00]).unwrap();
+        assert_eq!(exit_path(&rom), dir.join("loop.ch8.exit.state"));
+        assert_eq!(exit_save(&rom), ExitSave::None);
+
+        let chip = Chip::builder().profile(Profile::modern()).rom(&[0x12, 0x00]).build().unwrap();
+        save_to(&chip, &exit_path(&rom), Some(&rom)).unwrap();
+        assert_eq!(exit_save(&rom), ExitSave::Resumable(exit_path(&rom)));
+        assert!(!dir.join("loop.ch8.exit.state.tmp").exists());
+
+        // The ROM changed since.
+        std::fs::write(&rom, [0x12, 0x02]).unwrap();
+        assert_eq!(exit_save(&rom), ExitSave::Stale);
+        std::fs::write(exit_path(&rom), b"junk").unwrap();
+        assert_eq!(exit_save(&rom), ExitSave::Stale);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+// The quirks test of Timendus' CHIP-8 test suite (5-quirks.ch8) run once per
+// profile, the CHIP-8 platform picked in its menu, and its result screen
+// read back into a table for `chip quirks-report`:
+//
+//   quirk         original  modern  chip48
+//   vF reset      fail      fail    fail
+//   memory        pass      fail    pass
+//
+// A result is the check or cross mark at the end of its row, where MARK_X,
+// MARK_Y and ROW_HEIGHT say, shaped like CHECK or CROSS. A mark that reads
+// as neither is taken from the frame hash instead when the rom_suites test
+// has one blessed for the profile: that screen was checked by hand, so it
+// shows what the profile's settings predict. Results that differ from the
+// prediction are starred.
+
+use std::io;
+use std::path::Path;
+
+use crate::chip::Chip;
+use crate::frame_stream::FrameStream;
+use crate::framebuffer::Frame;
+use crate::golden::frame_hash;
+use crate::profile::Profile;
+use crate::rom;
+
+// Run like the rom_suites test does: 1 pressed at frame 60 and released at
+// 65 picks CHIP-8 in the menu.
+const FRAMES: usize = 600;
+const IPS: u32 = 600;
+const MENU_KEY: u8 = 0x1;
+const MENU_FRAMES: (u64, u64) = (60, 65);
+
+pub const PROFILES: [&str; 3] = ["original", "modern", "chip48"];
+
+// The marks are 8 pixels wide and 5 high, a row each from MARK_Y down.
+const MARK_X: u32 = 56;
+const MARK_Y: u32 = 1;
+const ROW_HEIGHT: u32 = 5;
+const CHECK: [u8; 5] = [0b00000010, 0b00000100, 0b10001000, 0b01010000, 0b00100000];
+const CROSS: [u8; 5] = [0b10001000, 0b01010000, 0b00100000, 0b01010000, 0b10001000];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail,
+    Unknown,
+}
+
+pub struct Quirk {
+    pub name: &'static str,
+    // Whether the ROM should pass the quirk on a profile, testing for the
+    // COSMAC VIP's behavior. None where the emulator doesn't model it.
+    pub expected: fn(&Profile) -> Option<bool>,
+}
+
+// In the order of the result screen's rows.
+pub const QUIRKS: [Quirk; 6] = [
+    // AND, OR and XOR never reset VF here.
+    Quirk { name: "vF reset", expected: |_| Some(false) },
+    Quirk { name: "memory", expected: |p| Some(p.op_fx55_store_i && p.op_fx65_store_i) },
+    Quirk { name: "display wait", expected: |_| None },
+    // Sprites are clipped at the edges on all profiles.
+    Quirk { name: "clipping", expected: |_| Some(true) },
+    Quirk { name: "shifting", expected: |p| Some(p.op_8xy6_use_vy && p.op_8xye_use_vy) },
+    Quirk { name: "jumping", expected: |p| Some(!p.op_bnnn_use_vx) },
+];
+
+// The mark of result row `row`.
+pub fn read_mark(frame: &Frame, row: u32) -> Verdict {
+    let top = MARK_Y + row * ROW_HEIGHT;
+    if MARK_X + 8 > frame.width() || top + ROW_HEIGHT > frame.height() {
+        return Verdict::Unknown;
+    }
+    let mark: Vec<u8> = (top..top + ROW_HEIGHT)
+        .map(|y| frame[y][MARK_X as usize..MARK_X as usize + 8].iter().fold(0, |bits, &p| (bits << 1) | (p != 0) as u8))
+        .collect();
+    if mark == CHECK {
+        Verdict::Pass
+    } else if mark == CROSS {
+        Verdict::Fail
+    } else {
+        Verdict::Unknown
+    }
+}
+
+// A profile's results.
+pub struct Column {
+    pub profile: String,
+    pub verdicts: Vec<Verdict>,
+    pub expected: Vec<Option<bool>>,
+}
+
+impl Column {
+    // Reads the results off `frame`. `blessed` is the frame hash the
+    // rom_suites expectation of the profile has, if any.
+    pub fn from_frame(profile: &str, settings: &Profile, frame: &Frame, blessed: Option<u64>) -> Column {
+        let expected: Vec<Option<bool>> = QUIRKS.iter().map(|q| (q.expected)(settings)).collect();
+        let from_hash = blessed == Some(frame_hash(frame));
+        let verdicts = (0..QUIRKS.len()).map(|row| match (read_mark(frame, row as u32), expected[row]) {
+            (Verdict::Unknown, Some(pass)) if from_hash => if pass { Verdict::Pass } else { Verdict::Fail },
+            (v, _) => v,
+        }).collect();
+        Column { profile: profile.to_string(), verdicts, expected }
+    }
+
+    fn differs(&self, row: usize) -> bool {
+        match (self.verdicts[row], self.expected[row]) {
+            (_, None) => false,
+            (Verdict::Pass, Some(pass)) => !pass,
+            (Verdict::Fail, Some(pass)) => pass,
+            (Verdict::Unknown, Some(_)) => true,
+        }
+    }
+
+    // True when a result isn't what the profile's settings predict.
+    pub fn has_mismatch(&self) -> bool {
+        (0..QUIRKS.len()).any(|row| self.differs(row))
+    }
+}
+
+// The quirks down, the profiles across.
+pub fn table(columns: &[Column]) -> String {
+    let name_width = QUIRKS.iter().map(|q| q.name.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = columns.iter().map(|c| c.profile.len().max(5)).collect();
+    let mut out = format!("{:<w$}", "quirk", w = name_width);
+    for (c, w) in columns.iter().zip(&widths) {
+        out.push_str(&format!("  {:<w$}", c.profile, w = *w));
+    }
+    out = out.trim_end().to_string();
+    out.push('\n');
+    for (row, quirk) in QUIRKS.iter().enumerate() {
+        let mut line = format!("{:<w$}", quirk.name, w = name_width);
+        for (c, w) in columns.iter().zip(&widths) {
+            let cell = match c.verdicts[row] {
+                Verdict::Pass => "pass",
+                Verdict::Fail => "fail",
+                Verdict::Unknown => "?",
+            };
+            let star = if c.differs(row) { "*" } else { "" };
+            line.push_str(&format!("  {:<w$}", format!("{}{}", cell, star), w = *w));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    if columns.iter().any(Column::has_mismatch) {
+        out.push_str("* not what the profile's settings predict\n");
+    }
+    out
+}
+
+// The hash line of the rom_suites expectation for `rom` on `profile`.
+fn blessed_hash(expected_dir: &Path, rom: &Path, profile: &str) -> Option<u64> {
+    let stem = rom.file_stem()?.to_string_lossy().into_owned();
+    let text = std::fs::read_to_string(expected_dir.join(format!("{}.{}.txt", stem, profile))).ok()?;
+    text.lines().find_map(|l| l.strip_prefix("hash ")).and_then(|h| u64::from_str_radix(h, 16).ok())
+}
+
+// Runs the quirks ROM at `path` headless on the named profile.
+pub fn run(path: &Path, profile: &str, expected_dir: &Path) -> io::Result<Column> {
+    let settings = Profile::from_name(profile)?;
+    let mut chip = Chip::builder().profile(settings).seed(0).build()?;
+    rom::start(&mut chip, path, &[])?;
+    let mut stream = FrameStream::new(chip, IPS)
+        .key_press(MENU_FRAMES.0, MENU_KEY)
+        .key_release(MENU_FRAMES.1, MENU_KEY);
+    let last = stream.by_ref().take(FRAMES).last();
+    if let Some(e) = stream.error() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} on the {} profile stopped with: {}", path.display(), profile, e)));
+    }
+    let frame = last.unwrap_or_default();
+    Ok(Column::from_frame(profile, &settings, &frame, blessed_hash(expected_dir, path, profile)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_mark, table, Column, Verdict, CHECK, CROSS, MARK_X, MARK_Y, QUIRKS, ROW_HEIGHT};
+    use crate::framebuffer::Frame;
+    use crate::golden::frame_hash;
+    use crate::profile::Profile;
+
+    // A result screen with `marks` drawn down the rows, None for a blank.
+    fn screen(marks: &[Option<bool>]) -> Frame {
+        let mut frame = Frame::new();
+        for (row, mark) in marks.iter().enumerate() {
+            let glyph = match mark {
+                Some(true) => CHECK,
+                Some(false) => CROSS,
+                None => continue,
+            };
+            for (dy, bits) in glyph.iter().enumerate() {
+                for dx in 0..8 {
+                    frame[MARK_Y + row as u32 * ROW_HEIGHT + dy as u32][(MARK_X + dx) as usize] = ((bits >> (7 - dx)) & 1) as u32;
+                }
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn marks() {
+        let frame = screen(&[Some(true), Some(false), None, Some(true), Some(true), Some(false)]);
+        let read: Vec<Verdict> = (0..6).map(|row| read_mark(&frame, row)).collect();
+        assert_eq!(read, [Verdict::Pass, Verdict::Fail, Verdict::Unknown, Verdict::Pass, Verdict::Pass, Verdict::Fail]);
+        // Off the screen.
+        assert_eq!(read_mark(&frame, 7), Verdict::Unknown);
+    }
+
+    #[test]
+    fn columns() {
+        // What the original profile should show.
+        let original = screen(&[Some(false), Some(true), Some(true), Some(true), Some(true), Some(true)]);
+        let column = Column::from_frame("original", &Profile::original(), &original, None);
+        assert_eq!(column.expected, [Some(false), Some(true), None, Some(true), Some(true), Some(true)]);
+        assert!(!column.has_mismatch());
+
+        // Modern fails memory and shifting, as its settings predict, but
+        // passes vF reset, which they don't.
+        let modern = screen(&[Some(true), Some(false), Some(false), Some(true), Some(false), Some(true)]);
+        let column = Column::from_frame("modern", &Profile::modern(), &modern, None);
+        assert!(column.has_mismatch());
+
+        // Unreadable marks count as mismatches, unless the screen is the
+        // blessed one.
+        let blank = screen(&[]);
+        assert!(Column::from_frame("chip48", &Profile::chip48(), &blank, None).has_mismatch());
+        let blessed = Column::from_frame("chip48", &Profile::chip48(), &blank, Some(frame_hash(&blank)));
+        assert!(!blessed.has_mismatch());
+        assert_eq!(blessed.verdicts[2], Verdict::Unknown);
+        assert_eq!(blessed.verdicts[5], Verdict::Fail);
+        assert_eq!(QUIRKS[5].name, "jumping");
+    }
+
+    #[test]
+    fn format() {
+        let original = screen(&[Some(false), Some(true), Some(true), Some(true), Some(true), Some(true)]);
+        let modern = screen(&[Some(true), Some(false), None, Some(true), Some(false), Some(true)]);
+        let columns = [
+            Column::from_frame("original", &Profile::original(), &original, None),
+            Column::from_frame("modern", &Profile::modern(), &modern, None),
+        ];
+        assert_eq!(table(&columns), concat!(
+            "quirk         original  modern\n",
+            "vF reset      fail      pass*\n",
+            "memory        pass      fail\n",
+            "display wait  pass      ?\n",
+            "clipping      pass      pass\n",
+            "shifting      pass      fail\n",
+            "jumping       pass      pass\n",
+            "* not what the profile's settings predict\n",
+        ));
+        assert!(!table(&columns[..1]).contains('*'));
+    }
+}
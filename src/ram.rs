@@ -1,58 +1,112 @@
 use crate::arch;
-use crate::util;
+use crate::error::ChipError;
 
-type RamBuf = util::Array<u8, { arch::RAMSIZE as usize}>;
-
-pub struct Ram {
-    pub mem: RamBuf,
+// Zero-initialized memory, sized at construction.
+#[derive(Clone)]
+pub(crate) struct Ram {
+    pub(crate) mem: Vec<u8>,
 }
 
 impl Ram {
     pub fn new() -> Ram {
+        Ram::with_size(arch::RAMSIZE)
+    }
+
+    pub fn with_size(size: u32) -> Ram {
         Ram {
-            mem: RamBuf::new(),
+            mem: vec![0; size as usize],
         }
     }
 
-    // TODO: handle overflow
+    pub fn size(&self) -> u32 {
+        self.mem.len() as u32
+    }
+
+    // True when the `len` bytes starting at `addr` are all inside the RAM.
+    pub fn contains(&self, addr: u32, len: u32) -> bool {
+        (addr as u64) + (len as u64) <= self.mem.len() as u64
+    }
 
-    pub fn write_u8(&mut self, addr: u32, value: u8) {
-        self.mem[addr] = value;
+    fn check(&self, addr: u32, len: u32) -> Result<(), ChipError> {
+        if !self.contains(addr, len) {
+            return Err(ChipError::RamOutOfBounds { addr: addr.max(self.size()) });
+        }
+        Ok(())
     }
 
-    pub fn read_u8(&self, addr: u32) -> u8 {
-        self.mem[addr]
+    pub fn write_u8(&mut self, addr: u32, value: u8) -> Result<(), ChipError> {
+        self.check(addr, 1)?;
+        self.mem[addr as usize] = value;
+        Ok(())
     }
 
-    pub fn read_u16(&self, addr: u32) -> u16 {
-        u16::from_be_bytes([self.mem[addr], self.mem[addr + 1]])
+    pub fn read_u8(&self, addr: u32) -> Result<u8, ChipError> {
+        self.check(addr, 1)?;
+        Ok(self.mem[addr as usize])
     }
 
-    pub fn write_u16(&mut self, addr: u32, v: u16) {
-        self.mem[addr] = ((v & 0xff00) >> 8) as u8;
-        self.mem[addr + 1] = (v & 0xff) as u8;
+    pub fn read_u16(&self, addr: u32) -> Result<u16, ChipError> {
+        self.check(addr, 2)?;
+        Ok(u16::from_be_bytes([self.mem[addr as usize], self.mem[addr as usize + 1]]))
     }
 
-    pub fn load_block_u16(&mut self, addr: u32, buf: &[u16]) {
-        let mut addr = addr;
-        for op in buf {
-            self.write_u16(addr, *op);
-            addr += 2;
+    pub fn write_u16(&mut self, addr: u32, v: u16) -> Result<(), ChipError> {
+        self.check(addr, 2)?;
+        self.mem[addr as usize..addr as usize + 2].copy_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn read_block(&self, addr: u32, len: u32) -> Result<&[u8], ChipError> {
+        self.check(addr, len)?;
+        Ok(&self.mem[addr as usize..(addr + len) as usize])
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.mem
+    }
+
+    // One line per 16 bytes of `range`, e.g. "200 60 2A 12 02 ...", with
+    // the address of the first byte. Stops at the end of the RAM.
+    pub fn hexdump(&self, range: std::ops::Range<u32>) -> Vec<String> {
+        let end = range.end.min(self.size());
+        let mut lines = Vec::new();
+        let mut addr = range.start;
+        while addr < end {
+            let row_end = (addr + 16).min(end);
+            let bytes: Vec<String> = self.mem[addr as usize..row_end as usize].iter().map(|b| format!("{:02X}", b)).collect();
+            lines.push(format!("{:03X} {}", addr, bytes.join(" ")));
+            addr = row_end;
         }
+        lines
     }
 
-    pub fn load_block_u8(&mut self, addr: u32, buf: &[u8]) -> u32 {
+    pub fn load_block_u16(&mut self, addr: u32, buf: &[u16]) -> Result<(), ChipError> {
+        self.check(addr, 2 * buf.len() as u32)?;
         let mut addr = addr;
         for op in buf {
-            self.write_u8(addr, *op);
-            addr += 1;
+            self.write_u16(addr, *op)?;
+            addr += 2;
         }
-        addr
+        Ok(())
+    }
+
+    pub fn load_block_u8(&mut self, addr: u32, buf: &[u8]) -> Result<u32, ChipError> {
+        self.check(addr, buf.len() as u32)?;
+        let end = addr + buf.len() as u32;
+        self.mem[addr as usize..end as usize].copy_from_slice(buf);
+        Ok(end)
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::ChipError;
     use crate::ram::Ram;
 
     #[test]
@@ -61,7 +115,7 @@ mod tests {
         let ram = Ram::new();
 
         for i in 0..arch::RAMSIZE {
-            assert_eq!(ram.read_u8(i), 0);
+            assert_eq!(ram.read_u8(i).unwrap(), 0);
         }
     }
 
@@ -69,41 +123,41 @@ mod tests {
     fn write_u8_read_u8() {
         let mut ram = Ram::new();
 
-        ram.write_u8(0x0, 0x00);
-        ram.write_u8(0x1, 0x10);
+        ram.write_u8(0x0, 0x00).unwrap();
+        ram.write_u8(0x1, 0x10).unwrap();
 
-        assert_eq!(ram.read_u8(0x0), 0x00);
-        assert_eq!(ram.read_u8(0x1), 0x10);
+        assert_eq!(ram.read_u8(0x0).unwrap(), 0x00);
+        assert_eq!(ram.read_u8(0x1).unwrap(), 0x10);
 
-        ram.write_u8(0xff0, 0x00);
-        ram.write_u8(0xff1, 0x10);
+        ram.write_u8(0xff0, 0x00).unwrap();
+        ram.write_u8(0xff1, 0x10).unwrap();
 
-        assert_eq!(ram.read_u8(0xff0), 0x00);
-        assert_eq!(ram.read_u8(0xff1), 0x10);
+        assert_eq!(ram.read_u8(0xff0).unwrap(), 0x00);
+        assert_eq!(ram.read_u8(0xff1).unwrap(), 0x10);
     }
 
     #[test]
     fn write_u16_read_u16() {
         let mut ram = Ram::new();
 
-        ram.write_u16(0x0, 0x1122);
-        ram.write_u16(0x2, 0x3344);
+        ram.write_u16(0x0, 0x1122).unwrap();
+        ram.write_u16(0x2, 0x3344).unwrap();
 
-        assert_eq!(ram.read_u16(0x0), 0x1122);
-        assert_eq!(ram.read_u16(0x2), 0x3344);
+        assert_eq!(ram.read_u16(0x0).unwrap(), 0x1122);
+        assert_eq!(ram.read_u16(0x2).unwrap(), 0x3344);
     }
 
     #[test]
     fn write_u16_read_u8() {
         let mut ram = Ram::new();
 
-        ram.write_u16(0x0, 0x1122);
-        assert_eq!(ram.read_u8(0x0), 0x11);
-        assert_eq!(ram.read_u8(0x1), 0x22);
+        ram.write_u16(0x0, 0x1122).unwrap();
+        assert_eq!(ram.read_u8(0x0).unwrap(), 0x11);
+        assert_eq!(ram.read_u8(0x1).unwrap(), 0x22);
 
-        ram.write_u16(0x2, 0x3344);
-        assert_eq!(ram.read_u8(0x2), 0x33);
-        assert_eq!(ram.read_u8(0x3), 0x44);
+        ram.write_u16(0x2, 0x3344).unwrap();
+        assert_eq!(ram.read_u8(0x2).unwrap(), 0x33);
+        assert_eq!(ram.read_u8(0x3).unwrap(), 0x44);
     }
 
     #[test]
@@ -111,14 +165,14 @@ mod tests {
         let mut ram = Ram::new();
 
         let data = [0x1122u16];
-        ram.load_block_u16(0, &data);
-        assert_eq!(ram.read_u16(0), 0x1122u16);
+        ram.load_block_u16(0, &data).unwrap();
+        assert_eq!(ram.read_u16(0).unwrap(), 0x1122u16);
 
         let data = [0x1122u16, 0x3344u16, 0x5566u16];
         let mut addr = 0x200;
-        ram.load_block_u16(addr, &data);
+        ram.load_block_u16(addr, &data).unwrap();
         for bb in data {
-            assert_eq!(ram.read_u16(addr), bb);
+            assert_eq!(ram.read_u16(addr).unwrap(), bb);
             addr += 2;
         }
     }
@@ -128,15 +182,65 @@ mod tests {
         let mut ram = Ram::new();
 
         let data = [0x12];
-        ram.load_block_u8(0, &data);
-        assert_eq!(ram.read_u8(0), 0x12);
+        ram.load_block_u8(0, &data).unwrap();
+        assert_eq!(ram.read_u8(0).unwrap(), 0x12);
 
         let data = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
-        let mut addr = 0x200;
-        ram.load_block_u8(addr, &data);
-        for bb in data {
-            assert_eq!(ram.read_u8(addr), bb);
-            addr += 1;
+        ram.load_block_u8(0x200, &data).unwrap();
+        for (addr, bb) in (0x200..).zip(data) {
+            assert_eq!(ram.read_u8(addr).unwrap(), bb);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        for size in [0x1000_u32, 0x10000_u32] {
+            let mut ram = Ram::with_size(size);
+            assert_eq!(ram.size(), size);
+            assert!(ram.as_slice().iter().all(|&b| b == 0));
+
+            ram.write_u8(size - 1, 0xAB).unwrap();
+            assert_eq!(ram.read_u8(size - 1).unwrap(), 0xAB);
+            ram.write_u16(size - 2, 0x1234).unwrap();
+            assert_eq!(ram.read_u16(size - 2).unwrap(), 0x1234);
+            assert_eq!(ram.read_block(size - 2, 2).unwrap(), &[0x12, 0x34]);
+
+            assert!(ram.contains(size - 2, 2));
+            assert!(!ram.contains(size - 1, 2));
+            assert!(!ram.contains(size, 1));
+            assert!(!ram.contains(u32::MAX, 1));
         }
+        assert_eq!(Ram::new().size(), 0x1000);
+    }
+
+    #[test]
+    fn hexdump() {
+        let mut ram = Ram::with_size(0x220);
+        ram.load_block_u8(0x200, &[0x60, 0x2A, 0x12, 0x02]).unwrap();
+
+        let lines = ram.hexdump(0x200..0x240);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "200 60 2A 12 02 00 00 00 00 00 00 00 00 00 00 00 00");
+        assert_eq!(lines[1], "210 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00");
+        assert_eq!(ram.hexdump(0x21E..0x230), vec!["21E 00 00"]);
+    }
+
+    #[test]
+    fn read_past_4k() {
+        assert_eq!(Ram::with_size(0x1000).read_u8(0x1000), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+    }
+
+    #[test]
+    fn read_u16_past_64k() {
+        assert_eq!(Ram::with_size(0x10000).read_u16(0xFFFF), Err(ChipError::RamOutOfBounds { addr: 0x10000 }));
+    }
+
+    #[test]
+    fn load_block_past_4k() {
+        let mut ram = Ram::with_size(0x1000);
+        assert_eq!(ram.load_block_u8(0xFFF, &[1, 2]), Err(ChipError::RamOutOfBounds { addr: 0x1000 }));
+        assert_eq!(ram.write_u16(0x2000, 0), Err(ChipError::RamOutOfBounds { addr: 0x2000 }));
+        // Nothing is written when the block doesn't fit.
+        assert_eq!(ram.read_u8(0xFFF), Ok(0));
     }
 }
@@ -3,13 +3,14 @@ use crate::util;
 
 type VxRegs = util::Array<u8, {arch::NVREGS as usize}>;
 
-pub struct RegMap {
-    pub vx: VxRegs,
-    pub dt: u8,
-    pub st: u8,
-    pub i: u16,
-    pub pc: u16,
-    pub sp: u8,
+#[derive(Clone)]
+pub(crate) struct RegMap {
+    pub(crate) vx: VxRegs,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) i: u16,
+    pub(crate) pc: u16,
+    pub(crate) sp: u8,
 }
 
 impl RegMap {
@@ -25,6 +26,12 @@ impl RegMap {
     }
 }
 
+impl Default for RegMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::arch;
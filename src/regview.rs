@@ -0,0 +1,257 @@
+use crate::chip::{self, Chip};
+use crate::framebuffer::Frame;
+use crate::memview::{self, ROWS};
+use crate::regs::RegMap;
+use crate::step::StateChange;
+use crate::text::{self, ADVANCE, GLYPH_HEIGHT, LINE_SPACING};
+
+// How long a changed value stays highlighted.
+const HIGHLIGHT_MS: u32 = 1000;
+// Text columns of the V register grid, "V0=00 V1=00 V2=00 V3=00".
+const COLUMNS: u32 = 23;
+// First line of the stack entries, the ones that don't fit are left out.
+const STACK_ROW: u32 = 7;
+// Pixels between panels shown side by side.
+const GAP: u32 = 4;
+
+// What a piece of the panel shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Stack(u8),
+    Label,
+}
+
+impl Field {
+    fn of(change: &StateChange) -> Option<Field> {
+        match *change {
+            StateChange::V { x, .. } => Some(Field::V(x)),
+            StateChange::I { .. } => Some(Field::I),
+            StateChange::Pc { .. } => Some(Field::Pc),
+            StateChange::Sp { .. } => Some(Field::Sp),
+            StateChange::Dt { .. } => Some(Field::Dt),
+            StateChange::St { .. } => Some(Field::St),
+            StateChange::Stack { level, .. } => Some(Field::Stack(level)),
+            StateChange::Mem { .. } | StateChange::Screen => None,
+        }
+    }
+}
+
+// Text at a line and column of the panel.
+#[derive(Debug, PartialEq)]
+struct Cell {
+    row: u32,
+    col: u32,
+    field: Field,
+    text: String,
+}
+
+fn cell(row: u32, col: u32, field: Field, text: String) -> Cell {
+    Cell { row, col, field, text }
+}
+
+// V0-VF in a 4x4 grid, then I, PC, SP and the timers, then the stack
+// entries below SP, the latest call first.
+fn cells(chip: &Chip) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = (0..16_u8)
+        .map(|x| cell(x as u32 / 4, 6 * (x as u32 % 4), Field::V(x), format!("V{:X}={:02X}", x, chip.v(x))))
+        .collect();
+    cells.extend([
+        cell(4, 0, Field::I, format!("I={:03X}", chip.i())),
+        cell(4, 6, Field::Pc, format!("PC={:03X}", chip.pc())),
+        cell(5, 0, Field::Sp, format!("SP={:X}", chip.sp())),
+        cell(5, 6, Field::Dt, format!("DT={:02X}", chip.dt())),
+        cell(5, 12, Field::St, format!("ST={:02X}", chip.st())),
+        cell(6, 0, Field::Label, "STACK".to_string()),
+    ]);
+    let calls = chip.call_stack();
+    for (row, (i, addr)) in (STACK_ROW..ROWS).zip(calls.iter().enumerate()) {
+        let level = calls.len() - 1 - i;
+        cells.push(cell(row, 0, Field::Stack(level as u8), format!("{:X}={:03X}", level, addr)));
+    }
+    cells
+}
+
+// Panels left to right, top aligned.
+pub fn join(panels: &[Frame]) -> Frame {
+    let width = panels.iter().map(|p| p.width() + GAP).sum::<u32>().saturating_sub(GAP);
+    let height = panels.iter().map(|p| p.height()).max().unwrap_or(0);
+    let mut frame = Frame::with_size(width, height);
+    let mut x = 0;
+    for panel in panels {
+        for (y, row) in panel.iter().enumerate() {
+            frame[y][x as usize..(x + panel.width()) as usize].copy_from_slice(row);
+        }
+        x += panel.width() + GAP;
+    }
+    frame
+}
+
+// The registers and the stack shown over the screen, with the values that
+// changed in the last second highlighted.
+#[derive(Default)]
+pub struct RegView {
+    visible: bool,
+    last: Option<(RegMap, Vec<u16>)>,
+    // When each field last changed.
+    changed: Vec<(Field, u32)>,
+}
+
+impl RegView {
+    pub fn new() -> RegView {
+        RegView::default()
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // Compares with the previous update, once a frame. Also runs while
+    // hidden, so showing the panel doesn't highlight stale changes.
+    pub fn update(&mut self, chip: &Chip, now_ms: u32) {
+        let now = (chip.regs(), chip.stack());
+        if let Some((regs, stack)) = &self.last {
+            for field in chip::diff_regs((regs, stack), now, None).iter().filter_map(Field::of) {
+                self.changed.retain(|&(f, _)| f != field);
+                self.changed.push((field, now_ms));
+            }
+        }
+        self.changed.retain(|&(_, ms)| now_ms.saturating_sub(ms) < HIGHLIGHT_MS);
+        self.last = Some((now.0.clone(), now.1.to_vec()));
+    }
+
+    fn is_highlighted(&self, field: Field) -> bool {
+        self.changed.iter().any(|&(f, _)| f == field)
+    }
+
+    // The panel as TEXT and HIGHLIGHT pixels on 0, as tall as the memory
+    // viewer so the two line up.
+    pub fn render(&self, chip: &Chip) -> Frame {
+        let line_height = GLYPH_HEIGHT + LINE_SPACING;
+        let mut frame = Frame::with_size(COLUMNS * ADVANCE - 1 + 2, ROWS * line_height - LINE_SPACING + 2);
+        for c in cells(chip) {
+            let (x, y) = (1 + c.col * ADVANCE, 1 + c.row * line_height);
+            text::draw_text(&mut frame, x, y, &c.text);
+            if self.is_highlighted(c.field) {
+                memview::highlight(&mut frame, x, y, text::text_width(&c.text));
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cells, join, Field, RegView};
+    use crate::chip::Chip;
+    use crate::framebuffer::Frame;
+    use crate::memview::{HIGHLIGHT, TEXT};
+    use crate::profile::Profile;
+
+    fn chip() -> Chip {
+        Chip::builder().profile(Profile::modern()).rom(&[
+            0x6A, 0x07, // LD VA, 7
+            0x22, 0x06, // CALL 0x206
+            0x12, 0x04, // JP 0x204
+            0x22, 0x0A, // CALL 0x20A
+            0x12, 0x08, // JP 0x208
+            0x60, 0x01, // LD V0, 1
+            0x00, 0xEE, // RET
+        ]).build().unwrap()
+    }
+
+    fn texts(chip: &Chip, row: u32) -> Vec<String> {
+        cells(chip).into_iter().filter(|c| c.row == row).map(|c| c.text).collect()
+    }
+
+    #[test]
+    fn layout() {
+        let mut chip = chip();
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        assert_eq!(texts(&chip, 2), vec!["V8=00", "V9=00", "VA=07", "VB=00"]);
+        assert_eq!(texts(&chip, 4), vec!["I=000", "PC=20A"]);
+        assert_eq!(texts(&chip, 5), vec!["SP=2", "DT=00", "ST=00"]);
+        // Return addresses, the latest call first.
+        assert_eq!(texts(&chip, 7), vec!["1=208"]);
+        assert_eq!(texts(&chip, 8), vec!["0=204"]);
+        assert!(texts(&chip, 9).is_empty());
+
+        let all = cells(&chip);
+        let vb = all.iter().find(|c| c.field == Field::V(0xB)).unwrap();
+        assert_eq!((vb.row, vb.col), (2, 18));
+        let last = all.iter().map(|c| c.col + c.text.len() as u32).max().unwrap();
+        assert_eq!(last, super::COLUMNS);
+    }
+
+    #[test]
+    fn highlights() {
+        let mut chip = chip();
+        let mut view = RegView::new();
+        view.update(&chip, 0);
+        assert!(view.changed.is_empty());
+
+        chip.step().unwrap();
+        view.update(&chip, 100);
+        assert!(view.is_highlighted(Field::V(0xA)));
+        assert!(view.is_highlighted(Field::Pc));
+        assert!(!view.is_highlighted(Field::V(0)));
+
+        chip.step().unwrap();
+        view.update(&chip, 600);
+        assert!(view.is_highlighted(Field::Sp));
+        assert!(view.is_highlighted(Field::Stack(0)));
+
+        // VA stays highlighted for a second, PC changed again since.
+        view.update(&chip, 1099);
+        assert!(view.is_highlighted(Field::V(0xA)));
+        view.update(&chip, 1100);
+        assert!(!view.is_highlighted(Field::V(0xA)));
+        assert!(view.is_highlighted(Field::Pc));
+        view.update(&chip, 1600);
+        assert!(view.changed.is_empty());
+    }
+
+    #[test]
+    fn render() {
+        let mut chip = chip();
+        let mut view = RegView::new();
+        view.update(&chip, 0);
+        chip.step().unwrap();
+        view.update(&chip, 16);
+        let frame = view.render(&chip);
+
+        assert_eq!((frame.width(), frame.height()), (23 * 4 - 1 + 2, 112));
+        // V0 in the first cell, VA highlighted on line 2, column 12.
+        assert!(frame[1_u32][1..12].contains(&TEXT));
+        assert!(!frame[1_u32][1..12].contains(&HIGHLIGHT));
+        let x = 1 + 12 * 4;
+        assert!(frame[1 + 2 * 7_u32][x..x + 19].contains(&HIGHLIGHT));
+        assert!(!frame[1 + 2 * 7_u32][x..x + 19].contains(&TEXT));
+    }
+
+    #[test]
+    fn join_panels() {
+        let mut left = Frame::with_size(3, 2);
+        left[1_u32][2] = TEXT;
+        let mut right = Frame::with_size(2, 4);
+        right[3_u32][0] = HIGHLIGHT;
+        let joined = join(&[left, right]);
+        assert_eq!((joined.width(), joined.height()), (3 + 4 + 2, 4));
+        assert_eq!(joined[1_u32][2], TEXT);
+        assert_eq!(joined[3_u32][7], HIGHLIGHT);
+        assert_eq!(joined.iter().flatten().filter(|&&p| p != 0).count(), 2);
+
+        assert_eq!(join(&[]).width(), 0);
+    }
+}
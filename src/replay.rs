@@ -0,0 +1,354 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::chip::Chip;
+use crate::error::ChipError;
+use crate::keypad::Keypad;
+use crate::poke::{self, Poke};
+use crate::statefile;
+use crate::util::{self, ByteReader};
+
+// A replay file (.c8replay) is a statefile container with the machine's
+// starting point in the header and everything that reached it from outside
+// the program in the body, stamped by the cycle it came before: the timer
+// ticks and the keypad changes. Replaying that against the same ROM takes
+// the machine through the exact same states, whatever the pacing of the
+// recorded run was.
+const MAGIC: &[u8; 4] = b"C8RP";
+// 2.0 is little-endian, like save states. 2.1 added the pokes.
+pub const MAJOR: u8 = 2;
+pub const MINOR: u8 = 1;
+
+// Where a recording started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayHeader {
+    pub rom_hash: u64,
+    // See Profile::name().
+    pub profile: String,
+    pub seed: u64,
+    pub start: u16,
+    // The --ips of the recorded run.
+    pub ips: u32,
+    // Written into RAM after the ROM was loaded, see play().
+    pub pokes: Vec<Poke>,
+}
+
+impl ReplayHeader {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.rom_hash.to_le_bytes().to_vec();
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.ips.to_le_bytes());
+        out.push(self.profile.len() as u8);
+        out.extend_from_slice(self.profile.as_bytes());
+        out.extend_from_slice(&(self.pokes.len() as u16).to_le_bytes());
+        for p in &self.pokes {
+            out.extend_from_slice(&p.addr.to_le_bytes());
+            out.extend_from_slice(&(p.bytes.len() as u16).to_le_bytes());
+            out.extend_from_slice(&p.bytes);
+        }
+        out
+    }
+
+    fn decode(version: (u8, u8), data: &[u8]) -> Option<ReplayHeader> {
+        let mut r = ByteReader::new(data);
+        let (rom_hash, seed, start, ips) = (r.u64()?, r.u64()?, r.u16()?, r.u32()?);
+        let len = r.u8()? as usize;
+        let profile = String::from_utf8_lossy(r.bytes(len)?).into_owned();
+        let mut pokes = Vec::new();
+        if version.1 >= 1 {
+            for _ in 0..r.u16()? {
+                let addr = r.u16()?;
+                let len = r.u16()? as usize;
+                pokes.push(Poke { addr, bytes: r.bytes(len)?.to_vec() });
+            }
+        }
+        Some(ReplayHeader { rom_hash, profile, seed, start, ips, pokes })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    // The timers ticked.
+    Frame,
+    // The keypad became this, see Keypad::bits(). Keys going down in the
+    // same cycle come one event each, in the order they were pressed.
+    Keys { down: u16, released: u16 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    // By cycle, in the order they happened.
+    pub events: Vec<(u64, Input)>,
+    pub cycles: u64,
+    // Chip::state_hash() at the end of the recording.
+    pub final_hash: u64,
+}
+
+impl Replay {
+    pub fn is_for_rom(&self, rom: &[u8]) -> bool {
+        self.header.rom_hash == util::fnv1a64(rom)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = self.cycles.to_le_bytes().to_vec();
+        body.extend_from_slice(&self.final_hash.to_le_bytes());
+        body.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for (cycle, input) in &self.events {
+            body.extend_from_slice(&cycle.to_le_bytes());
+            match input {
+                Input::Frame => body.push(0),
+                Input::Keys { down, released } => {
+                    body.push(1);
+                    body.extend_from_slice(&down.to_le_bytes());
+                    body.extend_from_slice(&released.to_le_bytes());
+                },
+            }
+        }
+        statefile::pack(MAGIC, (MAJOR, MINOR), &self.header.encode(), &body)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Replay, ChipError> {
+        let c = statefile::unpack(MAGIC, MAJOR, data)?;
+        let invalid = || ChipError::InvalidState("truncated replay".to_string());
+        let header = ReplayHeader::decode(c.version, c.header).ok_or_else(invalid)?;
+        let mut r = ByteReader::new(c.body);
+        let (cycles, final_hash) = r.u64().zip(r.u64()).ok_or_else(invalid)?;
+        let count = r.u32().ok_or_else(invalid)?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let cycle = r.u64().ok_or_else(invalid)?;
+            let input = match r.u8().ok_or_else(invalid)? {
+                0 => Input::Frame,
+                1 => Input::Keys { down: r.u16().ok_or_else(invalid)?, released: r.u16().ok_or_else(invalid)? },
+                kind => return Err(ChipError::InvalidState(format!("unknown replay event {}", kind))),
+            };
+            events.push((cycle, input));
+        }
+        Ok(Replay { header, events, cycles, final_hash })
+    }
+
+    pub fn read(path: &Path) -> io::Result<Replay> {
+        Ok(Replay::decode(&fs::read(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+}
+
+// The end of a replay.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Played {
+    pub hash: u64,
+    // What stopped the machine, as it stopped the recorded run.
+    pub error: Option<ChipError>,
+}
+
+fn apply(chip: &mut Chip, input: Input) {
+    match input {
+        Input::Frame => chip.cycle_timers(),
+        Input::Keys { down, released } => {
+            let mut keypad = *chip.keypad();
+            keypad.set_bits(down, released);
+            chip.set_keypad(keypad);
+        },
+    }
+}
+
+// Runs a machine set up like the header says through the recorded cycles,
+// after writing in the header's pokes. Pokes past the machine's RAM stop it
+// before the first cycle.
+pub fn play(chip: &mut Chip, replay: &Replay) -> Played {
+    if let Err(e) = poke::apply(chip, &replay.header.pokes) {
+        return Played { hash: chip.state_hash(), error: Some(e) };
+    }
+    let mut events = replay.events.iter().peekable();
+    for cycle in 0..replay.cycles {
+        while let Some((_, input)) = events.next_if(|(c, _)| *c == cycle) {
+            apply(chip, *input);
+        }
+        if let Err(e) = chip.cycle() {
+            return Played { hash: chip.state_hash(), error: Some(e) };
+        }
+    }
+    for (_, input) in events {
+        apply(chip, *input);
+    }
+    Played { hash: chip.state_hash(), error: None }
+}
+
+// Records a run for --record-replay. The run loop reports the timer ticks
+// and each cycle, keypad changes are picked up before each cycle.
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+    keypad: Keypad,
+}
+
+impl Recorder {
+    // Starts from the machine as it is, right after the ROM was loaded and
+    // `pokes` written in.
+    pub fn new(path: &Path, chip: &Chip, rom: &[u8], pokes: &[Poke], ips: u32) -> Recorder {
+        let header = ReplayHeader {
+            rom_hash: util::fnv1a64(rom),
+            profile: chip.profile().name().to_string(),
+            seed: chip.seed(),
+            start: chip.pc(),
+            ips,
+            pokes: pokes.to_vec(),
+        };
+        Recorder {
+            path: path.to_path_buf(),
+            replay: Replay { header, events: Vec::new(), cycles: 0, final_hash: 0 },
+            keypad: *chip.keypad(),
+        }
+    }
+
+    fn sync_keys(&mut self, chip: &Chip) {
+        if *chip.keypad() != self.keypad {
+            // A press at a time, so the replay presses them in this order.
+            let (_, released) = chip.keypad().bits();
+            for down in chip.keypad().steps_from(&self.keypad) {
+                self.replay.events.push((self.replay.cycles, Input::Keys { down, released }));
+            }
+            self.keypad = *chip.keypad();
+        }
+    }
+
+    pub fn frame(&mut self) {
+        self.replay.events.push((self.replay.cycles, Input::Frame));
+    }
+
+    // Right before chip.cycle().
+    pub fn cycle(&mut self, chip: &Chip) {
+        self.sync_keys(chip);
+        self.replay.cycles += 1;
+    }
+
+    // Writes the file, with the state the run ended in.
+    pub fn finish(mut self, chip: &Chip) -> io::Result<PathBuf> {
+        self.sync_keys(chip);
+        self.replay.final_hash = chip.state_hash();
+        self.replay.write(&self.path)?;
+        info!("Recorded {} cycles, state hash {:016x}", self.replay.cycles, self.replay.final_hash);
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{play, Input, Replay, MAGIC, MAJOR};
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::playlist::Playlist;
+    use crate::poke::{self, Poke};
+    use crate::profile::Profile;
+    use crate::rom;
+    use crate::statefile;
+    use crate::testing::{deterministic, TestFrontend};
+    use crate::runner::{run, Event, RunOptions};
+
+    // Adds random numbers into the delay timer while key 5 is down.
+    const ROM: [u8; 14] = [
+        0x61, 0x05, // LD V1, 5
+        0xE1, 0x9E, // SKP V1
+        0x12, 0x0C, // JP 0x20C
+        0xC2, 0xFF, // RND V2, 0xFF
+        0x83, 0x24, // ADD V3, V2
+        0xF3, 0x15, // LD DT, V3
+        0x12, 0x02, // JP 0x202
+    ];
+
+    fn fresh(replay: &Replay) -> Chip {
+        let mut chip = Chip::builder().profile(Profile::from_name(&replay.header.profile).unwrap())
+            .seed(replay.header.seed).rom(&ROM).build().unwrap();
+        chip.set_pc(replay.header.start);
+        chip
+    }
+
+    #[test]
+    fn record_and_verify() {
+        let dir = std::env::temp_dir().join(format!("chip8-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("keys.ch8");
+        std::fs::write(&rom_path, ROM).unwrap();
+        let replay_path = dir.join("keys.c8replay");
+
+        let mut chip = Chip::builder().profile(Profile::original()).seed(11).build().unwrap();
+        let mut playlist = Playlist::new(std::slice::from_ref(&rom_path)).unwrap();
+        playlist.open(|p| rom::start(&mut chip, p, &[])).unwrap();
+        let mut events = vec![vec![]; 12];
+        events[3] = vec![Event::KeyPress(5)];
+        events[7] = vec![Event::KeyUnpress(5)];
+        let opts = RunOptions { record_replay: Some(replay_path.clone()), ips: 900, ..deterministic() };
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(events), &opts);
+
+        let replay = Replay::read(&replay_path).unwrap();
+        assert!(replay.is_for_rom(&ROM));
+        assert_eq!((replay.header.seed, replay.header.start, replay.header.ips), (11, 0x200, 900));
+        assert_eq!(replay.final_hash, chip.state_hash());
+        assert_eq!(replay.events.iter().filter(|(_, i)| *i == Input::Frame).count(), 12);
+        let played = play(&mut fresh(&replay), &replay);
+        assert_eq!(played.hash, replay.final_hash);
+        assert_eq!(played.error, None);
+
+        // The key pressed a few cycles later.
+        let mut tampered = replay.clone();
+        let press = tampered.events.iter_mut().find(|(_, i)| matches!(i, Input::Keys { .. })).unwrap();
+        press.0 += 3;
+        let tampered = Replay::decode(&tampered.encode()).unwrap();
+        assert_ne!(play(&mut fresh(&tampered), &tampered).hash, replay.final_hash);
+
+        // A changed byte fails the checksum before anything runs.
+        let mut data = replay.encode();
+        let last = data.len() - 9;
+        data[last] ^= 1;
+        assert_eq!(Replay::decode(&data), Err(ChipError::StateChecksum));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pokes() {
+        let dir = std::env::temp_dir().join(format!("chip8-replay-pokes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("keys.ch8");
+        std::fs::write(&rom_path, ROM).unwrap();
+        let replay_path = dir.join("keys.c8replay");
+
+        // Key 6 instead of 5.
+        let pokes = vec![poke::parse("0x201=6").unwrap()];
+        let mut chip = Chip::builder().profile(Profile::original()).seed(11).build().unwrap();
+        let mut playlist = Playlist::new(std::slice::from_ref(&rom_path)).unwrap();
+        playlist.open(|p| rom::start(&mut chip, p, &pokes)).unwrap();
+        let mut events = vec![vec![]; 6];
+        events[2] = vec![Event::KeyPress(6)];
+        let opts = RunOptions { record_replay: Some(replay_path.clone()), pokes: pokes.clone(), ..deterministic() };
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(events), &opts);
+
+        let replay = Replay::read(&replay_path).unwrap();
+        assert_eq!(replay.header.pokes, pokes);
+        // fresh() leaves them to play().
+        assert_eq!(play(&mut fresh(&replay), &replay).hash, replay.final_hash);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // A 2.0 file has none.
+        let data = replay.encode();
+        let c = statefile::unpack(MAGIC, MAJOR, &data).unwrap();
+        let header = &c.header[..c.header.len() - (2 + 2 + 2 + 1)];
+        let old = Replay::decode(&statefile::pack(MAGIC, (2, 0), header, c.body)).unwrap();
+        assert_eq!(old.header.pokes, Vec::<Poke>::new());
+        assert_eq!((old.header.start, old.events.len()), (replay.header.start, replay.events.len()));
+        assert_ne!(play(&mut fresh(&old), &old).hash, replay.final_hash);
+
+        // Pokes past the machine's RAM stop it before the first cycle.
+        let mut far = replay.clone();
+        far.header.pokes = vec![Poke { addr: 0x1000, bytes: vec![1] }];
+        let played = play(&mut fresh(&far), &far);
+        assert_eq!(played.error, Some(ChipError::RamOutOfBounds { addr: 0x1000 }));
+    }
+}
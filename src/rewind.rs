@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+use crate::chip::Chip;
+
+// While rewinding, a snapshot is restored every this many frames, about
+// 30 a second.
+pub const PLAYBACK_FRAMES: u32 = 2;
+
+// The bytes of an older state that differ from the next newer one, as
+// (offset, bytes) runs. Frames apart, most of RAM is the same.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn new(older: &[u8], newer: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (at, &b) in older.iter().enumerate() {
+            if newer.get(at) == Some(&b) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == at => bytes.push(b),
+                _ => runs.push((at, vec![b])),
+            }
+        }
+        Delta { len: older.len(), runs }
+    }
+
+    // Turns the newer state back into the older one.
+    fn apply(&self, state: &mut Vec<u8>) {
+        state.resize(self.len, 0);
+        for (start, bytes) in &self.runs {
+            state[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+// Snapshots of the last seconds of play, taken every `interval` frames, for
+// running time backwards while the rewind key is held. Only the newest is a
+// whole save state, each older one is kept as a delta against the one after
+// it, so dropping the oldest costs nothing.
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    older: VecDeque<Delta>,
+    frames: u32,
+    playback: u32,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32, interval: u32) -> Rewind {
+        let interval = interval.max(1);
+        Rewind {
+            interval,
+            capacity: ((seconds * 60 / interval) as usize).max(1),
+            newest: None,
+            older: VecDeque::new(),
+            frames: 0,
+            playback: 0,
+        }
+    }
+
+    // Snapshots that can be restored.
+    pub fn len(&self) -> usize {
+        self.older.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Bytes held, the newest state and the deltas.
+    pub fn size(&self) -> usize {
+        self.newest.as_ref().map_or(0, |s| s.len()) + self.older.iter().map(Delta::size).sum::<usize>()
+    }
+
+    // Called at the start of each emulated frame, takes a snapshot every
+    // `interval` of them.
+    pub fn frame(&mut self, chip: &Chip) {
+        if self.frames.is_multiple_of(self.interval) {
+            self.capture(chip);
+        }
+        self.frames += 1;
+    }
+
+    pub fn capture(&mut self, chip: &Chip) {
+        let state = chip.save_state();
+        if let Some(previous) = self.newest.take() {
+            self.older.push_back(Delta::new(&previous, &state));
+            if self.older.len() == self.capacity {
+                self.older.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    // Restores the newest snapshot and drops it, so that going forward from
+    // there records a new future. The keys held now stay held. Returns
+    // false when there is nothing left.
+    pub fn step_back(&mut self, chip: &mut Chip) -> bool {
+        let Some(mut state) = self.newest.take() else { return false };
+        let order = chip.keypad().to_bytes();
+        chip.load_state(&state).expect("captured state");
+        // Pressed again in the order they went down.
+        let mut held: Vec<u8> = (0..16).filter(|&k| order[k as usize] != 0).collect();
+        held.sort_by_key(|&k| order[k as usize]);
+        for k in 0..16 {
+            chip.key_unpress(k);
+        }
+        for k in held {
+            chip.key_press(k);
+        }
+        if let Some(delta) = self.older.pop_back() {
+            delta.apply(&mut state);
+            self.newest = Some(state);
+        }
+        // The restored state is the start of a frame, take it again as the
+        // first snapshot when running on.
+        self.frames = 0;
+        true
+    }
+
+    // A frame with the rewind key held: steps back every PLAYBACK_FRAMES
+    // frames, and stays on the oldest snapshot once there.
+    pub fn playback_frame(&mut self, chip: &mut Chip) {
+        if self.playback.is_multiple_of(PLAYBACK_FRAMES) {
+            self.step_back(chip);
+        }
+        self.playback += 1;
+    }
+
+    // The rewind key was released.
+    pub fn stop(&mut self) {
+        self.playback = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delta, Rewind};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    fn chip() -> Chip {
+        Chip::builder().profile(Profile::modern()).seed(3).rom(&[
+            0xC0, 0x3F, // RND V0, 0x3f
+            0xC1, 0x1F, // RND V1, 0x1f
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x72, 0x01, // ADD V2, 0x1
+            0x12, 0x00, // JP 0x200
+        ]).build().unwrap()
+    }
+
+    // One frame of emulation, a snapshot first.
+    fn frame(chip: &mut Chip, rewind: &mut Rewind) {
+        rewind.frame(chip);
+        for _ in 0..10 {
+            chip.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn delta() {
+        let newer = vec![1, 2, 3, 4, 5, 6];
+        let older = vec![1, 9, 9, 4, 5, 7, 8];
+        let delta = Delta::new(&older, &newer);
+        assert_eq!(delta.runs, vec![(1, vec![9, 9]), (5, vec![7, 8])]);
+        let mut state = newer;
+        delta.apply(&mut state);
+        assert_eq!(state, older);
+        let mut state = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        Delta::new(&[1, 2], &state).apply(&mut state);
+        assert_eq!(state, vec![1, 2]);
+    }
+
+    #[test]
+    fn capture_restore_truncate() {
+        let mut chip = chip();
+        // A second at 60 frames, a snapshot every 6.
+        let mut rewind = Rewind::new(1, 6);
+        let mut hashes = Vec::new();
+        for f in 0..90 {
+            if f % 6 == 0 {
+                hashes.push(chip.state_hash());
+            }
+            frame(&mut chip, &mut rewind);
+        }
+        assert_eq!(rewind.len(), 10);
+        // The deltas are far smaller than whole states.
+        assert!(rewind.size() < 2 * chip.save_state().len());
+
+        for back in 1..=3 {
+            assert!(rewind.step_back(&mut chip));
+            assert_eq!(chip.state_hash(), hashes[hashes.len() - back]);
+        }
+        assert_eq!(rewind.len(), 7);
+
+        // Running on drops the rewound future and records from there.
+        frame(&mut chip, &mut rewind);
+        assert_eq!(rewind.len(), 8);
+        assert!(rewind.step_back(&mut chip));
+        assert_eq!(chip.state_hash(), hashes[hashes.len() - 3]);
+        while rewind.step_back(&mut chip) {}
+        assert!(rewind.is_empty());
+        assert_eq!(chip.state_hash(), hashes[hashes.len() - 10]);
+    }
+
+    #[test]
+    fn forward_after_rewind_is_deterministic() {
+        let mut chip = chip();
+        let mut rewind = Rewind::new(10, 1);
+        let mut hashes = Vec::new();
+        for _ in 0..20 {
+            frame(&mut chip, &mut rewind);
+            hashes.push(chip.state_hash());
+        }
+        // Keys held while rewinding stay held.
+        chip.key_press(0x5);
+        for _ in 0..8 {
+            rewind.playback_frame(&mut chip);
+        }
+        rewind.stop();
+        assert!(chip.keypad().is_down(0x5));
+        chip.key_unpress(0x5);
+        // Four steps back, to the start of frame 16.
+        for &hash in &hashes[16..] {
+            frame(&mut chip, &mut rewind);
+            assert_eq!(chip.state_hash(), hash);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+
+use crate::arch;
+use crate::chip::Chip;
+use crate::error::ChipError;
+use crate::poke::{self, Poke};
+
+pub const START_ADDR: u32 = 0x200;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// Searched last, so the bundled test ROMs work from the source tree.
+pub const DEFAULT_ROM_DIR: &str = "rom/tests";
+// Extra search directories, separated like PATH.
+pub const ROM_PATH_ENV: &str = "CHIP8_ROM_PATH";
+
+// The directories bare ROM names are looked up in, in order: `dirs` (from
+// --rom-dir), then the entries of `env` (from CHIP8_ROM_PATH), then
+// DEFAULT_ROM_DIR.
+pub fn search_path(dirs: &[PathBuf], env: Option<&OsStr>) -> Vec<PathBuf> {
+    let mut search = dirs.to_vec();
+    if let Some(env) = env {
+        search.extend(env::split_paths(env).filter(|p| !p.as_os_str().is_empty()));
+    }
+    search.push(PathBuf::from(DEFAULT_ROM_DIR));
+    search
+}
+
+// A name without a directory part, e.g. "pong.ch8".
+fn is_bare(name: &Path) -> bool {
+    let mut components = name.components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+// Paths with a directory part, and names that exist in the current
+// directory, are used as given. Other names are looked up in `search` and
+// the first directory that has them wins. Unknown names come back unchanged
+// so that opening them reports the error.
+pub fn resolve(name: &Path, search: &[PathBuf]) -> PathBuf {
+    if !is_bare(name) || name.exists() {
+        return name.to_path_buf();
+    }
+    search.iter()
+        .map(|dir| dir.join(name))
+        .find(|p| p.exists())
+        .unwrap_or_else(|| name.to_path_buf())
+}
+
+// A ROM file, decompressed first when it is gzipped like the .ch8.gz of
+// some collections. Only ROMs too large for any arch are refused here,
+// loading checks the machine's own RAM.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut f = File::open(path)?;
+
+    let mut buffer = Vec::new();
+    f.read_to_end(&mut buffer)?;
+
+    // Everything above the interpreter area must fit into RAM.
+    let max_size = (arch::MAX_RAMSIZE - START_ADDR) as usize;
+    let gz_name = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gz"));
+    if buffer.starts_with(&GZIP_MAGIC) || gz_name {
+        buffer = gunzip(&buffer, max_size)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: invalid gzip file, {}", path.display(), e)))?;
+    }
+    if buffer.len() > max_size {
+        return Err(ChipError::RomTooLarge { size: buffer.len(), max: max_size }.into());
+    }
+
+    Ok(buffer)
+}
+
+// At most `max_size` + 1 bytes are decompressed, enough to tell a ROM that
+// is too large without inflating all of a huge one.
+fn gunzip(data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no gzip header"));
+    }
+    let mut out = Vec::new();
+    GzDecoder::new(data).take(max_size as u64 + 1).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// The normal reset/load path, used at startup and when switching ROMs.
+pub fn start(chip: &mut Chip, path: &Path, pokes: &[Poke]) -> io::Result<()> {
+    let buffer = read(path)?;
+
+    chip.reset();
+    chip.load_rom(&buffer, START_ADDR)?;
+    chip.set_pc(START_ADDR as u16);
+    poke::apply(chip, pokes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use super::{resolve, search_path};
+    use crate::arch::Arch;
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::profile::Profile;
+
+    #[test]
+    fn read_ibm() {
+        let rom = super::read(Path::new("rom/tests/ibm.ch8")).unwrap();
+        assert_eq!(rom.len(), 132);
+    }
+
+    #[test]
+    fn read_gzip() {
+        let ibm = super::read(Path::new("rom/tests/ibm.ch8")).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&ibm).unwrap();
+        let gz = gz.finish().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("chip8-rom-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Told by the magic bytes, whatever the name.
+        std::fs::write(dir.join("ibm.ch8.gz"), &gz).unwrap();
+        std::fs::write(dir.join("ibm.ch8"), &gz).unwrap();
+        let mut truncated = gz.clone();
+        truncated.truncate(gz.len() / 2);
+        std::fs::write(dir.join("truncated.ch8.gz"), &truncated).unwrap();
+        std::fs::write(dir.join("plain.ch8.gz"), &ibm).unwrap();
+        let read = |name: &str| super::read(&dir.join(name));
+        let (named, unnamed, truncated, plain) = (read("ibm.ch8.gz"), read("ibm.ch8"), read("truncated.ch8.gz"), read("plain.ch8.gz"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(named.unwrap(), ibm);
+        assert_eq!(unnamed.unwrap(), ibm);
+        for e in [truncated.unwrap_err(), plain.unwrap_err()] {
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            assert!(e.to_string().contains("invalid gzip file"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn read_too_large() {
+        let path = std::env::temp_dir().join(format!("chip8-rom-too-large-{}.ch8", std::process::id()));
+        std::fs::write(&path, vec![0u8; 0x10000]).unwrap();
+        let err = super::read(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        let e = err.get_ref().and_then(|e| e.downcast_ref::<ChipError>());
+        assert_eq!(e, Some(&ChipError::RomTooLarge { size: 0x10000, max: 0xFE00 }));
+
+        // Decompressed, and only as far as it takes to tell.
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&vec![0u8; 1 << 20]).unwrap();
+        let path = path.with_extension("ch8.gz");
+        std::fs::write(&path, gz.finish().unwrap()).unwrap();
+        let err = super::read(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        let e = err.get_ref().and_then(|e| e.downcast_ref::<ChipError>());
+        assert_eq!(e, Some(&ChipError::RomTooLarge { size: 0xFE01, max: 0xFE00 }));
+    }
+
+    // A ROM too large for 4K loads on a machine with more RAM.
+    #[test]
+    fn start_ram_size() {
+        let path = std::env::temp_dir().join(format!("chip8-rom-64k-{}.ch8", std::process::id()));
+        std::fs::write(&path, vec![0x12; 0x1400]).unwrap();
+        let mut chip = Chip::new(Profile::modern());
+        let err = super::start(&mut chip, &path, &[]).unwrap_err();
+        let e = err.get_ref().and_then(|e| e.downcast_ref::<ChipError>());
+        assert_eq!(e, Some(&ChipError::RomTooLarge { size: 0x1400, max: 0xE00 }));
+
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        let mut chip = Chip::new(Profile::builder().arch(arch).max_address(None).build());
+        super::start(&mut chip, &path, &[]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(chip.rom_range(), 0x200..0x1600);
+    }
+
+    #[test]
+    fn read_missing() {
+        assert!(super::read(Path::new("rom/tests/no_such_rom.ch8")).is_err());
+    }
+
+    #[test]
+    fn search_order() {
+        let env = std::env::join_paths(["c", "d"]).unwrap();
+        let search = search_path(&[PathBuf::from("a"), PathBuf::from("b")], Some(&env));
+        let expected: Vec<PathBuf> = ["a", "b", "c", "d", "rom/tests"].iter().map(PathBuf::from).collect();
+        assert_eq!(search, expected);
+
+        assert_eq!(search_path(&[], None), vec![PathBuf::from("rom/tests")]);
+    }
+
+    #[test]
+    fn resolve_bare_names() {
+        let root = std::env::temp_dir().join(format!("chip8-rom-dirs-{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        std::fs::write(second.join("only_second.ch8"), [0x12, 0x00]).unwrap();
+        std::fs::write(first.join("both.ch8"), [0x12, 0x00]).unwrap();
+        std::fs::write(second.join("both.ch8"), [0x12, 0x00]).unwrap();
+        let search = search_path(&[first.clone(), second.clone()], None);
+
+        let only_second = resolve(Path::new("only_second.ch8"), &search);
+        let both = resolve(Path::new("both.ch8"), &search);
+        let bundled = resolve(Path::new("ibm.ch8"), &search);
+        let missing = resolve(Path::new("missing.ch8"), &search);
+        let with_dir = resolve(&Path::new("x").join("both.ch8"), &search);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(only_second, second.join("only_second.ch8"));
+        // First match wins.
+        assert_eq!(both, first.join("both.ch8"));
+        assert_eq!(bundled, Path::new("rom/tests").join("ibm.ch8"));
+        assert_eq!(missing, Path::new("missing.ch8"));
+        // Not a bare name, not searched.
+        assert_eq!(with_dir, Path::new("x").join("both.ch8"));
+    }
+}
@@ -0,0 +1,1757 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use log::{error, info, trace, warn};
+
+use crate::audio::Sound;
+use crate::autosave::AutoSave;
+use crate::capture::{self, TestCapture};
+use crate::chip::Chip;
+use crate::clock::{Clock, InstrClock};
+use crate::compat::CompatReport;
+use crate::debugger::Debugger;
+use crate::disasm;
+use crate::disview::DisView;
+use crate::error::ChipError;
+use crate::framebuffer::Frame;
+use crate::halt::{self, WaitOnHalt};
+use crate::heatmap;
+use crate::history::History;
+use crate::hooks::{ActiveHooks, SharedHooks};
+use crate::hud::Hud;
+use crate::keyview::KeyView;
+use crate::memview::MemView;
+use crate::pause::Pause;
+use crate::playlist::Playlist;
+use crate::poke::{self, Poke};
+use crate::quicksave;
+use crate::regview::{self, RegView};
+use crate::replay::Recorder;
+use crate::rewind::Rewind;
+use crate::rom;
+use crate::stats::RunStats;
+use crate::step::StepResult;
+use crate::text;
+use crate::trace::TraceSample;
+use crate::tracediff::{TraceDiff, TraceLine};
+use crate::watch_csv::{RegisterCsv, WatchCsv, WatchItem};
+use crate::watchdog::{ClsWatchdog, DrawBeforeI, DrawGuard};
+
+// Timer ticks / presented frames alternate between these intervals, ~60 Hz.
+const FRAME_INTERVALS_MS: [u32; 3] = [17, 17, 16];
+// Pause after every instruction unless running fast.
+const CYCLE_SLEEP_US: u64 = 1000;
+
+// What the program did on its last cycle, for pacing the loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunState {
+    Running,
+    // Jumping to itself.
+    Halted,
+    // Blocked in LD Vx, K.
+    WaitingForKey,
+    // Paused from the UI.
+    Paused,
+}
+
+impl RunState {
+    fn after_cycle(chip: &Chip, executed: u64) -> RunState {
+        if chip.is_halted() {
+            RunState::Halted
+        } else if chip.instructions() == executed {
+            RunState::WaitingForKey
+        } else {
+            RunState::Running
+        }
+    }
+}
+
+// When to wake up after a cycle, in host microseconds. Input is polled and
+// the timers tick only on frame syncs, so a program that can't get anywhere
+// before the next one sleeps until then instead of every millisecond. A key
+// press is seen just as early as when spinning.
+fn sleep_deadline(state: RunState, now_us: u64, next_frame_us: u64) -> u64 {
+    match state {
+        RunState::Running => now_us + CYCLE_SLEEP_US,
+        RunState::Halted | RunState::WaitingForKey | RunState::Paused => next_frame_us.max(now_us + CYCLE_SLEEP_US),
+    }
+}
+
+// What to do when a frame draws more sprites than RunOptions::max_draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawLimit {
+    // Log it once per frame and carry on.
+    Warn,
+    // Present the screen as it is and start counting again, so that the
+    // window keeps up with the program.
+    Present,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    KeyPress(u8),
+    KeyUnpress(u8),
+    NextRom,
+    PrevRom,
+    ApplyPokes,
+    ToggleHud,
+    TogglePause,
+    ToggleMemView,
+    ToggleRegView,
+    ToggleDisasm,
+    // Save the machine next to the ROM, and load it back.
+    SaveState,
+    LoadState,
+    // Execute one instruction while paused.
+    StepInstruction,
+    // Undo the last instruction while paused, see RunOptions::history.
+    StepBack,
+    // The rewind key went down and up, see RunOptions::rewind.
+    RewindStart,
+    RewindStop,
+    Quit,
+}
+
+// What the run loop needs from the host: input, video, sound and time.
+pub trait Frontend {
+    fn poll_events(&mut self) -> Vec<Event>;
+    fn present(&mut self, frame: &Frame);
+    // Like present(), with `overlay` drawn over the screen at a finer scale
+    // than the Chip-8 pixels: 0 is background, memview::TEXT and
+    // memview::HIGHLIGHT are text. Without overlay support only the frame
+    // is shown.
+    fn present_overlay(&mut self, frame: &Frame, overlay: &Frame) {
+        let _ = overlay;
+        self.present(frame);
+    }
+    // The sound timer buzzer.
+    fn set_sound(&mut self, on: bool);
+    fn play_sound(&mut self, sound: Sound);
+    fn set_title(&mut self, title: &str);
+    // Host time, for pacing the loop.
+    fn clock(&self) -> &dyn Clock;
+    // A debugger command line, None at the end of input.
+    fn read_command(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    pub fast: bool,
+    // Instruction-count scheduling, see InstrClock. No sleeping and no sound.
+    pub deterministic: bool,
+    pub ips: u32,
+    pub explain: bool,
+    // Explain only the instructions this picks.
+    pub trace_sample: Option<TraceSample>,
+    // Write every executed instruction here, see TraceLine.
+    pub trace_file: Option<PathBuf>,
+    // Compare every executed instruction with a reference log while running,
+    // and stop after the last mismatch it reports.
+    pub trace_against: Option<TraceDiff>,
+    pub collision_beep: bool,
+    pub wait_on_halt: bool,
+    // A key press latches the key down until the next press of it, releases
+    // are ignored.
+    pub sticky_keys: bool,
+    // Start with the HUD shown.
+    pub hud: bool,
+    // Also poll input every this many cycles, not only once a frame.
+    pub input_poll_cycles: Option<u32>,
+    // Show the keypad with the keys held down highlighted.
+    pub show_keys: bool,
+    // Blank screen time before the first instruction.
+    pub start_delay_ms: u32,
+    // Stop when a frame executes more CLS instructions than this.
+    pub cls_watchdog: Option<u32>,
+    // DRW instructions allowed per frame, and what happens beyond that.
+    pub max_draws: Option<(u32, DrawLimit)>,
+    // Warn when the first DRW runs before any LD I, see DrawBeforeI.
+    pub warn_draw_before_i: bool,
+    // Present the screen after every DRW and wait this many milliseconds,
+    // to watch sprites being drawn one by one.
+    pub draw_debug: Option<u32>,
+    // Interval in seconds and base path.
+    pub autosave: Option<(u32, PathBuf)>,
+    // Where to save the machine when the run ends, see quicksave::save_to().
+    pub save_state_on_quit: Option<PathBuf>,
+    // Save next to the current ROM when the run ends without an error, see
+    // quicksave::exit_path().
+    pub autosave_exit: bool,
+    // F4 loads a state saved for another ROM file too.
+    pub force_state_load: bool,
+    // Output path and the values written to it once a frame.
+    pub watch_csv: Option<(PathBuf, Vec<WatchItem>)>,
+    // Write the registers after every executed instruction here, see
+    // RegisterCsv.
+    pub csv_registers: Option<PathBuf>,
+    // Output path and profile name for the generated tests.
+    pub capture_tests: Option<(PathBuf, String)>,
+    // Count the executed instructions that depend on a quirk, and report
+    // them with the stats.
+    pub compat_report: bool,
+    // Count executions per address and write them here as JSON at the end.
+    pub profile_out: Option<PathBuf>,
+    // Write a disassembly of the ROM with the instructions that never
+    // executed marked here at the end.
+    pub coverage: Option<PathBuf>,
+    // Stop before executing the instruction at these addresses.
+    pub breakpoints: Vec<u16>,
+    // Stop after an instruction writes into these ranges.
+    pub watchpoints: Vec<Range<u32>>,
+    // Stop in the debugger on an unknown opcode, to skip it or abort,
+    // instead of ending the run with the error.
+    pub debug: bool,
+    // Instructions that can be stepped back, from the debugger or while
+    // paused. 0 records nothing.
+    pub history: usize,
+    // Seconds of play kept for rewinding, and frames between snapshots.
+    pub rewind: Option<(u32, u32)>,
+    // Record the run into this replay file, see replay::Recorder.
+    pub record_replay: Option<PathBuf>,
+    // Called on every frame, executed instruction and key event.
+    pub hooks: Option<SharedHooks>,
+    pub pokes: Vec<Poke>,
+}
+
+pub fn window_title(path: &Path) -> String {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    format!("Chip-8 emulator - {}", name.to_string_lossy())
+}
+
+fn now_ms(clock: &Option<InstrClock>, frontend: &dyn Frontend) -> u32 {
+    match clock {
+        Some(c) => c.now_ms(),
+        None => (frontend.clock().now_micros() / 1000) as u32,
+    }
+}
+
+fn switch_rom(chip: &mut Chip, playlist: &mut Playlist, frontend: &mut dyn Frontend, opts: &RunOptions, forward: bool) {
+    let load = |p: &Path| rom::start(chip, p, &opts.pokes);
+    let switched = if forward { playlist.next(load) } else { playlist.prev(load) };
+    if switched.is_some() {
+        info!("Switched to {}", playlist.current().display());
+        frontend.set_title(&window_title(playlist.current()));
+    }
+}
+
+// Writing stops at the first error.
+fn write_registers(csv: &mut Option<RegisterCsv>, cycle: u64, pc: u16, chip: &Chip) {
+    if let Some(Err(e)) = csv.as_mut().map(|c| c.write_instruction(cycle, pc, chip)) {
+        warn!("Writing the register CSV failed: {}", e);
+        *csv = None;
+    }
+}
+
+// Ends a recording early, when something outside the recorded inputs is
+// about to change the machine.
+fn stop_recording(recorder: &mut Option<Recorder>, chip: &Chip, why: &str) {
+    if let Some(r) = recorder.take() {
+        match r.finish(chip) {
+            Ok(path) => warn!("Stopped recording the replay, {}. Wrote {}", why, path.display()),
+            Err(e) => warn!("Writing the replay failed: {}", e),
+        }
+    }
+}
+
+fn print_step(r: &StepResult) {
+    let changes: Vec<String> = r.changes.iter().map(|c| c.to_string()).collect();
+    println!("[{:#05x}] {:04X}  {:<20} {}", r.pc, r.opcode, r.mnemonic, r.description);
+    if !changes.is_empty() {
+        println!("{:16}{}", "", changes.join(", "));
+    }
+}
+
+// The trace file and the comparison with a reference log.
+struct Tracing {
+    out: Option<BufWriter<File>>,
+    against: Option<TraceDiff>,
+}
+
+impl Tracing {
+    fn new(opts: &RunOptions) -> Tracing {
+        let out = opts.trace_file.as_ref().and_then(|path| match File::create(path) {
+            Ok(f) => Some(BufWriter::new(f)),
+            Err(e) => {
+                warn!("Writing the trace failed: {}", e);
+                None
+            },
+        });
+        Tracing { out, against: opts.trace_against.clone() }
+    }
+
+    fn is_on(&self) -> bool {
+        self.out.is_some() || self.against.is_some()
+    }
+
+    // Returns false once the comparison reported its last mismatch.
+    fn record(&mut self, line: TraceLine) -> bool {
+        if let Some(out) = self.out.as_mut() {
+            if let Err(e) = writeln!(out, "{}", line) {
+                warn!("Writing the trace failed: {}", e);
+                self.out = None;
+            }
+        }
+        match self.against.as_mut() {
+            Some(d) => {
+                if let Some(m) = d.push(line) {
+                    print!("{}", m);
+                }
+                !d.is_done()
+            },
+            None => true,
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(Err(e)) = self.out.as_mut().map(|out| out.flush()) {
+            warn!("Writing the trace failed: {}", e);
+        }
+    }
+}
+
+// Polls input every `every` cycles between frame syncs, so that SKP and
+// SKNP see presses sooner than the next frame at low instruction rates.
+struct InputPoll {
+    every: u32,
+    cycles: u32,
+}
+
+impl InputPoll {
+    fn new(every: u32) -> InputPoll {
+        InputPoll { every: every.max(1), cycles: 0 }
+    }
+
+    // Counts a cycle, true when input is due.
+    fn cycle(&mut self) -> bool {
+        self.cycles += 1;
+        if self.cycles < self.every {
+            return false;
+        }
+        self.cycles = 0;
+        true
+    }
+
+    // Input was just polled on a frame sync.
+    fn reset(&mut self) {
+        self.cycles = 0;
+    }
+}
+
+// Sampling counts executed instructions only, not FX0A waiting.
+fn explain_step(chip: &mut Chip, sample: Option<&mut TraceSample>) -> Result<(), ChipError> {
+    let executed = chip.instructions();
+    let step = chip.step()?;
+    let traced = match sample {
+        Some(s) => chip.instructions() > executed && s.sample(step.opcode),
+        None => true,
+    };
+    if traced {
+        print_step(&step);
+    }
+    Ok(())
+}
+
+// Presents the screen with the overlays on top.
+fn present(chip: &Chip, hud: &Hud, views: (&DisView, &MemView, &RegView, &KeyView), debugger: &Debugger, frontend: &mut dyn Frontend, halted: bool) {
+    let (disview, memview, regview, keyview) = views;
+    let mut f: Frame = chip.get_frame().clone();
+    if halted {
+        text::draw_banner(&mut f, &halt::MESSAGE);
+    }
+    hud.draw(&mut f);
+    // Disassembly, memory, registers and keypad from left to right.
+    let mut panels = Vec::new();
+    if disview.is_visible() {
+        panels.push(disview.render(chip, debugger.breakpoints()));
+    }
+    if memview.is_visible() {
+        panels.push(memview.render(chip));
+    }
+    if regview.is_visible() {
+        panels.push(regview.render(chip));
+    }
+    if keyview.is_visible() {
+        panels.push(keyview.render(chip.keypad()));
+    }
+    if panels.is_empty() {
+        frontend.present(&f);
+    } else {
+        frontend.present_overlay(&f, &regview::join(&panels));
+    }
+}
+
+// Runs the loaded program until the frontend asks to quit.
+pub fn run(chip: &mut Chip, playlist: &mut Playlist, frontend: &mut dyn Frontend, opts: &RunOptions) -> RunStats {
+    let mut clock = if opts.deterministic {
+        Some(InstrClock::new(opts.ips))
+    } else {
+        None
+    };
+    let mut wait_on_halt = if opts.wait_on_halt {
+        Some(WaitOnHalt::new())
+    } else {
+        None
+    };
+
+    let mut cls_watchdog = opts.cls_watchdog.map(ClsWatchdog::new);
+    let mut draw_guard = opts.max_draws.map(|(limit, action)| (DrawGuard::new(limit), action));
+    let mut draw_warned = false;
+    let mut capture = opts.capture_tests.as_ref().map(|_| TestCapture::new());
+    let mut compat = opts.compat_report.then(CompatReport::new);
+    let mut draw_before_i = opts.warn_draw_before_i.then(DrawBeforeI::new);
+    let mut trace_sample = opts.trace_sample.clone();
+    let mut tracing = Tracing::new(opts);
+    let mut hooks = ActiveHooks::new(opts.hooks.clone());
+    let mut watch_csv = opts.watch_csv.as_ref().and_then(|(path, items)| match WatchCsv::create(path, items) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            warn!("Writing the watch CSV failed: {}", e);
+            None
+        },
+    });
+    let mut register_csv = opts.csv_registers.as_ref().and_then(|path| match RegisterCsv::create(path) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            warn!("Writing the register CSV failed: {}", e);
+            None
+        },
+    });
+    let mut emulated_frames = 0;
+    let mut input_poll = opts.input_poll_cycles.map(InputPoll::new);
+    let mut poll_due = false;
+    if opts.profile_out.is_some() || opts.coverage.is_some() {
+        chip.enable_heatmap();
+    }
+    let mut debugger = Debugger::new(&opts.breakpoints, &opts.watchpoints);
+    debugger.set_pokes(&opts.pokes);
+    let mut pause = Pause::new(opts.ips);
+    let mut history = History::new(opts.history);
+    let mut rewind = opts.rewind.map(|(seconds, interval)| Rewind::new(seconds, interval));
+    let mut rewinding = false;
+    let mut recorder = match &opts.record_replay {
+        Some(_) if playlist.is_empty() => {
+            warn!("No ROM file to record a replay for");
+            None
+        },
+        Some(path) => match rom::read(playlist.current()) {
+            Ok(rom) => Some(Recorder::new(path, chip, &rom, &opts.pokes, opts.ips)),
+            Err(e) => {
+                warn!("Recording the replay failed: {}", e);
+                None
+            },
+        },
+        None => None,
+    };
+
+    let start_ms = now_ms(&clock, frontend);
+    let mut autosave = opts.autosave.as_ref()
+        .map(|(interval, path)| AutoSave::new(path, *interval, start_ms));
+
+    let mut stats = RunStats::new(start_ms);
+    let mut hud = Hud::new(opts.hud, start_ms);
+    let mut memview = MemView::new();
+    let mut regview = RegView::new();
+    let mut disview = DisView::new();
+    let keyview = KeyView::new(opts.show_keys);
+    let mut last_frame_ms = start_ms;
+    let mut frame_idx = 0;
+    let mut running = true;
+    let mut state = RunState::Running;
+
+    while running {
+        let now_ms = now_ms(&clock, frontend);
+        let frame_sync = match &clock {
+            Some(c) => c.frame_sync(),
+            None => now_ms - last_frame_ms > FRAME_INTERVALS_MS[frame_idx],
+        };
+
+        if frame_sync {
+            last_frame_ms = now_ms;
+            frame_idx = (frame_idx + 1) % FRAME_INTERVALS_MS.len();
+            if let Some(p) = input_poll.as_mut() {
+                p.reset();
+            }
+        }
+
+        if frame_sync || poll_due {
+            poll_due = false;
+            for e in frontend.poll_events() {
+                match e {
+                    Event::KeyPress(key) => hooks.key(key, true),
+                    Event::KeyUnpress(key) => hooks.key(key, false),
+                    _ => {},
+                }
+                match e {
+                    Event::Quit =>  { info!("Quit!"); std::io::stdout().flush().unwrap(); running = false },
+                    Event::KeyPress(_) if wait_on_halt.as_ref().is_some_and(|h| h.key_press()) => {
+                        info!("Quit after halt");
+                        running = false;
+                    },
+                    Event::KeyPress(key) if opts.sticky_keys && chip.keypad().is_down(key) => {
+                        trace!("Sticky key unlatched: {}", key);
+                        chip.key_unpress(key)
+                    },
+                    Event::KeyUnpress(_) if opts.sticky_keys => {},
+                    Event::KeyPress(key) => { trace!("Key pressed: {}", key); chip.key_press(key) },
+                    Event::KeyUnpress(key) => { trace!("Key unpressed {}", key); chip.key_unpress(key) },
+                    // PageUp/PageDown scroll the memory viewer while it is shown.
+                    Event::NextRom if memview.is_visible() => memview.scroll(1, chip),
+                    Event::PrevRom if memview.is_visible() => memview.scroll(-1, chip),
+                    Event::NextRom | Event::PrevRom => {
+                        stop_recording(&mut recorder, chip, "the ROM is switched");
+                        switch_rom(chip, playlist, frontend, opts, e == Event::NextRom);
+                        draw_before_i = opts.warn_draw_before_i.then(DrawBeforeI::new);
+                    },
+                    Event::ApplyPokes => {
+                        stop_recording(&mut recorder, chip, "pokes are applied");
+                        match poke::apply(chip, &opts.pokes) {
+                            Ok(()) => info!("Applied {} poke(s)", opts.pokes.len()),
+                            Err(e) => warn!("Poke failed: {}", e),
+                        }
+                    },
+                    Event::ToggleHud => hud.toggle(),
+                    Event::TogglePause => {
+                        pause.toggle();
+                        if pause.is_paused() {
+                            stop_recording(&mut recorder, chip, "the run is paused");
+                        }
+                        info!("{}", if pause.is_paused() { "Paused" } else { "Resumed" });
+                    },
+                    Event::StepInstruction => pause.request_step(),
+                    Event::StepBack if pause.is_paused() => {
+                        if !history.step_back(chip) {
+                            info!("No more history");
+                        }
+                    },
+                    Event::StepBack => {},
+                    Event::RewindStart if rewind.is_some() => {
+                        stop_recording(&mut recorder, chip, "the run is rewound");
+                        rewinding = true;
+                        frontend.set_sound(false);
+                    },
+                    Event::RewindStop if rewinding => {
+                        rewinding = false;
+                        rewind.as_mut().unwrap().stop();
+                    },
+                    Event::RewindStart | Event::RewindStop => {},
+                    Event::ToggleMemView => memview.toggle(),
+                    Event::ToggleRegView => regview.toggle(),
+                    Event::ToggleDisasm => disview.toggle(),
+                    Event::SaveState | Event::LoadState if playlist.is_empty() => warn!("No ROM file to keep the save state next to"),
+                    Event::SaveState => match quicksave::save(chip, playlist.current()) {
+                        Ok(path) => info!("Saved state to {}", path.display()),
+                        Err(e) => warn!("Saving state failed: {}", e),
+                    },
+                    Event::LoadState => match quicksave::load(chip, playlist.current(), opts.force_state_load) {
+                        Ok(path) if recorder.is_some() => {
+                            stop_recording(&mut recorder, chip, "a state was loaded");
+                            info!("Loaded state from {}", path.display());
+                        },
+                        Ok(path) => info!("Loaded state from {}", path.display()),
+                        Err(e) => warn!("Loading state failed: {}", e),
+                    },
+                }
+            }
+        }
+        if frame_sync {
+            hooks.frame(chip);
+        }
+
+        stats.loop_iteration();
+
+        if running && now_ms - start_ms < opts.start_delay_ms {
+            // Hold on a blank screen before the first instruction. In
+            // deterministic mode the delay is emulated time.
+            if frame_sync {
+                let screen = chip.get_frame();
+                frontend.present(&Frame::with_size(screen.width(), screen.height()));
+                stats.frame_presented(now_ms);
+            }
+            if let Some(c) = clock.as_mut() {
+                c.advance();
+            }
+        } else if running && rewinding {
+            // No instructions run, the frames come from the snapshots.
+            if frame_sync {
+                rewind.as_mut().unwrap().playback_frame(chip);
+                present(chip, &hud, (&disview, &memview, &regview, &keyview), &debugger, frontend, false);
+                stats.frame_presented(now_ms);
+            }
+            if let Some(c) = clock.as_mut() {
+                c.advance();
+            }
+        } else if running && pause.is_paused() {
+            // Only stepped instructions run, see Pause for the timers. Steps
+            // come with the events, so the frame presented right after shows
+            // them.
+            let first = chip.instructions();
+            match pause.take_steps(chip, &mut history) {
+                Ok(steps) => {
+                    for (i, s) in steps.iter().enumerate() {
+                        print_step(s);
+                        if let Some(c) = compat.as_mut() {
+                            c.record(s.opcode);
+                        }
+                        if let Some(w) = draw_before_i.as_mut().and_then(|d| d.instruction(chip, s.pc, s.opcode)) {
+                            warn!("{}", w);
+                        }
+                        hooks.instruction(s.pc, s.opcode);
+                        if tracing.is_on() && !tracing.record(TraceLine { cycle: first + i as u64, pc: s.pc, opcode: s.opcode }) {
+                            running = false;
+                        }
+                    }
+                    // Only the state after the last step is known.
+                    if let Some(s) = steps.last() {
+                        write_registers(&mut register_csv, chip.instructions() - 1, s.pc, chip);
+                    }
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    stats.stopped_by(e);
+                    break;
+                },
+            }
+            state = RunState::Paused;
+            if frame_sync {
+                hud.update(now_ms, &stats);
+                regview.update(chip, now_ms);
+                present(chip, &hud, (&disview, &memview, &regview, &keyview), &debugger, frontend, false);
+                stats.frame_presented(now_ms);
+            }
+            if let Some(c) = clock.as_mut() {
+                c.advance();
+            }
+        } else if running {
+            if frame_sync {
+                info!("frame_sync");
+                if let Some(a) = autosave.as_mut().filter(|a| a.is_due(now_ms)) {
+                    match a.save(now_ms, chip) {
+                        Ok(slot) => info!("Autosaved to {}", slot.display()),
+                        Err(e) => warn!("Autosave failed: {}", e),
+                    }
+                }
+                if let Some(Err(e)) = watch_csv.as_mut().map(|w| w.write_frame(emulated_frames, chip)) {
+                    warn!("Writing the watch CSV failed: {}", e);
+                    watch_csv = None;
+                }
+                emulated_frames += 1;
+                if let Some(r) = rewind.as_mut() {
+                    r.frame(chip);
+                }
+                if let Some(w) = cls_watchdog.as_mut() {
+                    w.start_frame(chip);
+                }
+                if let Some((g, _)) = draw_guard.as_mut() {
+                    g.start_frame(chip);
+                    draw_warned = false;
+                }
+                chip.cycle_timers();
+                if let Some(r) = recorder.as_mut() {
+                    r.frame();
+                }
+                if !opts.deterministic {
+                    // No audio in deterministic mode, it would only add host timing to the run.
+                    frontend.set_sound(chip.is_sound_on());
+                }
+            }
+
+            if debugger.should_stop(chip) {
+                stop_recording(&mut recorder, chip, "the debugger stopped");
+                if !debugger.stop(chip, &mut history, || frontend.read_command()) {
+                    info!("Quit from the debugger");
+                    break;
+                }
+            }
+
+            let pc = chip.pc();
+            let watched = debugger.watched_bytes(chip);
+            let executed = chip.instructions();
+            let draws = chip.draws();
+            let snapshot = history.snapshot(chip);
+            let opcode = if compat.is_some() || draw_before_i.is_some() || tracing.is_on() || hooks.is_on() { chip.ram().read_u16(pc as u32).ok() } else { None };
+            if let Some(r) = recorder.as_mut() {
+                r.cycle(chip);
+            }
+            let result = if opts.explain {
+                explain_step(chip, trace_sample.as_mut())
+            } else if let Some(c) = capture.as_mut() {
+                c.cycle(chip)
+            } else {
+                chip.cycle()
+            };
+            if let Err(e) = result {
+                let skipped = match e {
+                    ChipError::UnknownOpcode { opcode, .. } if opts.debug => {
+                        stop_recording(&mut recorder, chip, "the debugger stopped");
+                        debugger.unknown_opcode(chip, &mut history, opcode, || frontend.read_command())
+                    },
+                    _ => false,
+                };
+                if !skipped {
+                    error!("{}", e);
+                    stats.stopped_by(e);
+                    break;
+                }
+            }
+            stats.cycle(chip.instructions() > executed);
+            poll_due = input_poll.as_mut().is_some_and(|p| p.cycle());
+            if chip.instructions() > executed {
+                history.record(snapshot);
+                write_registers(&mut register_csv, executed, pc, chip);
+                if let Some(opcode) = opcode {
+                    if let Some(c) = compat.as_mut() {
+                        c.record(opcode);
+                    }
+                    if let Some(w) = draw_before_i.as_mut().and_then(|d| d.instruction(chip, pc, opcode)) {
+                        warn!("{}", w);
+                    }
+                    hooks.instruction(pc, opcode);
+                    if tracing.is_on() && !tracing.record(TraceLine { cycle: executed, pc, opcode }) {
+                        info!("Stopping after the last trace mismatch");
+                        running = false;
+                    }
+                }
+            }
+            state = RunState::after_cycle(chip, executed);
+
+            if let Some(before) = watched {
+                let hits = debugger.watch_hits(chip, &before);
+                for hit in &hits {
+                    println!("{}, written at {:#05x}", hit, pc);
+                }
+                if !hits.is_empty() {
+                    stop_recording(&mut recorder, chip, "the debugger stopped");
+                    if !debugger.stop(chip, &mut history, || frontend.read_command()) {
+                        info!("Quit from the debugger");
+                        break;
+                    }
+                }
+            }
+
+            if chip.take_collision() && opts.collision_beep && !opts.deterministic {
+                frontend.play_sound(Sound::Collision);
+            }
+            if let Some(w) = cls_watchdog.as_ref().filter(|w| w.tripped(chip)) {
+                warn!("Watchdog: {} CLS in one frame at PC {:#05x}, stopping", w.clears_this_frame(chip), chip.regs().pc);
+                running = false;
+            }
+            match draw_guard.as_mut() {
+                Some((g, DrawLimit::Warn)) if !draw_warned && g.tripped(chip) => {
+                    warn!("More than {} DRW in one frame at PC {:#05x}", g.draws_this_frame(chip) - 1, chip.regs().pc);
+                    draw_warned = true;
+                },
+                Some((g, DrawLimit::Present)) if g.tripped(chip) => {
+                    trace!("Draw limit reached, presenting early");
+                    frontend.present(chip.get_frame());
+                    g.start_frame(chip);
+                },
+                _ => {},
+            }
+            if let Some(delay_ms) = opts.draw_debug.filter(|_| chip.draws() > draws) {
+                frontend.present(chip.get_frame());
+                if !opts.deterministic {
+                    let host = frontend.clock();
+                    host.sleep_until(host.now_micros() + delay_ms as u64 * 1000);
+                }
+            }
+            if wait_on_halt.as_mut().is_some_and(|h| h.check(chip)) {
+                info!("Program halted, waiting for a key");
+            }
+            if let Some(c) = clock.as_mut() {
+                c.advance();
+            }
+
+            if frame_sync {
+                hud.update(now_ms, &stats);
+                regview.update(chip, now_ms);
+                present(chip, &hud, (&disview, &memview, &regview, &keyview), &debugger, frontend, wait_on_halt.as_ref().is_some_and(|h| h.is_waiting()));
+                stats.frame_presented(now_ms);
+            }
+        }
+        if !opts.fast && !opts.deterministic {
+            // Frame syncs happen once the interval has fully passed.
+            let next_frame_us = (last_frame_ms + FRAME_INTERVALS_MS[frame_idx] + 1) as u64 * 1000;
+            let host = frontend.clock();
+            host.sleep_until(sleep_deadline(state, host.now_micros(), next_frame_us));
+        }
+    }
+
+    if let (Some(c), Some((path, profile))) = (capture, &opts.capture_tests) {
+        let source = if playlist.is_empty() {
+            "a ROM".to_string()
+        } else {
+            playlist.current().display().to_string()
+        };
+        match std::fs::write(path, capture::to_rust(c.cases(), profile, &source)) {
+            Ok(()) => info!("Wrote {} test(s) to {}", c.cases().len(), path.display()),
+            Err(e) => warn!("Writing tests failed: {}", e),
+        }
+    }
+
+    if let (Some(h), Some(path)) = (chip.heatmap(), &opts.profile_out) {
+        match std::fs::write(path, h.to_json()) {
+            Ok(()) => info!("Wrote the heatmap to {}", path.display()),
+            Err(e) => warn!("Writing the heatmap failed: {}", e),
+        }
+    }
+    if let (Some(h), Some(path)) = (chip.heatmap(), &opts.coverage) {
+        let rom = chip.rom_range();
+        let entries: Vec<u16> = std::iter::once(rom.start as u16).chain(h.executed().map(|(addr, _)| addr)).collect();
+        let lines = disasm::disassemble_from(chip.ram_slice(rom.clone()).unwrap(), rom.start as u16, &entries);
+        match std::fs::write(path, heatmap::coverage_report(&lines, h)) {
+            Ok(()) => info!("Wrote the coverage report to {}", path.display()),
+            Err(e) => warn!("Writing the coverage report failed: {}", e),
+        }
+    }
+    if let Some(r) = recorder {
+        match r.finish(chip) {
+            Ok(path) => info!("Wrote the replay to {}", path.display()),
+            Err(e) => warn!("Writing the replay failed: {}", e),
+        }
+    }
+    if let Some(c) = compat {
+        stats.set_compat(c);
+    }
+    tracing.finish();
+    if let Some(Err(e)) = watch_csv.map(|w| w.finish()) {
+        warn!("Writing the watch CSV failed: {}", e);
+    }
+    if let Some(Err(e)) = register_csv.map(|c| c.finish()) {
+        warn!("Writing the register CSV failed: {}", e);
+    }
+    if let Some(path) = &opts.save_state_on_quit {
+        let rom = (!playlist.is_empty()).then(|| playlist.current());
+        match quicksave::save_to(chip, path, rom) {
+            Ok(()) => info!("Saved state to {}", path.display()),
+            Err(e) => warn!("Saving state failed: {}", e),
+        }
+    }
+    if opts.autosave_exit && stats.error().is_none() && !playlist.is_empty() {
+        let path = quicksave::exit_path(playlist.current());
+        match quicksave::save_to(chip, &path, Some(playlist.current())) {
+            Ok(()) => info!("Saved state to {}", path.display()),
+            Err(e) => warn!("Saving state failed: {}", e),
+        }
+    }
+    stats.finish(now_ms(&clock, frontend));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::testing::{deterministic, fixtures, FakeFrontend, MockClock, TestFrontend, Timeline};
+    use super::{run, sleep_deadline, DrawLimit, Event, InputPoll, RunOptions, RunState};
+    use crate::error::ChipError;
+    use crate::audio::Sound;
+    use crate::chip::Chip;
+    use crate::compat::Quirk;
+    use crate::framebuffer::Frame;
+    use crate::playlist::Playlist;
+    use crate::profile::Profile;
+    use crate::quicksave;
+    use crate::rom;
+    use crate::tracediff::{self, TraceDiff};
+    use crate::watch_csv::WatchItem;
+
+    #[test]
+    fn quit() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![], vec![], vec![Event::Quit]]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        // Events are polled on the frame sync before the third frame.
+        assert_eq!(frontend.frames.len(), 2);
+        assert_eq!(stats.frames(), 2);
+        assert_eq!(chip.instructions(), 20);
+        assert!(frontend.sound.is_empty());
+    }
+
+    #[test]
+    fn input_poll_cycles() {
+        let mut poll = InputPoll::new(3);
+        let due: Vec<bool> = (0..7).map(|_| poll.cycle()).collect();
+        assert_eq!(due, vec![false, false, true, false, false, true, false]);
+        poll.reset();
+        assert!(!poll.cycle());
+        assert!(!poll.cycle());
+        assert!(poll.cycle());
+
+        // The quit is seen after 8 instructions instead of on the third frame.
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![], vec![], vec![Event::Quit]]);
+        let opts = RunOptions { input_poll_cycles: Some(4), ..deterministic() };
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(chip.instructions(), 8);
+        assert_eq!(frontend.frames.len(), 1);
+    }
+
+    #[test]
+    fn real_time_pacing() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![]; 5]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &RunOptions::default());
+
+        // One instruction per millisecond of sleep, a frame every 16-17 ms.
+        assert_eq!(frontend.frames.len(), 5);
+        assert_eq!(stats.avg_frame_interval(), Some(17.75));
+        assert_eq!(frontend.sound, vec![false; 5]);
+        assert_eq!(frontend.clock.now() / 1000, stats.duration_ms() as u64);
+    }
+
+    #[test]
+    fn switch_rom() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![Event::NextRom], vec![], vec![Event::PrevRom]]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        assert_eq!(frontend.titles, vec!["Chip-8 emulator - draw_1.ch8", "Chip-8 emulator - draw_0.ch8"]);
+        assert_eq!(playlist.current(), PathBuf::from("rom/fixtures/draw_0.ch8"));
+    }
+
+    #[test]
+    fn collision_sound() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0xA0, 0x00, // LD I, 0x000
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x02, // JP 0x202
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+
+        let mut frontend = TestFrontend::new(vec![vec![]]);
+        run(&mut chip, &mut playlist, &mut frontend, &RunOptions::default());
+        assert!(frontend.sounds.is_empty());
+
+        // Every other draw erases the sprite again, colliding.
+        let opts = RunOptions { collision_beep: true, ..Default::default() };
+        let mut frontend = TestFrontend::new(vec![vec![]]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert!(frontend.sounds.len() >= 8);
+        assert!(frontend.sounds.iter().all(|&s| s == Sound::Collision));
+    }
+
+    #[test]
+    fn wait_on_halt() {
+        let (mut chip, mut playlist) = fixtures();
+        let opts = RunOptions { wait_on_halt: true, ..deterministic() };
+        // draw_0 halts after two instructions, the key press then ends the run.
+        let mut frontend = TestFrontend::new(vec![vec![], vec![Event::KeyPress(0x5)], vec![], vec![]]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(frontend.frames.len(), 1);
+        assert_eq!(frontend.events.len(), 2);
+    }
+
+    #[test]
+    fn sticky_keys() {
+        let (mut chip, mut playlist) = fixtures();
+        let opts = RunOptions { sticky_keys: true, ..deterministic() };
+        let press = || vec![vec![Event::KeyPress(0x5)], vec![], vec![Event::KeyUnpress(0x5)], vec![]];
+
+        // The first press latches the key through the release.
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(press()), &opts);
+        assert!(chip.keypad().is_down(0x5));
+
+        // The second one releases it.
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(press()), &opts);
+        assert!(!chip.keypad().is_down(0x5));
+        assert!(chip.keypad().just_released(0x5));
+
+        // Held keys without the option.
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(press()), &deterministic());
+        assert!(!chip.keypad().is_down(0x5));
+    }
+
+    #[test]
+    fn start_delay() {
+        let (mut chip, mut playlist) = fixtures();
+        let opts = RunOptions { start_delay_ms: 50, ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 5]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // The program starts at 50 ms, on the fourth frame, and draws right
+        // after it is presented.
+        assert_eq!(frontend.frames.len(), 5);
+        assert!(frontend.frames[..4].iter().all(|f| f.iter().all(|row| row.iter().all(|&p| p == 0))));
+        assert!(frontend.frames[4].iter().any(|row| row.iter().any(|&p| p != 0)));
+        // Two frames of 10 instructions ran after the delay.
+        assert_eq!(stats.instructions(), 20);
+    }
+
+    #[test]
+    fn quit_during_start_delay() {
+        let (mut chip, mut playlist) = fixtures();
+        let opts = RunOptions { start_delay_ms: 10_000, ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![], vec![Event::Quit], vec![]]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(chip.instructions(), 0);
+        assert_eq!(frontend.frames.len(), 1);
+        assert_eq!(frontend.events.len(), 1);
+    }
+
+    #[test]
+    fn toggle_hud() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![Event::ToggleHud], vec![], vec![Event::ToggleHud]]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        // The top row of "IPS" shows while the HUD is on.
+        let top_left = |f: &Frame| (0..4).map(|x| f[0_u32][x]).collect::<Vec<u32>>();
+        assert_eq!(top_left(&frontend.frames[0]), vec![1, 1, 1, 0]);
+        assert_eq!(top_left(&frontend.frames[1]), vec![1, 1, 1, 0]);
+        // Off again, the program's own pixels are back.
+        assert_eq!(top_left(&frontend.frames[2]), top_left(chip.get_frame()));
+        assert_ne!(top_left(chip.get_frame()), vec![1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn memview() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![
+            vec![],
+            vec![Event::ToggleMemView],
+            vec![Event::NextRom],
+            vec![Event::ToggleMemView],
+            vec![Event::NextRom],
+        ]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        // Shown for two frames, and PageDown scrolled it instead of
+        // switching ROMs.
+        let shown: Vec<usize> = frontend.overlays.iter().map(|(i, _)| *i).collect();
+        assert_eq!(shown, vec![1, 2]);
+        assert_ne!(frontend.overlays[0].1, frontend.overlays[1].1);
+        assert_eq!(frontend.titles, vec!["Chip-8 emulator - draw_1.ch8"]);
+    }
+
+    #[test]
+    fn regview() {
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![
+            vec![Event::ToggleRegView],
+            vec![Event::ToggleMemView],
+            vec![Event::ToggleRegView],
+            vec![Event::ToggleDisasm],
+        ]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        // Registers alone, then memory on their left, then memory alone,
+        // then the disassembly left of it.
+        let widths: Vec<u32> = frontend.overlays.iter().map(|(_, o)| o.width()).collect();
+        assert_eq!(widths, vec![93, 206 + 4 + 93, 206, 129 + 4 + 206]);
+    }
+
+    #[test]
+    fn cls_watchdog() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x00, 0xE0, // CLS
+            0x12, 0x00, // JP 0x200
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+
+        // 1000 instructions, 500 clears per frame.
+        let opts = RunOptions { cls_watchdog: Some(200), ips: 60_000, ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(chip.clears(), 201);
+        assert_eq!(frontend.events.len(), 2);
+
+        // Under the threshold the script runs out and quits.
+        chip.reset();
+        chip.load_rom(&[0x00, 0xE0, 0x12, 0x00], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let opts = RunOptions { cls_watchdog: Some(500), ips: 60_000, ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(chip.clears(), 1500);
+    }
+
+    #[test]
+    fn max_draws() {
+        let draw_loop = || {
+            let mut chip = Chip::new_seed(0, Profile::modern());
+            chip.load_rom(&[
+                0xD0, 0x01, // DRW V0, V0, 1
+                0x12, 0x00, // JP 0x200
+            ], 0x200).unwrap();
+            chip.set_pc(0x200);
+            chip
+        };
+        let mut playlist = Playlist::new(&[]).unwrap();
+
+        // 50 draws per frame, the limit is 20: two early presents per frame.
+        let opts = RunOptions { max_draws: Some((20, DrawLimit::Present)), ips: 6000, ..deterministic() };
+        let mut chip = draw_loop();
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(stats.frames(), 3);
+        assert_eq!(frontend.frames.len(), 3 * 3);
+
+        // Warning only, the frames are presented as usual.
+        let opts = RunOptions { max_draws: Some((20, DrawLimit::Warn)), ips: 6000, ..deterministic() };
+        let mut chip = draw_loop();
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(frontend.frames.len(), 3);
+        assert_eq!(chip.draws(), 150);
+    }
+
+    #[test]
+    fn draw_debug() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0xA2, 0x06, // LD I, 0x206
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x12, 0x02, // JP 0x202
+            0x80, 0x00,
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { draw_debug: Some(100), ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // One present per DRW on top of one per frame, each showing the
+        // sprite toggled by the XOR.
+        assert_eq!(chip.draws(), 15);
+        assert_eq!(frontend.frames.len(), 15 + 3);
+        assert_ne!(frontend.frames[0], frontend.frames[1]);
+        assert_ne!(frontend.frames[1], frontend.frames[2]);
+        assert_eq!(frontend.frames[0], frontend.frames[2]);
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x3C, // LD V0, 60
+            0xF0, 0x15, // LD DT, V0
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x04, // JP 0x204
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        // Ten instructions per frame: paused after the first frame, then
+        // steps, held down for a while.
+        let mut frontend = TestFrontend::new(vec![
+            vec![],
+            vec![Event::TogglePause],
+            vec![Event::StepInstruction],
+            vec![],
+            vec![Event::StepInstruction; 6],
+            vec![Event::StepInstruction; 6],
+            vec![Event::TogglePause],
+        ]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        // A frame, 13 steps and a frame after resuming.
+        assert_eq!(chip.instructions(), 10 + 13 + 10);
+        assert_eq!(frontend.frames.len(), 7);
+        // No ticks for the paused frames, one for the tenth step and one
+        // for the frame after resuming.
+        assert_eq!(chip.dt(), 60 - 2);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn hooks() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::script_hooks::{ScriptHooks, DEFAULT_BUDGET};
+
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0xF1, 0x0A, // LD V1, K
+            0x12, 0x02, // JP 0x202
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let script = Arc::new(Mutex::new(ScriptHooks::read(Path::new("tests/scripts/press_5.rhai"), DEFAULT_BUDGET).unwrap()));
+        let opts = RunOptions { hooks: Some(script.clone()), ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![], vec![], vec![], vec![], vec![Event::KeyPress(0x3)], vec![]]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // The key the script pressed, not the one from the frontend.
+        assert_eq!(chip.v(1), 0x5);
+        let state = script.lock().unwrap().state().clone_cast::<rhai::Map>();
+        assert_eq!(state["frames"].as_int(), Ok(7));
+        assert_eq!(state["instructions"].as_int(), Ok(chip.instructions() as i64));
+        assert_eq!(state["keys"].to_string(), "[[3, true]]");
+    }
+
+    #[test]
+    fn step_back() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x3C, // LD V0, 60
+            0xF0, 0x15, // LD DT, V0
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x04, // JP 0x204
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { history: 4, ..deterministic() };
+        // Stepping back is ignored while running.
+        let mut frontend = TestFrontend::new(vec![
+            vec![Event::StepBack],
+            vec![Event::TogglePause],
+            vec![Event::StepBack; 6],
+            vec![Event::StepInstruction],
+        ]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // Four ADD V1 in the first frame, two of them undone and one
+        // stepped again.
+        assert_eq!(chip.instructions(), 10 + 1);
+        assert_eq!(chip.v(1), 3);
+        assert_eq!(chip.pc(), 0x206);
+    }
+
+    #[test]
+    fn chip_error() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x01, // LD V0, 1
+            0x00, 0xEE, // RET
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let mut frontend = TestFrontend::new(vec![vec![]; 3]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+
+        assert_eq!(stats.error(), Some(&ChipError::StackUnderflow { pc: 0x202 }));
+        assert_eq!(chip.regs().pc, 0x202);
+        assert_eq!(frontend.events.len(), 2);
+    }
+
+    #[test]
+    fn idle_sleep() {
+        // A millisecond per instruction while running.
+        assert_eq!(sleep_deadline(RunState::Running, 5_000, 17_000), 6_000);
+        // Idle until the next frame sync, but never shorter than that.
+        assert_eq!(sleep_deadline(RunState::Halted, 5_000, 17_000), 17_000);
+        assert_eq!(sleep_deadline(RunState::WaitingForKey, 5_000, 17_000), 17_000);
+        assert_eq!(sleep_deadline(RunState::WaitingForKey, 16_500, 17_000), 17_500);
+    }
+
+    #[test]
+    fn idle_throttle() {
+        let mut playlist = Playlist::new(&[]).unwrap();
+        for rom in [[0x12, 0x00], [0xF0, 0x0A]] { // JP 0x200, LD V0, K
+            let mut chip = Chip::new_seed(0, Profile::modern());
+            chip.load_rom(&rom, 0x200).unwrap();
+            chip.set_pc(0x200);
+            let mut frontend = TestFrontend::new(vec![vec![]; 5]);
+
+            let stats = run(&mut chip, &mut playlist, &mut frontend, &RunOptions::default());
+
+            // One loop iteration per frame instead of one per millisecond.
+            assert_eq!(frontend.frames.len(), 5);
+            assert!(stats.instructions() <= 6);
+        }
+    }
+
+    fn counting_loop() -> Chip {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x00, // JP 0x200
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    fn debug_frontend(commands: &[&str]) -> TestFrontend {
+        let mut frontend = TestFrontend::new(vec![vec![]; 100]);
+        frontend.commands = commands.iter().map(|c| c.to_string()).collect();
+        frontend
+    }
+
+    #[test]
+    fn breakpoint_every_iteration() {
+        let mut chip = counting_loop();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        // Thousands of instructions a frame still stop on the right one.
+        let opts = RunOptions { breakpoints: vec![0x200], ips: 60_000, ..deterministic() };
+        let mut frontend = debug_frontend(&["c", "c", "c", "q"]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(chip.regs().vx[0_u8], 3);
+        assert_eq!(chip.regs().pc, 0x200);
+        assert_eq!(chip.instructions(), 6);
+        assert!(frontend.commands.is_empty());
+    }
+
+    fn data_in_code() -> Chip {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x01, // LD V0, 1
+            0x51, 0x21, // not an instruction
+            0x61, 0x02, // LD V1, 2
+            0x12, 0x06, // JP 0x206
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    #[test]
+    fn skip_unknown_opcode() {
+        let mut chip = data_in_code();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { debug: true, ..deterministic() };
+        // Only skip or abort go on from there.
+        let mut frontend = debug_frontend(&["c", "s", "bt", "skip"]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(stats.error(), None);
+        assert_eq!(chip.v(0), 1);
+        assert_eq!(chip.v(1), 2);
+        assert_eq!(chip.pc(), 0x206);
+        assert!(frontend.commands.is_empty());
+    }
+
+    #[test]
+    fn abort_unknown_opcode() {
+        let unknown = ChipError::UnknownOpcode { pc: 0x202, opcode: 0x5121 };
+        for (debug, commands) in [(true, &["abort"][..]), (true, &[][..]), (false, &["skip"][..])] {
+            let mut chip = data_in_code();
+            let mut playlist = Playlist::new(&[]).unwrap();
+            let opts = RunOptions { debug, ..deterministic() };
+            let mut frontend = debug_frontend(commands);
+
+            let stats = run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+            assert_eq!(stats.error(), Some(&unknown));
+            assert_eq!(chip.pc(), 0x202);
+            assert_eq!(chip.v(1), 0);
+            // Without --debug the debugger is never asked.
+            assert_eq!(frontend.commands.is_empty(), debug);
+        }
+    }
+
+    #[test]
+    fn delete_breakpoint() {
+        let mut chip = counting_loop();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { breakpoints: vec![0x200, 0x202], ..deterministic() };
+        let mut frontend = debug_frontend(&["info breakpoints", "delete 1", "c", "c", "q"]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // Stopped at 0x200 once, then only at 0x202.
+        assert_eq!(chip.regs().vx[0_u8], 2);
+        assert_eq!(chip.regs().pc, 0x202);
+        assert!(frontend.commands.is_empty());
+    }
+
+    #[test]
+    fn step_and_break() {
+        let mut chip = counting_loop();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { breakpoints: vec![0x202], ..deterministic() };
+        // Stops at 0x202, after the step at 0x200, then on the new
+        // breakpoint, where the input runs out and ends the run.
+        let mut frontend = debug_frontend(&["s", "b 0x200", "delete 1", "c"]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(chip.regs().vx[0_u8], 2);
+        assert_eq!(chip.regs().pc, 0x200);
+        assert_eq!(chip.instructions(), 4);
+    }
+
+    #[test]
+    fn watchpoint() {
+        let stores = || {
+            let mut chip = Chip::new_seed(0, Profile::modern());
+            chip.load_rom(&[
+                0x60, 0x05, // LD V0, 5
+                0x61, 0x07, // LD V1, 7
+                0xA3, 0x00, // LD I, 0x300
+                0xF1, 0x55, // LD [I], V1
+                0xA3, 0x10, // LD I, 0x310
+                0xF1, 0x55, // LD [I], V1
+                0x12, 0x0C, // JP 0x20C
+            ], 0x200).unwrap();
+            chip.set_pc(0x200);
+            chip
+        };
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { watchpoints: vec![0x301..0x302, 0x400..0x410], ..deterministic() };
+
+        // Stops right after the first store, the second one is elsewhere.
+        let mut chip = stores();
+        let mut frontend = debug_frontend(&["q"]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(chip.regs().pc, 0x208);
+        assert_eq!(chip.instructions(), 4);
+        assert!(frontend.commands.is_empty());
+
+        // Continuing runs into the halt without stopping again.
+        let mut chip = stores();
+        let mut frontend = debug_frontend(&["c"]);
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        assert_eq!(chip.regs().pc, 0x20C);
+        assert_eq!(chip.ram_slice(0x310..0x312).unwrap(), [5, 7]);
+    }
+
+    #[test]
+    fn compat_report() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x81, 0x26, // SHR V1, V2
+            0xF2, 0x55, // LD [I], V2
+            0x12, 0x00, // JP 0x200
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let opts = RunOptions { compat_report: true, ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 2]);
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        assert_eq!(stats.compat().unwrap().quirks(), vec![Quirk::Shift, Quirk::MemoryIncrement]);
+        // Two frames, 20 instructions.
+        assert!(stats.report().contains("\nshift: 8XY6 x7, the original and modern profiles differ\n"));
+        assert!(stats.report().ends_with("\njump: not used\ndraw wait: not used\n"));
+
+        // Off unless asked for.
+        let stats = run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![]), &deterministic());
+        assert!(stats.compat().is_none());
+    }
+
+    #[test]
+    fn profile_out() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x00, // LD V0, 0
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x02, // JP 0x202
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-heatmap-{}.json", std::process::id()));
+        let opts = RunOptions { profile_out: Some(path.clone()), ..deterministic() };
+        let mut frontend = TestFrontend::new(vec![vec![]; 2]);
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+
+        // The loop gets all but the first of the 20 instructions.
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"0x200\": 1, \"0x202\": 10, \"0x204\": 9}\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watch_csv() {
+        let mut chip = counting_loop();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-watch-{}.csv", std::process::id()));
+        let opts = RunOptions { watch_csv: Some((path.clone(), vec![WatchItem::V(0), WatchItem::Pc])), ..deterministic() };
+
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 3]), &opts);
+
+        // Five ADD V0 per frame of ten instructions.
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text, "frame,v0,pc\n0,0,512\n1,5,512\n2,10,512\n");
+    }
+
+    #[test]
+    fn csv_registers() {
+        let mut chip = counting_loop();
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-registers-{}.csv", std::process::id()));
+        let opts = RunOptions { csv_registers: Some(path.clone()), ..deterministic() };
+
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 3]), &opts);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "cycle,pc,v0,v1,v2,v3,v4,v5,v6,v7,v8,v9,va,vb,vc,vd,ve,vf,i,sp,dt,st");
+        assert_eq!(lines[1].split(',').count(), 22);
+        assert_eq!(lines[1], "0,512,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0");
+        assert_eq!(lines[2], "1,514,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0");
+        assert_eq!(lines.len() as u64, 1 + chip.instructions());
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let dir = std::env::temp_dir().join(format!("chip8-quicksave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rnd.ch8");
+        std::fs::write(&path, [
+            0xC0, 0xFF, // RND V0, 0xFF
+            0x81, 0x04, // ADD V1, V0
+            0xF1, 0x15, // LD DT, V1
+            0x12, 0x00, // JP 0x200
+        ]).unwrap();
+        let machine = |seed: u64| {
+            let mut chip = Chip::new_seed(seed, Profile::modern());
+            let mut playlist = Playlist::new(std::slice::from_ref(&path)).unwrap();
+            playlist.open(|p| rom::start(&mut chip, p, &[])).unwrap();
+            (chip, playlist)
+        };
+
+        // Saved after the first frame, then three more.
+        let (mut first, mut playlist) = machine(1);
+        run(&mut first, &mut playlist, &mut TestFrontend::new(vec![vec![], vec![Event::SaveState], vec![], vec![]]), &deterministic());
+        assert_eq!(first.instructions(), 40);
+
+        // Another seed, loaded before the first instruction, then three frames.
+        let (mut second, mut playlist) = machine(2);
+        run(&mut second, &mut playlist, &mut TestFrontend::new(vec![vec![Event::LoadState], vec![], vec![]]), &deterministic());
+        assert_eq!(second.state_hash(), first.state_hash());
+
+        // Not for another ROM.
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+        let (mut third, _) = machine(2);
+        let before = third.state_hash();
+        assert!(quicksave::load(&mut third, &path, false).is_err());
+        assert_eq!(third.state_hash(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn autosave_exit() {
+        let dir = std::env::temp_dir().join(format!("chip8-autosave-exit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("ok.ch8");
+        std::fs::write(&rom_path, [0x70, 0x01, 0x12, 0x00]).unwrap();
+        let opts = RunOptions { autosave_exit: true, ..deterministic() };
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        let mut playlist = Playlist::new(std::slice::from_ref(&rom_path)).unwrap();
+        playlist.open(|p| rom::start(&mut chip, p, &[])).unwrap();
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+        assert_eq!(quicksave::exit_save(&rom_path), quicksave::ExitSave::Resumable(quicksave::exit_path(&rom_path)));
+
+        // Not after an error.
+        let rom_path = dir.join("ret.ch8");
+        std::fs::write(&rom_path, [0x00, 0xEE]).unwrap();
+        let mut playlist = Playlist::new(std::slice::from_ref(&rom_path)).unwrap();
+        playlist.open(|p| rom::start(&mut chip, p, &[])).unwrap();
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+        assert_eq!(quicksave::exit_save(&rom_path), quicksave::ExitSave::None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewind() {
+        let mut chip = counting_loop();
+        let mut events = vec![vec![]; 6];
+        events.extend([vec![Event::RewindStart], vec![], vec![], vec![], vec![Event::RewindStop], vec![]]);
+        let mut frontend = TestFrontend::new(events);
+        let opts = RunOptions { rewind: Some((60, 1)), ..deterministic() };
+        run(&mut chip, &mut Playlist::new(&[]).unwrap(), &mut frontend, &opts);
+        // Six frames of 5 ADDs, back two snapshots to the start of frame 4,
+        // then two more frames.
+        assert_eq!(chip.v(0), 30);
+        assert_eq!(frontend.frames.len(), 12);
+        assert_eq!(frontend.sound, vec![false]);
+    }
+
+    #[test]
+    fn load_and_save_state_on_quit() {
+        let path = std::env::temp_dir().join(format!("chip8-quit-{}.state", std::process::id()));
+        let opts = RunOptions { save_state_on_quit: Some(path.clone()), ..deterministic() };
+        let mut first = counting_loop();
+        run(&mut first, &mut Playlist::new(&[]).unwrap(), &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+
+        // The state goes with any ROM, having been saved without one.
+        let mut second = Chip::new_seed(5, Profile::modern());
+        quicksave::load_from(&mut second, &path, Some(Path::new("rom/fixtures/draw_0.ch8")), false).unwrap();
+        assert_eq!(second.state_hash(), first.state_hash());
+
+        // Going on from there ends where a longer first run does.
+        run(&mut second, &mut Playlist::new(&[]).unwrap(), &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+        run(&mut first, &mut Playlist::new(&[]).unwrap(), &mut TestFrontend::new(vec![vec![]; 2]), &deterministic());
+        assert_eq!(second.state_hash(), first.state_hash());
+        let mut third = Chip::new_seed(6, Profile::chip48());
+        quicksave::load_from(&mut third, &path, None, false).unwrap();
+        assert_eq!(third.state_hash(), first.state_hash());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn trace_file_and_against() {
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-trace-{}.log", std::process::id()));
+        let opts = RunOptions { trace_file: Some(path.clone()), ..deterministic() };
+        let mut chip = counting_loop();
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(text.starts_with("0 0x200 7001\n1 0x202 1200\n2 0x200 7001\n"));
+        let mut reference = tracediff::parse_log(&text).unwrap();
+        assert_eq!(reference.len(), 20);
+
+        // Stops at the planted divergence.
+        reference[7].pc = 0x204;
+        let opts = RunOptions { trace_against: Some(TraceDiff::new(reference, &[], 1)), ..deterministic() };
+        let mut chip = counting_loop();
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]; 2]), &opts);
+        assert_eq!(chip.instructions(), 8);
+    }
+
+    #[test]
+    fn coverage() {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[
+            0x60, 0x01, // LD V0, 1
+            0x30, 0x01, // SE V0, 1
+            0x61, 0x02, // LD V1, 2
+            0x12, 0x06, // JP 0x206
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-coverage-{}.txt", std::process::id()));
+        let opts = RunOptions { coverage: Some(path.clone()), ..deterministic() };
+
+        run(&mut chip, &mut playlist, &mut TestFrontend::new(vec![vec![]]), &opts);
+
+        // The skip is always taken.
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), [
+            "  0x200  6001  LD V0, 0x1",
+            "  0x202  3001  SE V0, 0x1",
+            "! 0x204  6102  LD V1, 0x2",
+            "  0x206  1206  JP 0x206",
+            "Executed 3 of 4 instructions, 75.0%",
+            "",
+        ].join("\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn capture_tests() {
+        let path = std::env::temp_dir().join(format!("chip8-capture-{}.rs", std::process::id()));
+        let (mut chip, mut playlist) = fixtures();
+        let mut frontend = TestFrontend::new(vec![vec![], vec![Event::Quit]]);
+        let opts = RunOptions { capture_tests: Some((path.clone(), "modern".to_string())), ..deterministic() };
+
+        run(&mut chip, &mut playlist, &mut frontend, &opts);
+        let src = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(src.starts_with("// Generated by --capture-tests from rom/fixtures/draw_0.ch8.\n"));
+        assert!(src.matches("#[test]").count() > 1);
+    }
+
+    // ADD V1, 1 in a loop, V1 counting every other instruction.
+    fn counter() -> Chip {
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[0x71, 0x01, 0x12, 0x00], 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    #[test]
+    fn timeline_pause() {
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let mut running = counter();
+        let mut frontend = FakeFrontend::new(Timeline::new().at(40, Event::Quit));
+        run(&mut running, &mut playlist, &mut frontend, &deterministic());
+
+        // Paused for 20 of the 40 frames.
+        let mut paused = counter();
+        let mut paused_frontend = FakeFrontend::new(Timeline::new()
+            .at(10, Event::TogglePause)
+            .at(30, Event::TogglePause)
+            .at(40, Event::Quit));
+        run(&mut paused, &mut playlist, &mut paused_frontend, &deterministic());
+
+        assert_eq!(running.instructions() - paused.instructions(), 20 * 10);
+        // The screen is still presented while paused.
+        assert_eq!(paused_frontend.frames.presented, frontend.frames.presented);
+        assert_eq!(paused_frontend.timeline.polls(), 41);
+    }
+
+    #[test]
+    fn timeline_reset() {
+        // Moving on in a playlist of one starts its ROM over.
+        let path = std::env::temp_dir().join(format!("chip8-counter-{}.ch8", std::process::id()));
+        std::fs::write(&path, [0x71, 0x01, 0x12, 0x00]).unwrap();
+        let mut chip = counter();
+        let mut playlist = Playlist::new(std::slice::from_ref(&path)).unwrap();
+        let mut frontend = FakeFrontend::new(Timeline::new().at(20, Event::NextRom).at(25, Event::Quit));
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &deterministic());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(frontend.titles.len(), 1);
+        assert!(stats.instructions() > 200);
+        assert_eq!(chip.instructions(), 5 * 10);
+        assert_eq!(chip.v(1), 5 * 10 / 2);
+    }
+
+    #[test]
+    fn timeline_turbo() {
+        // The clock moves 100us every time it is read, so frames still come
+        // without sleeping.
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let mut throttled = counter();
+        let mut frontend = FakeFrontend::new(Timeline::new().at(10, Event::Quit)).with_clock(MockClock::with_step(100));
+        run(&mut throttled, &mut playlist, &mut frontend, &RunOptions::default());
+        assert!(frontend.clock.sleeps() > 0);
+
+        let mut turbo = counter();
+        let mut turbo_frontend = FakeFrontend::new(Timeline::new().at(10, Event::Quit)).with_clock(MockClock::with_step(100));
+        run(&mut turbo, &mut playlist, &mut turbo_frontend, &RunOptions { fast: true, ..Default::default() });
+        assert_eq!(turbo_frontend.clock.sleeps(), 0);
+
+        assert!(turbo.instructions() > 4 * throttled.instructions(), "{} vs {}", turbo.instructions(), throttled.instructions());
+        assert_eq!(turbo_frontend.timeline.polls(), frontend.timeline.polls());
+    }
+
+    #[test]
+    fn quit_flushes_stats() {
+        // LD V0, 30; LD ST, V0, then spins: half a second of buzzer.
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.load_rom(&[0x60, 0x1E, 0xF0, 0x18, 0x12, 0x04], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut playlist = Playlist::new(&[]).unwrap();
+        let mut frontend = FakeFrontend::new(Timeline::new().at(60, Event::Quit)).with_clock(MockClock::with_step(10));
+
+        let stats = run(&mut chip, &mut playlist, &mut frontend, &RunOptions::default());
+
+        assert_eq!(stats.instructions(), chip.instructions());
+        assert_eq!(stats.frames(), frontend.frames.presented as u64);
+        // Up to the quit, ending at the time it was read last.
+        assert_eq!(stats.duration_ms() as u64, (frontend.clock.now() - 10) / 1000);
+        assert!(stats.duration_ms() >= 59 * 16);
+        assert_eq!(frontend.audio.beeps(), 1);
+        assert!(frontend.audio.buzzer.iter().filter(|&&on| on).count() >= 29);
+        assert!(stats.report().contains(&format!("Execution time: {} ms", stats.duration_ms())));
+    }
+}
@@ -0,0 +1,276 @@
+// Hooks written in rhai, for --script-hooks. A script defines any of
+//
+//   fn init() { #{ frames: 0 } }
+//   fn on_frame(chip) { this.frames += 1; if this.frames == 60 { chip.press(5) } }
+//   fn on_instruction(pc, opcode) { ... }
+//   fn on_key(key, pressed) { ... }
+//
+// Functions can't see the script's variables, so they keep their state in
+// `this`: the map init() returns, or an empty one. `chip` has what HookApi
+// allows: v(x), the i, pc, dt and st properties, read(addr), press(key)
+// and release(key). Key presses take effect when on_frame returns.
+//
+// A frame's calls, on_frame and the instructions and keys up to the next
+// one, share a budget of operations. Running past it is an error, which
+// turns the hooks off like any other. rhai can't say where a hook was when
+// it stopped, so the error has the line the hook is defined on.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+
+use crate::hooks::{HookApi, HookError, Hooks};
+
+pub const DEFAULT_BUDGET: u64 = 100_000;
+
+// The machine as a script sees it during on_frame.
+#[derive(Clone)]
+struct ScriptChip {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    dt: u8,
+    st: u8,
+    memory: Arc<Vec<u8>>,
+    // Key changes to make once on_frame returns, true for a press.
+    keys: Arc<Mutex<Vec<(u8, bool)>>>,
+}
+
+impl ScriptChip {
+    fn new(api: &HookApi) -> ScriptChip {
+        let mut v = [0; 16];
+        for (x, r) in v.iter_mut().enumerate() {
+            *r = api.v(x as u8);
+        }
+        ScriptChip {
+            v,
+            i: api.i(),
+            pc: api.pc(),
+            dt: api.dt(),
+            st: api.st(),
+            memory: Arc::new(api.memory().to_vec()),
+            keys: Arc::default(),
+        }
+    }
+
+    fn key(&mut self, key: INT, pressed: bool) -> Result<(), Box<EvalAltResult>> {
+        if !(0..16).contains(&key) {
+            return Err(format!("no key {}, keys are 0-15", key).into());
+        }
+        self.keys.lock().unwrap().push((key as u8, pressed));
+        Ok(())
+    }
+}
+
+fn engine(budget: u64, used: Arc<AtomicU64>, ops: Arc<AtomicU64>) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|s| info!("script: {}", s));
+    engine.on_progress(move |n| {
+        ops.store(n, Ordering::Relaxed);
+        (used.load(Ordering::Relaxed) + n > budget).then_some(Dynamic::UNIT)
+    });
+    engine.register_type_with_name::<ScriptChip>("Chip")
+        .register_get("i", |c: &mut ScriptChip| c.i as INT)
+        .register_get("pc", |c: &mut ScriptChip| c.pc as INT)
+        .register_get("dt", |c: &mut ScriptChip| c.dt as INT)
+        .register_get("st", |c: &mut ScriptChip| c.st as INT)
+        .register_fn("v", |c: &mut ScriptChip, x: INT| -> Result<INT, Box<EvalAltResult>> {
+            usize::try_from(x).ok().and_then(|x| c.v.get(x)).map(|&r| r as INT)
+                .ok_or_else(|| format!("no register V{}", x).into())
+        })
+        .register_fn("read", |c: &mut ScriptChip, addr: INT| -> Result<INT, Box<EvalAltResult>> {
+            usize::try_from(addr).ok().and_then(|a| c.memory.get(a)).map(|&b| b as INT)
+                .ok_or_else(|| format!("address {:#x} is outside RAM", addr).into())
+        })
+        .register_fn("press", |c: &mut ScriptChip, key: INT| c.key(key, true))
+        .register_fn("release", |c: &mut ScriptChip, key: INT| c.key(key, false));
+    engine
+}
+
+// The error where the script went wrong, not where the hook was called.
+fn hook_error(mut e: EvalAltResult, budget: u64) -> HookError {
+    while let EvalAltResult::ErrorInFunctionCall(_, _, inner, _) = e {
+        e = *inner;
+    }
+    let line = e.take_position().line().map(|l| l as u32);
+    let message = match e {
+        EvalAltResult::ErrorTerminated(..) => format!("ran past the budget of {} operations a frame", budget),
+        e => e.to_string(),
+    };
+    HookError { line, message }
+}
+
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: AST,
+    // `this` of the script's functions.
+    state: Dynamic,
+    budget: u64,
+    // Operations run this frame before the current call, and in it so far.
+    used: Arc<AtomicU64>,
+    ops: Arc<AtomicU64>,
+    // Which of on_frame, on_instruction and on_key the script has.
+    defined: [bool; 3],
+    // Where each function is defined, for errors rhai gives no line.
+    lines: HashMap<String, u32>,
+}
+
+impl ScriptHooks {
+    // Compiles `source` and runs its top level and init(), within `budget`.
+    pub fn new(source: &str, budget: u64) -> Result<ScriptHooks, HookError> {
+        let (used, ops) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+        let engine = engine(budget, used.clone(), ops.clone());
+        let ast = engine.compile(source).map_err(|e| HookError {
+            line: e.position().line().map(|l| l as u32),
+            message: e.0.to_string(),
+        })?;
+        let has = |name: &str, params: usize| ast.iter_functions().any(|f| f.name == name && f.params.len() == params);
+        let defined = [has("on_frame", 1), has("on_instruction", 2), has("on_key", 2)];
+        let init = has("init", 0);
+        let lines = source.lines().zip(1..).filter_map(|(l, n)| {
+            let name = l.trim_start().strip_prefix("fn ")?.split('(').next()?.trim();
+            Some((name.to_string(), n))
+        }).collect();
+        engine.run_ast(&ast).map_err(|e| hook_error(*e, budget))?;
+
+        let mut hooks = ScriptHooks { engine, ast, state: Dynamic::from(Map::new()), budget, used, ops, defined, lines };
+        if init {
+            hooks.state = hooks.call("init", ())?;
+        }
+        Ok(hooks)
+    }
+
+    pub fn read(path: &Path, budget: u64) -> std::io::Result<ScriptHooks> {
+        let source = std::fs::read_to_string(path)?;
+        ScriptHooks::new(&source, budget)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn state(&self) -> &Dynamic {
+        &self.state
+    }
+
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> Result<Dynamic, HookError> {
+        self.ops.store(0, Ordering::Relaxed);
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args);
+        self.used.fetch_add(self.ops.load(Ordering::Relaxed), Ordering::Relaxed);
+        result.map_err(|e| {
+            let mut e = hook_error(*e, self.budget);
+            e.line = e.line.or_else(|| self.lines.get(name).copied());
+            e
+        })
+    }
+}
+
+impl Hooks for ScriptHooks {
+    fn on_frame(&mut self, api: &mut HookApi) -> Result<(), HookError> {
+        self.used.store(0, Ordering::Relaxed);
+        if !self.defined[0] {
+            return Ok(());
+        }
+        let chip = ScriptChip::new(api);
+        let keys = chip.keys.clone();
+        let _ = self.call("on_frame", (chip,))?;
+        for (key, pressed) in keys.lock().unwrap().drain(..) {
+            if pressed {
+                api.press(key);
+            } else {
+                api.release(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_instruction(&mut self, pc: u16, opcode: u16) -> Result<(), HookError> {
+        if self.defined[1] {
+            let _ = self.call("on_instruction", (pc as INT, opcode as INT))?;
+        }
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: u8, pressed: bool) -> Result<(), HookError> {
+        if self.defined[2] {
+            let _ = self.call("on_key", (key as INT, pressed))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScriptHooks, DEFAULT_BUDGET};
+    use crate::chip::Chip;
+    use crate::hooks::{HookApi, HookError, Hooks};
+    use crate::profile::Profile;
+
+    fn chip() -> Chip {
+        Chip::builder().profile(Profile::modern()).rom(&[0x6A, 0x07, 0x12, 0x02]).build().unwrap()
+    }
+
+    #[test]
+    fn api() {
+        let mut hooks = ScriptHooks::new(r#"
+            fn init() { #{ seen: [] } }
+            fn on_frame(chip) {
+                this.seen = [chip.v(10), chip.pc, chip.i, chip.read(0x200)];
+                chip.press(12);
+            }
+        "#, DEFAULT_BUDGET).unwrap();
+        let mut chip = chip();
+        chip.step().unwrap();
+        hooks.on_frame(&mut HookApi::new(&mut chip)).unwrap();
+
+        let seen: Vec<i64> = hooks.state().clone_cast::<rhai::Map>()["seen"].clone().into_typed_array().unwrap();
+        assert_eq!(seen, [7, 0x202, 0, 0x6A]);
+        assert!(chip.keypad().is_down(0xC));
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        assert_eq!(ScriptHooks::new("fn on_frame(chip) {\n  let x = ;\n}", DEFAULT_BUDGET).err().map(|e| e.line), Some(Some(2)));
+
+        let mut hooks = ScriptHooks::new("fn on_frame(chip) {\n  let x = 1;\n  chip.press(16);\n}", DEFAULT_BUDGET).unwrap();
+        let e = hooks.on_frame(&mut HookApi::new(&mut chip())).unwrap_err();
+        assert_eq!(e.line, Some(3));
+        assert!(e.message.contains("no key 16"), "{}", e);
+
+        let mut hooks = ScriptHooks::new("fn on_key(key, pressed) {\n  key.nope()\n}", DEFAULT_BUDGET).unwrap();
+        assert_eq!(hooks.on_key(1, true).unwrap_err().line, Some(2));
+    }
+
+    #[test]
+    fn budget() {
+        let mut hooks = ScriptHooks::new("// Spins.\nfn on_frame(chip) {\n  loop {}\n}", 1000).unwrap();
+        let e = hooks.on_frame(&mut HookApi::new(&mut chip())).unwrap_err();
+        assert_eq!(e.message, "ran past the budget of 1000 operations a frame");
+        assert_eq!(e.line, Some(2));
+
+        // Shared by the calls of a frame, and renewed by the next one.
+        let mut hooks = ScriptHooks::new("fn on_instruction(pc, opcode) { let x = 0; for i in 0..10 { x += i } }", 100).unwrap();
+        let mut chip = chip();
+        let mut failed = None;
+        for n in 0..100 {
+            if let Err(HookError { message, .. }) = hooks.on_instruction(0x200, 0x1202) {
+                failed = Some((n, message));
+                break;
+            }
+        }
+        assert!(matches!(failed, Some((n, _)) if n > 1), "{:?}", failed);
+        hooks.on_frame(&mut HookApi::new(&mut chip)).unwrap();
+        hooks.on_instruction(0x200, 0x1202).unwrap();
+    }
+
+    #[test]
+    fn read_file() {
+        let hooks = ScriptHooks::read(std::path::Path::new("tests/scripts/press_5.rhai"), DEFAULT_BUDGET).unwrap();
+        assert!(hooks.defined.iter().all(|&d| d));
+        let e = ScriptHooks::read(std::path::Path::new("tests/scripts/no_such.rhai"), DEFAULT_BUDGET).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,191 @@
+// Frame snapshots for tests: the screen as golden::render() draws it, kept
+// in a text file under tests/ so a change to drawing shows up as a text
+// diff in review.
+//
+//   assert_frame_snapshot!(chip, "snapshots/ibm_after_200.txt");
+//
+// takes anything with a get_frame(). On a mismatch the actual frame is
+// written next to the snapshot, with an .actual extension, and the panic
+// message has a unified diff. UPDATE_SNAPSHOTS=1 writes the snapshots that
+// don't match instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::Frame;
+use crate::golden::render;
+
+// Lines of context around each change in a diff.
+const CONTEXT: usize = 2;
+
+macro_rules! assert_frame_snapshot {
+    ($source:expr, $path:expr) => {
+        let path = $crate::snapshot::path($path);
+        if let Err(e) = $crate::snapshot::check($source.get_frame(), &path, $crate::snapshot::updating()) {
+            panic!("{}", e);
+        }
+    };
+}
+pub(crate) use assert_frame_snapshot;
+
+// Snapshot paths are relative to tests/.
+pub(crate) fn path(relative: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(relative)
+}
+
+pub(crate) fn updating() -> bool {
+    std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|v| v == "1")
+}
+
+fn actual_path(path: &Path) -> PathBuf {
+    path.with_extension("actual")
+}
+
+// Compares `frame` with the snapshot at `path`, or writes it there when
+// `update` is set.
+pub(crate) fn check(frame: &Frame, path: &Path, update: bool) -> Result<(), String> {
+    let actual = render(frame);
+    let expected = fs::read_to_string(path).ok();
+    if expected.as_deref() == Some(actual.as_str()) {
+        let _ = fs::remove_file(actual_path(path));
+        return Ok(());
+    }
+    let write = |to: &Path| {
+        fs::create_dir_all(to.parent().unwrap()).and_then(|_| fs::write(to, &actual))
+            .map_err(|e| format!("can't write {}: {}", to.display(), e))
+    };
+    if update {
+        write(path)?;
+        let _ = fs::remove_file(actual_path(path));
+        return Ok(());
+    }
+    write(&actual_path(path))?;
+    Err(match expected {
+        Some(expected) => format!("frame differs from {}, the actual one is in {}\n{}",
+            path.display(), actual_path(path).display(), unified_diff(&expected, &actual)),
+        None => format!("no snapshot {}, UPDATE_SNAPSHOTS=1 writes it. Actual:\n{}", path.display(), actual),
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Line {
+    Same,
+    Removed,
+    Added,
+}
+
+// The lines of `expected` and `actual` along their longest common
+// subsequence, each with its line numbers in both before it.
+fn diff_lines<'a>(expected: &[&'a str], actual: &[&'a str]) -> Vec<(Line, &'a str, usize, usize)> {
+    let (n, m) = (expected.len(), actual.len());
+    // lcs[i][j]: the common subsequence of expected[i..] and actual[j..].
+    let mut lcs = vec![vec![0_usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            out.push((Line::Same, expected[i], i, j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push((Line::Removed, expected[i], i, j));
+            i += 1;
+        } else {
+            out.push((Line::Added, actual[j], i, j));
+            j += 1;
+        }
+    }
+    out
+}
+
+// A unified diff from `expected` to `actual`, with CONTEXT lines around
+// each hunk.
+pub(crate) fn unified_diff(expected: &str, actual: &str) -> String {
+    let (a, b): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    let lines = diff_lines(&a, &b);
+    let changes: Vec<usize> = (0..lines.len()).filter(|&k| lines[k].0 != Line::Same).collect();
+    let mut out = String::from("--- expected\n+++ actual\n");
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(CONTEXT);
+        let mut end = (changes[k] + CONTEXT + 1).min(lines.len());
+        while k + 1 < changes.len() && changes[k + 1] <= end + CONTEXT {
+            k += 1;
+            end = (changes[k] + CONTEXT + 1).min(lines.len());
+        }
+        k += 1;
+        let hunk = &lines[start..end];
+        let old = hunk.iter().filter(|l| l.0 != Line::Added).count();
+        let new = hunk.iter().filter(|l| l.0 != Line::Removed).count();
+        // An empty side is numbered by the line before it.
+        let (i, j) = (hunk[0].2, hunk[0].3);
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", i + (old > 0) as usize, old, j + (new > 0) as usize, new));
+        for (kind, text, _, _) in hunk {
+            let mark = match kind {
+                Line::Same => ' ',
+                Line::Removed => '-',
+                Line::Added => '+',
+            };
+            out.push(mark);
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{actual_path, check, unified_diff};
+    use crate::framebuffer::Framebuffer;
+
+    #[test]
+    fn diff() {
+        let expected = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let actual = "a\nb\nC\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(unified_diff(expected, actual), "--- expected\n+++ actual\n\
+            @@ -1,5 +1,5 @@\n a\n b\n-c\n+C\n d\n e\n\
+            @@ -9,2 +9,3 @@\n i\n j\n+k\n");
+        // Changes close together share a hunk.
+        assert_eq!(unified_diff("a\nb\nc\nd\n", "x\nb\nc\ny\n"), "--- expected\n+++ actual\n\
+            @@ -1,4 +1,4 @@\n-a\n+x\n b\n c\n-d\n+y\n");
+        assert_eq!(unified_diff("", "a\n"), "--- expected\n+++ actual\n@@ -0,0 +1,1 @@\n+a\n");
+        assert_eq!(unified_diff("a\n", "a\n"), "--- expected\n+++ actual\n");
+    }
+
+    #[test]
+    fn update_and_compare() {
+        let dir = std::env::temp_dir().join(format!("chip8-snapshot-{}", std::process::id()));
+        let path = dir.join("dot.txt");
+        let mut d = Framebuffer::with_size(8, 2);
+        d.set_pixel(1, 0, true);
+
+        // No snapshot yet: fails, and leaves the actual frame next to it.
+        let e = check(d.get_frame(), &path, false).unwrap_err();
+        assert!(e.contains("UPDATE_SNAPSHOTS=1"));
+        assert_eq!(fs::read_to_string(actual_path(&path)).unwrap(), ".#......\n........\n");
+        assert!(!path.exists());
+
+        check(d.get_frame(), &path, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), ".#......\n........\n");
+        assert!(!actual_path(&path).exists());
+        check(d.get_frame(), &path, false).unwrap();
+
+        d.set_pixel(7, 1, true);
+        let e = check(d.get_frame(), &path, false).unwrap_err();
+        assert!(e.contains("@@ -1,2 +1,2 @@\n .#......\n-........\n+.......#\n"), "{}", e);
+        assert_eq!(fs::read_to_string(actual_path(&path)).unwrap(), ".#......\n.......#\n");
+        // The snapshot stays as it was until it's updated.
+        assert_eq!(fs::read_to_string(&path).unwrap(), ".#......\n........\n");
+        check(d.get_frame(), &path, true).unwrap();
+        check(d.get_frame(), &path, false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
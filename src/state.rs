@@ -0,0 +1,121 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::profile::Profile;
+
+// The whole machine as plain data, for tools outside the emulator. With the
+// serde feature it goes through any serde format. Chip::save_state() is the
+// compact byte form of the same, without the profile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChipState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+    pub stack: [u16; 16],
+    // See Keypad::to_bytes(), 0 for a key up, else its place in the press
+    // order.
+    pub keys: [u8; 16],
+    pub ram: Vec<u8>,
+    pub display_width: u32,
+    pub display_height: u32,
+    // A byte per pixel, row by row, 1 for on.
+    pub pixels: Vec<u8>,
+    // oorandom's state and increment.
+    pub rng: (u64, u64),
+    // The quirks and the arch the state was taken with.
+    pub profile: Profile,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::Arch;
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::profile::{Profile, ProfileBuilder};
+
+    fn busy_chip() -> Chip {
+        let mut chip = Chip::builder().profile(Profile::original()).seed(7).rom(&[
+            0xC0, 0xFF, // RND V0, 0xFF
+            0xA2, 0x0A, // LD I, 0x20A
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x22, 0x0A, // CALL 0x20A
+            0x00, 0x00,
+            0xF0, 0x15, // LD DT, V0
+        ]).build().unwrap();
+        chip.key_press(0xA);
+        for _ in 0..5 {
+            chip.cycle().unwrap();
+        }
+        chip
+    }
+
+    #[test]
+    fn export_import() {
+        let chip = busy_chip();
+        let state = chip.export_state();
+        assert_eq!((state.pc, state.sp, state.stack[0]), (0x20C, 1, 0x208));
+        assert_eq!(state.keys[0xA], 1);
+        assert_eq!(state.pixels.len(), 64 * 32);
+        assert_eq!(state.profile, Profile::original());
+
+        // Onto a machine with another seed and profile.
+        let mut other = Chip::builder().profile(Profile::modern()).seed(1).build().unwrap();
+        other.import_state(state.clone()).unwrap();
+        assert_eq!(other.export_state(), state);
+        assert_eq!(other.save_state(), chip.save_state());
+    }
+
+    #[test]
+    fn import_checks_sizes() {
+        let state = busy_chip().export_state();
+        let arch = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        let mut big = Chip::new_seed(0, ProfileBuilder::new(Profile::original()).arch(arch).build());
+        let before = big.save_state();
+        assert_eq!(big.import_state(state.clone()),
+            Err(ChipError::InvalidState("RAM of 4096 bytes, the machine has 65536".to_string())));
+        assert_eq!(big.save_state(), before);
+
+        let mut chip = Chip::new_seed(0, Profile::original());
+        let mut bad = state.clone();
+        bad.pixels.pop();
+        assert!(chip.import_state(bad).is_err());
+        let mut bad = state;
+        bad.sp = 17;
+        assert!(chip.import_state(bad).is_err());
+    }
+
+    #[test]
+    fn import_checks_profile() {
+        let mut chip = busy_chip();
+        let before = chip.save_state();
+        let mut bad = chip.export_state();
+        bad.profile = ProfileBuilder::new(Profile::original()).stack_in_ram(Some(0xFF0)).build();
+        assert_eq!(chip.import_state(bad),
+            Err(ChipError::InvalidProfile("stack at 0x0ff0 runs past 0x1000".to_string())));
+        assert_eq!(chip.save_state(), before);
+        assert_eq!(chip.instructions(), 5);
+
+        // What the old run counted goes with it.
+        let state = chip.export_state();
+        chip.import_state(state).unwrap();
+        assert_eq!(chip.instructions(), 0);
+        assert!(chip.recent_pcs().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let state = busy_chip().export_state();
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<super::ChipState>(&json).unwrap(), state);
+        let bytes = bincode::serialize(&state).unwrap();
+        let back: super::ChipState = bincode::deserialize(&bytes).unwrap();
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        chip.import_state(back).unwrap();
+        assert_eq!(chip.save_state(), busy_chip().save_state());
+    }
+}
@@ -0,0 +1,415 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::arch::Arch;
+use crate::chip::Chip;
+use crate::error::ChipError;
+use crate::profile::Profile;
+use crate::util::{self, ByteReader};
+
+// The container of save state and replay files:
+//
+//   magic, major and minor version, header length (u16), header,
+//   body length (u32), body, FNV-1a 64 of the body
+//
+// with every multi-byte field little-endian, as in what they hold.
+//
+// Minor versions only append header fields, so a reader skips the ones it
+// doesn't know. Nothing defaults the ones an older file lacks: a decoder
+// reads a field a later minor version added only when the file's version
+// has it. A new major version is a layout this code can't read.
+pub(crate) fn pack(magic: &[u8; 4], version: (u8, u8), header: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.extend_from_slice(&[version.0, version.1]);
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&util::fnv1a64(body).to_le_bytes());
+    out
+}
+
+pub(crate) struct Container<'a> {
+    pub(crate) version: (u8, u8),
+    pub(crate) header: &'a [u8],
+    pub(crate) body: &'a [u8],
+}
+
+// Checks the magic, the major version and the checksum.
+pub(crate) fn unpack<'a>(magic: &[u8; 4], major: u8, data: &'a [u8]) -> Result<Container<'a>, ChipError> {
+    let truncated = || ChipError::InvalidState("truncated file".to_string());
+    let mut r = ByteReader::new(data);
+    if r.bytes(magic.len()) != Some(magic.as_slice()) {
+        return Err(ChipError::NotAStateFile);
+    }
+    let version = (r.u8().ok_or_else(truncated)?, r.u8().ok_or_else(truncated)?);
+    if version.0 != major {
+        return Err(ChipError::UnsupportedStateVersion { major: version.0, minor: version.1 });
+    }
+    let len = r.u16().ok_or_else(truncated)? as usize;
+    let header = r.bytes(len).ok_or_else(truncated)?;
+    let len = r.u32().ok_or_else(truncated)? as usize;
+    let body = r.bytes(len).ok_or_else(truncated)?;
+    if r.u64().ok_or_else(truncated)? != util::fnv1a64(body) {
+        return Err(ChipError::StateChecksum);
+    }
+    Ok(Container { version, header, body })
+}
+
+// A save state file holds Chip::save_state().
+const MAGIC: &[u8; 4] = b"C8SF";
+// 2.0 went from big-endian to little-endian fields. 1.1 had added the
+// creation time.
+pub const MAJOR: u8 = 2;
+pub const MINOR: u8 = 0;
+
+// What a state file says about the state it holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: (u8, u8),
+    // None when the state wasn't saved for a ROM file, it goes with any.
+    pub rom_hash: Option<u64>,
+    // See Profile::name().
+    pub profile: String,
+    pub arch: Arch,
+    // Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl Header {
+    // A header of the current version, created now.
+    pub fn new(rom: Option<&[u8]>, profile: &Profile) -> Header {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Header {
+            version: (MAJOR, MINOR),
+            rom_hash: rom.map(util::fnv1a64),
+            profile: profile.name().to_string(),
+            arch: profile.arch(),
+            created: now,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.rom_hash.unwrap_or(0).to_le_bytes().to_vec();
+        out.extend_from_slice(&self.arch.ram_size.to_le_bytes());
+        out.extend_from_slice(&self.arch.display_width.to_le_bytes());
+        out.extend_from_slice(&self.arch.display_height.to_le_bytes());
+        out.push(self.profile.len() as u8);
+        out.extend_from_slice(self.profile.as_bytes());
+        out.extend_from_slice(&self.created.to_le_bytes());
+        out
+    }
+
+    fn decode(version: (u8, u8), data: &[u8]) -> Option<Header> {
+        let mut r = ByteReader::new(data);
+        let rom_hash = r.u64()?;
+        let arch = Arch { ram_size: r.u32()?, display_width: r.u32()?, display_height: r.u32()? };
+        let len = r.u8()? as usize;
+        let profile = String::from_utf8_lossy(r.bytes(len)?).into_owned();
+        let created = r.u64()?;
+        Some(Header { version, rom_hash: (rom_hash != 0).then_some(rom_hash), profile, arch, created })
+    }
+}
+
+// How a state file fits the machine and the ROM it is loaded into.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Compatibility {
+    // Saved for another ROM file. Refused unless forced.
+    pub other_rom: bool,
+    // RAM or screen size differences, the state doesn't fit. Always refused.
+    pub layout: Vec<String>,
+    // Instructions would execute differently from where the state was
+    // saved. Loads with a warning.
+    pub quirks: Vec<String>,
+}
+
+impl Compatibility {
+    pub fn is_exact(&self) -> bool {
+        *self == Compatibility::default()
+    }
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items: Vec<String> = Vec::new();
+        if self.other_rom {
+            items.push("saved for another ROM".to_string());
+        }
+        items.extend(self.layout.iter().cloned());
+        if !self.quirks.is_empty() {
+            items.push(format!("quirks differ: {}", self.quirks.join(", ")));
+        }
+        write!(f, "{}", items.join("; "))
+    }
+}
+
+// A decoded state file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateFile {
+    pub header: Header,
+    pub state: Vec<u8>,
+}
+
+impl StateFile {
+    pub fn is_for_rom(&self, rom: &[u8]) -> bool {
+        self.header.rom_hash.is_none_or(|h| h == util::fnv1a64(rom))
+    }
+
+    // Compares the state with a machine of `profile` running `rom`, None
+    // for no ROM check.
+    pub fn compatibility(&self, profile: &Profile, rom: Option<&[u8]>) -> Compatibility {
+        let (saved, arch) = (self.header.arch, profile.arch());
+        let mut layout = Vec::new();
+        if saved.ram_size != arch.ram_size {
+            layout.push(format!("RAM of {} bytes, this machine has {}", saved.ram_size, arch.ram_size));
+        }
+        if (saved.display_width, saved.display_height) != (arch.display_width, arch.display_height) {
+            layout.push(format!("screen of {}x{}, this machine has {}x{}",
+                saved.display_width, saved.display_height, arch.display_width, arch.display_height));
+        }
+        // The file names the profile, the quirks of a named one are known.
+        let quirks = match Profile::from_name(&self.header.profile) {
+            Ok(p) => p.quirk_differences(profile).into_iter().map(str::to_string).collect(),
+            Err(_) if profile.name() != "custom" => vec![format!("saved with a {} profile", self.header.profile)],
+            Err(_) => Vec::new(),
+        };
+        Compatibility { other_rom: rom.is_some_and(|rom| !self.is_for_rom(rom)), layout, quirks }
+    }
+
+    // Loads the state into the machine, when it was saved for `rom` and fits
+    // the machine's RAM and screen. Other quirks are only warned about. The
+    // machine is left untouched on errors.
+    pub fn load_into(&self, chip: &mut Chip, rom: Option<&[u8]>) -> Result<(), ChipError> {
+        let c = self.compatibility(chip.profile(), rom);
+        if !c.layout.is_empty() {
+            return Err(ChipError::InvalidState(c.to_string()));
+        }
+        if c.other_rom {
+            return Err(ChipError::StateForOtherRom);
+        }
+        if !c.quirks.is_empty() {
+            warn!("The state doesn't match the machine exactly, {}", c);
+        }
+        chip.load_state(&self.state)
+    }
+}
+
+pub fn encode(header: &Header, state: &[u8]) -> Vec<u8> {
+    pack(MAGIC, header.version, &header.encode(), state)
+}
+
+pub fn decode(data: &[u8]) -> Result<StateFile, ChipError> {
+    let c = unpack(MAGIC, MAJOR, data)?;
+    let header = Header::decode(c.version, c.header).ok_or_else(|| ChipError::InvalidState("truncated file".to_string()))?;
+    Ok(StateFile { header, state: c.body.to_vec() })
+}
+
+// Writes the machine to `path`, tied to the ROM if given. The file is written
+// under another name first and renamed, so a crash never leaves half a state
+// behind.
+pub fn write(path: &Path, chip: &Chip, rom: Option<&[u8]>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, encode(&Header::new(rom, chip.profile()), &chip.save_state()))?;
+    fs::rename(&tmp, path)
+}
+
+pub fn read(path: &Path) -> io::Result<StateFile> {
+    Ok(decode(&fs::read(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, read, Compatibility, Header, MAJOR};
+    use crate::arch::Arch;
+    use crate::chip::Chip;
+    use crate::error::ChipError;
+    use crate::profile::Profile;
+
+    const ROM: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    pub(super) const FIXTURE_ROM: [u8; 48] = [
+        0xA2, 0x20, // LD I, 0x220
+        0xFF, 0x65, // LD V0..VF, [I]
+        0xF1, 0x15, // LD DT, V1
+        0xF2, 0x18, // LD ST, V2
+        0x22, 0x0C, // CALL 0x20C
+        0x00, 0x00,
+        0x22, 0x10, // CALL 0x210
+        0x00, 0x00,
+        0xF5, 0x29, // LD F, V5
+        0xD3, 0x45, // DRW V3, V4, 5
+        0xA2, 0x34, // LD I, 0x234
+        0xC6, 0xFF, // RND V6, 0xFF
+        0x12, 0x18, // JP 0x218
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // V0 to VF
+        0x10, 0x3C, 0x1E, 0x08, 0x04, 0x0A, 0x00, 0x77,
+        0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+    ];
+
+    pub(super) fn fixture_chip() -> Chip {
+        let mut chip = Chip::builder().profile(Profile::modern()).seed(0x0123_4567_89AB_CDEF).rom(&FIXTURE_ROM).build().unwrap();
+        chip.key_press(0x3);
+        chip.key_press(0xA);
+        for _ in 0..12 {
+            chip.step().unwrap();
+        }
+        chip
+    }
+
+    fn chip() -> Chip {
+        let mut chip = Chip::builder().profile(Profile::original()).seed(2).rom(&ROM).build().unwrap();
+        for _ in 0..7 {
+            chip.step().unwrap();
+        }
+        chip
+    }
+
+    fn header() -> Header {
+        Header {
+            version: (2, 0),
+            rom_hash: Some(crate::util::fnv1a64(&ROM)),
+            profile: "original".to_string(),
+            arch: Arch::chip8(),
+            created: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let chip = chip();
+        let data = encode(&header(), &chip.save_state());
+        let file = decode(&data).unwrap();
+        assert_eq!(file.header, header());
+        let mut other = Chip::new_seed(0, Profile::original());
+        file.load_into(&mut other, Some(&ROM)).unwrap();
+        assert_eq!(other.state_hash(), chip.state_hash());
+
+        let fresh = Header::new(None, &Profile::chip48());
+        assert_eq!((fresh.version, fresh.rom_hash, fresh.profile.as_str()), ((2, 0), None, "chip48"));
+        assert!(fresh.created > 1_700_000_000);
+        assert!(decode(&encode(&fresh, &[])).unwrap().is_for_rom(&ROM));
+    }
+
+    #[test]
+    fn rejections() {
+        let chip = chip();
+        let data = encode(&header(), &chip.save_state());
+
+        assert_eq!(decode(b"C8ST0000"), Err(ChipError::NotAStateFile));
+        let mut newer = data.clone();
+        newer[4] = MAJOR + 1;
+        assert_eq!(decode(&newer), Err(ChipError::UnsupportedStateVersion { major: 3, minor: 0 }));
+        assert_eq!(decode(&newer).unwrap_err().to_string(), "file format 3.0 is not supported");
+        let mut damaged = data.clone();
+        damaged[100] ^= 1;
+        assert_eq!(decode(&damaged), Err(ChipError::StateChecksum));
+        assert_eq!(decode(&data[..data.len() - 1]), Err(ChipError::InvalidState("truncated file".to_string())));
+
+        let mut other = Chip::new_seed(0, Profile::original());
+        let before = other.save_state();
+        let file = decode(&data).unwrap();
+        assert_eq!(file.load_into(&mut other, Some(&[0x12, 0x00])), Err(ChipError::StateForOtherRom));
+        let big = Arch { ram_size: 0x10000, ..Arch::chip8() };
+        let mut wrong_arch = Header { arch: big, ..header() };
+        wrong_arch.profile = "custom".to_string();
+        assert!(matches!(decode(&encode(&wrong_arch, &chip.save_state())).unwrap().load_into(&mut other, None),
+            Err(ChipError::InvalidState(_))));
+        assert_eq!(other.save_state(), before);
+    }
+
+    #[test]
+    fn compatibility() {
+        let state = chip().save_state();
+        let file = |header: Header| decode(&encode(&header, &state)).unwrap();
+        let original = Profile::original();
+        assert!(file(header()).compatibility(&original, Some(&ROM)).is_exact());
+
+        // Another ROM: refused.
+        let c = file(header()).compatibility(&original, Some(&[0x12, 0x00]));
+        assert_eq!(c, Compatibility { other_rom: true, ..Default::default() });
+        assert_eq!(c.to_string(), "saved for another ROM");
+
+        // Another layout: refused, with every difference listed.
+        let big = Arch { ram_size: 0x10000, display_width: 128, display_height: 64 };
+        let c = file(Header { arch: big, ..header() }).compatibility(&original, None);
+        assert_eq!(c.layout, ["RAM of 65536 bytes, this machine has 4096", "screen of 128x64, this machine has 64x32"]);
+        let mut other = Chip::new_seed(0, original);
+        assert_eq!(file(Header { arch: big, ..header() }).load_into(&mut other, None), Err(ChipError::InvalidState(
+            "RAM of 65536 bytes, this machine has 4096; screen of 128x64, this machine has 64x32".to_string())));
+
+        // Other quirks: loads.
+        let modern = Profile::modern();
+        let c = file(header()).compatibility(&modern, Some(&ROM));
+        assert_eq!(c.quirks, ["8XY6 shifts VY", "8XYE shifts VY", "FX55 moves I", "FX65 moves I"]);
+        assert_eq!(c.to_string(), "quirks differ: 8XY6 shifts VY, 8XYE shifts VY, FX55 moves I, FX65 moves I");
+        let mut other = Chip::new_seed(0, modern);
+        file(header()).load_into(&mut other, Some(&ROM)).unwrap();
+        assert_eq!(other.state_hash(), chip().state_hash());
+        let custom = Header { profile: "custom".to_string(), ..header() };
+        assert_eq!(file(custom.clone()).compatibility(&modern, None).quirks, ["saved with a custom profile"]);
+        let nop = Profile::builder().nop(Some(0)).build();
+        assert!(file(custom).compatibility(&nop, None).is_exact());
+    }
+
+    #[test]
+    fn older_major() {
+        // 1.x was big-endian.
+        let mut old = encode(&header(), &chip().save_state());
+        old[4..6].copy_from_slice(&[1, 1]);
+        assert_eq!(decode(&old), Err(ChipError::UnsupportedStateVersion { major: 1, minor: 1 }));
+    }
+
+    #[test]
+    fn newer_minor_fixture() {
+        // Written as version 2.9, with header fields this version doesn't
+        // know after the creation time.
+        let file = read(std::path::Path::new("rom/fixtures/states/minor_2_9.state")).unwrap();
+        assert_eq!(file.header, Header { version: (2, 9), ..header() });
+        let mut other = Chip::new_seed(0, Profile::original());
+        file.load_into(&mut other, Some(&ROM)).unwrap();
+        assert_eq!(other.state_hash(), chip().state_hash());
+    }
+
+    #[test]
+    fn fields_fixture() {
+        // Written on x86-64 from fixture_chip(). Every multi-byte field is
+        // little-endian and fixed width, so it reads the same on any host.
+        let data = std::fs::read("rom/fixtures/states/fields.state").unwrap();
+        let file = decode(&data).unwrap();
+        assert_eq!(file.header, Header {
+            version: (2, 0),
+            rom_hash: Some(crate::util::fnv1a64(&FIXTURE_ROM)),
+            profile: "modern".to_string(),
+            arch: Arch::chip8(),
+            created: 1_760_000_000,
+        });
+
+        let mut chip = Chip::new_seed(0, Profile::modern());
+        file.load_into(&mut chip, Some(&FIXTURE_ROM)).unwrap();
+        let s = chip.export_state();
+        assert_eq!(s.v, [0x10, 0x3C, 0x1E, 0x08, 0x04, 0x0A, 0x51, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x00]);
+        assert_eq!((s.i, s.pc, s.sp, s.dt, s.st), (0x234, 0x218, 2, 0x3C, 0x1E));
+        assert_eq!(s.stack[..3], [0x20A, 0x20E, 0]);
+        let down: Vec<usize> = (0..16).filter(|&k| s.keys[k] != 0).collect();
+        assert_eq!(down, [0x3, 0xA]);
+        assert_eq!((s.keys[0x3], s.keys[0xA]), (1, 2));
+        assert_eq!(s.ram.len(), 0x1000);
+        assert_eq!(s.ram[0x200..0x230], FIXTURE_ROM[..0x30]);
+        assert_eq!(crate::util::fnv1a64(&s.ram), 0x38ba469282b50b57);
+        // The "A" glyph at (8, 4).
+        let rows: Vec<u8> = (4..9).map(|y| (8..16).fold(0, |row, x| row << 1 | s.pixels[y * 64 + x])).collect();
+        assert_eq!(rows, [0x60, 0x90, 0xF0, 0x90, 0x90]);
+        assert_eq!(s.pixels.iter().filter(|&&p| p != 0).count(), 12);
+        assert_eq!(s.rng, (0xa6ae6c5d0a1420d0, 0x280af6fdeecf029f));
+
+        // And the encoder still writes exactly these bytes.
+        assert_eq!(encode(&file.header, &fixture_chip().save_state()), data);
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::Write;
+
+use crate::compat::CompatReport;
+use crate::error::ChipError;
+
+// Frame intervals are counted by the millisecond up to this, longer ones
+// together in the last bucket, so a long run takes no more memory.
+const INTERVAL_BUCKETS: usize = 1000;
+
+// Run statistics collected by the main loop.
+pub struct RunStats {
+    start_ms: u32,
+    end_ms: u32,
+    loop_iterations: u64,
+    instructions: u64,
+    blocked_cycles: u64,
+    last_frame_ms: Option<u32>,
+    // Frames presented after the first, with the sums of their intervals
+    // and of the squares, and how many took each whole millisecond.
+    intervals: u64,
+    interval_sum: u64,
+    interval_squares: u128,
+    interval_histogram: Vec<u64>,
+    error: Option<ChipError>,
+    compat: Option<CompatReport>,
+}
+
+impl RunStats {
+    pub(crate) fn new(start_ms: u32) -> RunStats {
+        RunStats {
+            start_ms,
+            end_ms: start_ms,
+            loop_iterations: 0,
+            instructions: 0,
+            blocked_cycles: 0,
+            last_frame_ms: None,
+            intervals: 0,
+            interval_sum: 0,
+            interval_squares: 0,
+            interval_histogram: vec![0; INTERVAL_BUCKETS],
+            error: None,
+            compat: None,
+        }
+    }
+
+    pub(crate) fn loop_iteration(&mut self) {
+        self.loop_iterations += 1;
+    }
+
+    // `executed` is false when the chip did not get past the current
+    // instruction, e.g. while FX0A waits for a key.
+    pub(crate) fn cycle(&mut self, executed: bool) {
+        if executed {
+            self.instructions += 1;
+        } else {
+            self.blocked_cycles += 1;
+        }
+    }
+
+    pub(crate) fn frame_presented(&mut self, now_ms: u32) {
+        if let Some(last) = self.last_frame_ms {
+            let interval = now_ms - last;
+            self.intervals += 1;
+            self.interval_sum += interval as u64;
+            self.interval_squares += interval as u128 * interval as u128;
+            self.interval_histogram[(interval as usize).min(INTERVAL_BUCKETS - 1)] += 1;
+        }
+        self.last_frame_ms = Some(now_ms);
+    }
+
+    // The run ended because the chip failed.
+    pub(crate) fn stopped_by(&mut self, e: ChipError) {
+        self.error = Some(e);
+    }
+
+    pub fn error(&self) -> Option<&ChipError> {
+        self.error.as_ref()
+    }
+
+    // Printed after the stats.
+    pub(crate) fn set_compat(&mut self, report: CompatReport) {
+        self.compat = Some(report);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn compat(&self) -> Option<&CompatReport> {
+        self.compat.as_ref()
+    }
+
+    pub(crate) fn finish(&mut self, end_ms: u32) {
+        self.end_ms = end_ms;
+    }
+
+    pub fn duration_ms(&self) -> u32 {
+        self.end_ms - self.start_ms
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn frames(&self) -> u64 {
+        match self.last_frame_ms {
+            Some(_) => self.intervals + 1,
+            None => 0,
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        1000.0 * self.instructions as f64 / self.duration_ms().max(1) as f64
+    }
+
+    pub fn avg_frame_interval(&self) -> Option<f64> {
+        if self.intervals == 0 {
+            return None;
+        }
+        Some(self.interval_sum as f64 / self.intervals as f64)
+    }
+
+    // Nearest-rank 95th percentile. Intervals past the histogram count as
+    // its last bucket.
+    pub fn p95_frame_interval(&self) -> Option<u32> {
+        if self.intervals == 0 {
+            return None;
+        }
+        let rank = (self.intervals * 95).div_ceil(100).max(1);
+        let mut seen = 0;
+        self.interval_histogram.iter().position(|&n| {
+            seen += n;
+            seen >= rank
+        }).map(|ms| ms as u32)
+    }
+
+    // Standard deviation of the frame interval.
+    pub fn jitter(&self) -> Option<f64> {
+        let avg = self.avg_frame_interval()?;
+        let var = self.interval_squares as f64 / self.intervals as f64 - avg * avg;
+        Some(var.max(0.0).sqrt())
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "Stats.").unwrap();
+        writeln!(out, "Execution time: {} ms", self.duration_ms()).unwrap();
+        writeln!(out, "Loop iterations: {}", self.loop_iterations).unwrap();
+        writeln!(out, "Instructions: {}", self.instructions).unwrap();
+        writeln!(out, "Instructions per second: {:.1}", self.instructions_per_second()).unwrap();
+        writeln!(out, "Blocked cycles: {}", self.blocked_cycles).unwrap();
+        writeln!(out, "Frames presented: {}", self.frames()).unwrap();
+        if let (Some(avg), Some(p95), Some(jitter)) = (self.avg_frame_interval(), self.p95_frame_interval(), self.jitter()) {
+            writeln!(out, "Frame interval: avg {:.2} ms, p95 {} ms, jitter {:.2} ms", avg, p95, jitter).unwrap();
+        }
+        if let Some(e) = &self.error {
+            writeln!(out, "Stopped by error: {}", e).unwrap();
+        }
+        if let Some(c) = &self.compat {
+            write!(out, "{}", c).unwrap();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RunStats, INTERVAL_BUCKETS};
+    use crate::error::ChipError;
+
+    #[test]
+    fn counters() {
+        let mut s = RunStats::new(1000);
+        for i in 0..10 {
+            s.loop_iteration();
+            s.cycle(i % 5 != 0);
+        }
+        s.loop_iteration();
+        s.finish(1500);
+
+        assert_eq!(s.duration_ms(), 500);
+        assert_eq!(s.instructions, 8);
+        assert_eq!(s.blocked_cycles, 2);
+        assert_eq!(s.loop_iterations, 11);
+        assert_eq!(s.instructions_per_second(), 16.0);
+    }
+
+    #[test]
+    fn no_frames() {
+        let s = RunStats::new(0);
+        assert_eq!(s.frames(), 0);
+        assert_eq!(s.avg_frame_interval(), None);
+        assert_eq!(s.p95_frame_interval(), None);
+        assert_eq!(s.jitter(), None);
+        assert!(!s.report().contains("Frame interval"));
+    }
+
+    #[test]
+    fn frame_intervals() {
+        let mut s = RunStats::new(0);
+        let mut now = 0;
+        // 19 intervals of 16 ms and one of 36 ms.
+        s.frame_presented(now);
+        for i in 0..20 {
+            now += if i == 10 { 36 } else { 16 };
+            s.frame_presented(now);
+        }
+
+        assert_eq!(s.frames(), 21);
+        assert_eq!(s.avg_frame_interval(), Some(17.0));
+        assert_eq!(s.p95_frame_interval(), Some(16));
+        let jitter = s.jitter().unwrap();
+        assert!((jitter - 19.0_f64.sqrt()).abs() < 1e-9);
+
+        s.frame_presented(now + 40);
+        assert_eq!(s.p95_frame_interval(), Some(36));
+
+        // A stall longer than the histogram.
+        let mut s = RunStats::new(0);
+        s.frame_presented(0);
+        s.frame_presented(5000);
+        assert_eq!(s.avg_frame_interval(), Some(5000.0));
+        assert_eq!(s.p95_frame_interval(), Some(INTERVAL_BUCKETS as u32 - 1));
+    }
+
+    #[test]
+    fn report() {
+        let mut s = RunStats::new(0);
+        s.cycle(true);
+        s.cycle(false);
+        s.frame_presented(0);
+        s.frame_presented(17);
+        s.finish(1000);
+
+        let r = s.report();
+        assert!(r.contains("Instructions: 1\n"));
+        assert!(r.contains("Blocked cycles: 1\n"));
+        assert!(r.contains("Frames presented: 2\n"));
+        assert!(r.contains("Frame interval: avg 17.00 ms, p95 17 ms, jitter 0.00 ms\n"));
+        assert!(!r.contains("No frame cycles"));
+        assert!(!r.contains("Stopped by error"));
+
+        s.stopped_by(ChipError::StackUnderflow { pc: 0x20A });
+        assert!(s.report().contains("Stopped by error: stack underflow at 0x20a\n"));
+    }
+}
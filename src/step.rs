@@ -0,0 +1,226 @@
+use std::fmt;
+
+use crate::instr::Operation;
+use crate::keypad::Keypad;
+use crate::profile::Profile;
+use crate::regs::RegMap;
+
+// What a single executed instruction did, for educational stepping.
+pub struct StepResult {
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub description: String,
+    pub changes: Vec<StateChange>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateChange {
+    V { x: u8, old: u8, new: u8 },
+    I { old: u16, new: u16 },
+    // Only reported when the instruction did not simply fall through.
+    Pc { old: u16, new: u16 },
+    Sp { old: u8, new: u8 },
+    Dt { old: u8, new: u8 },
+    St { old: u8, new: u8 },
+    Stack { level: u8, old: u16, new: u16 },
+    Mem { addr: u16, old: u8, new: u8 },
+    Screen,
+}
+
+impl fmt::Display for StateChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateChange::V { x, old, new } => write!(f, "V{:X}: {:#04x} -> {:#04x}", x, old, new),
+            StateChange::I { old, new } => write!(f, "I: {:#05x} -> {:#05x}", old, new),
+            StateChange::Pc { old, new } => write!(f, "PC: {:#05x} -> {:#05x}", old, new),
+            StateChange::Sp { old, new } => write!(f, "SP: {} -> {}", old, new),
+            StateChange::Dt { old, new } => write!(f, "DT: {} -> {}", old, new),
+            StateChange::St { old, new } => write!(f, "ST: {} -> {}", old, new),
+            StateChange::Stack { level, old, new } => write!(f, "stack[{}]: {:#05x} -> {:#05x}", level, old, new),
+            StateChange::Mem { addr, old, new } => write!(f, "[{:#05x}]: {:#04x} -> {:#04x}", addr, old, new),
+            StateChange::Screen => write!(f, "screen updated"),
+        }
+    }
+}
+
+// Differences between two save states, see Chip::diff_state().
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub changes: Vec<StateChange>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.changes {
+            writeln!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+fn skip(skipped: bool) -> &'static str {
+    if skipped { "skip next instruction" } else { "do not skip next instruction" }
+}
+
+// Plain-English description of `op`, given the machine state before it runs.
+pub fn describe(op: &Operation, regs: &RegMap, keys: &Keypad, profile: &Profile) -> String {
+    let v = |x: u8| regs.vx[x];
+
+    match *op {
+        Operation::Cls => "clear the screen".to_string(),
+        Operation::Ret => "return from the current subroutine".to_string(),
+        Operation::Jp { nnn } => format!("jump to {:#05x}", nnn),
+        Operation::Call { nnn } => format!("call the subroutine at {:#05x}", nnn),
+        Operation::SeVxNn { x, nn } => {
+            if v(x) == nn {
+                format!("{} because V{:X} equals {:#04x}", skip(true), x, nn)
+            } else {
+                format!("{} because V{:X} ({:#04x}) does not equal {:#04x}", skip(false), x, v(x), nn)
+            }
+        },
+        Operation::SneVxNn { x, nn } => {
+            if v(x) != nn {
+                format!("{} because V{:X} ({:#04x}) does not equal {:#04x}", skip(true), x, v(x), nn)
+            } else {
+                format!("{} because V{:X} equals {:#04x}", skip(false), x, nn)
+            }
+        },
+        Operation::SeVxVy { x, y } => {
+            if v(x) == v(y) {
+                format!("{} because V{:X} equals V{:X} ({:#04x})", skip(true), x, y, v(x))
+            } else {
+                format!("{} because V{:X} ({:#04x}) does not equal V{:X} ({:#04x})", skip(false), x, v(x), y, v(y))
+            }
+        },
+        Operation::SneVxVy { x, y } => {
+            if v(x) != v(y) {
+                format!("{} because V{:X} ({:#04x}) does not equal V{:X} ({:#04x})", skip(true), x, v(x), y, v(y))
+            } else {
+                format!("{} because V{:X} equals V{:X} ({:#04x})", skip(false), x, y, v(x))
+            }
+        },
+        Operation::LdVxNn { x, nn } => format!("set V{:X} to {:#04x}", x, nn),
+        Operation::AddVxNn { x, nn } => {
+            format!("add {:#04x} to V{:X} ({:#04x}), giving {:#04x}", nn, x, v(x), v(x).wrapping_add(nn))
+        },
+        Operation::LdVxVy { x, y } => format!("copy V{:X} ({:#04x}) into V{:X}", y, v(y), x),
+        Operation::OrVxVy { x, y } => {
+            format!("set V{:X} to V{:X} ({:#04x}) OR V{:X} ({:#04x}), giving {:#04x}", x, x, v(x), y, v(y), v(x) | v(y))
+        },
+        Operation::AndVxVy { x, y } => {
+            format!("set V{:X} to V{:X} ({:#04x}) AND V{:X} ({:#04x}), giving {:#04x}", x, x, v(x), y, v(y), v(x) & v(y))
+        },
+        Operation::XorVxVy { x, y } => {
+            format!("set V{:X} to V{:X} ({:#04x}) XOR V{:X} ({:#04x}), giving {:#04x}", x, x, v(x), y, v(y), v(x) ^ v(y))
+        },
+        Operation::AddVxVy { x, y } => {
+            let (sum, carry) = v(x).overflowing_add(v(y));
+            format!("add V{:X} ({:#04x}) to V{:X} ({:#04x}), giving {:#04x}, VF = {} ({})",
+                y, v(y), x, v(x), sum, carry as u8, if carry { "carry" } else { "no carry" })
+        },
+        Operation::SubVxVy { x, y } => {
+            let (diff, borrow) = v(x).overflowing_sub(v(y));
+            format!("subtract V{:X} ({:#04x}) from V{:X} ({:#04x}), giving {:#04x}, VF = {} ({})",
+                y, v(y), x, v(x), diff, !borrow as u8, if borrow { "borrow" } else { "no borrow" })
+        },
+        Operation::SubnVxVy { x, y } => {
+            let (diff, borrow) = v(y).overflowing_sub(v(x));
+            format!("set V{:X} to V{:X} ({:#04x}) minus V{:X} ({:#04x}), giving {:#04x}, VF = {} ({})",
+                x, y, v(y), x, v(x), diff, !borrow as u8, if borrow { "borrow" } else { "no borrow" })
+        },
+        Operation::ShrVxVy { x, y } => {
+            let src = if profile.op_8xy6_use_vy { y } else { x };
+            format!("shift V{:X} ({:#04x}) right by one into V{:X}, giving {:#04x}, VF = {} (bit shifted out)",
+                src, v(src), x, v(src) >> 1, v(src) & 0x01)
+        },
+        Operation::ShlVxVy { x, y } => {
+            let src = if profile.op_8xye_use_vy { y } else { x };
+            format!("shift V{:X} ({:#04x}) left by one into V{:X}, giving {:#04x}, VF = {} (bit shifted out)",
+                src, v(src), x, v(src) << 1, v(src) >> 7)
+        },
+        Operation::LdINnn { nnn } => format!("set I to {:#05x}", nnn),
+        Operation::JpV0Nnn { nnn } => {
+            let x = if profile.op_bnnn_use_vx { (nnn >> 8) as u8 } else { 0 };
+            format!("jump to {:#05x} + V{:X} ({:#04x}) = {:#05x}", nnn, x, v(x), nnn + v(x) as u16)
+        },
+        Operation::RndVxNn { x, nn } => format!("set V{:X} to a random number AND {:#04x}", x, nn),
+        Operation::Drw { x, y, n } => {
+            format!("draw the {}-byte sprite at I ({:#05x}) at ({}, {}) from V{:X} and V{:X}, VF = collision",
+                n, regs.i, v(x), v(y), x, y)
+        },
+        Operation::SkpVx { x } => {
+            let key = v(x) & 0xf;
+            if keys.is_down(key) {
+                format!("{} because key {:X} (V{:X}) is pressed", skip(true), key, x)
+            } else {
+                format!("{} because key {:X} (V{:X}) is not pressed", skip(false), key, x)
+            }
+        },
+        Operation::SkpnVx { x } => {
+            let key = v(x) & 0xf;
+            if !keys.is_down(key) {
+                format!("{} because key {:X} (V{:X}) is not pressed", skip(true), key, x)
+            } else {
+                format!("{} because key {:X} (V{:X}) is pressed", skip(false), key, x)
+            }
+        },
+        Operation::LdVxDt { x } => format!("set V{:X} to the delay timer ({})", x, regs.dt),
+        Operation::LdVxK { x } => {
+            match profile.key_wait.key(keys) {
+                Some(key) => format!("store pressed key {:X} in V{:X}", key, x),
+                None => format!("wait for a key press to store in V{:X}", x),
+            }
+        },
+        Operation::LdDtVx { x } => format!("set the delay timer to V{:X} ({})", x, v(x)),
+        Operation::LdStVx { x } => format!("set the sound timer to V{:X} ({})", x, v(x)),
+        Operation::AddIVx { x } => {
+            format!("add V{:X} ({:#04x}) to I ({:#05x}), giving {:#05x}", x, v(x), regs.i, regs.i.wrapping_add(v(x) as u16))
+        },
+        Operation::LdFVx { x } => format!("point I at the font sprite for digit {:X} (V{:X})", v(x) & 0xf, x),
+        Operation::LdBVx { x } => format!("store the decimal digits of V{:X} ({}) at I ({:#05x})", x, v(x), regs.i),
+        Operation::LdIVx { x } => format!("store V0..V{:X} in memory at I ({:#05x})", x, regs.i),
+        Operation::LdVxI { x } => format!("load V0..V{:X} from memory at I ({:#05x})", x, regs.i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe;
+    use crate::instr::Instr;
+    use crate::keypad::Keypad;
+    use crate::profile::Profile;
+    use crate::regs::RegMap;
+
+    fn describe_opcode(opcode: u16, regs: &RegMap) -> String {
+        let op = Instr::new(opcode).operation().unwrap();
+        describe(&op, regs, &Keypad::new(), &Profile::modern())
+    }
+
+    #[test]
+    fn skip() {
+        let mut regs = RegMap::new();
+        regs.vx[2] = 0x22_u8;
+
+        assert_eq!(describe_opcode(0x3222, &regs), "skip next instruction because V2 equals 0x22");
+        assert_eq!(describe_opcode(0x3223, &regs), "do not skip next instruction because V2 (0x22) does not equal 0x23");
+        assert_eq!(describe_opcode(0x4222, &regs), "do not skip next instruction because V2 equals 0x22");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut regs = RegMap::new();
+        regs.vx[2] = 0xFE_u8;
+        regs.vx[3] = 0x03_u8;
+
+        assert_eq!(describe_opcode(0x8234, &regs), "add V3 (0x03) to V2 (0xfe), giving 0x01, VF = 1 (carry)");
+        assert_eq!(describe_opcode(0x8235, &regs), "subtract V3 (0x03) from V2 (0xfe), giving 0xfb, VF = 1 (no borrow)");
+        assert_eq!(describe_opcode(0x7203, &regs), "add 0x03 to V2 (0xfe), giving 0x01");
+    }
+}
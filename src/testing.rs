@@ -0,0 +1,295 @@
+// Test doubles for the run loop. TestFrontend hands out one event batch per
+// poll. FakeFrontend is built from parts instead, for tests that care about
+// when things happen rather than what is presented:
+//
+//   let mut frontend = FakeFrontend::new(Timeline::new()
+//       .at(100, Event::KeyPress(0x5))
+//       .at(500, Event::Quit));
+//
+// The timeline counts polls, which the loop makes once a frame.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+
+use crate::audio::Sound;
+use crate::chip::Chip;
+use crate::clock::Clock;
+use crate::framebuffer::Frame;
+use crate::playlist::Playlist;
+use crate::profile::Profile;
+use crate::rom;
+use crate::runner::{Event, Frontend, RunOptions};
+
+// Frontend double: hands out scripted events, one batch per poll, and
+// records everything the loop sends. Time only moves when the loop sleeps.
+#[derive(Default)]
+pub(crate) struct TestFrontend {
+    pub(crate) events: VecDeque<Vec<Event>>,
+    // Debugger input, one line per prompt.
+    pub(crate) commands: VecDeque<String>,
+    pub(crate) frames: Vec<Frame>,
+    // Frames presented with an overlay, by index in `frames`.
+    pub(crate) overlays: Vec<(usize, Frame)>,
+    pub(crate) sound: Vec<bool>,
+    pub(crate) sounds: Vec<Sound>,
+    pub(crate) titles: Vec<String>,
+    pub(crate) clock: MockClock,
+}
+
+impl TestFrontend {
+    pub(crate) fn new(events: Vec<Vec<Event>>) -> TestFrontend {
+        TestFrontend {
+            events: events.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Frontend for TestFrontend {
+    // Quits once the script runs out.
+    fn poll_events(&mut self) -> Vec<Event> {
+        self.events.pop_front().unwrap_or_else(|| vec![Event::Quit])
+    }
+
+    fn present(&mut self, frame: &Frame) {
+        self.frames.push(frame.clone());
+    }
+
+    fn present_overlay(&mut self, frame: &Frame, overlay: &Frame) {
+        self.overlays.push((self.frames.len(), overlay.clone()));
+        self.present(frame);
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        self.sound.push(on);
+    }
+
+    fn play_sound(&mut self, sound: Sound) {
+        self.sounds.push(sound);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.titles.push(title.to_string());
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
+    }
+
+    fn read_command(&mut self) -> Option<String> {
+        self.commands.pop_front()
+    }
+}
+
+// Events by the poll they come with, counting from 0. Polls past the last
+// scripted one quit, so a test can't run forever.
+#[derive(Default)]
+pub(crate) struct Timeline {
+    events: BTreeMap<u64, Vec<Event>>,
+    polls: u64,
+}
+
+impl Timeline {
+    pub(crate) fn new() -> Timeline {
+        Timeline::default()
+    }
+
+    pub(crate) fn at(mut self, poll: u64, event: Event) -> Timeline {
+        self.events.entry(poll).or_default().push(event);
+        self
+    }
+
+    pub(crate) fn polls(&self) -> u64 {
+        self.polls
+    }
+
+    fn next(&mut self) -> Vec<Event> {
+        let poll = self.polls;
+        self.polls += 1;
+        match self.events.keys().next_back() {
+            Some(&last) if poll <= last => self.events.remove(&poll).unwrap_or_default(),
+            _ => vec![Event::Quit],
+        }
+    }
+}
+
+// Counts the frames presented and keeps the last one.
+#[derive(Default)]
+pub(crate) struct FrameCounter {
+    pub(crate) presented: usize,
+    pub(crate) overlays: usize,
+    pub(crate) last: Option<Frame>,
+}
+
+impl FrameCounter {
+    fn present(&mut self, frame: &Frame) {
+        self.presented += 1;
+        self.last = Some(frame.clone());
+    }
+}
+
+// Host time that only moves when a test advances it or the loop sleeps,
+// and by `step` microseconds every time it is read, as it would while the
+// loop spins.
+#[derive(Default)]
+pub(crate) struct MockClock {
+    now: Cell<u64>,
+    step: u64,
+    sleeps: Cell<usize>,
+}
+
+impl MockClock {
+    pub(crate) fn with_step(step: u64) -> MockClock {
+        MockClock { step, ..Default::default() }
+    }
+
+    // The time, without moving it on.
+    pub(crate) fn now(&self) -> u64 {
+        self.now.get()
+    }
+
+    pub(crate) fn advance(&self, micros: u64) {
+        self.now.set(self.now.get() + micros);
+    }
+
+    pub(crate) fn sleeps(&self) -> usize {
+        self.sleeps.get()
+    }
+}
+
+impl Clock for MockClock {
+    fn now_micros(&self) -> u64 {
+        let now = self.now.get();
+        self.now.set(now + self.step);
+        now
+    }
+
+    fn sleep_until(&self, deadline_micros: u64) {
+        self.sleeps.set(self.sleeps.get() + 1);
+        self.now.set(self.now.get().max(deadline_micros));
+    }
+}
+
+// Everything the loop asks to be heard.
+#[derive(Default)]
+pub(crate) struct AudioRecorder {
+    // The buzzer, as set on every frame.
+    pub(crate) buzzer: Vec<bool>,
+    pub(crate) sounds: Vec<Sound>,
+}
+
+impl AudioRecorder {
+    // Times the buzzer was turned on.
+    pub(crate) fn beeps(&self) -> usize {
+        let mut on = false;
+        self.buzzer.iter().filter(|&&b| !std::mem::replace(&mut on, b) && b).count()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FakeFrontend {
+    pub(crate) timeline: Timeline,
+    pub(crate) frames: FrameCounter,
+    pub(crate) clock: MockClock,
+    pub(crate) audio: AudioRecorder,
+    pub(crate) titles: Vec<String>,
+}
+
+impl FakeFrontend {
+    pub(crate) fn new(timeline: Timeline) -> FakeFrontend {
+        FakeFrontend { timeline, ..Default::default() }
+    }
+
+    pub(crate) fn with_clock(mut self, clock: MockClock) -> FakeFrontend {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Frontend for FakeFrontend {
+    fn poll_events(&mut self) -> Vec<Event> {
+        self.timeline.next()
+    }
+
+    fn present(&mut self, frame: &Frame) {
+        self.frames.present(frame);
+    }
+
+    fn present_overlay(&mut self, frame: &Frame, _overlay: &Frame) {
+        self.frames.overlays += 1;
+        self.frames.present(frame);
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        self.audio.buzzer.push(on);
+    }
+
+    fn play_sound(&mut self, sound: Sound) {
+        self.audio.sounds.push(sound);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.titles.push(title.to_string());
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
+    }
+
+    fn read_command(&mut self) -> Option<String> {
+        None
+    }
+}
+
+pub(crate) fn fixtures() -> (Chip, Playlist) {
+    let mut chip = Chip::new_seed(0, Profile::modern());
+    let mut playlist = Playlist::new(&[
+        PathBuf::from("rom/fixtures/draw_0.ch8"),
+        PathBuf::from("rom/fixtures/draw_1.ch8"),
+    ]).unwrap();
+    playlist.open(|p| rom::start(&mut chip, p, &[])).unwrap();
+    (chip, playlist)
+}
+
+pub(crate) fn deterministic() -> RunOptions {
+    RunOptions { deterministic: true, ips: 600, ..Default::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioRecorder, MockClock, Timeline};
+    use crate::clock::Clock;
+    use crate::runner::Event;
+
+    #[test]
+    fn timeline() {
+        let mut t = Timeline::new().at(1, Event::KeyPress(0x5)).at(1, Event::ToggleHud).at(3, Event::Quit);
+        assert_eq!(t.next(), []);
+        assert_eq!(t.next(), [Event::KeyPress(0x5), Event::ToggleHud]);
+        assert_eq!(t.next(), []);
+        assert_eq!(t.next(), [Event::Quit]);
+        // Past the script.
+        assert_eq!(t.next(), [Event::Quit]);
+        assert_eq!(t.polls(), 5);
+        assert_eq!(Timeline::new().next(), [Event::Quit]);
+    }
+
+    #[test]
+    fn clock_and_audio() {
+        let clock = MockClock::with_step(10);
+        assert_eq!((clock.now_micros(), clock.now_micros()), (0, 10));
+        clock.sleep_until(15);
+        assert_eq!((clock.now(), clock.sleeps()), (20, 1));
+        clock.sleep_until(100);
+        assert_eq!(clock.now_micros(), 100);
+        clock.advance(50);
+        assert_eq!(clock.now(), 160);
+        // A deadline already passed.
+        clock.sleep_until(0);
+        assert_eq!((clock.now(), clock.sleeps()), (160, 3));
+
+        let audio = AudioRecorder { buzzer: vec![true, true, false, false, true, false], sounds: Vec::new() };
+        assert_eq!(audio.beeps(), 2);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::framebuffer::Frame;
+
+// Small 3x5 font for messages drawn over the Chip-8 screen. Each row uses
+// the low three bits, most significant bit on the left.
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+// Glyph width plus one column of spacing.
+pub const ADVANCE: u32 = GLYPH_WIDTH + 1;
+
+pub const LINE_SPACING: u32 = 2;
+
+type Glyph = [u8; GLYPH_HEIGHT as usize];
+
+fn glyph(c: char) -> Glyph {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],    // '?'
+    }
+}
+
+pub fn text_width(text: &str) -> u32 {
+    match text.chars().count() as u32 {
+        0 => 0,
+        n => n * ADVANCE - 1,
+    }
+}
+
+// Sets the pixels of `text` with its top left corner at (x, y). Anything
+// outside the screen is clipped.
+pub fn draw_text(frame: &mut Frame, x: u32, y: u32, text: &str) {
+    for (n, c) in text.chars().enumerate() {
+        let gx = x + n as u32 * ADVANCE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let py = y + row as u32;
+            for col in 0..GLYPH_WIDTH {
+                let px = gx + col;
+                if px >= frame.width() || py >= frame.height() {
+                    continue;
+                }
+                if bits & (0b100 >> col) != 0 {
+                    frame[py][px as usize] = 1;
+                }
+            }
+        }
+    }
+}
+
+// Draws `lines` centered on the screen over a blank band, so the message
+// stays readable whatever the program left on the screen.
+pub fn draw_banner(frame: &mut Frame, lines: &[&str]) {
+    let n = lines.len() as u32;
+    if n == 0 {
+        return;
+    }
+    let height = n * GLYPH_HEIGHT + (n - 1) * LINE_SPACING;
+    let top = frame.height().saturating_sub(height) / 2;
+
+    let band_start = top.saturating_sub(1);
+    let band_end = (top + height + 1).min(frame.height());
+    for y in band_start..band_end {
+        frame[y].fill(0);
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        let x = frame.width().saturating_sub(text_width(line)) / 2;
+        let y = top + i as u32 * (GLYPH_HEIGHT + LINE_SPACING);
+        draw_text(frame, x, y, line);
+    }
+}
+
+// Draws `lines` left-aligned from (x, y) over a blank box with a one pixel
+// margin on the right and bottom.
+pub fn draw_panel(frame: &mut Frame, x: u32, y: u32, lines: &[&str]) {
+    let n = lines.len() as u32;
+    if n == 0 {
+        return;
+    }
+    let width = lines.iter().map(|l| text_width(l)).max().unwrap_or(0);
+    let height = n * GLYPH_HEIGHT + (n - 1) * LINE_SPACING;
+
+    let right = (x + width + 1).min(frame.width());
+    for py in y..(y + height + 1).min(frame.height()) {
+        if x < right {
+            frame[py][x as usize..right as usize].fill(0);
+        }
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(frame, x, y + i as u32 * (GLYPH_HEIGHT + LINE_SPACING), line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{draw_banner, draw_panel, draw_text, text_width};
+    use crate::framebuffer::Frame;
+
+    fn row(frame: &Frame, y: u32, x: u32, n: u32) -> Vec<u32> {
+        (x..x + n).map(|x| frame[y][x as usize]).collect()
+    }
+
+    #[test]
+    fn width() {
+        assert_eq!(text_width(""), 0);
+        assert_eq!(text_width("A"), 3);
+        assert_eq!(text_width("PROGRAM ENDED"), 51);
+    }
+
+    #[test]
+    fn glyphs() {
+        let mut frame = Frame::new();
+        draw_text(&mut frame, 1, 2, "Ah");
+
+        assert_eq!(row(&frame, 2, 0, 8), vec![0, 0, 1, 0, 0, 1, 0, 1]);
+        assert_eq!(row(&frame, 4, 0, 8), vec![0, 1, 1, 1, 0, 1, 1, 1]);
+        assert_eq!(row(&frame, 6, 0, 8), vec![0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(row(&frame, 7, 0, 8), vec![0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn clipped() {
+        let mut frame = Frame::new();
+        draw_text(&mut frame, 62, 30, "W");
+        assert_eq!(row(&frame, 30, 62, 2), vec![1, 0]);
+        assert_eq!(row(&frame, 31, 62, 2), vec![1, 0]);
+    }
+
+    #[test]
+    fn banner() {
+        let mut frame = Frame::new();
+        for y in 0..32_u32 {
+            frame[y].fill(1);
+        }
+        draw_banner(&mut frame, &["I"]);
+
+        // Centered 5-row line at y = 13, blank band from 12 to 18.
+        assert!(row(&frame, 11, 0, 64).iter().all(|&p| p == 1));
+        assert!(row(&frame, 12, 0, 64).iter().all(|&p| p == 0));
+        assert_eq!(row(&frame, 13, 29, 5), vec![0, 1, 1, 1, 0]);
+        assert_eq!(row(&frame, 14, 29, 5), vec![0, 0, 1, 0, 0]);
+        assert!(row(&frame, 18, 0, 64).iter().all(|&p| p == 0));
+        assert!(row(&frame, 19, 0, 64).iter().all(|&p| p == 1));
+    }
+
+    #[test]
+    fn panel() {
+        let mut frame = Frame::new();
+        for y in 0..32_u32 {
+            frame[y].fill(1);
+        }
+        draw_panel(&mut frame, 0, 0, &["I", "II"]);
+
+        // 7 by 12 box, the second line starts at y = 7.
+        assert_eq!(row(&frame, 0, 0, 9), vec![1, 1, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(row(&frame, 7, 0, 9), vec![1, 1, 1, 0, 1, 1, 1, 0, 1]);
+        assert!(row(&frame, 12, 0, 8).iter().all(|&p| p == 0));
+        assert!(row(&frame, 13, 0, 8).iter().all(|&p| p == 1));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::instr;
+
+// Which executed instructions --explain prints: every `every`-th one, or
+// every `every`-th of those matching `family`, e.g. "DXYN".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceSample {
+    every: u64,
+    family: Option<String>,
+    // Instructions counted so far.
+    seen: u64,
+}
+
+impl TraceSample {
+    pub fn new(every: u64, family: Option<&str>) -> TraceSample {
+        TraceSample {
+            every: every.max(1),
+            family: family.map(|f| f.to_ascii_uppercase()),
+            seen: 0,
+        }
+    }
+
+    // Counts an executed instruction, true when it is to be traced.
+    pub fn sample(&mut self, opcode: u16) -> bool {
+        if self.family.as_deref().is_some_and(|f| !instr::pattern_matches(f, opcode)) {
+            return false;
+        }
+        self.seen += 1;
+        self.seen.is_multiple_of(self.every)
+    }
+}
+
+// Parses "N" or "N:PATTERN", e.g. "1000" or "10:8XYN". A pattern has four
+// hex digits or X, Y and N for any nibble.
+pub fn parse_trace_sample(s: &str) -> Result<TraceSample, String> {
+    let (every, family) = match s.split_once(':') {
+        Some((every, family)) => (every, Some(family.trim())),
+        None => (s, None),
+    };
+    let every = match every.trim().parse::<u64>() {
+        Ok(n) if n > 0 => n,
+        _ => return Err(format!("invalid sample rate '{}'", every.trim())),
+    };
+    if let Some(f) = family {
+        if f.len() != 4 || !f.chars().all(|c| c.is_ascii_hexdigit() || "xynXYN".contains(c)) {
+            return Err(format!("invalid opcode pattern '{}'", f));
+        }
+    }
+    Ok(TraceSample::new(every, family))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_trace_sample, TraceSample};
+
+    #[test]
+    fn one_in_ten() {
+        let mut sample = TraceSample::new(10, None);
+        let traced: Vec<usize> = (0..100).filter(|_| sample.sample(0x7001)).collect();
+        assert_eq!(traced, vec![9, 19, 29, 39, 49, 59, 69, 79, 89, 99]);
+    }
+
+    #[test]
+    fn family() {
+        let mut sample = parse_trace_sample("2:dxyn").unwrap();
+        let opcodes = [0xD015, 0x7001, 0xD125, 0x1200, 0xD015, 0xD015];
+        let traced: Vec<u16> = opcodes.iter().copied().filter(|&op| sample.sample(op)).collect();
+        // The second and fourth DRW.
+        assert_eq!(traced, vec![0xD125, 0xD015]);
+
+        assert_eq!(parse_trace_sample("1000"), Ok(TraceSample::new(1000, None)));
+        assert_eq!(parse_trace_sample("0"), Err("invalid sample rate '0'".to_string()));
+        assert_eq!(parse_trace_sample("5:8XY"), Err("invalid opcode pattern '8XY'".to_string()));
+        assert_eq!(parse_trace_sample("5:8XYZ"), Err("invalid opcode pattern '8XYZ'".to_string()));
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Lines shown before and after a mismatch.
+pub const CONTEXT: usize = 3;
+
+// One executed instruction of a trace log, written by --trace-file as
+// "<cycle> <pc> <opcode>": the instructions executed before it in decimal,
+// then PC and the opcode in hex. Columns past these are ignored, so logs
+// with registers after them parse too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceLine {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:#05x} {:04X}", self.cycle, self.pc, self.opcode)
+    }
+}
+
+impl TraceLine {
+    fn get(&self, field: Field) -> u64 {
+        match field {
+            Field::Cycle => self.cycle,
+            Field::Pc => self.pc as u64,
+            Field::Opcode => self.opcode as u64,
+        }
+    }
+}
+
+// Hex with or without 0x.
+fn parse_hex(s: &str) -> Option<u16> {
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+pub fn parse_line(line: &str) -> Result<TraceLine, String> {
+    let words: Vec<&str> = line.split_whitespace().take(3).collect();
+    let invalid = || format!("invalid trace line '{}'", line.trim());
+    match words.as_slice() {
+        [cycle, pc, opcode] => Ok(TraceLine {
+            cycle: cycle.parse().map_err(|_| invalid())?,
+            pc: parse_hex(pc).ok_or_else(invalid)?,
+            opcode: parse_hex(opcode).ok_or_else(invalid)?,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+// The lines of a log, blank lines left out.
+pub fn parse_log(text: &str) -> Result<Vec<TraceLine>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| parse_line(l).map_err(|e| format!("line {}: {}", n + 1, e)))
+        .collect()
+}
+
+pub fn read_log(path: &Path) -> io::Result<Vec<TraceLine>> {
+    let text = std::fs::read_to_string(path)?;
+    parse_log(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+// A column of the trace log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Cycle,
+    Pc,
+    Opcode,
+}
+
+impl Field {
+    const ALL: [Field; 3] = [Field::Cycle, Field::Pc, Field::Opcode];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Cycle => "cycle",
+            Field::Pc => "pc",
+            Field::Opcode => "opcode",
+        }
+    }
+}
+
+// Parses a comma separated list of columns, e.g. "cycle" or "cycle,pc".
+pub fn parse_fields(s: &str) -> Result<Vec<Field>, String> {
+    s.split(',')
+        .map(|name| {
+            Field::ALL.into_iter()
+                .find(|f| f.name() == name.trim())
+                .ok_or_else(|| format!("unknown trace column '{}', expected cycle, pc or opcode", name.trim()))
+        })
+        .collect()
+}
+
+// The first line where two logs disagree, or where one of them ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    // 1-based, counting executed instructions.
+    pub line: usize,
+    // The columns that differ, empty when a log ended.
+    pub fields: Vec<Field>,
+    pub mine: Option<TraceLine>,
+    pub theirs: Option<TraceLine>,
+    // My lines before it and the reference lines after it.
+    before: Vec<TraceLine>,
+    after: Vec<TraceLine>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.mine, self.theirs) {
+            (None, _) => writeln!(f, "Line {}: mine ends", self.line)?,
+            (_, None) => writeln!(f, "Line {}: theirs ends", self.line)?,
+            _ => {
+                let names: Vec<&str> = self.fields.iter().map(|f| f.name()).collect();
+                writeln!(f, "Line {} differs in {}", self.line, names.join(", "))?;
+            },
+        }
+        let first = self.line - self.before.len();
+        for (i, l) in self.before.iter().enumerate() {
+            writeln!(f, "  {:>6}  {}", first + i, l)?;
+        }
+        let end = |l: Option<TraceLine>| l.map_or("(end)".to_string(), |l| l.to_string());
+        writeln!(f, "< {:>6}  {}", self.line, end(self.mine))?;
+        writeln!(f, "> {:>6}  {}", self.line, end(self.theirs))?;
+        for (i, l) in self.after.iter().enumerate() {
+            writeln!(f, "> {:>6}  {}", self.line + 1 + i, l)?;
+        }
+        Ok(())
+    }
+}
+
+// Compares my log line by line against a reference, as the lines come in,
+// so that it also works while the program runs.
+#[derive(Clone, Debug)]
+pub struct TraceDiff {
+    reference: Vec<TraceLine>,
+    compared: Vec<Field>,
+    // Mismatches reported before stopping.
+    max: usize,
+    mismatches: usize,
+    lines: usize,
+    recent: VecDeque<TraceLine>,
+}
+
+impl TraceDiff {
+    pub fn new(reference: Vec<TraceLine>, ignore: &[Field], max: usize) -> TraceDiff {
+        TraceDiff {
+            reference,
+            compared: Field::ALL.into_iter().filter(|f| !ignore.contains(f)).collect(),
+            max: max.max(1),
+            mismatches: 0,
+            lines: 0,
+            recent: VecDeque::with_capacity(CONTEXT),
+        }
+    }
+
+    // True once the last mismatch to report was.
+    pub fn is_done(&self) -> bool {
+        self.mismatches >= self.max
+    }
+
+    fn mismatch(&mut self, mine: Option<TraceLine>, theirs: Option<TraceLine>, fields: Vec<Field>) -> Mismatch {
+        self.mismatches += 1;
+        let line = self.lines + 1;
+        let end = (line + CONTEXT).min(self.reference.len());
+        Mismatch {
+            line,
+            fields,
+            mine,
+            theirs,
+            before: self.recent.iter().copied().collect(),
+            after: self.reference.get(line..end).unwrap_or_default().to_vec(),
+        }
+    }
+
+    // Compares my next line, returns the mismatch if it is one. A log that
+    // ends is the last mismatch.
+    pub fn push(&mut self, mine: TraceLine) -> Option<Mismatch> {
+        if self.is_done() {
+            return None;
+        }
+        let theirs = self.reference.get(self.lines).copied();
+        let m = match theirs {
+            Some(t) => {
+                let fields: Vec<Field> = self.compared.iter().copied().filter(|&f| mine.get(f) != t.get(f)).collect();
+                (!fields.is_empty()).then(|| self.mismatch(Some(mine), theirs, fields))
+            },
+            None => {
+                let m = self.mismatch(Some(mine), None, Vec::new());
+                self.mismatches = self.max;
+                Some(m)
+            },
+        };
+        if self.recent.len() == CONTEXT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(mine);
+        self.lines += 1;
+        m
+    }
+
+    // At the end of my log: the reference going on is a mismatch.
+    pub fn finish(&mut self) -> Option<Mismatch> {
+        if self.is_done() || self.lines >= self.reference.len() {
+            return None;
+        }
+        let theirs = self.reference[self.lines];
+        Some(self.mismatch(None, Some(theirs), Vec::new()))
+    }
+}
+
+// Compares two whole logs, up to `max` mismatches.
+pub fn diff(mine: &[TraceLine], theirs: Vec<TraceLine>, ignore: &[Field], max: usize) -> Vec<Mismatch> {
+    let mut d = TraceDiff::new(theirs, ignore, max);
+    let mut found: Vec<Mismatch> = mine.iter().filter_map(|&l| d.push(l)).collect();
+    found.extend(d.finish());
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, parse_fields, parse_line, parse_log, Field, TraceLine};
+
+    fn log(lines: &[(u64, u16, u16)]) -> String {
+        lines.iter().map(|&(cycle, pc, opcode)| format!("{}\n", TraceLine { cycle, pc, opcode })).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_line("12 0x20a 6003"), Ok(TraceLine { cycle: 12, pc: 0x20A, opcode: 0x6003 }));
+        assert_eq!(parse_line("12 20A 0x6003 V0=03"), Ok(TraceLine { cycle: 12, pc: 0x20A, opcode: 0x6003 }));
+        assert!(parse_line("12 0x20a").is_err());
+        assert!(parse_line("x 0x20a 6003").is_err());
+        assert_eq!(parse_log("0 0x200 6001\n\n1 0x202 z\n"), Err("line 3: invalid trace line '1 0x202 z'".to_string()));
+        assert_eq!(parse_fields("cycle, pc"), Ok(vec![Field::Cycle, Field::Pc]));
+        assert!(parse_fields("pc,v0").is_err());
+    }
+
+    #[test]
+    fn planted_divergence() {
+        let mut lines: Vec<(u64, u16, u16)> = (0..10).map(|i| (i, 0x200 + 2 * i as u16, 0x7001)).collect();
+        let mine = parse_log(&log(&lines)).unwrap();
+        lines[6].1 = 0x300;
+        lines[8].2 = 0x7002;
+        let theirs = parse_log(&log(&lines)).unwrap();
+
+        let found = diff(&mine, theirs.clone(), &[], 1);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, 7);
+        assert_eq!(found[0].fields, vec![Field::Pc]);
+        assert_eq!(found[0].to_string(), concat!(
+            "Line 7 differs in pc\n",
+            "       4  3 0x206 7001\n",
+            "       5  4 0x208 7001\n",
+            "       6  5 0x20a 7001\n",
+            "<      7  6 0x20c 7001\n",
+            ">      7  6 0x300 7001\n",
+            ">      8  7 0x20e 7001\n",
+            ">      9  8 0x210 7002\n",
+            ">     10  9 0x212 7001\n",
+        ));
+
+        let found = diff(&mine, theirs.clone(), &[], 5);
+        let at: Vec<(usize, Vec<Field>)> = found.into_iter().map(|m| (m.line, m.fields)).collect();
+        assert_eq!(at, vec![(7, vec![Field::Pc]), (9, vec![Field::Opcode])]);
+
+        // Ignoring PC leaves the opcode.
+        let found = diff(&mine, theirs, &[Field::Pc], 5);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, 9);
+    }
+
+    #[test]
+    fn shifted_cycles_and_ends() {
+        let mine = parse_log(&log(&[(0, 0x200, 0x6001), (1, 0x202, 0x1202)])).unwrap();
+        let theirs = parse_log(&log(&[(1, 0x200, 0x6001), (2, 0x202, 0x1202), (3, 0x202, 0x1202)])).unwrap();
+
+        let found = diff(&mine, theirs.clone(), &[Field::Cycle], 5);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].line, found[0].mine), (3, None));
+        assert!(found[0].to_string().starts_with("Line 3: mine ends\n"));
+
+        assert_eq!(diff(&mine, theirs.clone(), &[], 5).len(), 3);
+        assert!(diff(&theirs[..2], mine.clone(), &[Field::Cycle], 5).is_empty());
+        let found = diff(&theirs, mine, &[Field::Cycle], 5);
+        assert_eq!((found[0].line, found[0].theirs), (3, None));
+    }
+}
@@ -1,88 +1,520 @@
 extern crate sdl2;
 
-use log::trace;
+use log::{info, trace, warn};
 
-use sdl2::keyboard::Keycode;
+use sdl2::controller::{Button, GameController};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::BlendMode;
+use sdl2::surface::Surface;
 use sdl2::{pixels::Color, rect::Rect};
 
-use crate::arch;
-use crate::framebuffer::Frame;
+use chip8::audio::{AudioSink, Sound};
+use chip8::memview;
+use chip8::{Arch, Clock, Event, Frame, Frontend};
 
 const PIXEL_SIZE: u32 = 14;
 const BORDER_SIZE: u32 = 1;
-const PIXEL_INNER_SIZE: u32 = PIXEL_SIZE - 2 * BORDER_SIZE;
-
-const SCREEN_WIDTH: u32 = PIXEL_SIZE * arch::DISPLAY_WIDTH;
-const SCREEN_HEIGHT: u32 = PIXEL_SIZE * arch::DISPLAY_HEIGHT;
 
 const BACKGROUND_COLOR: Color = Color::BLUE;
 const PIXEL_COLOR: Color = Color::RGB(200, 200, 200);
+const HIGHLIGHT_COLOR: Color = Color::YELLOW;
+// --flash-draws: the color of a pixel that just flipped, and the frames it
+// takes to fade back to the pixel's own color.
+const FLASH_COLOR: Color = Color::RGB(255, 64, 64);
+const FLASH_FRAMES: u8 = 8;
+
+// What the taskbar shows before a ROM is loaded, and the X11 window class.
+const APP_NAME: &str = "Chip-8 emulator";
+const WINDOW_CLASS: &str = "chip8";
+// The window icon: the "8" of the built-in font, scaled up and centered on
+// a square of the background color.
+const ICON_GLYPH: [u8; 5] = [0xF0, 0x90, 0xF0, 0x90, 0xF0];
+const ICON_SIZE: u32 = 32;
+const ICON_SCALE: u32 = 5;
+
+// The icon's RGBA32 pixels, ICON_SIZE x ICON_SIZE, row after row.
+fn icon_pixels() -> Vec<u8> {
+    let (left, top) = ((ICON_SIZE - 4 * ICON_SCALE) / 2, (ICON_SIZE - 5 * ICON_SCALE) / 2);
+    let mut out = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let (gx, gy) = (x.wrapping_sub(left) / ICON_SCALE, y.wrapping_sub(top) / ICON_SCALE);
+            let lit = gx < 4 && gy < 5 && ICON_GLYPH[gy as usize] & (0x80 >> gx) != 0;
+            let c = if lit { PIXEL_COLOR } else { BACKGROUND_COLOR };
+            out.extend_from_slice(&[c.r, c.g, c.b, 255]);
+        }
+    }
+    out
+}
+
+// Parses a pixel aspect ratio given as "W:H", e.g. "1:2" for pixels twice
+// as tall as they are wide. Returns W/H.
+pub fn parse_aspect(s: &str) -> Result<f32, String> {
+    let (w, h) = s.split_once(':').ok_or_else(|| format!("expected W:H, got '{}'", s))?;
+    let w: f32 = w.trim().parse().map_err(|_| format!("invalid width '{}'", w))?;
+    let h: f32 = h.trim().parse().map_err(|_| format!("invalid height '{}'", h))?;
+    if !(w > 0.0 && h > 0.0 && w.is_finite() && h.is_finite()) {
+        return Err(format!("aspect must be positive, got '{}'", s));
+    }
+    Ok(w / h)
+}
+
+// SDL render backend for the window canvas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    Software,
+    Accelerated,
+}
+
+impl Renderer {
+    // Backends to try, in order. Software rendering works everywhere, so
+    // it is the fallback when acceleration isn't available, e.g. over VNC.
+    pub fn candidates(self) -> Vec<Renderer> {
+        match self {
+            Renderer::Accelerated => vec![Renderer::Accelerated, Renderer::Software],
+            Renderer::Software => vec![Renderer::Software],
+        }
+    }
+}
+
+// Tries `create` with each candidate of `requested` and returns the first
+// that works, or the last error.
+pub fn select_renderer<T, F>(requested: Renderer, mut create: F) -> Result<(Renderer, T), String>
+where F: FnMut(Renderer) -> Result<T, String> {
+    let mut last_err = String::new();
+    for r in requested.candidates() {
+        match create(r) {
+            Ok(t) => return Ok((r, t)),
+            Err(e) => {
+                warn!("{:?} renderer failed: {}", r, e);
+                last_err = e;
+            },
+        }
+    }
+    Err(last_err)
+}
+
+// On-screen size of one Chip-8 pixel. The shorter side is PIXEL_SIZE, the
+// other one is stretched by the aspect ratio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelGeometry {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelGeometry {
+    pub fn new(aspect: f32) -> PixelGeometry {
+        let stretched = |ratio: f32| (PIXEL_SIZE as f32 * ratio).round() as u32;
+        if aspect >= 1.0 {
+            PixelGeometry { width: stretched(aspect), height: PIXEL_SIZE }
+        } else {
+            PixelGeometry { width: PIXEL_SIZE, height: stretched(1.0 / aspect) }
+        }
+    }
+
+    pub fn screen_size(&self, arch: &Arch, orientation: Orientation) -> (u32, u32) {
+        let (columns, rows) = orientation.size(arch.display_width, arch.display_height);
+        (self.width * columns, self.height * rows)
+    }
+
+    // Area to fill for the pixel at column `x`, row `y`, border excluded.
+    pub fn pixel_rect(&self, x: u32, y: u32) -> Rect {
+        Rect::new(
+            (self.width * x + BORDER_SIZE) as i32,
+            (self.height * y + BORDER_SIZE) as i32,
+            self.width - 2 * BORDER_SIZE,
+            self.height - 2 * BORDER_SIZE)
+    }
+}
 
-pub enum Event {
-    KeyPress(u8),
-    KeyUnpress(u8),
-    Quit,
+// How the screen is turned on the way to the window, for rotated displays
+// and mirrored projection: turned clockwise by `quarter_turns`, then flipped.
+// Only where pixels are drawn changes, the machine's frame stays as it is.
+// Overlays are drawn upright.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    pub quarter_turns: u8,
+    // Mirror left to right.
+    pub flip_x: bool,
+    // Mirror top to bottom.
+    pub flip_y: bool,
 }
 
-pub struct EventIterator<'a> {
-    event_pump: &'a mut sdl2::EventPump,
+impl Orientation {
+    // Columns and rows on the window for a `width` x `height` frame.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.quarter_turns % 2 == 1 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    // Where the frame pixel at column `x`, row `y` goes on the window.
+    pub fn transform(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (x, y) = match self.quarter_turns % 4 {
+            1 => (height - 1 - y, x),
+            2 => (width - 1 - x, height - 1 - y),
+            3 => (y, width - 1 - x),
+            _ => (x, y),
+        };
+        let (columns, rows) = self.size(width, height);
+        (if self.flip_x { columns - 1 - x } else { x }, if self.flip_y { rows - 1 - y } else { y })
+    }
 }
 
-impl<'a> Iterator for EventIterator<'a> {
-    type Item = Event;
+// The color the screen is cleared with and the blend mode pixels are drawn
+// in. A transparent background has alpha 0, and blending keeps the pixels
+// drawn over it opaque, for compositing the window over other content.
+pub fn background(transparent: bool) -> (Color, BlendMode) {
+    if transparent {
+        (Color { a: 0, ..BACKGROUND_COLOR }, BlendMode::Blend)
+    } else {
+        (BACKGROUND_COLOR, BlendMode::None)
+    }
+}
 
-    fn next(self: &mut EventIterator<'a>) -> Option<Self::Item> {
-        Events::match_event(self.event_pump.poll_event())
+// The screen colors as --print-config shows them.
+pub fn palette(transparent: bool) -> String {
+    let hex = |c: Color| format!("#{:02X}{:02X}{:02X}", c.r, c.g, c.b);
+    format!("pixels {}, background {}{}", hex(PIXEL_COLOR), hex(BACKGROUND_COLOR), if transparent { " transparent" } else { "" })
+}
+
+// How recently each pixel flipped, from FLASH_FRAMES on the frame it did
+// down to 0, for highlighting what the last draws changed. Only looks at the
+// presented frames, the machine knows nothing of it.
+#[derive(Default)]
+pub struct FlashDraws {
+    previous: Option<Frame>,
+    levels: Vec<u8>,
+}
+
+impl FlashDraws {
+    // Call once per presented frame.
+    pub fn update(&mut self, frame: &Frame) {
+        let width = frame.width() as usize;
+        let same_size = self.previous.as_ref().is_some_and(|p| (p.width(), p.height()) == (frame.width(), frame.height()));
+        if !same_size {
+            self.levels = vec![0; width * frame.height() as usize];
+        }
+        for l in self.levels.iter_mut() {
+            *l = l.saturating_sub(1);
+        }
+        if let Some(previous) = self.previous.as_ref().filter(|_| same_size) {
+            for (y, (old, new)) in previous.iter().zip(frame.iter()).enumerate() {
+                for (x, _) in old.iter().zip(new.iter()).enumerate().filter(|(_, (a, b))| a != b) {
+                    self.levels[y * width + x] = FLASH_FRAMES;
+                }
+            }
+        }
+        self.previous = Some(frame.clone());
+    }
+
+    // FLASH_FRAMES right after the pixel flipped, 0 once it faded out.
+    pub fn level(&self, x: u32, y: u32) -> u8 {
+        let width = self.previous.as_ref().map_or(0, |p| p.width());
+        if x >= width {
+            return 0;
+        }
+        self.levels.get((y * width + x) as usize).copied().unwrap_or(0)
     }
 }
 
+// From `base` at level 0 to FLASH_COLOR at FLASH_FRAMES.
+fn flash_color(base: Color, level: u8) -> Color {
+    let t = level as u32;
+    let blend = |a: u8, b: u8| ((a as u32 * (FLASH_FRAMES as u32 - t) + b as u32 * t) / FLASH_FRAMES as u32) as u8;
+    Color::RGB(blend(base.r, FLASH_COLOR.r), blend(base.g, FLASH_COLOR.g), blend(base.b, FLASH_COLOR.b))
+}
+
+// How the screen is drawn.
+#[derive(Clone, Copy, Default)]
+pub struct Look {
+    // Clear with alpha 0 instead of the solid background.
+    pub transparent: bool,
+    // Highlight the pixels that just flipped.
+    pub flash_draws: bool,
+    pub orientation: Orientation,
+}
+
 pub struct Display {
     canvas: sdl2::render::WindowCanvas,
+    geometry: PixelGeometry,
+    orientation: Orientation,
+    clear_color: Color,
+    flash: Option<FlashDraws>,
 }
 
 impl Display {
-    pub fn new(canvas: sdl2::render::WindowCanvas) -> Display {
+    pub fn new(mut canvas: sdl2::render::WindowCanvas, geometry: PixelGeometry, look: Look) -> Display {
+        let (clear_color, blend) = background(look.transparent);
+        canvas.set_blend_mode(blend);
         Display {
             canvas,
+            geometry,
+            orientation: look.orientation,
+            clear_color,
+            flash: look.flash_draws.then(FlashDraws::default),
         }
     }
 
-    pub fn present_frame(&mut self, frame: &Frame) {
-        self.canvas.set_draw_color(BACKGROUND_COLOR);
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).unwrap();
+    }
+
+    // The window area of the frame pixel at column `x`, row `y`.
+    fn pixel_rect(&self, frame: &Frame, x: u32, y: u32) -> Rect {
+        let (x, y) = self.orientation.transform(x, y, frame.width(), frame.height());
+        self.geometry.pixel_rect(x, y)
+    }
+
+    pub fn present_frame(&mut self, frame: &Frame, overlay: Option<&Frame>) {
+        self.canvas.set_draw_color(self.clear_color);
         self.canvas.clear();
         self.canvas.set_draw_color(PIXEL_COLOR);
         let mut pixels: Vec<Rect> = Vec::new();
         for (i, row) in frame.iter().enumerate() {
             for (j, p) in row.iter().enumerate() {
                 if *p!= 0 {
-                    let x: i32 = (PIXEL_SIZE * (j as u32) + BORDER_SIZE) as i32;
-                    let y: i32 = (PIXEL_SIZE * (i as u32) + BORDER_SIZE) as i32;
-
-                    pixels.push(Rect::new(x, y, PIXEL_INNER_SIZE, PIXEL_INNER_SIZE));
-                    //println!("Draw pixel {} {} {} {}", x,y, PIXEL_INNER_SIZE, PIXEL_INNER_SIZE);
+                    pixels.push(self.pixel_rect(frame, j as u32, i as u32));
                 }
             }
         }
         self.canvas.fill_rects(&pixels).unwrap();
+        if self.flash.is_some() {
+            self.draw_flashes(frame);
+        }
+        if let Some(overlay) = overlay {
+            self.draw_overlay(overlay);
+        }
         self.canvas.present();
     }
+
+    // Redraws the recently flipped pixels, brightest for the latest flips.
+    fn draw_flashes(&mut self, frame: &Frame) {
+        let Some(flash) = self.flash.as_mut() else { return };
+        flash.update(frame);
+        let flash = self.flash.as_ref().unwrap();
+        // Rects by level and by whether the pixel is on.
+        let mut groups: Vec<[Vec<Rect>; 2]> = vec![Default::default(); FLASH_FRAMES as usize + 1];
+        for (i, row) in frame.iter().enumerate() {
+            for (j, p) in row.iter().enumerate() {
+                let level = flash.level(j as u32, i as u32);
+                if level > 0 {
+                    groups[level as usize][(*p != 0) as usize].push(self.pixel_rect(frame, j as u32, i as u32));
+                }
+            }
+        }
+        for (level, group) in groups.iter().enumerate().skip(1) {
+            for (base, pixels) in [BACKGROUND_COLOR, PIXEL_COLOR].into_iter().zip(group) {
+                if !pixels.is_empty() {
+                    self.canvas.set_draw_color(flash_color(base, level as u8));
+                    self.canvas.fill_rects(pixels).unwrap();
+                }
+            }
+        }
+    }
+
+    fn draw_overlay(&mut self, overlay: &Frame) {
+        let (w, h) = self.canvas.output_size().unwrap();
+        let (x0, y0, scale) = overlay_placement((w, h), (overlay.width(), overlay.height()));
+        self.canvas.set_draw_color(BACKGROUND_COLOR);
+        self.canvas.fill_rect(Rect::new(x0, y0, overlay.width() * scale, overlay.height() * scale)).unwrap();
+        for (value, color) in [(memview::TEXT, PIXEL_COLOR), (memview::HIGHLIGHT, HIGHLIGHT_COLOR)] {
+            let mut pixels: Vec<Rect> = Vec::new();
+            for (i, row) in overlay.iter().enumerate() {
+                for (j, p) in row.iter().enumerate() {
+                    if *p == value {
+                        pixels.push(Rect::new(x0 + (j as u32 * scale) as i32, y0 + (i as u32 * scale) as i32, scale, scale));
+                    }
+                }
+            }
+            self.canvas.set_draw_color(color);
+            self.canvas.fill_rects(&pixels).unwrap();
+        }
+    }
+}
+
+// Top left corner and integer scale of an overlay centered in the window, as
+// large as fits.
+pub fn overlay_placement(window: (u32, u32), overlay: (u32, u32)) -> (i32, i32, u32) {
+    let scale = (window.0 / overlay.0).min(window.1 / overlay.1).max(1);
+    let x = (window.0 as i32 - (overlay.0 * scale) as i32) / 2;
+    let y = (window.1 as i32 - (overlay.1 * scale) as i32) / 2;
+    (x, y, scale)
+}
+
+// Gamepad buttons by their SDL mapping names.
+const BUTTON_NAMES: [(Button, &str); 15] = [
+    (Button::A, "a"),
+    (Button::B, "b"),
+    (Button::X, "x"),
+    (Button::Y, "y"),
+    (Button::Back, "back"),
+    (Button::Guide, "guide"),
+    (Button::Start, "start"),
+    (Button::LeftStick, "leftstick"),
+    (Button::RightStick, "rightstick"),
+    (Button::LeftShoulder, "leftshoulder"),
+    (Button::RightShoulder, "rightshoulder"),
+    (Button::DPadUp, "dpup"),
+    (Button::DPadDown, "dpdown"),
+    (Button::DPadLeft, "dpleft"),
+    (Button::DPadRight, "dpright"),
+];
+
+// The d-pad sits on 2/4/6/8, the usual CHIP-8 directions, A on 5 which most
+// games use as "fire".
+const DEFAULT_GAMEPAD_MAP: [(Button, u8); 14] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::A, 0x5),
+    (Button::B, 0x0),
+    (Button::X, 0x1),
+    (Button::Y, 0x3),
+    (Button::LeftShoulder, 0x7),
+    (Button::RightShoulder, 0x9),
+    (Button::Back, 0xA),
+    (Button::Start, 0xB),
+    (Button::LeftStick, 0xC),
+    (Button::RightStick, 0xD),
+];
+
+// Which CHIP-8 key each gamepad button presses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GamepadMap {
+    keys: Vec<(Button, u8)>,
+}
+
+impl GamepadMap {
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.keys.iter().find(|(b, _)| *b == button).map(|(_, k)| *k)
+    }
+
+    fn set(&mut self, button: Button, key: u8) {
+        self.keys.retain(|(b, _)| *b != button);
+        self.keys.push((button, key));
+    }
+
+    // Parses "button=key[,button=key...]" on top of the default map, e.g.
+    // "a=f,start=0". Buttons use the SDL names, keys are hex digits.
+    pub fn parse(s: &str) -> Result<GamepadMap, String> {
+        let mut map = GamepadMap::default();
+        for entry in s.split(',') {
+            let (name, key) = entry.split_once('=').ok_or_else(|| format!("expected button=key, got '{}'", entry))?;
+            let button = BUTTON_NAMES.iter()
+                .find(|(_, n)| n.eq_ignore_ascii_case(name.trim()))
+                .map(|(b, _)| *b)
+                .ok_or_else(|| format!("unknown button '{}'", name))?;
+            let key = match u8::from_str_radix(key.trim(), 16) {
+                Ok(k) if k <= 0xF => k,
+                _ => return Err(format!("invalid key '{}'", key)),
+            };
+            map.set(button, key);
+        }
+        Ok(map)
+    }
+}
+
+impl Default for GamepadMap {
+    fn default() -> Self {
+        GamepadMap { keys: DEFAULT_GAMEPAD_MAP.to_vec() }
+    }
+}
+
+// Game controllers, opened and closed as they are plugged in and out.
+pub struct Gamepad {
+    subsystem: sdl2::GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    map: GamepadMap,
+    // Keys held down, with the instance id of the controller holding them.
+    held: Vec<(u32, u8)>,
+}
+
+impl Gamepad {
+    // Controllers already connected are reported as added on the first poll.
+    pub fn new(subsystem: sdl2::GameControllerSubsystem, map: GamepadMap) -> Gamepad {
+        Gamepad {
+            subsystem,
+            controllers: Vec::new(),
+            map,
+            held: Vec::new(),
+        }
+    }
+
+    fn handle(&mut self, sdl2_event: &sdl2::event::Event) -> Vec<Event> {
+        match *sdl2_event {
+            sdl2::event::Event::ControllerDeviceAdded { which, .. } => {
+                match self.subsystem.open(which) {
+                    Ok(c) => {
+                        info!("Gamepad connected: {}", c.name());
+                        self.controllers.push(c);
+                    },
+                    Err(e) => warn!("Cannot open gamepad {}: {}", which, e),
+                }
+                vec![]
+            },
+            sdl2::event::Event::ControllerDeviceRemoved { which, .. } => {
+                info!("Gamepad disconnected");
+                self.controllers.retain(|c| c.instance_id() != which);
+                // Release whatever the unplugged controller was holding.
+                let (released, held) = self.held.iter().partition(|(id, _)| *id == which);
+                self.held = held;
+                released.into_iter().map(|(_, key)| Event::KeyUnpress(key)).collect()
+            },
+            sdl2::event::Event::ControllerButtonDown { which, button, .. } => match self.map.key(button) {
+                Some(key) => {
+                    self.held.push((which, key));
+                    vec![Event::KeyPress(key)]
+                },
+                None => vec![],
+            },
+            sdl2::event::Event::ControllerButtonUp { which, button, .. } => match self.map.key(button) {
+                Some(key) => {
+                    self.held.retain(|&h| h != (which, key));
+                    vec![Event::KeyUnpress(key)]
+                },
+                None => vec![],
+            },
+            _ => vec![],
+        }
+    }
 }
 
 pub struct Events {
     event_pump: sdl2::EventPump,
+    gamepad: Option<Gamepad>,
+    step_key: Keycode,
 }
 
 impl Events {
-    pub fn new(event_pump: sdl2::EventPump) -> Events {
+    pub fn new(event_pump: sdl2::EventPump, gamepad: Option<Gamepad>, step_key: Keycode) -> Events {
         Events {
             event_pump,
+            gamepad,
+            step_key,
         }
     }
 
-    pub fn poll_iter(&mut self) -> EventIterator {
-        EventIterator {
-            event_pump: &mut self.event_pump,
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(e) = self.event_pump.poll_event() {
+            if let Some(g) = self.gamepad.as_mut() {
+                events.extend(g.handle(&e));
+            }
+            match e {
+                // Repeats while held down.
+                sdl2::event::Event::KeyDown { keycode: Some(k), keymod, .. } if k == self.step_key && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                    events.push(Event::StepBack)
+                },
+                sdl2::event::Event::KeyDown { keycode: Some(k), .. } if k == self.step_key => events.push(Event::StepInstruction),
+                e => events.extend(Events::match_event(Some(e))),
+            }
         }
+        events
     }
 
     fn match_event(sdl2_event: Option<sdl2::event::Event>) -> Option<Event> {
@@ -90,6 +522,19 @@ impl Events {
             Some(sdl2::event::Event::Quit {..}) |
                 Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. }) => Some(Event::Quit),
 
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::PageDown), repeat: false, .. }) => Some(Event::NextRom),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::PageUp), repeat: false, .. }) => Some(Event::PrevRom),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. }) => Some(Event::ApplyPokes),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. }) => Some(Event::ToggleHud),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. }) => Some(Event::LoadState),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. }) => Some(Event::SaveState),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. }) => Some(Event::ToggleMemView),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. }) => Some(Event::ToggleRegView),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. }) => Some(Event::ToggleDisasm),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. }) => Some(Event::TogglePause),
+            Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. }) => Some(Event::RewindStart),
+            Some(sdl2::event::Event::KeyUp { keycode: Some(Keycode::Backspace), repeat: false, .. }) => Some(Event::RewindStop),
+
             // Row 1
             Some(sdl2::event::Event::KeyDown { keycode: Some(Keycode::Num1), repeat: false, .. }) => Some(Event::KeyPress(0x1)),
             Some(sdl2::event::Event::KeyUp { keycode: Some(Keycode::Num1), repeat: false, .. }) => Some(Event::KeyUnpress(0x1)),
@@ -158,13 +603,48 @@ impl Timers {
         }
     }
 
-    pub fn get_ms(&self) -> u32 {
-        self.timer_subsystem.ticks()
+}
+
+// SDL's performance counter.
+impl Clock for Timers {
+    fn now_micros(&self) -> u64 {
+        let counter = self.timer_subsystem.performance_counter() as u128;
+        let freq = self.timer_subsystem.performance_frequency() as u128;
+        (counter * 1_000_000 / freq) as u64
+    }
+
+    fn sleep_until(&self, deadline_micros: u64) {
+        let now = self.now_micros();
+        if deadline_micros > now {
+            std::thread::sleep(std::time::Duration::from_micros(deadline_micros - now));
+        }
     }
 }
 
+const BUZZER_FREQ: f32 = 440.0;
+const COLLISION_FREQ: f32 = 1320.0;
+const COLLISION_MS: u32 = 40;
+
+// What Audio needs from an SDL audio device, so that tests can fake one.
+trait AudioOut {
+    // Fails once the device is gone, e.g. an unplugged headset.
+    fn resume(&mut self) -> Result<(), String>;
+    fn freq(&self) -> u32;
+    fn set_buzzer(&mut self, on: bool);
+    fn start_collision(&mut self, samples: u32);
+    // Ignored by a mono device.
+    fn set_pan(&mut self, pan: f32);
+}
+
+type OpenAudio = Box<dyn FnMut() -> Result<Box<dyn AudioOut>, String>>;
+
+// The sound output. A device that fails is reopened once, after that sound
+// is off for the rest of the session.
 pub struct Audio {
-    dev: sdl2::audio::AudioDevice<SinWave>,
+    // None once sound is off.
+    dev: Option<Box<dyn AudioOut>>,
+    open: OpenAudio,
+    reopened: bool,
     is_on: bool,
 }
 
@@ -182,53 +662,176 @@ impl SinWave {
                 volume: 0.25,
         }
     }
+
+    fn sample(&mut self) -> f32 {
+        let phase = self.phase * 2.0 * std::f32::consts::PI;
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        phase.sin() * self.volume
+    }
+}
+
+// Left and right gains for a pan from -1.0, all left, to 1.0, all right.
+// The power is the same at every position.
+pub fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    [angle.cos(), angle.sin()]
+}
+
+// Parses a stereo position between -1.0, left, and 1.0, right.
+pub fn parse_pan(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(pan) if (-1.0..=1.0).contains(&pan) => Ok(pan),
+        _ => Err(format!("expected a pan from -1.0 to 1.0, got '{}'", s)),
+    }
+}
+
+// The buzzer plus one-shot effects. The device keeps running, silence is
+// produced while nothing plays.
+struct Mixer {
+    buzzer: SinWave,
+    buzzer_on: bool,
+    collision: SinWave,
+    collision_left: u32,
+    // 1 or 2, the first `channels` gains apply.
+    channels: usize,
+    gains: [f32; 2],
 }
 
-impl sdl2::audio::AudioCallback for SinWave {
+impl sdl2::audio::AudioCallback for Mixer {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        for i in out.iter_mut() {
-            let phase = self.phase * 2.0 * std::f32::consts::PI;
-            *i = phase.sin() * self.volume;
+        for frame in out.chunks_mut(self.channels) {
+            let mut v = 0.0;
+            if self.buzzer_on {
+                v += self.buzzer.sample();
+            }
+            if self.collision_left > 0 {
+                v += self.collision.sample();
+                self.collision_left -= 1;
+            }
+            for (i, gain) in frame.iter_mut().zip(self.gains) {
+                *i = v * gain;
+            }
+        }
+    }
+}
 
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+impl AudioOut for sdl2::audio::AudioDevice<Mixer> {
+    fn resume(&mut self) -> Result<(), String> {
+        sdl2::audio::AudioDevice::resume(self);
+        // SDL reports a lost device as stopped.
+        match self.status() {
+            sdl2::audio::AudioStatus::Playing => Ok(()),
+            status => Err(format!("device is {:?}", status)),
         }
     }
+
+    fn freq(&self) -> u32 {
+        self.spec().freq as u32
+    }
+
+    fn set_buzzer(&mut self, on: bool) {
+        self.lock().buzzer_on = on;
+    }
+
+    fn start_collision(&mut self, samples: u32) {
+        self.lock().collision_left = samples;
+    }
+
+    fn set_pan(&mut self, pan: f32) {
+        let mut mixer = self.lock();
+        if mixer.channels == 2 {
+            mixer.gains = pan_gains(pan);
+        }
+    }
+}
+
+fn open_device(audio_subsystem: &sdl2::AudioSubsystem, stereo: bool) -> Result<Box<dyn AudioOut>, String> {
+    let spec = sdl2::audio::AudioSpecDesired {
+        freq: Some(44100),
+        channels: Some(if stereo { 2 } else { 1 }),
+        samples: None,
+    };
+    let dev = audio_subsystem.open_playback(None, &spec, |spec| {
+        let channels = spec.channels.clamp(1, 2) as usize;
+        Mixer {
+            buzzer: SinWave::new(BUZZER_FREQ, &spec),
+            buzzer_on: false,
+            collision: SinWave::new(COLLISION_FREQ, &spec),
+            collision_left: 0,
+            channels,
+            gains: if channels == 2 { pan_gains(0.0) } else { [1.0, 1.0] },
+        }
+    })?;
+    Ok(Box::new(dev))
 }
 
 impl Audio {
-    pub fn new(audio_subsystem: sdl2::AudioSubsystem) -> Audio {
-        let spec = sdl2::audio::AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: None,
-        };
-        let dev = audio_subsystem.open_playback(None, &spec, |spec| {
-            SinWave::new(440.0, &spec)
-        }).unwrap();
-        Audio {
-            dev,
+    // Mono without `pan`, otherwise stereo with the sound at `pan`.
+    pub fn new(audio_subsystem: sdl2::AudioSubsystem, pan: Option<f32>) -> Audio {
+        let mut audio = Audio::with_device(Box::new(move || open_device(&audio_subsystem, pan.is_some()))).unwrap();
+        if let Some(pan) = pan {
+            audio.set_pan(pan);
+        }
+        audio
+    }
+
+    // Moves the sound between the left and right channels, see pan_gains().
+    pub fn set_pan(&mut self, pan: f32) {
+        self.use_device(|d| d.set_pan(pan));
+    }
+
+    fn with_device(mut open: OpenAudio) -> Result<Audio, String> {
+        let mut dev = open()?;
+        dev.resume()?;
+        Ok(Audio {
+            dev: Some(dev),
+            open,
+            reopened: false,
             is_on: false,
+        })
+    }
+
+    // Runs `f` on a live device, reopening it once if it failed.
+    fn use_device(&mut self, f: impl Fn(&mut dyn AudioOut)) {
+        while let Some(dev) = self.dev.as_mut() {
+            match dev.resume() {
+                Ok(()) => return f(dev.as_mut()),
+                Err(e) if !self.reopened => {
+                    warn!("Audio device failed: {}, reopening it", e);
+                    self.reopened = true;
+                    self.dev = match (self.open)() {
+                        Ok(dev) => Some(dev),
+                        Err(e) => {
+                            warn!("Reopening the audio device failed: {}, sound is off", e);
+                            None
+                        },
+                    };
+                },
+                Err(e) => {
+                    warn!("Audio device failed again: {}, sound is off", e);
+                    self.dev = None;
+                },
+            }
         }
     }
+}
 
-    pub fn on(&mut self) {
-        if self.is_on {
+impl AudioSink for Audio {
+    fn set_buzzer(&mut self, on: bool) {
+        if self.is_on == on {
             return;
         }
-        trace!("Sound on");
-        self.dev.resume();
-        self.is_on = true;
+        trace!("Sound {}", if on { "on" } else { "off" });
+        self.use_device(|d| d.set_buzzer(on));
+        self.is_on = on;
     }
 
-    pub fn off(&mut self) {
-        if !self.is_on {
-            return;
+    fn play(&mut self, sound: Sound) {
+        match sound {
+            Sound::Collision => self.use_device(|d| d.start_collision(d.freq() * COLLISION_MS / 1000)),
         }
-        trace!("Sound off");
-        self.dev.pause();
-        self.is_on = false;
     }
 }
 
@@ -240,27 +843,379 @@ pub struct Ui {
 }
 
 impl Ui {
-    pub fn new() -> Self {
-        let sdl_ctx = sdl2::init().unwrap();
-        let video = sdl_ctx.video().unwrap();
-        let window = video.window("rust-sdl2 demo", SCREEN_WIDTH, SCREEN_HEIGHT)
-            .position_centered()
-            .build()
-            .unwrap();
-        let mut canvas = window.into_canvas().accelerated().build().unwrap();
-        canvas.set_draw_color(BACKGROUND_COLOR);
+    // The window fits `arch`'s screen. `renderer` is the preferred canvas
+    // backend. `gamepad` enables controller input with the given button map.
+    // `step_key` is the SDL name of the key that executes one instruction
+    // while paused, e.g. "N" or "F5". `pan` opens the audio in stereo.
+    // `look` has the background and the debug highlights.
+    pub fn new(aspect: f32, arch: &Arch, renderer: Renderer, gamepad: Option<GamepadMap>, step_key: &str, pan: Option<f32>, look: Look) -> Result<Self, String> {
+        let step_key = Keycode::from_name(step_key).ok_or_else(|| format!("unknown key '{}'", step_key))?;
+        let geometry = PixelGeometry::new(aspect);
+        let (screen_width, screen_height) = geometry.screen_size(arch, look.orientation);
+
+        // Hints only count when set before the video subsystem starts.
+        sdl2::hint::set("SDL_VIDEO_X11_WMCLASS", WINDOW_CLASS);
+        sdl2::hint::set("SDL_APP_NAME", APP_NAME);
+        let sdl_ctx = sdl2::init()?;
+        let video = sdl_ctx.video()?;
+        let mut icon = icon_pixels();
+        let icon = Surface::from_data(&mut icon, ICON_SIZE, ICON_SIZE, ICON_SIZE * 4, PixelFormatEnum::RGBA32)?;
+        // The window goes with a failed canvas, so every attempt opens one.
+        let (renderer, mut canvas) = select_renderer(renderer, |r| {
+            let mut window = video.window(APP_NAME, screen_width, screen_height)
+                .position_centered()
+                .build()
+                .map_err(|e| e.to_string())?;
+            window.set_icon(&icon);
+            let builder = match r {
+                Renderer::Accelerated => window.into_canvas().accelerated(),
+                Renderer::Software => window.into_canvas().software(),
+            };
+            builder.build().map_err(|e| e.to_string())
+        })?;
+        info!("Using the {:?} renderer", renderer);
+        canvas.set_draw_color(background(look.transparent).0);
         canvas.clear();
         canvas.present();
 
-        let event_pump = sdl_ctx.event_pump().unwrap();
-        let timer_subsystem = sdl_ctx.timer().unwrap();
-        let audio_subsystem = sdl_ctx.audio().unwrap();
+        let event_pump = sdl_ctx.event_pump()?;
+        let timer_subsystem = sdl_ctx.timer()?;
+        let audio_subsystem = sdl_ctx.audio()?;
+        let gamepad = match gamepad {
+            Some(map) => Some(Gamepad::new(sdl_ctx.game_controller()?, map)),
+            None => None,
+        };
 
-        Ui {
-            display: Display::new(canvas),
-            events: Events::new(event_pump),
+        Ok(Ui {
+            display: Display::new(canvas, geometry, look),
+            events: Events::new(event_pump, gamepad, step_key),
             timers: Timers::new(timer_subsystem),
-            audio: Audio::new(audio_subsystem),
+            audio: Audio::new(audio_subsystem, pan),
+        })
+    }
+}
+
+impl Frontend for Ui {
+    fn poll_events(&mut self) -> Vec<Event> {
+        self.events.poll()
+    }
+
+    fn present(&mut self, frame: &Frame) {
+        self.display.present_frame(frame, None);
+    }
+
+    fn present_overlay(&mut self, frame: &Frame, overlay: &Frame) {
+        self.display.present_frame(frame, Some(overlay));
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        self.audio.set_buzzer(on);
+    }
+
+    fn play_sound(&mut self, sound: Sound) {
+        self.audio.play(sound);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.display.set_title(title);
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.timers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::f32::consts::FRAC_1_SQRT_2;
+    use std::rc::Rc;
+
+    use super::{background, flash_color, icon_pixels, overlay_placement, pan_gains, parse_aspect, parse_pan, select_renderer, Audio, AudioOut, FlashDraws, GamepadMap, Orientation, PixelGeometry, Renderer, BACKGROUND_COLOR, BUTTON_NAMES, FLASH_COLOR, FLASH_FRAMES, ICON_SIZE, PIXEL_COLOR};
+    use chip8::audio::{AudioSink, Sound};
+    use chip8::{Arch, Frame};
+    use sdl2::controller::Button;
+    use sdl2::pixels::Color;
+    use sdl2::rect::Rect;
+    use sdl2::render::BlendMode;
+
+    #[test]
+    fn aspect() {
+        assert_eq!(parse_aspect("1:1"), Ok(1.0));
+        assert_eq!(parse_aspect("1:2"), Ok(0.5));
+        assert_eq!(parse_aspect("4:3"), Ok(4.0 / 3.0));
+        assert!(parse_aspect("2").is_err());
+        assert!(parse_aspect("0:1").is_err());
+        assert!(parse_aspect("1:x").is_err());
+    }
+
+    #[test]
+    fn flash_draws() {
+        let mut frame = Frame::with_size(4, 2);
+        let mut flash = FlashDraws::default();
+        flash.update(&frame);
+        assert_eq!(flash.level(0, 0), 0);
+
+        // A draw turns two pixels on, the next one turns one of them off.
+        frame[0_u32][1] = 1;
+        frame[1_u32][2] = 1;
+        flash.update(&frame);
+        assert_eq!((flash.level(1, 0), flash.level(2, 1), flash.level(0, 0)), (FLASH_FRAMES, FLASH_FRAMES, 0));
+        flash.update(&frame);
+        frame[0_u32][1] = 0;
+        flash.update(&frame);
+        assert_eq!((flash.level(1, 0), flash.level(2, 1)), (FLASH_FRAMES, FLASH_FRAMES - 2));
+
+        // Then they fade out and stay out.
+        for _ in 0..FLASH_FRAMES {
+            flash.update(&frame);
+        }
+        assert_eq!((flash.level(1, 0), flash.level(2, 1)), (0, 0));
+        // Past the edge.
+        assert_eq!(flash.level(9, 9), 0);
+
+        assert_eq!(flash_color(PIXEL_COLOR, FLASH_FRAMES), FLASH_COLOR);
+        assert_eq!(flash_color(PIXEL_COLOR, 0), PIXEL_COLOR);
+    }
+
+    #[test]
+    fn transparent_background() {
+        assert_eq!(background(false), (Color::RGBA(0, 0, 255, 255), BlendMode::None));
+        assert_eq!(background(true), (Color::RGBA(0, 0, 255, 0), BlendMode::Blend));
+    }
+
+    #[test]
+    fn renderer_fallback() {
+        let mut tried = Vec::new();
+        let picked = select_renderer(Renderer::Accelerated, |r| {
+            tried.push(r);
+            match r {
+                Renderer::Accelerated => Err("no GL".to_string()),
+                Renderer::Software => Ok(1),
+            }
+        });
+        assert_eq!(picked, Ok((Renderer::Software, 1)));
+        assert_eq!(tried, vec![Renderer::Accelerated, Renderer::Software]);
+
+        // Accelerated is used when it works.
+        assert_eq!(select_renderer(Renderer::Accelerated, Ok::<_, String>), Ok((Renderer::Accelerated, Renderer::Accelerated)));
+
+        // Software has nothing to fall back to.
+        let picked: Result<(Renderer, ()), String> = select_renderer(Renderer::Software, |_| Err("no window".to_string()));
+        assert_eq!(picked, Err("no window".to_string()));
+    }
+
+    #[test]
+    fn geometry() {
+        let square = PixelGeometry::new(1.0);
+        assert_eq!(square, PixelGeometry { width: 14, height: 14 });
+        assert_eq!(square.screen_size(&Arch::chip8(), Orientation::default()), (896, 448));
+        let hires = Arch { display_width: 128, display_height: 64, ..Arch::chip8() };
+        assert_eq!(square.screen_size(&hires, Orientation::default()), (1792, 896));
+
+        let tall = PixelGeometry::new(0.5);
+        assert_eq!(tall, PixelGeometry { width: 14, height: 28 });
+        assert_eq!(tall.screen_size(&Arch::chip8(), Orientation::default()), (896, 896));
+        let sideways = Orientation { quarter_turns: 1, ..Default::default() };
+        assert_eq!(tall.screen_size(&Arch::chip8(), sideways), (448, 1792));
+        assert_eq!(tall.pixel_rect(1, 1), Rect::new(15, 29, 12, 26));
+
+        let wide = PixelGeometry::new(1.5);
+        assert_eq!(wide, PixelGeometry { width: 21, height: 14 });
+        assert_eq!(wide.pixel_rect(2, 0), Rect::new(43, 1, 19, 12));
+    }
+
+    #[test]
+    fn orientation() {
+        // Where the top left and top right pixels of a 3x2 frame go, and
+        // the window size in pixels.
+        let expected = [
+            // Turns, flip x, flip y.
+            ((0, false, false), (0, 0), (2, 0), (3, 2)),
+            ((0, true, false), (2, 0), (0, 0), (3, 2)),
+            ((0, false, true), (0, 1), (2, 1), (3, 2)),
+            ((0, true, true), (2, 1), (0, 1), (3, 2)),
+            ((1, false, false), (1, 0), (1, 2), (2, 3)),
+            ((1, true, false), (0, 0), (0, 2), (2, 3)),
+            ((1, false, true), (1, 2), (1, 0), (2, 3)),
+            ((1, true, true), (0, 2), (0, 0), (2, 3)),
+            ((2, false, false), (2, 1), (0, 1), (3, 2)),
+            ((2, true, false), (0, 1), (2, 1), (3, 2)),
+            ((2, false, true), (2, 0), (0, 0), (3, 2)),
+            ((2, true, true), (0, 0), (2, 0), (3, 2)),
+            ((3, false, false), (0, 2), (0, 0), (2, 3)),
+            ((3, true, false), (1, 2), (1, 0), (2, 3)),
+            ((3, false, true), (0, 0), (0, 2), (2, 3)),
+            ((3, true, true), (1, 0), (1, 2), (2, 3)),
+        ];
+        for ((quarter_turns, flip_x, flip_y), top_left, top_right, size) in expected {
+            let o = Orientation { quarter_turns, flip_x, flip_y };
+            assert_eq!(o.transform(0, 0, 3, 2), top_left, "{:?}", o);
+            assert_eq!(o.transform(2, 0, 3, 2), top_right, "{:?}", o);
+            assert_eq!(o.size(3, 2), size);
+            // Every pixel lands on its own spot inside the window.
+            let mut spots: Vec<(u32, u32)> = (0..2).flat_map(|y| (0..3).map(move |x| o.transform(x, y, 3, 2))).collect();
+            spots.sort();
+            spots.dedup();
+            assert_eq!(spots.len(), 6);
+            assert!(spots.iter().all(|&(x, y)| x < size.0 && y < size.1));
+        }
+    }
+
+    #[test]
+    fn overlay() {
+        // The memory viewer at 4x in the default window.
+        assert_eq!(overlay_placement((896, 448), (206, 112)), (36, 0, 4));
+        assert_eq!(overlay_placement((1000, 300), (206, 112)), (294, 38, 2));
+        // With the register panel next to it.
+        assert_eq!(overlay_placement((896, 448), (303, 112)), (145, 112, 2));
+        // Never below 1x, cut off when the window is too small.
+        assert_eq!(overlay_placement((200, 100), (206, 112)), (-3, -6, 1));
+    }
+
+    // A device that fails to resume once `fail` is set. The buzzer and
+    // collision calls it got go to `log`.
+    struct FakeOut {
+        name: &'static str,
+        fail: Rc<Cell<bool>>,
+        log: Log,
+    }
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    impl AudioOut for FakeOut {
+        fn resume(&mut self) -> Result<(), String> {
+            match self.fail.get() {
+                true => Err("device is Stopped".to_string()),
+                false => Ok(()),
+            }
         }
+
+        fn freq(&self) -> u32 {
+            1000
+        }
+
+        fn set_buzzer(&mut self, on: bool) {
+            self.log.borrow_mut().push(format!("{} buzzer {}", self.name, on));
+        }
+
+        fn start_collision(&mut self, samples: u32) {
+            self.log.borrow_mut().push(format!("{} collision {}", self.name, samples));
+        }
+
+        fn set_pan(&mut self, pan: f32) {
+            self.log.borrow_mut().push(format!("{} pan {}", self.name, pan));
+        }
+    }
+
+    // Audio on fake devices, the first one opened working and the others
+    // failing when `reopen_fails` is set.
+    fn fake_audio(reopen_fails: bool) -> (Audio, Rc<Cell<bool>>, Log) {
+        let fail = Rc::new(Cell::new(false));
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (first_fail, first_log) = (fail.clone(), log.clone());
+        let mut opened = 0;
+        let audio = Audio::with_device(Box::new(move || {
+            opened += 1;
+            match opened {
+                1 => Ok(Box::new(FakeOut { name: "first", fail: first_fail.clone(), log: first_log.clone() }) as Box<dyn AudioOut>),
+                _ if reopen_fails => Err("no device".to_string()),
+                _ => Ok(Box::new(FakeOut { name: "second", fail: Rc::new(Cell::new(false)), log: first_log.clone() }) as Box<dyn AudioOut>),
+            }
+        })).unwrap();
+        (audio, fail, log)
+    }
+
+    #[test]
+    fn audio_reopen() {
+        let (mut audio, fail, log) = fake_audio(false);
+        audio.set_buzzer(true);
+        fail.set(true);
+        audio.set_buzzer(false);
+        audio.play(Sound::Collision);
+        assert!(audio.dev.is_some());
+        assert_eq!(*log.borrow(), ["first buzzer true", "second buzzer false", "second collision 40"]);
+    }
+
+    #[test]
+    fn pan() {
+        let close = |pan: f32, [l, r]: [f32; 2]| {
+            let [gl, gr] = pan_gains(pan);
+            (gl - l).abs() < 1e-4 && (gr - r).abs() < 1e-4
+        };
+        assert!(close(-1.0, [1.0, 0.0]));
+        assert!(close(0.0, [FRAC_1_SQRT_2, FRAC_1_SQRT_2]));
+        assert!(close(0.5, [0.3827, 0.9239]));
+        assert!(close(1.0, [0.0, 1.0]));
+        // Past the ends is all on one side.
+        assert!(close(3.0, [0.0, 1.0]));
+
+        assert_eq!(parse_pan("-0.25"), Ok(-0.25));
+        assert!(parse_pan("1.5").is_err());
+        assert!(parse_pan("left").is_err());
+    }
+
+    #[test]
+    fn audio_lost() {
+        let (mut audio, fail, log) = fake_audio(true);
+        fail.set(true);
+        audio.set_buzzer(true);
+        assert!(audio.dev.is_none());
+        // Sound stays off without panicking.
+        audio.set_buzzer(false);
+        audio.play(Sound::Collision);
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn gamepad_map() {
+        let map = GamepadMap::default();
+        assert_eq!(map.key(Button::DPadUp), Some(0x2));
+        assert_eq!(map.key(Button::DPadDown), Some(0x8));
+        assert_eq!(map.key(Button::DPadLeft), Some(0x4));
+        assert_eq!(map.key(Button::DPadRight), Some(0x6));
+        assert_eq!(map.key(Button::A), Some(0x5));
+        assert_eq!(map.key(Button::Guide), None);
+
+        // Every mapped button presses a different key.
+        let mut keys: Vec<u8> = BUTTON_NAMES.iter().filter_map(|(b, _)| map.key(*b)).collect();
+        assert_eq!(keys.len(), 14);
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), 14);
+        assert!(keys.iter().all(|&k| k <= 0xF));
+
+        let map = GamepadMap::parse("a=f, Guide=E,dpup=2").unwrap();
+        assert_eq!(map.key(Button::A), Some(0xF));
+        assert_eq!(map.key(Button::Guide), Some(0xE));
+        assert_eq!(map.key(Button::B), Some(0x0));
+
+        assert!(GamepadMap::parse("a").is_err());
+        assert!(GamepadMap::parse("z=1").is_err());
+        assert!(GamepadMap::parse("a=10").is_err());
+        assert!(GamepadMap::parse("a=g").is_err());
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(super::palette(false), "pixels #C8C8C8, background #0000FF");
+        assert_eq!(super::palette(true), "pixels #C8C8C8, background #0000FF transparent");
+    }
+
+    #[test]
+    fn icon() {
+        let pixels = icon_pixels();
+        assert_eq!(pixels.len(), (ICON_SIZE * ICON_SIZE * 4) as usize);
+        let at = |x: u32, y: u32| {
+            let i = ((y * ICON_SIZE + x) * 4) as usize;
+            Color::RGBA(pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3])
+        };
+        assert_eq!(at(0, 0), BACKGROUND_COLOR);
+        assert_eq!(at(ICON_SIZE - 1, ICON_SIZE - 1), BACKGROUND_COLOR);
+        // The top left of the "8", and the hole in its upper loop.
+        assert_eq!(at(6, 3), PIXEL_COLOR);
+        assert_eq!(at(13, 8), BACKGROUND_COLOR);
+        assert_eq!(at(25, 27), PIXEL_COLOR);
+        assert_eq!(at(26, 27), BACKGROUND_COLOR);
     }
 }
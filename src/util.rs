@@ -1,6 +1,9 @@
+use std::ops::Deref;
+use std::ops::DerefMut;
 use std::ops::Index;
 use std::ops::IndexMut;
 use std::ops::Range;
+use std::ops::RangeFull;
 use std::ops::RangeTo;
 use std::ops::Add;
 use num::Zero;
@@ -57,6 +60,119 @@ impl<T: Zero + Copy, const SIZE: usize> Array<T, SIZE> {
     }
 }
 
+impl<T: Zero + Copy, const SIZE: usize> Default for Array<T, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Zero + Copy, const SIZE: usize> Deref for Array<T, SIZE> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.buf
+    }
+}
+
+impl<T: Zero + Copy, const SIZE: usize> DerefMut for Array<T, SIZE> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.buf
+    }
+}
+
+impl<T: Zero + Copy, const SIZE: usize> AsRef<[T]> for Array<T, SIZE> {
+    fn as_ref(&self) -> &[T] {
+        &self.buf
+    }
+}
+
+impl<T: Zero + Copy, const SIZE: usize> AsMut<[T]> for Array<T, SIZE> {
+    fn as_mut(&mut self) -> &mut [T] {
+        &mut self.buf
+    }
+}
+
+impl<T: Zero + Copy, const SIZE: usize> IntoIterator for Array<T, SIZE> {
+    type Item = T;
+    type IntoIter = std::array::IntoIter<T, SIZE>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buf.into_iter()
+    }
+}
+
+impl<'a, T: Zero + Copy, const SIZE: usize> IntoIterator for &'a Array<T, SIZE> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buf.iter()
+    }
+}
+
+impl<'a, T: Zero + Copy, const SIZE: usize> IntoIterator for &'a mut Array<T, SIZE> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buf.iter_mut()
+    }
+}
+
+// Serialized as a fixed-length tuple. Deserializing checks the length.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    use num::Zero;
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::ser::SerializeTuple;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Array;
+
+    impl<T, const SIZE: usize> Serialize for Array<T, SIZE>
+    where T: Zero + Copy + Serialize {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut t = serializer.serialize_tuple(SIZE)?;
+            for v in &self.buf {
+                t.serialize_element(v)?;
+            }
+            t.end()
+        }
+    }
+
+    struct ArrayVisitor<T, const SIZE: usize>(PhantomData<T>);
+
+    impl<'de, T, const SIZE: usize> Visitor<'de> for ArrayVisitor<T, SIZE>
+    where T: Zero + Copy + Deserialize<'de> {
+        type Value = Array<T, SIZE>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an array of {} elements", SIZE)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut a = Array::new();
+            for i in 0..SIZE {
+                a.buf[i] = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            }
+            if seq.next_element::<T>()?.is_some() {
+                return Err(de::Error::invalid_length(SIZE + 1, &self));
+            }
+            Ok(a)
+        }
+    }
+
+    impl<'de, T, const SIZE: usize> Deserialize<'de> for Array<T, SIZE>
+    where T: Zero + Copy + Deserialize<'de> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_tuple(SIZE, ArrayVisitor(PhantomData))
+        }
+    }
+}
+
 macro_rules! impl_index_slice {
     ($t:ty) => {
         impl <T: Zero + Copy, const SIZE: usize> Index<$t> for Array<T, SIZE> {
@@ -71,6 +187,7 @@ macro_rules! impl_index_slice {
 
 impl_index_slice!(Range<usize>);
 impl_index_slice!(RangeTo<usize>);
+impl_index_slice!(RangeFull);
 
 macro_rules! impl_index {
     ($t:ty) => {
@@ -97,9 +214,65 @@ impl_index!(u8);
 impl_index!(usize);
 impl_index!(i32);
 
+// 64-bit FNV-1a. Stable across platforms and Rust versions, unlike std's hasher.
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Sequential little-endian reader over a byte slice.
+pub struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        ByteReader {
+            buf,
+            pos: 0,
+        }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let out = self.buf.get(self.pos..end)?;
+        self.pos = end;
+        Some(out)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(self.bytes(8)?);
+        Some(u64::from_le_bytes(v))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Array;
+    use super::ByteReader;
+    use super::fnv1a64;
     use num::Zero;
 
     #[test]
@@ -118,4 +291,77 @@ mod tests {
         a.clear();
         assert!(a.is_zero());
     }
+
+    #[test]
+    fn slice_deref() {
+        let mut a: Array<u8, 4> = Array::default();
+        assert_eq!(a.len(), 4);
+        assert!(a.is_zero());
+
+        a.copy_from_slice(&[4, 3, 2, 1]);
+        a.sort();
+        assert_eq!(&a[..], &[1, 2, 3, 4]);
+        assert_eq!(a.first(), Some(&1));
+        assert_eq!(a.as_ref(), &[1, 2, 3, 4]);
+
+        a.as_mut()[0] = 9;
+        assert_eq!(a[0_u8], 9);
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut a: Array<u16, 3> = Array::new();
+        for v in &mut a {
+            *v += 2;
+        }
+        a.iter_mut().for_each(|v| *v *= 3);
+
+        let refs: Vec<&u16> = (&a).into_iter().collect();
+        assert_eq!(refs, vec![&6, &6, &6]);
+        let mut sum = 0;
+        for v in &a {
+            sum += v;
+        }
+        assert_eq!(sum, 18);
+
+        let owned: Vec<u16> = a.into_iter().collect();
+        assert_eq!(owned, vec![6, 6, 6]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut a: Array<u8, 4096> = Array::new();
+        for (i, v) in a.iter_mut().enumerate() {
+            *v = (i * 7) as u8;
+        }
+        let json = serde_json::to_string(&a).unwrap();
+        let b: Array<u8, 4096> = serde_json::from_str(&json).unwrap();
+        assert_eq!(&a[..], &b[..]);
+
+        // Wrong lengths are rejected.
+        assert!(serde_json::from_str::<Array<u8, 4>>("[1,2,3]").is_err());
+        assert!(serde_json::from_str::<Array<u8, 4>>("[1,2,3,4,5]").is_err());
+        assert!(serde_json::from_str::<Array<u8, 4>>("[1,2,3,4]").is_ok());
+    }
+
+    #[test]
+    fn byte_reader() {
+        let data = [0x01, 0x02, 0x03, 0, 0, 0, 0, 0, 0, 0x01, 0x00];
+        let mut r = ByteReader::new(&data);
+
+        assert_eq!(r.u8(), Some(0x01));
+        assert_eq!(r.u16(), Some(0x0302));
+        assert_eq!(r.u64(), Some(0x0001_0000_0000_0000));
+        assert!(r.is_empty());
+        assert_eq!(r.u8(), None);
+        assert_eq!(ByteReader::new(&[2, 0, 1, 0]).u32(), Some(0x10002));
+    }
+
+    #[test]
+    fn fnv() {
+        assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a64(b"foobar"), 0x85944171f73967e8);
+    }
 }
@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::chip::Chip;
+use crate::debugger;
+
+// A value logged every frame by --watch-csv.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchItem {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Mem(u16),
+}
+
+impl WatchItem {
+    // The column name, as it is given on the command line.
+    pub fn name(&self) -> String {
+        match self {
+            WatchItem::V(x) => format!("v{:x}", x),
+            WatchItem::I => "i".to_string(),
+            WatchItem::Pc => "pc".to_string(),
+            WatchItem::Sp => "sp".to_string(),
+            WatchItem::Dt => "dt".to_string(),
+            WatchItem::St => "st".to_string(),
+            WatchItem::Mem(addr) => format!("mem:{:#05x}", addr),
+        }
+    }
+
+    // None for memory past the end of RAM.
+    pub fn value(&self, chip: &Chip) -> Option<u16> {
+        match *self {
+            WatchItem::V(x) => Some(chip.v(x) as u16),
+            WatchItem::I => Some(chip.i()),
+            WatchItem::Pc => Some(chip.pc()),
+            WatchItem::Sp => Some(chip.sp() as u16),
+            WatchItem::Dt => Some(chip.dt() as u16),
+            WatchItem::St => Some(chip.st() as u16),
+            WatchItem::Mem(addr) => chip.ram_slice(addr as u32..addr as u32 + 1).map(|b| b[0] as u16),
+        }
+    }
+}
+
+// Parses "v0" to "vf", "i", "pc", "sp", "dt", "st" or "mem:ADDR" with the
+// address in decimal or 0x-prefixed hex.
+pub fn parse_watch_item(s: &str) -> Result<WatchItem, String> {
+    let s = s.trim().to_ascii_lowercase();
+    if let Some(addr) = s.strip_prefix("mem:") {
+        return debugger::parse_addr(addr).map(WatchItem::Mem);
+    }
+    match s.as_str() {
+        "i" => Ok(WatchItem::I),
+        "pc" => Ok(WatchItem::Pc),
+        "sp" => Ok(WatchItem::Sp),
+        "dt" => Ok(WatchItem::Dt),
+        "st" => Ok(WatchItem::St),
+        _ => match s.strip_prefix('v').map(|x| u8::from_str_radix(x, 16)) {
+            Some(Ok(x)) if s.len() == 2 => Ok(WatchItem::V(x)),
+            _ => Err(format!("invalid watch '{}', expected v0-vf, i, pc, sp, dt, st or mem:ADDR", s)),
+        },
+    }
+}
+
+pub fn header(items: &[WatchItem]) -> String {
+    let mut names = vec!["frame".to_string()];
+    names.extend(items.iter().map(|w| w.name()));
+    names.join(",")
+}
+
+// The frame number and the values in decimal, memory past RAM left empty.
+pub fn row(frame: u64, items: &[WatchItem], chip: &Chip) -> String {
+    let mut values = vec![frame.to_string()];
+    values.extend(items.iter().map(|w| w.value(chip).map_or(String::new(), |v| v.to_string())));
+    values.join(",")
+}
+
+// The CSV file of a run, a row per frame.
+pub struct WatchCsv {
+    out: BufWriter<File>,
+    items: Vec<WatchItem>,
+}
+
+impl WatchCsv {
+    // Creates the file and writes the header.
+    pub fn create(path: &Path, items: &[WatchItem]) -> io::Result<WatchCsv> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", header(items))?;
+        Ok(WatchCsv { out, items: items.to_vec() })
+    }
+
+    pub fn write_frame(&mut self, frame: u64, chip: &Chip) -> io::Result<()> {
+        writeln!(self.out, "{}", row(frame, &self.items, chip))
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// The columns of --csv-registers after the cycle and the PC.
+pub const REGISTERS: [WatchItem; 20] = [
+    WatchItem::V(0x0), WatchItem::V(0x1), WatchItem::V(0x2), WatchItem::V(0x3),
+    WatchItem::V(0x4), WatchItem::V(0x5), WatchItem::V(0x6), WatchItem::V(0x7),
+    WatchItem::V(0x8), WatchItem::V(0x9), WatchItem::V(0xA), WatchItem::V(0xB),
+    WatchItem::V(0xC), WatchItem::V(0xD), WatchItem::V(0xE), WatchItem::V(0xF),
+    WatchItem::I, WatchItem::Sp, WatchItem::Dt, WatchItem::St,
+];
+
+// The registers after every executed instruction, a row each, for
+// --csv-registers. The cycle counts the executed instructions before it,
+// as in a trace, and pc is the instruction's address.
+pub struct RegisterCsv {
+    out: BufWriter<File>,
+}
+
+impl RegisterCsv {
+    pub fn create(path: &Path) -> io::Result<RegisterCsv> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let names: Vec<String> = REGISTERS.iter().map(|w| w.name()).collect();
+        writeln!(out, "cycle,pc,{}", names.join(","))?;
+        Ok(RegisterCsv { out })
+    }
+
+    pub fn write_instruction(&mut self, cycle: u64, pc: u16, chip: &Chip) -> io::Result<()> {
+        write!(self.out, "{},{}", cycle, pc)?;
+        for w in &REGISTERS {
+            write!(self.out, ",{}", w.value(chip).unwrap())?;
+        }
+        writeln!(self.out)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header, parse_watch_item, row, WatchItem};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_watch_item("v3"), Ok(WatchItem::V(3)));
+        assert_eq!(parse_watch_item("VF"), Ok(WatchItem::V(0xF)));
+        assert_eq!(parse_watch_item("i"), Ok(WatchItem::I));
+        assert_eq!(parse_watch_item("DT"), Ok(WatchItem::Dt));
+        assert_eq!(parse_watch_item("mem:0x340"), Ok(WatchItem::Mem(0x340)));
+        assert_eq!(parse_watch_item("mem:832"), Ok(WatchItem::Mem(0x340)));
+        for bad in ["v", "v10", "vg", "x", "mem:", "mem:0xz"] {
+            assert!(parse_watch_item(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rows() {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(&[
+            0x63, 0x2A, // LD V3, 42
+            0xA3, 0x40, // LD I, 0x340
+        ]).build().unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        let items = [WatchItem::V(3), WatchItem::I, WatchItem::Pc, WatchItem::Mem(0x201), WatchItem::Mem(0xFFFF)];
+        assert_eq!(header(&items), "frame,v3,i,pc,mem:0x201,mem:0xffff");
+        assert_eq!(row(7, &items, &chip), "7,42,832,516,42,");
+    }
+}
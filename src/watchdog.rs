@@ -0,0 +1,216 @@
+use crate::chip::Chip;
+use crate::instr::{Instr, Operation};
+
+// Trips when one frame executes more CLS instructions than `threshold`.
+// No real program clears the screen hundreds of times per frame; a crashed
+// one running through a block of 00E0 does.
+pub struct ClsWatchdog {
+    threshold: u32,
+    frame_start: u64,
+}
+
+impl ClsWatchdog {
+    pub fn new(threshold: u32) -> ClsWatchdog {
+        ClsWatchdog {
+            threshold,
+            frame_start: 0,
+        }
+    }
+
+    pub fn start_frame(&mut self, chip: &Chip) {
+        self.frame_start = chip.clears();
+    }
+
+    pub fn clears_this_frame(&self, chip: &Chip) -> u64 {
+        // The counter restarts when the chip is reset mid-frame.
+        chip.clears().saturating_sub(self.frame_start)
+    }
+
+    pub fn tripped(&self, chip: &Chip) -> bool {
+        self.clears_this_frame(chip) > self.threshold as u64
+    }
+}
+
+// Counts DRW instructions per frame against `limit`. Busy games draw a few
+// dozen sprites a frame; a runaway loop of DRW draws thousands.
+pub struct DrawGuard {
+    limit: u32,
+    frame_start: u64,
+}
+
+impl DrawGuard {
+    pub fn new(limit: u32) -> DrawGuard {
+        DrawGuard {
+            limit,
+            frame_start: 0,
+        }
+    }
+
+    pub fn start_frame(&mut self, chip: &Chip) {
+        self.frame_start = chip.draws();
+    }
+
+    pub fn draws_this_frame(&self, chip: &Chip) -> u64 {
+        chip.draws().saturating_sub(self.frame_start)
+    }
+
+    // True from the draw that goes over the limit until the next frame.
+    pub fn tripped(&self, chip: &Chip) -> bool {
+        self.draws_this_frame(chip) > self.limit as u64
+    }
+}
+
+// Catches a program drawing before it ever set I: the sprite then comes
+// from address 0, the font, and shows up as a stray digit. Only the first
+// DRW is looked at, later ones with I at 0 can be deliberate.
+#[derive(Default)]
+pub struct DrawBeforeI {
+    seen_ld_i: bool,
+    checked: bool,
+}
+
+impl DrawBeforeI {
+    pub fn new() -> DrawBeforeI {
+        DrawBeforeI::default()
+    }
+
+    // After each executed instruction. Returns the warning, once.
+    pub fn instruction(&mut self, chip: &Chip, pc: u16, opcode: u16) -> Option<String> {
+        if self.checked {
+            return None;
+        }
+        match Instr::new(opcode).operation()? {
+            Operation::LdINnn { .. } | Operation::LdFVx { .. } => self.seen_ld_i = true,
+            // DRW leaves I as it was.
+            Operation::Drw { .. } => {
+                self.checked = true;
+                if !self.seen_ld_i && chip.i() == 0 {
+                    return Some(format!("DRW at {:#05x} before any LD I draws the font at address 0", pc));
+                }
+            },
+            _ => {},
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClsWatchdog, DrawBeforeI, DrawGuard};
+    use crate::chip::Chip;
+    use crate::profile::Profile;
+
+    fn cls_spam() -> Chip {
+        let mut chip = Chip::new(Profile::modern());
+        chip.load_rom(&[
+            0x00, 0xE0, // CLS
+            0x00, 0xE0, // CLS
+            0x00, 0xE0, // CLS
+            0x12, 0x00, // JP 0x200
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        chip
+    }
+
+    #[test]
+    fn fires() {
+        let mut chip = cls_spam();
+        let mut watchdog = ClsWatchdog::new(100);
+        watchdog.start_frame(&chip);
+
+        let mut cycles = 0;
+        while !watchdog.tripped(&chip) {
+            chip.cycle().unwrap();
+            cycles += 1;
+            assert!(cycles < 1000);
+        }
+        assert_eq!(watchdog.clears_this_frame(&chip), 101);
+        assert_eq!(cycles, 134);
+    }
+
+    #[test]
+    fn per_frame() {
+        let mut chip = cls_spam();
+        let mut watchdog = ClsWatchdog::new(100);
+
+        for _ in 0..10 {
+            watchdog.start_frame(&chip);
+            for _ in 0..100 {
+                chip.cycle().unwrap();
+                assert!(!watchdog.tripped(&chip));
+            }
+        }
+    }
+
+    #[test]
+    fn draw_guard() {
+        let mut chip = Chip::new(Profile::modern());
+        chip.load_rom(&[
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x12, 0x00, // JP 0x200
+        ], 0x200).unwrap();
+        chip.set_pc(0x200);
+        let mut guard = DrawGuard::new(10);
+
+        for _ in 0..3 {
+            guard.start_frame(&chip);
+            assert_eq!(guard.draws_this_frame(&chip), 0);
+            for _ in 0..20 {
+                chip.cycle().unwrap();
+                assert!(!guard.tripped(&chip));
+            }
+        }
+        chip.cycle().unwrap();
+        chip.cycle().unwrap();
+        assert_eq!(guard.draws_this_frame(&chip), 11);
+        assert!(guard.tripped(&chip));
+        assert_eq!(chip.draws(), 31);
+    }
+
+    fn first_draw_warning(rom: &[u8]) -> Option<String> {
+        let mut chip = Chip::builder().profile(Profile::modern()).rom(rom).build().unwrap();
+        let mut check = DrawBeforeI::new();
+        let mut warnings = Vec::new();
+        for _ in 0..8 {
+            let (pc, opcode) = (chip.pc(), chip.ram().read_u16(chip.pc() as u32).unwrap());
+            chip.cycle().unwrap();
+            warnings.extend(check.instruction(&chip, pc, opcode));
+        }
+        assert!(warnings.len() <= 1);
+        warnings.pop()
+    }
+
+    #[test]
+    fn draw_before_i() {
+        assert_eq!(first_draw_warning(&[
+            0x60, 0x08, // LD V0, 8
+            0xD0, 0x05, // DRW V0, V0, 5
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x04, // JP 0x204
+        ]), Some("DRW at 0x202 before any LD I draws the font at address 0".to_string()));
+        assert_eq!(first_draw_warning(&[
+            0xA2, 0x06, // LD I, 0x206
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x02, // JP 0x202
+        ]), None);
+        // The "0" glyph on purpose.
+        assert_eq!(first_draw_warning(&[
+            0x60, 0x00, // LD V0, 0
+            0xF0, 0x29, // LD F, V0
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x04, // JP 0x204
+        ]), None);
+    }
+
+    #[test]
+    fn reset_mid_frame() {
+        let mut chip = cls_spam();
+        let mut watchdog = ClsWatchdog::new(1);
+        for _ in 0..4 {
+            chip.cycle().unwrap();
+        }
+        watchdog.start_frame(&chip);
+        chip.reset();
+        assert_eq!(watchdog.clears_this_frame(&chip), 0);
+    }
+}
@@ -0,0 +1,109 @@
+// Community test ROMs run headless and deterministic, their last frame
+// compared with the expectations in tests/expected. The ROMs aren't all
+// bundled, so this is ignored by default:
+//
+//   CHIP8_TEST_ROMS=/path/to/roms cargo test --test rom_suites -- --ignored
+//
+// Without CHIP8_TEST_ROMS the bundled rom/tests is used. ROMs not found are
+// skipped. CHIP8_BLESS=1 writes the expectation of a case that has none or
+// doesn't match; check its picture before committing it.
+
+use std::path::{Path, PathBuf};
+
+use chip8::golden::{frame_hash, render};
+use chip8::{rom, Chip, FrameStream, Profile};
+
+struct Case {
+    rom: &'static str,
+    profile: &'static str,
+    frames: usize,
+    // (frame, key, pressed), for the menus of the Timendus ROMs.
+    keys: &'static [(u64, u8, bool)],
+}
+
+const CASES: &[Case] = &[
+    Case { rom: "test_opcode.ch8", profile: "modern", frames: 120, keys: &[] },
+    Case { rom: "test_opcode.ch8", profile: "original", frames: 120, keys: &[] },
+    Case { rom: "4-flags.ch8", profile: "modern", frames: 300, keys: &[] },
+    Case { rom: "4-flags.ch8", profile: "original", frames: 300, keys: &[] },
+    // 1 picks CHIP-8 in the platform menu.
+    Case { rom: "5-quirks.ch8", profile: "original", frames: 600, keys: &[(60, 0x1, true), (65, 0x1, false)] },
+    Case { rom: "5-quirks.ch8", profile: "modern", frames: 600, keys: &[(60, 0x1, true), (65, 0x1, false)] },
+    // 3 picks the FX0A test, which then waits for a key.
+    Case { rom: "6-keypad.ch8", profile: "modern", frames: 300, keys: &[(30, 0x3, true), (35, 0x3, false), (90, 0xA, true), (95, 0xA, false)] },
+];
+
+// 600 instructions a second, --deterministic's default.
+const IPS: u32 = 600;
+
+fn expectation_path(case: &Case) -> PathBuf {
+    let stem = Path::new(case.rom).file_stem().unwrap().to_string_lossy().into_owned();
+    Path::new("tests/expected").join(format!("{}.{}.txt", stem, case.profile))
+}
+
+// The expectation file: a comment line, the frame hash, then the frame as
+// golden::render() draws it.
+fn expectation(case: &Case, hash: u64, picture: &str) -> String {
+    format!("# {}, {} profile, {} frames\nhash {:016x}\n{}", case.rom, case.profile, case.frames, hash, picture)
+}
+
+// The last frame, or why there is none.
+fn run(case: &Case, path: &Path) -> Result<chip8::Frame, String> {
+    let profile = Profile::from_name(case.profile).map_err(|e| e.to_string())?;
+    let mut chip = Chip::builder().profile(profile).seed(0).build().map_err(|e| e.to_string())?;
+    rom::start(&mut chip, path, &[]).map_err(|e| e.to_string())?;
+    let mut stream = FrameStream::new(chip, IPS);
+    for &(frame, key, pressed) in case.keys {
+        stream = if pressed { stream.key_press(frame, key) } else { stream.key_release(frame, key) };
+    }
+    let last = stream.by_ref().take(case.frames).last();
+    match (stream.error(), last) {
+        (Some(e), _) => Err(format!("stopped with: {}", e)),
+        (None, Some(frame)) => Ok(frame),
+        (None, None) => Err("no frames".to_string()),
+    }
+}
+
+#[test]
+#[ignore]
+fn rom_suites() {
+    let dir = PathBuf::from(std::env::var_os("CHIP8_TEST_ROMS").unwrap_or_else(|| "rom/tests".into()));
+    let bless = std::env::var_os("CHIP8_BLESS").is_some();
+    let mut failures = Vec::new();
+    for case in CASES {
+        let path = dir.join(case.rom);
+        if !path.exists() {
+            println!("skipped {}, not in {}", case.rom, dir.display());
+            continue;
+        }
+        let name = format!("{} ({})", case.rom, case.profile);
+        let frame = match run(case, &path) {
+            Ok(frame) => frame,
+            Err(e) => {
+                failures.push(format!("{}: {}", name, e));
+                continue;
+            },
+        };
+        let (hash, picture) = (frame_hash(&frame), render(&frame));
+        let expected_path = expectation_path(case);
+        let expected = std::fs::read_to_string(&expected_path).ok();
+        let expected_hash = expected.as_deref()
+            .and_then(|e| e.lines().find_map(|l| l.strip_prefix("hash ")))
+            .and_then(|h| u64::from_str_radix(h, 16).ok());
+        if expected_hash == Some(hash) {
+            println!("ok {}", name);
+            continue;
+        }
+        if bless {
+            std::fs::write(&expected_path, expectation(case, hash, &picture)).unwrap();
+            println!("wrote {}", expected_path.display());
+            continue;
+        }
+        match expected {
+            Some(e) => println!("{} differs.\nExpected:\n{}\nActual, hash {:016x}:\n{}", name, e, hash, picture),
+            None => println!("{} has no {}. Actual, hash {:016x}:\n{}", name, expected_path.display(), hash, picture),
+        }
+        failures.push(name);
+    }
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}